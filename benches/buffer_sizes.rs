@@ -0,0 +1,110 @@
+// Times compressing and decompressing files at both ends of the sizes the
+// pipeline picks buffers for, 10k files of 4 KiB and one of 256 MiB, with
+// the buffers sized from each file and with the default for every file.
+// Run with:
+//
+//   cargo bench --bench buffer_sizes
+
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use zap::{
+    compression::CompressionType,
+    encryption::{EncryptionSecret, EncryptionType},
+    pipeline::PipelineConfig,
+    signing::SigningType,
+};
+
+const SMALL_FILES: usize = 10_000;
+const SMALL_FILE_SIZE: usize = 4096;
+const LARGE_FILE_SIZE: usize = 256 * 1024 * 1024;
+
+// Compressible, but not so much that compressing it costs nothing.
+fn contents(len: usize, seed: u32) -> Vec<u8> {
+    let mut state = seed.wrapping_add(1);
+
+    (0..len)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            b"abcdefgh"[(state >> 29) as usize]
+        })
+        .collect()
+}
+
+fn config() -> PipelineConfig {
+    PipelineConfig::new(
+        EncryptionType::Passthrough,
+        EncryptionSecret::None,
+        CompressionType::Lz4,
+        flate2::Compression::default(),
+        SigningType::default(),
+    )
+}
+
+// Compresses each of 'files' next to itself and decompresses it back,
+// returning how long each took.
+fn round_trip(config: &PipelineConfig, files: &[PathBuf], sized: bool) -> (Duration, Duration) {
+    let builder = |len: u64| match sized {
+        true => config.builder().with_size_hint(len),
+        false => config.builder(),
+    };
+
+    let start = Instant::now();
+
+    for file in files {
+        let len = fs::metadata(file).unwrap().len();
+        let output = File::create(file.with_extension("lz4")).unwrap();
+
+        builder(len).build().compress_stream(&mut File::open(file).unwrap(), output).unwrap();
+    }
+
+    let compressed = start.elapsed();
+    let start = Instant::now();
+
+    for file in files {
+        let input = file.with_extension("lz4");
+        let len = fs::metadata(&input).unwrap().len();
+        let mut output = File::create(file.with_extension("out")).unwrap();
+
+        builder(len).build().decompress_stream(File::open(&input).unwrap(), &mut output).unwrap();
+    }
+
+    (compressed, start.elapsed())
+}
+
+fn bench(name: &str, files: &[PathBuf]) {
+    let config = config();
+
+    let (default_compress, default_decompress) = round_trip(&config, files, false);
+    let (sized_compress, sized_decompress) = round_trip(&config, files, true);
+
+    println!(
+        "{:>6}: compress default {:>10.2?}, sized {:>10.2?}; decompress default {:>10.2?}, sized {:>10.2?}",
+        name, default_compress, sized_compress, default_decompress, sized_decompress,
+    );
+}
+
+fn write_files(dir: &Path, count: usize, len: usize) -> Vec<PathBuf> {
+    fs::create_dir_all(dir).unwrap();
+
+    (0..count)
+        .map(|i| {
+            let path = dir.join(format!("file{}.bin", i));
+            fs::write(&path, contents(len, i as u32)).unwrap();
+
+            path
+        })
+        .collect()
+}
+
+fn main() {
+    let root = std::env::temp_dir().join(format!("zap-bench-buffers-{}", std::process::id()));
+
+    bench("small", &write_files(&root.join("small"), SMALL_FILES, SMALL_FILE_SIZE));
+    bench("large", &write_files(&root.join("large"), 1, LARGE_FILE_SIZE));
+
+    fs::remove_dir_all(&root).unwrap();
+}
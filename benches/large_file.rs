@@ -0,0 +1,80 @@
+// Times compressing a directory holding a single 512 MiB file on one
+// thread, then with --threads-per-file set to the number of CPUs. Run with:
+//
+//   cargo bench --bench large_file
+
+use std::{
+    fs,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use zap::{
+    compression::CompressionType,
+    encryption::{EncryptionSecret, EncryptionType},
+    options::CompressOptions,
+    signing::SigningType,
+};
+
+const FILE_SIZE: usize = 512 * 1024 * 1024;
+
+// Compressible, but not so much that compressing it costs nothing.
+fn contents() -> Vec<u8> {
+    let mut state: u32 = 1;
+
+    (0..FILE_SIZE)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            b"abcdefgh"[(state >> 29) as usize]
+        })
+        .collect()
+}
+
+fn compress(input: &Path, output: &Path, compression: &CompressionType, threads_per_file: usize) -> Duration {
+    let options = CompressOptions { threads_per_file, ..CompressOptions::default() };
+    let start = Instant::now();
+
+    zap::compress_directory(
+        &input.to_string_lossy(),
+        &output.to_string_lossy(),
+        EncryptionType::Passthrough,
+        EncryptionSecret::None,
+        compression.clone(),
+        flate2::Compression::default(),
+        SigningType::default(),
+        &options,
+    )
+    .unwrap();
+
+    let elapsed = start.elapsed();
+    fs::remove_dir_all(output).unwrap();
+
+    elapsed
+}
+
+fn main() {
+    let root = std::env::temp_dir().join(format!("zap-bench-large-{}", std::process::id()));
+    let input = root.join("input");
+    let output = root.join("output");
+
+    fs::create_dir_all(&input).unwrap();
+    fs::write(input.join("disk.img"), contents()).unwrap();
+
+    let threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+
+    for compression in [CompressionType::Lz4, CompressionType::Gzip, CompressionType::Snappy] {
+        let one = compress(&input, &output, &compression, 1);
+        let many = compress(&input, &output, &compression, threads);
+
+        println!(
+            "{:>6}: 1 thread {:>10.2?}, {} threads {:>10.2?}, {:.1}x faster",
+            format!("{:?}", compression),
+            one,
+            threads,
+            many,
+            one.as_secs_f64() / many.as_secs_f64(),
+        );
+    }
+
+    fs::remove_dir_all(&root).unwrap();
+}
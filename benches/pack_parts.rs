@@ -0,0 +1,107 @@
+// Times packing a compressed tree of 10k small files, as one archive and as
+// the parts written with --max-archive-size, one after another and in
+// parallel as the CLI does. A single archive is packed by zapf on one
+// thread, so only parts are packed in parallel. Run with:
+//
+//   cargo bench --bench pack_parts
+
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+use zap::{
+    compression::CompressionType,
+    encryption::{EncryptionSecret, EncryptionType},
+    options::CompressOptions,
+    signing::SigningType,
+};
+
+const FILES: usize = 10_000;
+const FILES_PER_DIR: usize = 100;
+const FILE_SIZE: usize = 4096;
+const PARTS: u64 = 8;
+
+fn tree(root: &Path) {
+    for i in 0..FILES {
+        let dir = root.join(format!("dir{}", i / FILES_PER_DIR));
+        fs::create_dir_all(&dir).unwrap();
+
+        // Varied enough between files that they don't all compress alike.
+        let line = format!("file {} of the packing benchmark\n", i * 7919);
+        let contents: Vec<u8> = line.bytes().cycle().take(FILE_SIZE).collect();
+
+        fs::write(dir.join(format!("file{}.txt", i)), contents).unwrap();
+    }
+}
+
+fn pack(dir: &Path, out: &Path) {
+    let mut writer = BufWriter::new(File::create(out).unwrap());
+
+    zapf::pack_files(&dir.to_string_lossy(), &mut writer).unwrap();
+    writer.flush().unwrap();
+}
+
+fn time(name: &str, f: impl FnOnce()) -> Duration {
+    let start = Instant::now();
+    f();
+    let elapsed = start.elapsed();
+
+    println!("{:>16}: {:>10.2?}", name, elapsed);
+
+    elapsed
+}
+
+fn dir_size(dir: &Path) -> u64 {
+    walkdir::WalkDir::new(dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter_map(|e| e.metadata().ok())
+        .filter(|m| m.is_file())
+        .map(|m| m.len())
+        .sum()
+}
+
+fn main() {
+    let root = std::env::temp_dir().join(format!("zap-bench-pack-{}", std::process::id()));
+    let input = root.join("input");
+    let staging = root.join("staging");
+
+    tree(&input);
+
+    zap::compress_directory(
+        &input.to_string_lossy(),
+        &staging.to_string_lossy(),
+        EncryptionType::Passthrough,
+        EncryptionSecret::None,
+        CompressionType::Lz4,
+        flate2::Compression::default(),
+        SigningType::default(),
+        &CompressOptions::default(),
+    )
+    .unwrap();
+
+    time("single archive", || pack(&staging, &root.join("single.zap")));
+
+    let parts: Vec<PathBuf> = zap::split_staged(&staging.to_string_lossy(), dir_size(&staging) / PARTS + 1).unwrap();
+    let out = |n: usize, kind: &str| root.join(format!("{}.part{}.zap", kind, n + 1));
+
+    let serial = time("parts, serial", || {
+        for (n, part) in parts.iter().enumerate() {
+            pack(part, &out(n, "serial"));
+        }
+    });
+
+    let parallel = time("parts, parallel", || {
+        let numbered: Vec<(usize, &PathBuf)> = parts.iter().enumerate().collect();
+        numbered.par_iter().for_each(|(n, part)| pack(part, &out(*n, "parallel")));
+    });
+
+    println!("{} parts, {:.1}x faster in parallel", parts.len(), serial.as_secs_f64() / parallel.as_secs_f64());
+
+    // Parts are moved out next to the staging directory, so under root too.
+    fs::remove_dir_all(&root).unwrap();
+}
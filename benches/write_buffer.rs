@@ -0,0 +1,158 @@
+// Times packing an archive of 16 files of 8 MiB, and extracting it, to a
+// disk that costs LATENCY for every write, as a spinning one seeking
+// would. Each is written through a buffer of 8 KiB, BufWriter's default,
+// then one of DEFAULT_WRITE_BUFFER_SIZE. Run with:
+//
+//   cargo bench --bench write_buffer
+
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Error, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use zap::{
+    compression::CompressionType,
+    encryption::{EncryptionSecret, EncryptionType},
+    fs::{FileInfo, FileSystem, RealFs},
+    options::{CompressOptions, DecompressOptions, DEFAULT_WRITE_BUFFER_SIZE},
+    signing::SigningType,
+};
+
+const FILES: usize = 16;
+const FILE_SIZE: usize = 8 * 1024 * 1024;
+const SMALL_BUFFER_SIZE: usize = 8 * 1024;
+const LATENCY: Duration = Duration::from_micros(100);
+
+// Compressible, but not so much that compressing it costs nothing.
+fn contents(seed: u32) -> Vec<u8> {
+    let mut state = seed.wrapping_add(1);
+
+    (0..FILE_SIZE)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            b"abcdefgh"[(state >> 29) as usize]
+        })
+        .collect()
+}
+
+// Waits LATENCY before every write.
+struct SlowWriter<W>(W);
+
+impl<W: Write> Write for SlowWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        std::thread::sleep(LATENCY);
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
+// The real filesystem, with every file created written through SlowWriter.
+struct SlowFs;
+
+impl FileSystem for SlowFs {
+    type Reader = File;
+    type Writer = SlowWriter<File>;
+
+    fn open(&self, path: &Path) -> Result<Self::Reader, Error> {
+        RealFs.open(path)
+    }
+
+    fn create(&self, path: &Path) -> Result<Self::Writer, Error> {
+        RealFs.create(path).map(SlowWriter)
+    }
+
+    fn metadata(&self, path: &Path) -> Result<FileInfo, Error> {
+        RealFs.metadata(path)
+    }
+
+    fn walk(&self, root: &Path) -> Result<Vec<PathBuf>, Error> {
+        RealFs.walk(root)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<(), Error> {
+        RealFs.create_dir_all(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<(), Error> {
+        RealFs.remove_file(path)
+    }
+}
+
+fn pack(staging: &Path, out: &Path, buffer_size: usize) -> Duration {
+    let start = Instant::now();
+
+    let mut writer = BufWriter::with_capacity(buffer_size, SlowWriter(File::create(out).unwrap()));
+    zapf::pack_files(&staging.to_string_lossy(), &mut writer).unwrap();
+    writer.flush().unwrap();
+
+    start.elapsed()
+}
+
+fn extract(staging: &Path, out: &Path, buffer_size: usize) -> Duration {
+    let options = DecompressOptions { write_buffer_size: Some(buffer_size), ..DecompressOptions::default() };
+    let start = Instant::now();
+
+    zap::decompress_directory_in(
+        &SlowFs,
+        &staging.to_string_lossy(),
+        &out.to_string_lossy(),
+        EncryptionType::Passthrough,
+        EncryptionSecret::None,
+        CompressionType::Auto,
+        SigningType::default(),
+        &options,
+    )
+    .unwrap();
+
+    let elapsed = start.elapsed();
+    fs::remove_dir_all(out).unwrap();
+
+    elapsed
+}
+
+fn main() {
+    let root = std::env::temp_dir().join(format!("zap-bench-write-buffer-{}", std::process::id()));
+    let input = root.join("input");
+    let staging = root.join("staging");
+
+    fs::create_dir_all(&input).unwrap();
+
+    for i in 0..FILES {
+        fs::write(input.join(format!("file{}.bin", i)), contents(i as u32)).unwrap();
+    }
+
+    zap::compress_directory(
+        &input.to_string_lossy(),
+        &staging.to_string_lossy(),
+        EncryptionType::Passthrough,
+        EncryptionSecret::None,
+        CompressionType::Lz4,
+        flate2::Compression::default(),
+        SigningType::default(),
+        &CompressOptions::default(),
+    )
+    .unwrap();
+
+    for (name, f) in [("pack", pack as fn(&Path, &Path, usize) -> Duration), ("extract", extract)] {
+        let out = root.join(name);
+        let small = f(&staging, &out, SMALL_BUFFER_SIZE);
+        let large = f(&staging, &out, DEFAULT_WRITE_BUFFER_SIZE);
+
+        println!(
+            "{:>8}: {} byte buffer {:>10.2?}, {} byte buffer {:>10.2?}, {:.1}x faster",
+            name,
+            SMALL_BUFFER_SIZE,
+            small,
+            DEFAULT_WRITE_BUFFER_SIZE,
+            large,
+            small.as_secs_f64() / large.as_secs_f64(),
+        );
+    }
+
+    fs::remove_dir_all(&root).unwrap();
+}
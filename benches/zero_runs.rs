@@ -0,0 +1,49 @@
+// Compares finding the holes of a sparse image a byte at a time with
+// zap::sparse's vectorised scan. Run with:
+//
+//   cargo bench --bench zero_runs
+
+use std::time::{Duration, Instant};
+
+use zap::sparse::{zero_runs, zero_runs_naive, BLOCK_SIZE};
+
+const IMAGE_SIZE: usize = 256 * 1024 * 1024;
+const ROUNDS: u32 = 5;
+
+// Mostly holes, as a freshly provisioned disk image is, with a data block
+// every so often whose only non zero byte is its last, the worst case for
+// a scan that stops early.
+fn image() -> Vec<u8> {
+    let mut buf = vec![0; IMAGE_SIZE];
+
+    for block in buf.chunks_mut(BLOCK_SIZE).step_by(16) {
+        *block.last_mut().unwrap() = 1;
+    }
+
+    buf
+}
+
+fn time(name: &str, buf: &[u8], scan: fn(&[u8], usize) -> Vec<std::ops::Range<usize>>) -> Duration {
+    let mut best = Duration::MAX;
+    let mut holes = 0;
+
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        holes = std::hint::black_box(scan(std::hint::black_box(buf), BLOCK_SIZE)).len();
+        best = best.min(start.elapsed());
+    }
+
+    let throughput = buf.len() as f64 / best.as_secs_f64() / (1024.0 * 1024.0);
+    println!("{:>6}: {:>10.2?} {:>8.0} MiB/s, {} holes", name, best, throughput, holes);
+
+    best
+}
+
+fn main() {
+    let buf = image();
+
+    let naive = time("naive", &buf, zero_runs_naive);
+    let lanes = time("lanes", &buf, zero_runs);
+
+    println!("{:.1}x faster", naive.as_secs_f64() / lanes.as_secs_f64());
+}
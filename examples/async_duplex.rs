@@ -0,0 +1,55 @@
+// Compresses a buffer into one end of a tokio duplex stream, as a server
+// would into a socket, and reads it back from the other. Run with:
+//
+//   cargo run --example async_duplex --features async
+
+use tokio::io::AsyncReadExt;
+use zap::{
+    async_io::compress_into,
+    compression::CompressionType,
+    encryption::{EncryptionSecret, EncryptionType},
+    pipeline::PipelineConfig,
+    signing::SigningType,
+};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let input: Vec<u8> = b"zap over a duplex stream\n".repeat(10_000);
+
+    let config = PipelineConfig::new(
+        EncryptionType::Passthrough,
+        EncryptionSecret::None,
+        CompressionType::Lz4,
+        flate2::Compression::default(),
+        SigningType::default(),
+    );
+
+    // Smaller than the output, so that compression waits on the reader.
+    let (mut client, mut server) = tokio::io::duplex(16 * 1024);
+
+    let reading = tokio::spawn(async move {
+        let mut received = Vec::new();
+        server.read_to_end(&mut received).await.map(|_| received)
+    });
+
+    let written = compress_into(std::io::Cursor::new(input.clone()), config, &mut client).await?;
+
+    // Closes the stream, ending the read.
+    drop(client);
+
+    let received = reading.await??;
+
+    let output = zap::decompress_bytes(
+        &received,
+        &EncryptionType::Passthrough,
+        &EncryptionSecret::None,
+        &CompressionType::Lz4,
+        &SigningType::default(),
+    )?;
+
+    assert_eq!(output, input);
+
+    println!("Compressed {} bytes to {} through the duplex stream", input.len(), written);
+
+    Ok(())
+}
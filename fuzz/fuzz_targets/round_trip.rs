@@ -0,0 +1,91 @@
+#![no_main]
+
+use libfuzzer_sys::{
+    arbitrary::{self, Arbitrary},
+    fuzz_target,
+};
+use zap::{
+    compression::CompressionType,
+    encryption::{EncryptionSecret, EncryptionType},
+    signing::SigningType,
+};
+
+#[derive(Debug, Arbitrary)]
+enum FuzzCompression {
+    Passthrough,
+    Lz4,
+    Gzip,
+    Snappy,
+}
+
+impl From<FuzzCompression> for CompressionType {
+    fn from(c: FuzzCompression) -> Self {
+        match c {
+            FuzzCompression::Passthrough => CompressionType::Passthrough,
+            FuzzCompression::Lz4 => CompressionType::Lz4,
+            FuzzCompression::Gzip => CompressionType::Gzip,
+            FuzzCompression::Snappy => CompressionType::Snappy,
+        }
+    }
+}
+
+#[derive(Debug, Arbitrary)]
+enum FuzzEncryption {
+    Passthrough,
+    XChaCha,
+    AesGcm,
+    ChaCha,
+}
+
+impl From<FuzzEncryption> for EncryptionType {
+    fn from(e: FuzzEncryption) -> Self {
+        match e {
+            FuzzEncryption::Passthrough => EncryptionType::Passthrough,
+            FuzzEncryption::XChaCha => EncryptionType::XChaCha,
+            FuzzEncryption::AesGcm => EncryptionType::AesGcm,
+            FuzzEncryption::ChaCha => EncryptionType::ChaCha,
+        }
+    }
+}
+
+#[derive(Debug, Arbitrary)]
+struct RoundTrip {
+    compression: FuzzCompression,
+    encryption: FuzzEncryption,
+    key: [u8; 32],
+    flip: usize,
+    data: Vec<u8>,
+}
+
+fuzz_target!(|input: RoundTrip| {
+    let compression: CompressionType = input.compression.into();
+    let encryption: EncryptionType = input.encryption.into();
+    let secret = match encryption {
+        EncryptionType::Passthrough => EncryptionSecret::None,
+        _ => EncryptionSecret::Password(input.key.to_vec()),
+    };
+    let level = flate2::Compression::fast();
+    let signing = SigningType::Passthrough;
+
+    let archive = zap::compress_bytes(&input.data, &encryption, &secret, &compression, &level, &signing)
+        .expect("Failed to compress input");
+
+    let restored = zap::decompress_bytes(&archive, &encryption, &secret, &compression, &signing)
+        .expect("Failed to decompress archive");
+
+    assert_eq!(restored, input.data, "Round trip produced different output");
+
+    // Authenticated encryption must reject any modified archive.
+    if matches!(encryption, EncryptionType::Passthrough) || archive.is_empty() {
+        return;
+    }
+
+    let mut tampered = archive;
+    let index = input.flip % tampered.len();
+    tampered[index] ^= 0x01;
+
+    assert!(
+        zap::decompress_bytes(&tampered, &encryption, &secret, &compression, &signing).is_err(),
+        "Tampered archive passed authentication"
+    );
+});
@@ -0,0 +1,71 @@
+/*
+ * C interface to zap, built with:
+ *
+ *   cargo rustc --lib --release --features ffi --crate-type cdylib
+ *
+ * Every function returns a zap_status_t, except for zap_config_new which
+ * returns NULL on invalid arguments. Panics are caught before they reach
+ * the caller and reported as ZAP_PANICKED. Anything allocated by zap must
+ * be freed with the matching zap_*_free function.
+ */
+
+#ifndef ZAP_H
+#define ZAP_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define ZAP_COMPRESSION_PASSTHROUGH 0
+#define ZAP_COMPRESSION_LZ4 1
+#define ZAP_COMPRESSION_GZIP 2
+#define ZAP_COMPRESSION_SNAPPY 3
+
+#define ZAP_ENCRYPTION_PASSTHROUGH 0
+#define ZAP_ENCRYPTION_XCHACHA 1
+#define ZAP_ENCRYPTION_AES_GCM 2
+#define ZAP_ENCRYPTION_CHACHA 3
+
+typedef enum {
+    ZAP_OK = 0,
+    /* A pointer was NULL, a path wasn't valid UTF-8 or a value was out of range. */
+    ZAP_INVALID_ARGUMENT = 1,
+    /* A file couldn't be opened, created or written. */
+    ZAP_IO_ERROR = 2,
+    ZAP_COMPRESSION_FAILED = 3,
+    /* Includes input that was tampered with or the wrong password. */
+    ZAP_DECOMPRESSION_FAILED = 4,
+    ZAP_PANICKED = 5,
+} zap_status_t;
+
+/* Opaque, holds the algorithms and secret used by the other functions. */
+typedef struct ZapConfig zap_config_t;
+
+/* Bytes allocated by zap, freed with zap_buffer_free. */
+typedef struct {
+    uint8_t *data;
+    size_t len;
+} zap_buffer_t;
+
+/* level is a gzip level from 0 to 9, ignored by other algorithms. */
+zap_config_t *zap_config_new(uint32_t compression, uint32_t encryption, uint32_t level);
+/* Required before using a config with encryption. */
+zap_status_t zap_config_set_password(zap_config_t *config, const uint8_t *password, size_t len);
+void zap_config_free(zap_config_t *config);
+
+zap_status_t zap_compress_buffer(const zap_config_t *config, const uint8_t *input, size_t len, zap_buffer_t *output);
+zap_status_t zap_decompress_buffer(const zap_config_t *config, const uint8_t *input, size_t len, zap_buffer_t *output);
+void zap_buffer_free(zap_buffer_t buffer);
+
+/* Paths are NUL terminated UTF-8. The output is removed if processing fails. */
+zap_status_t zap_compress_file(const zap_config_t *config, const char *input, const char *output);
+zap_status_t zap_decompress_file(const zap_config_t *config, const char *input, const char *output);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
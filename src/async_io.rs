@@ -0,0 +1,255 @@
+// Bridge for feeding the output of the pipeline into tokio, enabled with
+// the 'async' feature.
+//
+// The pipeline stays synchronous and runs on one of tokio's blocking
+// threads, started with spawn_blocking, so it never holds up the runtime's
+// workers. What it writes is sent in chunks of CHUNK_SIZE over a channel of
+// CHANNEL_CHUNKS, which is read from as an AsyncRead. Once the channel is
+// full the blocking thread waits for the reader to catch up, so a slow
+// socket slows compression down rather than it being buffered in memory.
+
+use std::{
+    future::Future,
+    io::{BufWriter, Error, ErrorKind, Read, Write},
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf},
+    sync::mpsc::{channel, Receiver, Sender},
+    task::JoinHandle,
+};
+
+use crate::pipeline::PipelineConfig;
+
+/// Size of the chunks the pipeline's output is sent in.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+/// Chunks written but not yet read before the pipeline waits, bounding
+/// the memory held to about CHUNK_SIZE times this.
+pub const CHANNEL_CHUNKS: usize = 8;
+
+/// The compressed output of 'input', read as it is produced. Compression
+/// runs on a blocking thread, so this must be called from within a tokio
+/// runtime. A failure of the pipeline is returned by the read that would
+/// have reached it, as an error of kind Other holding the
+/// PipelineCompressionError. Dropping the reader stops compression at its
+/// next write.
+pub fn compress_reader<R>(input: R, config: PipelineConfig) -> CompressedReader
+where R: Read + Send + 'static
+{
+    let (sender, receiver) = channel(CHANNEL_CHUNKS);
+
+    let task = tokio::task::spawn_blocking(move || {
+        let mut input = input;
+        let mut writer = BufWriter::with_capacity(CHUNK_SIZE, ChannelWriter(sender.clone()));
+
+        let result = config.builder()
+            .build()
+            .compress_stream(&mut input, &mut writer)
+            .and_then(|()| Ok(writer.flush()?));
+
+        // Nothing is left to read it when the reader was dropped.
+        if let Err(e) = result {
+            let _ = sender.blocking_send(Err(Error::other(e)));
+        }
+    });
+
+    CompressedReader {
+        receiver,
+        task: Some(task),
+        chunk: Vec::new(),
+        position: 0,
+    }
+}
+
+/// Compresses 'input' into 'writer', returning the number of bytes written.
+/// The writer is flushed but not shut down, so that more can be written to
+/// it, see compress_reader.
+pub async fn compress_into<R, W>(input: R, config: PipelineConfig, writer: &mut W) -> Result<u64, Error>
+where
+    R: Read + Send + 'static,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let mut reader = compress_reader(input, config);
+
+    let written = tokio::io::copy(&mut reader, writer).await?;
+    writer.flush().await?;
+
+    Ok(written)
+}
+
+pub struct CompressedReader {
+    receiver: Receiver<Result<Vec<u8>, Error>>,
+    // Checked once the channel closes, as a panicking pipeline closes it
+    // too and would otherwise look like the end of the output.
+    task: Option<JoinHandle<()>>,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl AsyncRead for CompressedReader {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<(), Error>> {
+        let this = self.get_mut();
+
+        loop {
+            if this.position < this.chunk.len() {
+                let n = buf.remaining().min(this.chunk.len() - this.position);
+
+                buf.put_slice(&this.chunk[this.position..this.position + n]);
+                this.position += n;
+
+                return Poll::Ready(Ok(()));
+            }
+
+            match ready!(this.receiver.poll_recv(cx)) {
+                Some(Ok(chunk)) => {
+                    this.chunk = chunk;
+                    this.position = 0;
+                },
+                Some(Err(e)) => return Poll::Ready(Err(e)),
+                None => {
+                    let Some(task) = this.task.as_mut() else {
+                        return Poll::Ready(Ok(()));
+                    };
+
+                    let finished = ready!(Pin::new(task).poll(cx));
+                    this.task = None;
+
+                    return Poll::Ready(finished.map_err(Error::other));
+                },
+            }
+        }
+    }
+}
+
+// Sends each write as a chunk, waiting while the channel is full.
+struct ChannelWriter(Sender<Result<Vec<u8>, Error>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .blocking_send(Ok(buf.to_vec()))
+            .map_err(|_| Error::new(ErrorKind::BrokenPipe, "the compressed output is no longer being read"))?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc, time::Duration};
+
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+    use crate::{
+        compression::CompressionType,
+        encryption::{EncryptionSecret, EncryptionType},
+        error::PipelineCompressionError,
+        signing::SigningType,
+    };
+
+    fn config() -> PipelineConfig {
+        PipelineConfig::new(
+            EncryptionType::Passthrough,
+            EncryptionSecret::None,
+            CompressionType::Lz4,
+            flate2::Compression::default(),
+            SigningType::default(),
+        )
+    }
+
+    struct Failing;
+
+    impl Read for Failing {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            Err(Error::other("source failed"))
+        }
+    }
+
+    // Never ends, and says so when the pipeline lets go of it.
+    struct Endless(mpsc::Sender<()>);
+
+    impl Read for Endless {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            buf.fill(b'z');
+            Ok(buf.len())
+        }
+    }
+
+    impl Drop for Endless {
+        fn drop(&mut self) {
+            let _ = self.0.send(());
+        }
+    }
+
+    #[tokio::test]
+    async fn compressed_into_a_writer_round_trips() {
+        let input = b"through tokio".repeat(20_000);
+        let (mut writer, mut reader) = tokio::io::duplex(CHUNK_SIZE);
+
+        let source = input.clone();
+        let mut compressed = Vec::new();
+
+        // Read at the same time, as the duplex holds less than the output.
+        let (written, read) = tokio::join!(
+            async move {
+                let written = compress_into(std::io::Cursor::new(source), config(), &mut writer).await;
+                drop(writer);
+                written
+            },
+            reader.read_to_end(&mut compressed),
+        );
+
+        assert_eq!(written.unwrap(), read.unwrap() as u64);
+
+        let decompressed = crate::decompress_bytes(
+            &compressed,
+            &EncryptionType::Passthrough,
+            &EncryptionSecret::None,
+            &CompressionType::Lz4,
+            &SigningType::default(),
+        )
+        .unwrap();
+
+        assert_eq!(decompressed, input);
+    }
+
+    #[tokio::test]
+    async fn failing_source_is_a_read_error() {
+        let e = compress_into(Failing, config(), &mut Vec::new()).await.unwrap_err();
+
+        assert_eq!(e.kind(), ErrorKind::Other);
+        assert!(e.get_ref().is_some_and(|e| e.is::<PipelineCompressionError>()));
+    }
+
+    #[tokio::test]
+    async fn dropped_reader_stops_compression() {
+        let (dropped, stopped) = mpsc::channel();
+        let mut reader = compress_reader(Endless(dropped), config());
+
+        let mut start = [0; 16];
+        reader.read_exact(&mut start).await.unwrap();
+        drop(reader);
+
+        // Waited on off the runtime, which the pipeline needs to finish.
+        let stopped = tokio::task::spawn_blocking(move || stopped.recv_timeout(Duration::from_secs(10))).await.unwrap();
+
+        assert!(stopped.is_ok());
+    }
+
+    #[test]
+    fn writes_without_a_reader_are_a_broken_pipe() {
+        let (sender, receiver) = channel(1);
+        drop(receiver);
+
+        let e = ChannelWriter(sender).write(b"chunk").unwrap_err();
+
+        assert_eq!(e.kind(), ErrorKind::BrokenPipe);
+    }
+}
@@ -0,0 +1,75 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use sha2::{Digest, Sha256, Sha512};
+
+#[derive(Debug, Default, Clone, ValueEnum)]
+pub enum ChecksumAlgorithm {
+    #[default]
+    Sha256,
+    Sha512,
+    Blake3,
+}
+
+impl ChecksumAlgorithm {
+    // Matching the tools that check them, eg: sha256sum and b3sum.
+    fn extension(&self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Sha256 => "sha256",
+            ChecksumAlgorithm::Sha512 => "sha512",
+            ChecksumAlgorithm::Blake3 => "b3",
+        }
+    }
+
+    fn digest(&self, path: &Path) -> Result<Vec<u8>, io::Error> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        Ok(match self {
+            ChecksumAlgorithm::Sha256 => {
+                let mut hasher = Sha256::new();
+                io::copy(&mut reader, &mut hasher)?;
+                hasher.finalize().to_vec()
+            },
+            ChecksumAlgorithm::Sha512 => {
+                let mut hasher = Sha512::new();
+                io::copy(&mut reader, &mut hasher)?;
+                hasher.finalize().to_vec()
+            },
+            ChecksumAlgorithm::Blake3 => {
+                let mut hasher = blake3::Hasher::new();
+                io::copy(&mut reader, &mut hasher)?;
+                hasher.finalize().as_bytes().to_vec()
+            },
+        })
+    }
+}
+
+/// Writes the digest of 'archive' next to it in the format read by
+/// `sha256sum -c` and friends. Only the file name is written, so the
+/// check is run from the directory holding both.
+pub fn write_sidecar(archive: &Path, algorithm: &ChecksumAlgorithm) -> Result<PathBuf, io::Error> {
+    let digest: String = algorithm.digest(archive)?
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+
+    let name = archive.file_name()
+        .ok_or_else(|| io::Error::other("Archive has no file name"))?
+        .to_string_lossy();
+
+    let mut sidecar = archive.as_os_str().to_os_string();
+    sidecar.push(".");
+    sidecar.push(algorithm.extension());
+
+    let sidecar = PathBuf::from(sidecar);
+
+    let mut file = File::create(&sidecar)?;
+    writeln!(file, "{}  {}", digest, name)?;
+    file.flush()?;
+
+    Ok(sidecar)
+}
@@ -1,13 +1,18 @@
 use clap::ValueEnum;
+use log::warn;
 use zap::compression::CompressionType;
 
+use super::error::RuntimeError;
 
 
-#[derive(Debug, Clone, ValueEnum)]
+
+#[derive(Debug, Clone)]
 pub enum CompressionLevel{
     Fastest,
     Best,
     Default,
+    /// Checked against the algorithm's range with 'validate'.
+    Level(u32),
 }
 
 impl From<String> for CompressionLevel {
@@ -20,12 +25,58 @@ impl From<String> for CompressionLevel {
     }
 }
 
+pub fn parse_compression_level(s: &str) -> Result<CompressionLevel, String> {
+    match s {
+        "fastest" => Ok(CompressionLevel::Fastest),
+        "best" => Ok(CompressionLevel::Best),
+        "default" => Ok(CompressionLevel::Default),
+        _ => s.parse()
+            .map(CompressionLevel::Level)
+            .map_err(|_| format!("expected 'fastest', 'best', 'default' or a number, got '{}'", s)),
+    }
+}
+
+impl CompressionLevel {
+    /// Checks a numeric level against every algorithm in use that takes one.
+    pub fn validate<'a>(&self, algorithms: impl IntoIterator<Item = &'a CompressionType>) -> Result<(), RuntimeError> {
+        let CompressionLevel::Level(level) = self else {
+            return Ok(());
+        };
+
+        let mut leveled = false;
+
+        for algorithm in algorithms {
+            let Some(range) = algorithm.level_range() else {
+                continue;
+            };
+
+            if !range.contains(*level) {
+                return Err(RuntimeError::InvalidArgument(format!(
+                    "compression level {} is out of range for {:?}, expected {} to {}",
+                    level, algorithm, range.min, range.max
+                )));
+            }
+
+            leveled = true;
+        }
+
+        if !leveled {
+            warn!("Compression level {} is ignored, none of the algorithms used take a level", level);
+        }
+
+        Ok(())
+    }
+}
+
 impl Into<flate2::Compression> for CompressionLevel {
     fn into(self) -> flate2::Compression {
         match self {
             CompressionLevel::Fastest => flate2::Compression::fast(),
             CompressionLevel::Best => flate2::Compression::best(),
-            CompressionLevel::Default => flate2::Compression::default(),
+            CompressionLevel::Default => flate2::Compression::new(
+                CompressionType::Gzip.level_range().expect("Gzip takes a level").default
+            ),
+            CompressionLevel::Level(level) => flate2::Compression::new(level),
         }
     }
 }
@@ -37,6 +88,8 @@ pub enum BinCompressionType {
     Lz4,
     Gzip,
     Snappy,
+    /// Detect the algorithm from the archive (extraction only)
+    Auto,
 }
 
 impl From<String> for BinCompressionType {
@@ -46,6 +99,7 @@ impl From<String> for BinCompressionType {
             "lz4" => Self::Lz4,
             "gzip" => Self::Gzip,
             "snappy" => Self::Snappy,
+            "auto" => Self::Auto,
             "" => Self::default(),
             _ => Self::Passthrough,
         }
@@ -59,6 +113,7 @@ impl From<CompressionType> for BinCompressionType {
             CompressionType::Lz4 => Self::Lz4,
             CompressionType::Gzip => Self::Gzip,
             CompressionType::Snappy => Self::Snappy,
+            CompressionType::Auto => Self::Auto,
         }
     }
 }
@@ -70,6 +125,7 @@ impl Into<CompressionType> for BinCompressionType {
             BinCompressionType::Lz4 => CompressionType::Lz4,
             BinCompressionType::Gzip => CompressionType::Gzip,
             BinCompressionType::Snappy => CompressionType::Snappy,
+            BinCompressionType::Auto => CompressionType::Auto,
         }
     }
 }
\ No newline at end of file
@@ -1,3 +1,11 @@
+use zap::error::{CompressionError, DecompressionError};
+
+/// Exit code for failures without a more specific code.
+pub const EXIT_FAILURE: u8 = 1;
+/// Exit code for when `compare` finds differences.
+pub const EXIT_DIFFERENCES: u8 = 2;
+/// Exit code for when the destination runs out of disk space.
+pub const EXIT_DISK_FULL: u8 = 3;
 
 
 #[derive(Debug, thiserror::Error)]
@@ -6,4 +14,38 @@ pub enum RuntimeError { // TODO : Rename this error
     NotYetImplemented(&'static str),
     #[error("File not found: {0}")]
     FileNotFound(String),
-}
\ No newline at end of file
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+    #[error("Archive is not signed: {0}")]
+    NotSigned(String),
+    #[error("Only one of --keypath, --password-file, --password-env and --keyring can be given, got: {0}")]
+    ConflictingSecrets(String),
+    #[error("Wrong kind of secret: {0}")]
+    SecretMismatch(String),
+    #[error("{0} (pass --force-algorithm to use it anyway)")]
+    AlgorithmMismatch(String),
+    #[error("Staging directory already exists, another zap may be running: {0} (pass --force to remove it)")]
+    StagingDirExists(String),
+    #[error("Archive {0} differs from {1}")]
+    ArchiveDiffers(String, String),
+    #[error("Archive is locked, another zap may be modifying it: {0} (locked through {1})")]
+    ArchiveLocked(String, String),
+    #[error("The key or password given doesn't open archive: {0}")]
+    SecretDoesNotOpen(String),
+    #[error("Empty or invalid archive: {0} ({1})")]
+    InvalidArchive(String, String),
+}
+pub fn exit_code(error: &anyhow::Error) -> u8 {
+    let disk_full = error.chain().any(|e| {
+        matches!(e.downcast_ref::<CompressionError>(), Some(CompressionError::DiskFull(_)))
+            || matches!(e.downcast_ref::<DecompressionError>(), Some(DecompressionError::DiskFull(_)))
+    });
+
+    let differs = error.chain().any(|e| matches!(e.downcast_ref::<RuntimeError>(), Some(RuntimeError::ArchiveDiffers(..))));
+
+    match (disk_full, differs) {
+        (true, _) => EXIT_DISK_FULL,
+        (false, true) => EXIT_DIFFERENCES,
+        (false, false) => EXIT_FAILURE,
+    }
+}
@@ -0,0 +1,305 @@
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use zap::{
+    footer::ArchiveFooter,
+    manifest::Manifest,
+    report::{ArchiveComparison, ArchiveListing, ListedEntry},
+};
+
+pub fn print_listing(archive: &str, listing: &ArchiveListing, footer: Option<&ArchiveFooter>) {
+    print_summary(archive, listing, footer);
+
+    for entry in listing.entries.iter() {
+        println!(
+            "{:>12}  {:<12}  {}{}",
+            entry.stored_size,
+            format!("{:?}", entry.compression),
+            escape_name(&entry.path.to_string_lossy()),
+            duplicate_of(entry)
+        );
+    }
+
+    println!("{} file(s)", listing.entries.len());
+}
+
+// Entries by directory, directories with the total stored beneath them.
+#[derive(Default)]
+struct TreeNode<'a> {
+    entry: Option<&'a ListedEntry>,
+    children: BTreeMap<String, TreeNode<'a>>,
+}
+
+impl<'a> TreeNode<'a> {
+    fn insert(&mut self, entry: &'a ListedEntry) {
+        let node = entry.path
+            .components()
+            .fold(self, |node, component| {
+                node.children.entry(component.as_os_str().to_string_lossy().into_owned()).or_default()
+            });
+
+        node.entry = Some(entry);
+    }
+
+    fn stored_size(&self) -> u64 {
+        self.entry.map_or(0, |e| e.stored_size) + self.children.values().map(TreeNode::stored_size).sum::<u64>()
+    }
+}
+
+pub fn print_listing_tree(archive: &str, listing: &ArchiveListing, footer: Option<&ArchiveFooter>) {
+    print_summary(archive, listing, footer);
+
+    for line in tree_lines(listing) {
+        println!("{}", line);
+    }
+
+    println!("{} file(s)", listing.entries.len());
+}
+
+// The lines of the tree, from the root down.
+fn tree_lines(listing: &ArchiveListing) -> Vec<String> {
+    let mut root = TreeNode::default();
+
+    for entry in listing.entries.iter() {
+        root.insert(entry);
+    }
+
+    let mut lines = vec![".".to_string()];
+
+    // Drawn with a stack rather than recursion, each node with the prefix
+    // of the levels above it.
+    let mut stack: Vec<(String, &str, &TreeNode, bool)> = root.children
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(n, (name, node))| (String::new(), name.as_str(), node, n == 0))
+        .collect();
+
+    while let Some((prefix, name, node, last)) = stack.pop() {
+        let (branch, indent) = match last {
+            true => ("└── ", "    "),
+            false => ("├── ", "│   "),
+        };
+
+        let suffix = match node.entry {
+            Some(entry) => duplicate_of(entry),
+            None => "/".into(),
+        };
+
+        lines.push(format!("{}{}{}{} ({})", prefix, branch, escape_name(name), suffix, node.stored_size()));
+
+        let prefix = format!("{}{}", prefix, indent);
+
+        stack.extend(
+            node.children
+                .iter()
+                .rev()
+                .enumerate()
+                .map(|(n, (name, child))| (prefix.clone(), name.as_str(), child, n == 0)),
+        );
+    }
+
+    lines
+}
+
+fn duplicate_of(entry: &ListedEntry) -> String {
+    entry.duplicate_of
+        .as_ref()
+        .map(|original| format!(" (duplicate of {})", escape_name(&original.to_string_lossy())))
+        .unwrap_or_default()
+}
+
+fn print_summary(archive: &str, listing: &ArchiveListing, footer: Option<&ArchiveFooter>) {
+    println!("Archive: {}", archive);
+
+    if let Some(footer) = footer {
+        println!("Digest: sha256:{}", footer.hex_digest());
+    }
+
+    match &listing.manifest {
+        Some(manifest) => {
+            if let Some(created_at) = manifest.created_at {
+                let created_at = UNIX_EPOCH + Duration::from_secs(created_at);
+                println!("Created: {}", humantime::format_rfc3339_seconds(created_at));
+            }
+            if let Some(version) = &manifest.zap_version {
+                println!("Zap version: {}", escape_name(version));
+            }
+            if let Some(comment) = &manifest.comment {
+                println!("Comment: {}", escape_name(comment));
+            }
+            println!("Compression: {:?}", manifest.compression);
+            println!("Encryption: {:?}", manifest.encryption);
+        },
+        None => println!("No manifest found, archive was created by an older version of zap."),
+    }
+
+    println!();
+}
+
+#[derive(Serialize)]
+struct JsonListing<'a> {
+    archive: &'a str,
+    digest: Option<String>,
+    manifest: Option<&'a Manifest>,
+    entries: Vec<JsonEntry>,
+}
+
+#[derive(Serialize)]
+struct JsonEntry {
+    path: String,
+    stored_size: u64,
+    compression: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duplicate_of: Option<String>,
+}
+
+// Names are left as they are, serde_json escapes them.
+pub fn print_listing_json(archive: &str, listing: &ArchiveListing, footer: Option<&ArchiveFooter>) -> Result<(), serde_json::Error> {
+    let json = JsonListing {
+        archive,
+        digest: footer.map(|f| format!("sha256:{}", f.hex_digest())),
+        manifest: listing.manifest.as_ref(),
+        entries: listing.entries
+            .iter()
+            .map(|entry| JsonEntry {
+                path: entry.path.to_string_lossy().into_owned(),
+                stored_size: entry.stored_size,
+                compression: format!("{:?}", entry.compression),
+                duplicate_of: entry.duplicate_of.as_ref().map(|p| p.to_string_lossy().into_owned()),
+            })
+            .collect(),
+    };
+
+    println!("{}", serde_json::to_string_pretty(&json)?);
+
+    Ok(())
+}
+
+pub fn print_comparison(comparison: &ArchiveComparison) {
+    let changes = [("added", &comparison.added), ("removed", &comparison.removed), ("modified", &comparison.modified)];
+
+    let mut lines: Vec<(&str, &PathBuf)> = changes.iter()
+        .flat_map(|(change, paths)| paths.iter().map(move |path| (*change, path)))
+        .collect();
+
+    lines.sort_by(|a, b| a.1.cmp(b.1));
+
+    for (change, path) in lines.iter() {
+        println!("{:<8}  {}", change, escape_name(&path.to_string_lossy()));
+    }
+
+    println!(
+        "{} added, {} removed, {} modified",
+        comparison.added.len(),
+        comparison.removed.len(),
+        comparison.modified.len()
+    );
+}
+
+// Entry names come from the archive, so anything that could move the
+// cursor, change colours or reorder text is escaped to stop a name from
+// spoofing other lines of output. Backslashes are escaped too so that
+// the escaped form can't be mistaken for a name containing it.
+fn escape_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '\\' => "\\\\".to_string(),
+            c if c.is_control() || is_bidi_control(c) => c.escape_default().to_string(),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+fn is_bidi_control(c: char) -> bool {
+    matches!(c, '\u{200E}' | '\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A name that would print a fake entry on a line of its own, in red.
+    const SPOOFING: &str = "real.txt\n         1  Lz4  \u{1b}[31mfake.txt";
+
+    #[test]
+    fn names_stay_on_one_line() {
+        let escaped = escape_name(SPOOFING);
+
+        assert_eq!(escaped, "real.txt\\n         1  Lz4  \\u{1b}[31mfake.txt");
+        assert!(!escaped.chars().any(|c| c.is_control()));
+    }
+
+    #[test]
+    fn escapes_are_unambiguous() {
+        assert_eq!(escape_name("a\\nb"), "a\\\\nb");
+        assert_ne!(escape_name("a\\nb"), escape_name("a\nb"));
+        assert_eq!(escape_name("\u{202E}txt.exe"), "\\u{202e}txt.exe");
+        assert_eq!(escape_name("plain/名前.txt"), "plain/名前.txt");
+    }
+
+    #[test]
+    fn json_names_are_escaped() {
+        let entry = JsonEntry {
+            path: SPOOFING.into(),
+            stored_size: 1,
+            compression: "Lz4".into(),
+            duplicate_of: None,
+        };
+
+        let json = serde_json::to_string_pretty(&entry).unwrap();
+
+        assert_eq!(json.lines().count(), 5);
+        assert!(json.contains(r#""path": "real.txt\n         1  Lz4  \u001b[31mfake.txt""#));
+    }
+
+    fn entry(path: &str, stored_size: u64, duplicate_of: Option<&str>) -> ListedEntry {
+        ListedEntry {
+            path: path.into(),
+            stored_size,
+            compression: zap::compression::CompressionType::Lz4,
+            duplicate_of: duplicate_of.map(PathBuf::from),
+        }
+    }
+
+    #[test]
+    fn tree_nests_directories() {
+        let listing = ArchiveListing {
+            manifest: None,
+            entries: vec![
+                entry("src/main.rs", 10, None),
+                entry("Readme.md", 5, None),
+                entry("src/util/io.rs", 20, None),
+                entry("src/util/copy.rs", 0, Some("src/util/io.rs")),
+                entry("src/lib.rs", 30, None),
+            ],
+        };
+
+        assert_eq!(
+            tree_lines(&listing),
+            [
+                ".",
+                "├── Readme.md (5)",
+                "└── src/ (60)",
+                "    ├── lib.rs (30)",
+                "    ├── main.rs (10)",
+                "    └── util/ (20)",
+                "        ├── copy.rs (duplicate of src/util/io.rs) (0)",
+                "        └── io.rs (20)",
+            ],
+        );
+    }
+
+    #[test]
+    fn tree_names_are_escaped() {
+        let listing = ArchiveListing { manifest: None, entries: vec![entry("dir\nname/file\u{1b}", 1, None)] };
+        let lines = tree_lines(&listing);
+
+        assert_eq!(lines.len(), 3);
+        assert!(!lines.iter().any(|line| line.chars().any(|c| c.is_control())));
+    }
+}
@@ -0,0 +1,71 @@
+use std::{
+    fs::{File, OpenOptions},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use fs2::FileExt;
+
+use super::error::RuntimeError;
+
+/// An advisory lock on an archive while it is modified in place, released
+/// when dropped. Reading an archive doesn't take it.
+pub struct ArchiveLock {
+    file: File,
+}
+
+impl ArchiveLock {
+    // The lock is taken on a file next to the archive rather than the
+    // archive itself, which is replaced by a rename and so isn't the same
+    // file throughout. It is left behind afterwards, removing it would let
+    // another run lock a file this one had already unlinked.
+    pub fn acquire(archive: &str, lock_file: Option<&Path>) -> Result<ArchiveLock, anyhow::Error> {
+        let path = lock_file.map(Path::to_path_buf).unwrap_or_else(|| PathBuf::from(format!("{}.lock", archive)));
+
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("Opening lock file '{}'", path.display()))?;
+
+        match file.try_lock_exclusive() {
+            Ok(()) => Ok(ArchiveLock { file }),
+            Err(e) if e.raw_os_error() == fs2::lock_contended_error().raw_os_error() => {
+                Err(RuntimeError::ArchiveLocked(archive.into(), path.to_string_lossy().into()).into())
+            },
+            Err(e) => Err(e).with_context(|| format!("Locking '{}'", path.display())),
+        }
+    }
+}
+
+impl Drop for ArchiveLock {
+    fn drop(&mut self) {
+        // Closing the file releases it too, this is only to do so first.
+        let _ = FileExt::unlock(&self.file);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn second_writer_fails_while_held() {
+        let archive = std::env::temp_dir()
+            .join(format!("zap-lock-test-{}.zap", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+
+        let held = ArchiveLock::acquire(&archive, None).unwrap();
+
+        let second = ArchiveLock::acquire(&archive, None).err().expect("a second lock was taken while held");
+        assert!(matches!(second.downcast_ref::<RuntimeError>(), Some(RuntimeError::ArchiveLocked(..))), "{:?}", second);
+
+        drop(held);
+
+        assert!(ArchiveLock::acquire(&archive, None).is_ok());
+
+        std::fs::remove_file(format!("{}.lock", archive)).unwrap();
+    }
+}
@@ -1,30 +1,74 @@
+mod checksum;
 mod compression;
 mod encryption;
 mod error;
+mod listing;
+mod lock;
 mod logging;
 mod password;
+mod policy;
+mod priority;
+mod progress;
+mod scratch;
+mod secret;
+mod signing;
+mod summary;
 mod util;
 
 use std::{
     fs::{self, File},
-    io::BufWriter, path::PathBuf,
+    io::{self, BufWriter, IsTerminal, Write}, path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
 use anyhow::Context;
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 
-use log::{info, debug};
-use zap::{encryption::EncryptionSecret, build_common_extension};
+use log::{info, debug, warn};
+use rayon::prelude::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use zap::{
+    compression::CompressionType,
+    encryption::{
+        secret::{resolve_secret, SecretProvider},
+        EncryptionType, SecretType,
+    },
+    footer::{append_footer, read_footer, verify_footer, write_footer, HashingWriter},
+    header::{copy_range, read_header, write_header, ArchiveHeader, HEADER_SIZE},
+    manifest::Manifest,
+    recovery::{append_recovery, read_recovery, repair},
+    report::ArchiveListing,
+    signing::SigningType,
+    build_common_extension,
+    options::{
+        CompressOptions, CompressionRule, DecompressOptions, Glob, PathMap, RenameTemplate, RetryPolicy, DEFAULT_IN_MEMORY_THRESHOLD,
+        DEFAULT_WRITE_BUFFER_SIZE, STORED_EXTENSIONS,
+    },
+    progress::Progress,
+};
 
 use zapf::{pack_files, unpack_files};
 
-use crate::cli_util::{logging::init_logger, password::get_password_confirm, error::RuntimeError};
+use crate::cli_util::{logging::init_logger, error::RuntimeError};
+
+pub use self::error::{exit_code, EXIT_DISK_FULL};
 
 use self::{
-    compression::{BinCompressionType, CompressionLevel},
+    checksum::{write_sidecar, ChecksumAlgorithm},
+    compression::{parse_compression_level, BinCompressionType, CompressionLevel},
     encryption::BinEncryptionType,
+    listing::{print_comparison, print_listing, print_listing_json, print_listing_tree},
+    lock::ArchiveLock,
     logging::Verbosity,
-    password::get_password_noconf,
+    password::PasswordCheck,
+    policy::{BinChangePolicy, BinConflictPolicy, BinSymlinkPolicy},
+    priority::lower_priority,
+    progress::{staged_bytes, ProgressDisplay, ProgressFormat, ProgressWriter},
+    scratch::{staged_dirs, staging_path, ScratchDir, StagingDir},
+    secret::{secret_provider, CachedProvider},
+    signing::BinSigningType,
+    summary::RunSummary,
+    util::{compression_for_extension, encryption_for_extension, parse_comment, parse_extensions, parse_rule, replace_file},
 };
 
 #[derive(Debug, Parser)]
@@ -49,10 +93,11 @@ impl Args {
 enum Command {
     /// Archive a folder 
     Archive {
-        /// Input folder
-        input: String,
+        /// Input folders, each stored under its own name when there are several
+        #[arg(required = true)]
+        inputs: Vec<String>,
         #[arg(short, long, default_value = None)]
-        /// Output file
+        /// Output file, required when archiving several folders
         output: Option<String>,
         /// Encrypt using default algorithm (XChaChaPoly1305)
         #[arg(short, long)]
@@ -60,9 +105,18 @@ enum Command {
         /// Compress using default algorithm (Lz4)
         #[arg(short, long)]
         compress: bool,
-        /// Path to private key file (not currently supported)
+        /// Path to a keyfile holding the raw key, as long as the algorithm's key, instead of a password
         #[arg(short, long)]
         keypath: Option<String>,
+        /// Read the password from the first line of a file instead of prompting for it
+        #[arg(long, conflicts_with = "keypath")]
+        password_file: Option<PathBuf>,
+        /// Read the password from an environment variable instead of prompting for it
+        #[arg(long, value_name = "VAR", conflicts_with_all = ["keypath", "password_file"])]
+        password_env: Option<String>,
+        /// Read the password from the OS keyring, stored under the archive's name or ACCOUNT
+        #[arg(long, value_name = "ACCOUNT", num_args = 0..=1, require_equals = true, conflicts_with_all = ["keypath", "password_file", "password_env"])]
+        keyring: Option<Option<String>>,
         /// Output verbosity
         #[arg(short, long, default_value = "normal")]
         verbosity: Verbosity,
@@ -72,60 +126,356 @@ enum Command {
         /// Override compression algorithm used
         #[arg(long, default_value = "passthrough")]
         compression_algorithm: BinCompressionType,
-        /// Compression level when using [--compression_algorithm gzip]
-        #[arg(long, default_value = "fastest")]
+        /// Compression level: fastest, best, default or a number in the range shown by `zap list-algorithms`
+        #[arg(long, default_value = "default", value_parser = parse_compression_level)]
         compression_level: CompressionLevel,
+        /// Signing algorithm used, entries are unsigned by default
+        #[arg(long, default_value = "passthrough")]
+        signing_algorithm: BinSigningType,
+        /// What to do when a file changes or disappears while archiving
+        #[arg(long, default_value = "skip")]
+        on_change: BinChangePolicy,
+        /// Compress files with an extension using a different algorithm, eg: --rule jpg=passthrough
+        #[arg(long = "rule", value_name = "EXT=ALGORITHM", value_parser = parse_rule)]
+        rules: Vec<CompressionRule>,
+        /// Store files with these extensions uncompressed, eg: jpg,png. By default common compressed formats, '' for none
+        #[arg(long, value_name = "EXTS", value_delimiter = ',')]
+        no_compress_extensions: Option<Vec<String>>,
+        /// Files smaller than this many bytes are compressed in memory, 0 to always stream
+        #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_IN_MEMORY_THRESHOLD)]
+        in_memory_threshold: u64,
+        /// Compress the manifest with the archive's compression algorithm
+        #[arg(long)]
+        compress_manifest: bool,
+        /// Skip files and directories whose name starts with '.'
+        #[arg(long, overrides_with = "include_hidden")]
+        exclude_hidden: bool,
+        /// Skip version control directories and files, eg: .git
+        #[arg(long)]
+        exclude_vcs: bool,
+        /// Read files without updating their access time, on Linux
+        #[arg(long)]
+        no_atime: bool,
+        /// Start reading this many files ahead, for network mounts, on Linux
+        #[arg(long, value_name = "FILES", num_args = 0..=1, default_missing_value = "8")]
+        prefetch: Option<usize>,
+        /// Compress up to this many blocks of a large file at once, on the threads shared by every file
+        #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+        threads_per_file: usize,
+        /// Store files with the same contents once
+        #[arg(long)]
+        dedup: bool,
+        /// Attach a note to the archive, shown by `zap list`
+        #[arg(long, value_parser = parse_comment)]
+        comment: Option<String>,
+        /// Include hidden files and directories, the default
+        #[arg(long, overrides_with = "exclude_hidden")]
+        include_hidden: bool,
+        /// Reject weak passwords instead of warning about them
+        #[arg(long, conflicts_with_all = ["skip_password_check", "keypath"])]
+        require_strong_password: bool,
+        /// Don't check the strength of the password
+        #[arg(long, conflicts_with = "keypath")]
+        skip_password_check: bool,
+        /// Append a digest of the whole archive, checked with `zap verify`
+        #[arg(long)]
+        digest: bool,
+        /// Append parity blocks for this percentage of the archive, used by `zap repair`
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..=100))]
+        recovery_percent: Option<u32>,
+        /// Warn that the files may not be compressible when compressing leaves them at least this fraction of their size
+        #[arg(long, value_name = "RATIO", default_value_t = 0.95)]
+        incompressible_ratio: f64,
+        /// Write several archives of at most about this many bytes each, eg: dir.part1.zap
+        #[arg(long, value_name = "BYTES", value_parser = clap::value_parser!(u64).range(1..))]
+        max_archive_size: Option<u64>,
+        /// Capacity of the buffer the archive is written through, larger is faster on slow disks
+        #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_WRITE_BUFFER_SIZE)]
+        write_buffer_size: usize,
+        /// Write a checksum of the archive next to it, eg: dir.zap.sha256
+        #[arg(long)]
+        checksum_sidecar: bool,
+        /// Algorithm used by --checksum-sidecar
+        #[arg(long, default_value = "sha256")]
+        checksum_algorithm: ChecksumAlgorithm,
+        /// Read the archive back and check every entry once it is written
+        #[arg(long)]
+        verify_after_write: bool,
+        /// Write a single archive, or leave the compressed files in a directory of the same name
+        #[arg(long, default_value = "zap")]
+        output_format: OutputFormat,
+        /// Display progress and an estimate of the time left
+        #[arg(long)]
+        progress: bool,
+        /// Write progress to stderr as a JSON object per line, for other programs to display
+        #[arg(long, conflicts_with = "progress")]
+        json_progress: bool,
+        /// How often the progress is refreshed, eg: 500ms
+        #[arg(long, value_name = "DURATION", default_value = "200ms", value_parser = humantime::parse_duration)]
+        progress_interval: Duration,
+        /// Retry file operations failing with transient errors, eg: on network filesystems
+        #[arg(long, value_name = "N", default_value_t = 0)]
+        io_retries: u32,
+        /// Delay before the first retry, doubling after each
+        #[arg(long, value_name = "DURATION", default_value = "100ms", value_parser = humantime::parse_duration)]
+        io_retry_delay: Duration,
+        /// Run at this niceness, from 1 to 19 (the lowest priority), on Unix
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(i32).range(1..=19))]
+        nice: Option<i32>,
+        /// Only read and write files when no other program is using the disk, on Linux
+        #[arg(long)]
+        ionice: bool,
+        /// Continue an interrupted run, only compressing the files it didn't
+        #[arg(long, conflicts_with = "force")]
+        resume: bool,
+        /// Remove a leftover staging directory instead of failing
+        #[arg(long)]
+        force: bool,
     },
     /// Extract an archive
     Extract {
-        /// Input file
-        input: String,
+        /// Input file, or several to extract the parts written with --max-archive-size
+        #[arg(required = true)]
+        inputs: Vec<String>,
         #[arg(short, long, default_value = None)]
         /// Output file
         output: Option<String>,
-        #[arg(short, long)]
-        /// Path to private key file (not currently supported)
+        /// Extract into a directory named after the archive within the output, unless it holds a single top level directory
+        #[arg(long)]
+        into_dir: bool,
+        /// Path to a keyfile holding the raw key, instead of a password
         #[arg(short, long)]
         keypath: Option<String>,
+        /// Read the password from the first line of a file instead of prompting for it
+        #[arg(long, conflicts_with = "keypath")]
+        password_file: Option<PathBuf>,
+        /// Read the password from an environment variable instead of prompting for it
+        #[arg(long, value_name = "VAR", conflicts_with_all = ["keypath", "password_file"])]
+        password_env: Option<String>,
+        /// Read the password from the OS keyring, stored under the archive's name or ACCOUNT
+        #[arg(long, value_name = "ACCOUNT", num_args = 0..=1, require_equals = true, conflicts_with_all = ["keypath", "password_file", "password_env"])]
+        keyring: Option<Option<String>>,
         /// Output verbosity
         #[arg(short, long, default_value = "normal")]
         verbosity: Verbosity,
-        /// Override encryption algorithm used
-        #[arg(long, default_value = "passthrough")]
-        encryption_algorithm: BinEncryptionType,
-        /// Override compression algorithm used
-        #[arg(long, default_value = "passthrough")]
+        /// Encryption algorithm used, must match the one the archive records
+        #[arg(long)]
+        encryption_algorithm: Option<BinEncryptionType>,
+        /// Compression algorithm used, must match the one the archive records
+        #[arg(long, default_value = "auto")]
         compression_algorithm: BinCompressionType,
+        /// Use the given algorithms even if the archive records different ones
+        #[arg(long)]
+        force_algorithm: bool,
+        #[arg(long)]
+        target_object: Option<String>,
+        /// What to do when entries would be extracted to the same path
+        #[arg(long, default_value = "rename")]
+        on_conflict: BinConflictPolicy,
+        /// Extract all files into the output directory without their directory structure
+        #[arg(long)]
+        flatten: bool,
+        /// Remove this many leading directories from each path, skipping files with fewer
+        #[arg(long, value_name = "N", default_value_t = 0)]
+        strip_components: usize,
+        /// Rename each extracted file with a template, eg: '{dir}/{stem}_{n}.{ext}'
+        #[arg(long, value_name = "TEMPLATE", value_parser = RenameTemplate::parse)]
+        rename: Option<RenameTemplate>,
+        /// File of 'archived/path => destination' lines, extracting entries under each path elsewhere
+        #[arg(long, value_name = "FILE")]
+        path_map: Option<PathBuf>,
+        /// Which symlink targets the archive may contain, by default only those within it
+        #[arg(long, default_value = "deny-escaping")]
+        symlink_policy: BinSymlinkPolicy,
+        /// Restore the modification times, and creation times on Windows and macOS, of the archived files
+        #[arg(long)]
+        preserve_timestamps: bool,
+        /// Capacity of the buffer each file is written through, larger is faster on slow disks
+        #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_WRITE_BUFFER_SIZE)]
+        write_buffer_size: usize,
+        /// Stop if any file would be extracted to more than this many bytes
+        #[arg(long, value_name = "BYTES")]
+        max_file_size: Option<u64>,
+        /// Stop if all files together would be extracted to more than this many bytes
+        #[arg(long, value_name = "BYTES")]
+        max_total_size: Option<u64>,
+        /// Display progress and an estimate of the time left
         #[arg(long)]
-        target_object: Option<String>
+        progress: bool,
+        /// Write progress to stderr as a JSON object per line, for other programs to display
+        #[arg(long, conflicts_with = "progress")]
+        json_progress: bool,
+        /// How often the progress is refreshed, eg: 500ms
+        #[arg(long, value_name = "DURATION", default_value = "200ms", value_parser = humantime::parse_duration)]
+        progress_interval: Duration,
+        /// Retry file operations failing with transient errors, eg: on network filesystems
+        #[arg(long, value_name = "N", default_value_t = 0)]
+        io_retries: u32,
+        /// Delay before the first retry, doubling after each
+        #[arg(long, value_name = "DURATION", default_value = "100ms", value_parser = humantime::parse_duration)]
+        io_retry_delay: Duration,
+        /// Remove a leftover staging directory instead of failing
+        #[arg(long)]
+        force: bool,
+    },
+    /// Check that a key or password opens an archive, without extracting it
+    CheckKey {
+        archive: String,
+        /// Path to a keyfile holding the raw key, instead of a password
+        #[arg(short, long)]
+        keypath: Option<String>,
+        /// Read the password from the first line of a file instead of prompting for it
+        #[arg(long, conflicts_with = "keypath")]
+        password_file: Option<PathBuf>,
+        /// Read the password from an environment variable instead of prompting for it
+        #[arg(long, value_name = "VAR", conflicts_with_all = ["keypath", "password_file"])]
+        password_env: Option<String>,
+        /// Read the password from the OS keyring, stored under the archive's name or ACCOUNT
+        #[arg(long, value_name = "ACCOUNT", num_args = 0..=1, require_equals = true, conflicts_with_all = ["keypath", "password_file", "password_env"])]
+        keyring: Option<Option<String>>,
+        #[arg(short, long, default_value = "normal")]
+        verbosity: Verbosity,
+    },
+    /// Compare an archive with a directory, without extracting it
+    Compare {
+        archive: String,
+        /// The directory that was archived
+        directory: String,
+        #[arg(short, long, default_value = "normal")]
+        verbosity: Verbosity,
     },
     /// List contents of an archive
     List {
         archive: String,
+        /// Print the listing as JSON
+        #[arg(long)]
+        json: bool,
+        /// Print the entries as a tree of directories, when writing to a terminal
+        #[arg(long, conflicts_with = "json")]
+        tree: bool,
+        /// Only list entries whose path matches a pattern, eg: 'src/**/*.rs'
+        #[arg(long, value_name = "PATTERN", value_parser = Glob::parse)]
+        filter: Option<Glob>,
+        #[arg(short, long, default_value = "normal")]
+        verbosity: Verbosity,
+    },
+    /// List the available algorithms and the compression levels they accept
+    ListAlgorithms,
+    /// Remove an entry from an archive
+    Remove {
+        archive: String,
+        /// Path of the entry, as shown by `zap list`
+        entry: PathBuf,
+        /// Lock this file while the archive is modified, instead of '<archive>.lock'
+        #[arg(long, value_name = "PATH")]
+        lock_file: Option<PathBuf>,
+        #[arg(short, long, default_value = "normal")]
+        verbosity: Verbosity,
+    },
+    /// Restore damaged blocks of an archive from its recovery record
+    Repair {
+        archive: String,
+        /// Lock this file while the archive is modified, instead of '<archive>.lock'
+        #[arg(long, value_name = "PATH")]
+        lock_file: Option<PathBuf>,
         #[arg(short, long, default_value = "normal")]
         verbosity: Verbosity,
     },
     /// Rotate the secrets of a Zap archive
     Rotate {
         archive: String,
+        /// Lock this file while the archive is modified, instead of '<archive>.lock'
+        #[arg(long, value_name = "PATH")]
+        lock_file: Option<PathBuf>,
+        #[arg(short, long, default_value = "normal")]
+        verbosity: Verbosity,
+    },
+    /// Verify an archive against the digest appended with `archive --digest`
+    Verify {
+        archive: String,
+        #[arg(short, long, default_value = "normal")]
+        verbosity: Verbosity,
+    },
+    /// Verify the signatures of an archive without extracting it
+    VerifySignature {
+        archive: String,
+        /// Path to a public key to check signatures with. Unused, as no signing method uses keys yet
+        #[arg(long)]
+        pubkey: Option<String>,
+        /// Read the password from the first line of a file instead of prompting for it
+        #[arg(long)]
+        password_file: Option<PathBuf>,
+        /// Read the password from an environment variable instead of prompting for it
+        #[arg(long, value_name = "VAR", conflicts_with = "password_file")]
+        password_env: Option<String>,
+        /// Read the password from the OS keyring, stored under the archive's name or ACCOUNT
+        #[arg(long, value_name = "ACCOUNT", num_args = 0..=1, require_equals = true, conflicts_with_all = ["password_file", "password_env"])]
+        keyring: Option<Option<String>>,
         #[arg(short, long, default_value = "normal")]
         verbosity: Verbosity,
     },
 }
 
+/// How `archive` leaves the compressed files.
+#[derive(Debug, Default, Clone, Copy, PartialEq, ValueEnum)]
+enum OutputFormat {
+    /// A single archive
+    #[default]
+    Zap,
+    /// A directory mirroring the input, with a compressed file for each, as staged before packing
+    Dir,
+}
+
 impl Command {
     pub fn execute(self) -> Result<(), anyhow::Error> {
         match self {
             Command::Archive {
-                input,
+                inputs,
                 output,
                 encrypt: encryption,
                 compress: compression,
                 keypath,
+                password_file,
+                password_env,
+                keyring,
                 verbosity,
                 mut encryption_algorithm,
                 mut compression_algorithm,
                 compression_level,
+                signing_algorithm,
+                on_change,
+                rules,
+                no_compress_extensions,
+                in_memory_threshold,
+                compress_manifest,
+                exclude_hidden,
+                exclude_vcs,
+                no_atime,
+                prefetch,
+                threads_per_file,
+                dedup,
+                comment,
+                include_hidden: _,
+                require_strong_password,
+                skip_password_check,
+                digest,
+                recovery_percent,
+                incompressible_ratio,
+                max_archive_size,
+                write_buffer_size,
+                checksum_sidecar,
+                checksum_algorithm,
+                verify_after_write,
+                output_format,
+                progress,
+                json_progress,
+                progress_interval,
+                io_retries,
+                io_retry_delay,
+                nice,
+                ionice,
+                resume,
+                force,
             } => {
                 if let (true, BinEncryptionType::Passthrough) = (encryption, &encryption_algorithm) {
                     encryption_algorithm = BinEncryptionType::XChaCha;
@@ -135,31 +485,145 @@ impl Command {
                     compression_algorithm = BinCompressionType::Lz4;
                 }
 
+                if let BinCompressionType::Auto = compression_algorithm {
+                    return Err(RuntimeError::InvalidArgument("'auto' compression is only valid for extraction".into()).into());
+                }
+
+                // After the rules given, which win for the same extension.
+                let stored = no_compress_extensions.unwrap_or_else(|| STORED_EXTENSIONS.iter().map(|ext| ext.to_string()).collect());
+
+                let rules: Vec<CompressionRule> = rules
+                    .into_iter()
+                    .chain(stored
+                        .iter()
+                        .map(|ext| ext.trim().trim_start_matches('.'))
+                        .filter(|ext| !ext.is_empty())
+                        .map(|ext| CompressionRule { extension: ext.into(), compression: CompressionType::Passthrough }))
+                    .collect();
+
+                let default_compression: CompressionType = compression_algorithm.clone().into();
+                compression_level.validate(std::iter::once(&default_compression).chain(rules.iter().map(|r| &r.compression)))?;
+
+                let password_check = match (require_strong_password, skip_password_check) {
+                    (true, _) => PasswordCheck::Require,
+                    (_, true) => PasswordCheck::Skip,
+                    _ => PasswordCheck::Warn,
+                };
+
+                if inputs.len() > 1 && output.is_none() {
+                    return Err(RuntimeError::InvalidArgument("pass --output to name an archive of several folders".into()).into());
+                }
+
+                // These are all written into, or next to, a single file.
+                if let (OutputFormat::Dir, true) = (output_format, digest || recovery_percent.is_some() || max_archive_size.is_some() || checksum_sidecar) {
+                    return Err(RuntimeError::InvalidArgument(
+                        "--digest, --recovery-percent, --max-archive-size and --checksum-sidecar need --output-format zap".into()
+                    ).into());
+                }
+
+                // Stored under the archive's name, which is named after the
+                // first folder unless given.
+                let archive = output.as_deref().unwrap_or(&inputs[0]);
+
+                let secret = secret_provider(keypath, password_file, password_env, keyring, archive, true, password_check)?;
+
                 Self::archive(
-                    input,
+                    inputs,
                     output,
-                    keypath,
+                    secret.as_ref(),
                     verbosity,
                     encryption_algorithm,
                     compression_algorithm,
                     compression_level,
+                    signing_algorithm,
+                    CompressOptions {
+                        on_change: on_change.into(),
+                        rules,
+                        in_memory_threshold,
+                        progress: (progress || json_progress).then(|| Arc::new(Progress::new())),
+                        compress_manifest,
+                        exclude_hidden,
+                        exclude_vcs,
+                        no_atime,
+                        dedup,
+                        io_retry: RetryPolicy { retries: io_retries, delay: io_retry_delay },
+                        comment,
+                        // Set by archive, next to the output.
+                        checkpoint: None,
+                        resume,
+                        prefetch: prefetch.unwrap_or(0),
+                        transform: None,
+                        secret_resolver: None,
+                        threads_per_file,
+                    },
+                    digest,
+                    recovery_percent,
+                    incompressible_ratio,
+                    max_archive_size,
+                    write_buffer_size,
+                    checksum_sidecar.then_some(checksum_algorithm),
+                    verify_after_write,
+                    output_format,
+                    progress_interval,
+                    match json_progress {
+                        true => ProgressFormat::Json,
+                        false => ProgressFormat::Human,
+                    },
+                    nice,
+                    ionice,
+                    resume,
+                    force,
                 )
             },
             Command::Extract {
-                input,
+                inputs,
                 output,
+                into_dir,
                 keypath,
+                password_file,
+                password_env,
+                keyring,
                 verbosity,
-                mut encryption_algorithm,
-                mut compression_algorithm,
+                encryption_algorithm,
+                compression_algorithm,
+                force_algorithm,
                 target_object,
+                on_conflict,
+                flatten,
+                strip_components,
+                rename,
+                path_map,
+                symlink_policy,
+                preserve_timestamps,
+                write_buffer_size,
+                max_file_size,
+                max_total_size,
+                progress,
+                json_progress,
+                progress_interval,
+                io_retries,
+                io_retry_delay,
+                force,
             } => {               
-                let input_file_path: PathBuf = PathBuf::from(&input);
+                // Or directories written with --output-format dir.
+                if let Some(missing) = inputs.iter().find(|input| !Path::new(input).is_file() && !Path::new(input).is_dir()) {
+                    return Err(RuntimeError::FileNotFound(missing.clone()).into());
+                }
 
-                if !input_file_path.is_file() {
-                    return Err(RuntimeError::FileNotFound(input_file_path.to_string_lossy().into()).into());
+                // Before the name is parsed for algorithms, or anything is
+                // staged, so that an empty file isn't taken for an archive.
+                for input in inputs.iter().filter(|input| Path::new(input).is_file()) {
+                    check_archive_size(input)?;
                 }
 
+                // The output is named after the first, without its part number.
+                let input_file_path: PathBuf = PathBuf::from(&inputs[0]);
+
+                let path_map = path_map
+                    .map(|path| PathMap::read(&path))
+                    .transpose()
+                    .context("Reading path map.")?;
+
                 let mut input_file_extensions: Vec<&str> = match input_file_path
                     .file_name() {
                         Some(ext) => ext.to_str()
@@ -169,40 +633,48 @@ impl Command {
                         None => return Err(RuntimeError::FileNotFound(input_file_path.to_string_lossy().into()).into()),
                     };
 
-                println!("input_file_extensions: {:?}", input_file_extensions);
-
-                for ext in input_file_extensions.iter() {
-                    match *ext {
-                        "xcha" => encryption_algorithm = BinEncryptionType::XChaCha,
-                        "aes" => encryption_algorithm = BinEncryptionType::AesGcm,
-                        "cha" => encryption_algorithm = BinEncryptionType::ChaCha,
-                        "lz4" => compression_algorithm = BinCompressionType::Lz4,
-                        "gz" => compression_algorithm = BinCompressionType::Gzip,
-                        "sz" => compression_algorithm = BinCompressionType::Snappy,
-                        _ => (),
+                let explicit_encryption = encryption_algorithm.is_some();
+                let mut encryption_algorithm = encryption_algorithm.unwrap_or(BinEncryptionType::Passthrough);
+
+                for ext in input_file_extensions.iter().filter(|_| !explicit_encryption) {
+                    if let Some(found) = encryption_for_extension(ext) {
+                        encryption_algorithm = found;
                     }
                 }
 
                 input_file_extensions
-                    .retain(|ext| !["xcha", "aes", "cha", "lz4", "gz", "sz"].contains(ext));
+                    .retain(|ext| encryption_for_extension(ext).is_none() && compression_for_extension(ext).is_none());
 
                 input_file_extensions.reverse();
 
-                let final_output = match output {
-                    Some(path) => path,
-                    None => input_file_path
-                        .parent()
-                        .expect("UNable to get parent directory.")
-                        .join(
-                            input_file_extensions.join(".")
-                        )
-                        .with_extension("")
-                        .to_str().expect("msg").to_string(),
+                // Only after the name itself, which could be 'part1' too.
+                let input_file_extensions: Vec<&str> = input_file_extensions
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(n, ext)| *n == 0 || !is_part_extension(ext))
+                    .map(|(_, ext)| ext)
+                    .collect();
+
+                let archive_dir = input_file_path
+                    .parent()
+                    .expect("UNable to get parent directory.");
+
+                let container = PathBuf::from(input_file_extensions.join(".")).with_extension("");
+
+                // With --into-dir the output is where the container is
+                // created, rather than the directory extracted into.
+                let final_output = match (output, into_dir) {
+                    (Some(path), _) => path,
+                    (None, true) if archive_dir.as_os_str().is_empty() => ".".into(),
+                    (None, true) => archive_dir.to_str().expect("msg").to_string(),
+                    (None, false) => archive_dir.join(&container).to_str().expect("msg").to_string(),
                 };
 
+                let container = into_dir.then(|| container.to_string_lossy().into_owned());
+
                 if let Some(object) = target_object {
                     Self::extract_target(
-                        input, 
+                        inputs[0].clone(), 
                         final_output, 
                         keypath, 
                         verbosity, 
@@ -211,99 +683,530 @@ impl Command {
                         object
                     )
                 } else {
+                    let secret = secret_provider(keypath, password_file, password_env, keyring, &inputs[0], false, PasswordCheck::Skip)?;
+
                     Self::extract(
-                        input,
+                        inputs,
                         final_output,
-                        keypath,
+                        container,
+                        secret.as_ref(),
                         verbosity,
                         encryption_algorithm,
+                        explicit_encryption,
                         compression_algorithm,
+                        DecompressOptions {
+                            on_conflict: on_conflict.into(),
+                            flatten,
+                            strip_components,
+                            max_file_size,
+                            max_total_size,
+                            progress: (progress || json_progress).then(|| Arc::new(Progress::new())),
+                            io_retry: RetryPolicy { retries: io_retries, delay: io_retry_delay },
+                            rename,
+                            force_algorithm,
+                            path_map,
+                            symlink_policy: symlink_policy.into(),
+                            preserve_timestamps,
+                            write_buffer_size: Some(write_buffer_size),
+                            secret_resolver: None,
+                        },
+                        progress_interval,
+                        match json_progress {
+                            true => ProgressFormat::Json,
+                            false => ProgressFormat::Human,
+                        },
+                        force,
                     )
                 }
             },
-            Command::List { archive, verbosity } => Self::list(archive, verbosity),
-            Command::Rotate { archive, verbosity } => Self::rotate(archive, verbosity),
+            Command::CheckKey { archive, keypath, password_file, password_env, keyring, verbosity } => {
+                let secret = secret_provider(keypath, password_file, password_env, keyring, &archive, false, PasswordCheck::Skip)?;
+                Self::check_key(archive, secret.as_ref(), verbosity)
+            },
+            Command::Compare { archive, directory, verbosity } => Self::compare(archive, directory, verbosity),
+            Command::List { archive, json, tree, filter, verbosity } => Self::list(archive, json, tree, filter, verbosity),
+            Command::Remove { archive, entry, lock_file, verbosity } => Self::remove(archive, entry, lock_file, verbosity),
+            Command::ListAlgorithms => Self::list_algorithms(),
+            Command::Repair { archive, lock_file, verbosity } => Self::repair(archive, lock_file, verbosity),
+            Command::Rotate { archive, lock_file, verbosity } => Self::rotate(archive, lock_file, verbosity),
+            Command::Verify { archive, verbosity } => Self::verify(archive, verbosity),
+            Command::VerifySignature { archive, pubkey, password_file, password_env, keyring, verbosity } => {
+                let secret = secret_provider(None, password_file, password_env, keyring, &archive, false, PasswordCheck::Skip)?;
+                Self::verify_signature(archive, pubkey, secret.as_ref(), verbosity)
+            },
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn archive(
-        input: String,
+        inputs: Vec<String>,
         output: Option<String>,
-        keypath: Option<String>,
+        secret: &dyn SecretProvider,
         verbosity: Verbosity,
         encryption_algorithm: BinEncryptionType,
         compression_algorithm: BinCompressionType,
         compression_level: CompressionLevel,
+        signing_algorithm: BinSigningType,
+        options: CompressOptions,
+        digest: bool,
+        recovery_percent: Option<u32>,
+        incompressible_ratio: f64,
+        max_archive_size: Option<u64>,
+        write_buffer_size: usize,
+        checksum: Option<ChecksumAlgorithm>,
+        verify_after_write: bool,
+        output_format: OutputFormat,
+        progress_interval: Duration,
+        progress_format: ProgressFormat,
+        nice: Option<i32>,
+        ionice: bool,
+        resume: bool,
+        force: bool,
     ) -> Result<(), anyhow::Error> {
+        let quiet = matches!(verbosity, Verbosity::Quiet);
+
         preamble(verbosity).context("Running preamble.")?;
 
-        let encryption_secret: EncryptionSecret = match (&encryption_algorithm, keypath) {
-            (BinEncryptionType::Passthrough, _) => EncryptionSecret::None,
-            (_, Some(path)) => EncryptionSecret::Key(path),
-            (_, None) => EncryptionSecret::Password(match get_password_confirm(256) {
-                Ok(pass) => pass,
-                Err(e) => return Err(e.into()),
-            }),
-        };
+        // Archiving is only slower for it, so it carries on either way.
+        if let Err(e) = lower_priority(nice, ionice) {
+            warn!("Unable to lower priority, continuing at the current one: {}", e);
+        }
 
         // TODO : Remove these clones
         let mut out_extension = build_common_extension(&encryption_algorithm.clone().into(), &compression_algorithm.clone().into());
         out_extension.push_str(".zap");
 
-        zap::compress_directory(
-            &input,
-            "/tmp/unpacked",
-            encryption_algorithm.into(),
-            encryption_secret,
-            compression_algorithm.into(),
-            compression_level.into(),
-            zap::signing::SigningType::default(),
-        ).context("Compressing directory.")?;
+        let out_name = match output {
+            Some(output) => output,
+            None => format!("{}{}", inputs[0].trim_end_matches('.'), out_extension),
+        };
 
-        let out_name = format!("{}{}", input.trim_end_matches('.'), out_extension);
+        // Resuming picks up the files staged by the run it resumes, and
+        // leaves them for the next should it fail too, as a directory
+        // output is left.
+        let staging_dir = match (output_format, resume) {
+            (OutputFormat::Zap, true) => {
+                let mut staging = StagingDir::new(&staging_path(&out_name));
+                staging.keep();
+                Some(staging)
+            },
+            (OutputFormat::Zap, false) => Some(prepare_staging_dir(&staging_path(&out_name), force)?),
+            (OutputFormat::Dir, false) if Path::new(&out_name).exists() => {
+                return Err(RuntimeError::InvalidArgument(format!("output already exists: {}", out_name)).into());
+            },
+            (OutputFormat::Dir, _) => None,
+        };
 
-        let out_file = File::create(out_name).context("Creating output file")?;
+        // A directory output is compressed into where it's left, rather
+        // than staged and packed.
+        let staging = match &staging_dir {
+            Some(staging) => staging.path().to_string(),
+            None => out_name.clone(),
+        };
 
-        let mut out_writer = BufWriter::new(out_file);
+        let encryption_secret = resolve_secret(&encryption_algorithm.clone().into(), secret)?;
 
-        pack_files("/tmp/unpacked", &mut out_writer).context("Packing files")?;
+        let header = ArchiveHeader {
+            encryption: encryption_algorithm.clone().into(),
+            compression: compression_algorithm.clone().into(),
+        };
+
+        let checkpoint = PathBuf::from(format!("{}.checkpoint", out_name));
+
+        let options = CompressOptions {
+            checkpoint: Some(checkpoint.clone()),
+            ..options
+        };
 
-        fs::remove_dir_all("/tmp/unpacked").context("Cleaning up...")
+        let display = options.progress.clone().map(|p| ProgressDisplay::start(p, progress_interval, progress_format));
+
+        let verification = verify_after_write.then(|| (
+            Into::<EncryptionType>::into(encryption_algorithm.clone()),
+            encryption_secret.clone(),
+            Into::<SigningType>::into(signing_algorithm.clone()),
+        ));
+
+        // Before compression_algorithm is moved into the directory functions.
+        let compressing = std::iter::once(Into::<CompressionType>::into(compression_algorithm.clone()))
+            .chain(options.rules.iter().map(|r| r.compression.clone()))
+            .any(|c| c != CompressionType::Passthrough);
+
+        let started = Instant::now();
+
+        // A single folder is stored at the root of the archive, as before
+        // several could be given.
+        let report = match inputs.as_slice() {
+            [input] => zap::compress_directory(
+                input,
+                &staging,
+                encryption_algorithm.into(),
+                encryption_secret,
+                compression_algorithm.into(),
+                compression_level.into(),
+                signing_algorithm.into(),
+                &options,
+            ),
+            inputs => zap::compress_directories(
+                &inputs.iter().map(String::as_str).collect::<Vec<_>>(),
+                &staging,
+                encryption_algorithm.into(),
+                encryption_secret,
+                compression_algorithm.into(),
+                compression_level.into(),
+                signing_algorithm.into(),
+                &options,
+            ),
+        };
+
+        if let Some(display) = display {
+            display.finish();
+        }
+
+        // A directory output is left whole or not at all, as a packed one
+        // is. One being resumed holds the files of an earlier run too.
+        let remove_output = |_: &anyhow::Error| {
+            if let (OutputFormat::Dir, false) = (output_format, resume) {
+                remove_partial_dir(&out_name, &checkpoint);
+            }
+        };
+
+        let report = report.context("Compressing directory.").inspect_err(remove_output)?;
+
+        debug!("Compressed {} file(s) in {:?}", report.processed.len(), started.elapsed());
+
+        if !report.skipped.is_empty() {
+            info!("Skipped {} file(s) while archiving", report.skipped.len());
+        }
+
+        if !report.duplicates.is_empty() {
+            info!("Stored {} duplicate file(s) once", report.duplicates.len());
+        }
+
+        // Includes the overhead of encryption, which is under 1%.
+        if let Some(ratio) = report.ratio() {
+            debug!("Compressed to {:.1}% of the original size", ratio * 100.0);
+
+            if compressing && ratio >= incompressible_ratio {
+                warn!(
+                    "Files were only compressed to {:.1}% of their size, they may already be compressed. \
+                    Consider --compression-algorithm passthrough, or --rule for those files.",
+                    ratio * 100.0
+                );
+            }
+        }
+
+        // pack_files stores the whole staging directory, so anything but
+        // the compressed files is removed from it first.
+        let stray = zap::retain_processed(&staging, &report).context("Checking staged files.").inspect_err(remove_output)?;
+
+        if !stray.is_empty() {
+            info!("Removed {} file(s) that weren't compressed before packing", stray.len());
+        }
+
+        let mut summary = RunSummary {
+            files: report.processed.len(),
+            bytes_in: report.bytes(),
+            ..RunSummary::default()
+        };
+
+        summary.add_phase("walk", report.walk_elapsed);
+        summary.add_phase("compress", report.process_elapsed);
+
+        let out_names = match output_format {
+            OutputFormat::Zap => {
+                let packing = Instant::now();
+
+                let out_names = pack_staged(
+                    &staging,
+                    &out_name,
+                    &header,
+                    digest,
+                    recovery_percent,
+                    max_archive_size,
+                    write_buffer_size,
+                    checksum.as_ref(),
+                    options.progress.clone(),
+                    progress_interval,
+                    progress_format,
+                )?;
+
+                summary.add_phase("pack", packing.elapsed());
+
+                out_names
+            },
+            OutputFormat::Dir => vec![out_name],
+        };
+
+        // Only needed once the archive is in place.
+        if checkpoint.exists() {
+            fs::remove_file(&checkpoint).context("Removing checkpoint.")?;
+        }
+
+        // Reads back what was written, so that corruption on the way to
+        // the disk is caught while the original files are still around.
+        if let Some((encryption, secret, signing)) = verification {
+            let verifying = Instant::now();
+
+            for out_name in out_names.iter() {
+                if digest {
+                    verify_footer(Path::new(out_name)).context("Verifying written digest.")?;
+                }
+
+                let verified = match output_format {
+                    OutputFormat::Zap => {
+                        let scratch = ScratchDir::create("verify").context("Creating scratch directory.")?;
+                        let unpacked = scratch.join("unpacked");
+
+                        unpack_archive(out_name, &unpacked)?;
+
+                        zap::verify_directory(&unpacked, encryption.clone(), secret.clone(), CompressionType::Auto, signing.clone())
+                    },
+                    OutputFormat::Dir => zap::verify_directory(out_name, encryption.clone(), secret.clone(), CompressionType::Auto, signing.clone()),
+                };
+
+                let verified = verified.context("Verifying written archive.")?;
+
+                info!("Verified {} file(s) in: {}", verified.len(), out_name);
+            }
+
+            summary.add_phase("verify", verifying.elapsed());
+        }
+
+        // Every part, with its header, digest and recovery record.
+        summary.bytes_out = match output_format {
+            OutputFormat::Zap => out_names.iter()
+                .map(|name| fs::metadata(name).map(|m| m.len()))
+                .sum::<Result<u64, _>>()
+                .context("Reading archive size.")?,
+            OutputFormat::Dir => staged_bytes(Path::new(&out_names[0])),
+        };
+        summary.elapsed = started.elapsed();
+
+        if !quiet {
+            summary.print("Archived");
+        }
+
+        Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn extract(
-        input: String,
+        inputs: Vec<String>,
         output: String,
-        keypath: Option<String>,
+        container: Option<String>,
+        secret: &dyn SecretProvider,
         verbosity: Verbosity,
         encryption_algorithm: BinEncryptionType,
+        explicit_encryption: bool,
         compression_algorithm: BinCompressionType,
+        options: DecompressOptions,
+        progress_interval: Duration,
+        progress_format: ProgressFormat,
+        force: bool,
     ) -> Result<(), anyhow::Error> {
+        let quiet = matches!(verbosity, Verbosity::Quiet);
+
         preamble(verbosity).context("Running preamble")?;
 
-        let encryption_secret: EncryptionSecret = match (&encryption_algorithm, keypath) {
-            (BinEncryptionType::Passthrough, _) => EncryptionSecret::None,
-            (_, None) => EncryptionSecret::Password(match get_password_noconf(256) {
-                Ok(pass) => pass,
-                Err(e) => return Err(e.into()),
-            }),
-            (_, Some(path)) => EncryptionSecret::Key(path),
+        // Parts are extracted one after another into the same output.
+        let secret = CachedProvider::new(secret);
+
+        let mut summary = RunSummary::default();
+
+        for input in inputs {
+            info!("Extracting archive: {}", input);
+
+            let extracted = Self::extract_archive(
+                input,
+                output.clone(),
+                container.clone(),
+                &secret,
+                encryption_algorithm.clone(),
+                explicit_encryption,
+                compression_algorithm.clone(),
+                options.clone(),
+                progress_interval,
+                progress_format,
+                force,
+            )?;
+
+            summary.merge(extracted);
+        }
+
+        if !quiet {
+            summary.print("Extracted");
+        }
+
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn extract_archive(
+        input: String,
+        output: String,
+        container: Option<String>,
+        secret: &dyn SecretProvider,
+        encryption_algorithm: BinEncryptionType,
+        explicit_encryption: bool,
+        compression_algorithm: BinCompressionType,
+        options: DecompressOptions,
+        progress_interval: Duration,
+        progress_format: ProgressFormat,
+        force: bool,
+    ) -> Result<RunSummary, anyhow::Error> {
+        // Directories written with --output-format dir are already laid
+        // out as an archive is unpacked, and are read where they are.
+        let unpacked = Path::new(&input).is_dir();
+
+        // Removed however extraction ends, eg: with the wrong password.
+        let staging_dir = match unpacked {
+            true => None,
+            false => Some(prepare_staging_dir(&staging_path(&output), force)?),
+        };
+
+        let staging = match &staging_dir {
+            Some(staging) => staging.path().to_string(),
+            None => input.clone(),
         };
 
+        // Before unpacking or asking for a secret, which would be wasted
+        // on a destination that can't be written to.
+        zap::check_output_writable(Path::new(&output)).context("Checking output directory.")?;
+
+        let unpacking = Instant::now();
+
         // Need to check if this function validates path names
         // to prevent directory traversal.
-        unpack_files(input, "/tmp/unpacked").context("Unpacking files.")?;
+        let header = match unpacked {
+            true => None,
+            false => unpack_archive(&input, &staging)?,
+        };
 
-        zap::decompress_directory(
-            "/tmp/unpacked",
+        let unpack_elapsed = unpacking.elapsed();
+
+        // The manifest is read before asking for a secret so that the
+        // secret can be checked against how the archive was encrypted.
+        let manifest = Manifest::read_from(Path::new(&staging)).context("Reading manifest.")?;
+
+        // The manifest, or else the header, records how the archive was
+        // created. Flags that disagree with it would only produce garbage,
+        // so they're rejected before asking for a secret unless forced.
+        let recorded_encryption = manifest.as_ref().map(|m| m.encryption.clone())
+            .or_else(|| header.as_ref().map(|h| h.encryption.clone()));
+        let recorded_compression = manifest.as_ref().map(|m| m.compression.clone())
+            .or_else(|| header.as_ref().map(|h| h.compression.clone()))
+            .filter(|c| *c != CompressionType::Auto);
+
+        let requested_encryption: EncryptionType = encryption_algorithm.into();
+        let requested_compression: CompressionType = compression_algorithm.into();
+
+        if !options.force_algorithm {
+            if let (true, Some(recorded)) = (explicit_encryption, &recorded_encryption) {
+                if *recorded != requested_encryption {
+                    return Err(RuntimeError::AlgorithmMismatch(format!(
+                        "--encryption-algorithm is {:?} but the archive was encrypted with {:?}",
+                        requested_encryption,
+                        recorded
+                    )).into());
+                }
+            }
+
+            if let (false, Some(recorded)) = (requested_compression == CompressionType::Auto, &recorded_compression) {
+                if *recorded != requested_compression {
+                    return Err(RuntimeError::AlgorithmMismatch(format!(
+                        "--compression-algorithm is {:?} but the archive was compressed with {:?}",
+                        requested_compression,
+                        recorded
+                    )).into());
+                }
+            }
+        }
+
+        let encryption_algorithm: EncryptionType = match recorded_encryption {
+            Some(recorded) if !(explicit_encryption && options.force_algorithm) => recorded,
+            _ => requested_encryption,
+        };
+
+        // Archives with a manifest record each entry's algorithm in it,
+        // older ones only have the header.
+        let compression_algorithm: CompressionType = match (requested_compression, &manifest, &header) {
+            (CompressionType::Auto, None, Some(header)) => header.compression.clone(),
+            (compression, _, _) => compression,
+        };
+
+        let secret_type = manifest.as_ref().and_then(|m| m.secret.clone());
+
+        let signing = manifest.as_ref().map(|m| m.signing.clone()).unwrap_or_default();
+
+        match (&encryption_algorithm, secret.secret_type(), secret_type) {
+            (EncryptionType::Passthrough, _, _) => (),
+            (_, SecretType::Key, Some(SecretType::Password)) => {
+                return Err(RuntimeError::SecretMismatch("a keyfile was given but the archive was encrypted with a password".into()).into())
+            },
+            (_, SecretType::Password, Some(SecretType::Key)) => {
+                return Err(RuntimeError::SecretMismatch("the archive was encrypted with a key, pass it with --keypath".into()).into())
+            },
+            _ => (),
+        }
+
+        let encryption_secret = resolve_secret(&encryption_algorithm, secret)?;
+
+        // After the secret, so that waiting on a prompt isn't counted.
+        let started = Instant::now();
+
+        // Archives holding a single directory already extract into one, so
+        // they're left as they are rather than nested in another.
+        let output = match container {
+            Some(container) if !has_single_top_level_dir(&zap::list_archive(&staging, None).context("Listing archive.")?) => {
+                Path::new(&output).join(container).to_string_lossy().into_owned()
+            },
+            _ => output,
+        };
+
+        info!("Extracting into: {}", output);
+
+        let display = options.progress.clone().map(|p| ProgressDisplay::start(p, progress_interval, progress_format));
+
+        let report = zap::decompress_directory(
+            &staging,
             &output,
-            encryption_algorithm.into(),
+            encryption_algorithm,
             encryption_secret,
-            compression_algorithm.into(),
-            zap::signing::SigningType::default(),
-        ).context("Decompressing directory.")?;
+            compression_algorithm,
+            signing,
+            &options,
+        );
 
-        fs::remove_dir_all("/tmp/unpacked").context("Cleaning up.")
+        if let Some(display) = display {
+            display.finish();
+        }
+
+        let report = report.context("Decompressing directory.")?;
+
+        if !report.skipped.is_empty() {
+            info!("Skipped {} file(s) while extracting", report.skipped.len());
+        }
+
+        let bytes_in = match unpacked {
+            true => staged_bytes(Path::new(&input)),
+            false => {
+                fs::remove_dir_all(&staging).context("Cleaning up.")?;
+                fs::metadata(&input).context("Reading archive size.")?.len()
+            },
+        };
+
+        let mut summary = RunSummary {
+            files: report.processed.len(),
+            bytes_in,
+            bytes_out: report.written_bytes(),
+            elapsed: unpack_elapsed + started.elapsed(),
+            ..RunSummary::default()
+        };
+
+        summary.add_phase("unpack", unpack_elapsed);
+        summary.add_phase("decompress", report.walk_elapsed + report.process_elapsed);
+
+        Ok(summary)
     }
 
     fn extract_target(
@@ -322,21 +1225,646 @@ impl Command {
         Err(RuntimeError::NotYetImplemented("Extracting target object").into())
     }
 
-    fn list(archive: String, verbosity: Verbosity) -> Result<(), anyhow::Error> {
+    fn list(archive: String, json: bool, tree: bool, filter: Option<Glob>, verbosity: Verbosity) -> Result<(), anyhow::Error> {
         preamble(verbosity).context("Running preamble")?;
 
         info!("Listing archive: {}", archive);
 
-        Err(RuntimeError::NotYetImplemented("Listing archives").into())
+        if !Path::new(&archive).is_file() {
+            return Err(RuntimeError::FileNotFound(archive).into());
+        }
+
+        let scratch = ScratchDir::create("list").context("Creating scratch directory.")?;
+        let unpacked = scratch.join("unpacked");
+
+        unpack_archive(&archive, &unpacked)?;
+
+        let listing = zap::list_archive(&unpacked, filter.as_ref());
+
+        let footer = read_footer(Path::new(&archive)).context("Reading digest.")?;
+
+        let listing = listing.context("Listing archive.")?;
+
+        // Piped output stays a line per entry, for other programs to read.
+        match (json, tree && io::stdout().is_terminal()) {
+            (true, _) => print_listing_json(&archive, &listing, footer.as_ref()).context("Writing listing.")?,
+            (false, true) => print_listing_tree(&archive, &listing, footer.as_ref()),
+            (false, false) => print_listing(&archive, &listing, footer.as_ref()),
+        }
+
+        Ok(())
     }
 
-    fn rotate(archive: String, verbosity: Verbosity) -> Result<(), anyhow::Error> {
+    fn check_key(archive: String, secret: &dyn SecretProvider, verbosity: Verbosity) -> Result<(), anyhow::Error> {
+        preamble(verbosity).context("Running preamble")?;
+
+        info!("Checking secret for archive: {}", archive);
+
+        let archive_path = PathBuf::from(&archive);
+
+        if !archive_path.is_file() {
+            return Err(RuntimeError::FileNotFound(archive).into());
+        }
+
+        let scratch = ScratchDir::create("check-key").context("Creating scratch directory.")?;
+        let unpacked = scratch.join("unpacked");
+
+        let header = unpack_archive(&archive, &unpacked)?;
+
+        let encryption_algorithm: EncryptionType = match (Manifest::read_from(Path::new(&unpacked)).context("Reading manifest.")?, header) {
+            (Some(manifest), _) => manifest.encryption,
+            (None, Some(header)) => header.encryption,
+            (None, None) => parse_extensions(&archive_path).0.into(),
+        };
+
+        // A mismatch fails the same way with or without a secret to resolve.
+        let opened = resolve_secret(&encryption_algorithm, secret)
+            .map_err(anyhow::Error::from)
+            .and_then(|secret| zap::check_secret(&unpacked, encryption_algorithm, secret).context("Checking secret."));
+
+        match opened? {
+            true => {
+                println!("Secret opens: {}", archive);
+                Ok(())
+            },
+            false => Err(RuntimeError::SecretDoesNotOpen(archive).into()),
+        }
+    }
+
+    fn compare(archive: String, directory: String, verbosity: Verbosity) -> Result<(), anyhow::Error> {
+        preamble(verbosity).context("Running preamble")?;
+
+        info!("Comparing archive: {} with {}", archive, directory);
+
+        if !Path::new(&archive).is_file() {
+            return Err(RuntimeError::FileNotFound(archive).into());
+        }
+
+        if !Path::new(&directory).is_dir() {
+            return Err(RuntimeError::FileNotFound(directory).into());
+        }
+
+        let scratch = ScratchDir::create("compare").context("Creating scratch directory.")?;
+        let unpacked = scratch.join("unpacked");
+
+        unpack_archive(&archive, &unpacked)?;
+
+        let comparison = zap::compare_archive(&unpacked, &directory);
+
+        let comparison = comparison.context("Comparing archive.")?;
+
+        print_comparison(&comparison);
+
+        if !comparison.unchecked.is_empty() {
+            warn!(
+                "{} file(s) were archived by an older version of zap, only whether they exist was compared",
+                comparison.unchecked.len()
+            );
+        }
+
+        match comparison.is_in_sync() {
+            true => Ok(()),
+            false => Err(RuntimeError::ArchiveDiffers(archive, directory).into()),
+        }
+    }
+
+    fn remove(archive: String, entry: PathBuf, lock_file: Option<PathBuf>, verbosity: Verbosity) -> Result<(), anyhow::Error> {
+        preamble(verbosity).context("Running preamble")?;
+
+        info!("Removing '{}' from archive: {}", entry.display(), archive);
+
+        if !Path::new(&archive).is_file() {
+            return Err(RuntimeError::FileNotFound(archive).into());
+        }
+
+        // Held until the new archive has replaced the old.
+        let _lock = ArchiveLock::acquire(&archive, lock_file.as_deref())?;
+
+        let footer = read_footer(Path::new(&archive)).context("Reading digest.")?;
+        let recovery = read_recovery(Path::new(&archive)).context("Reading recovery record.")?;
+
+        let scratch = ScratchDir::create("remove").context("Creating scratch directory.")?;
+        let unpacked = scratch.join("unpacked");
+
+        let header = unpack_archive(&archive, &unpacked)?;
+
+        zap::remove_entry(&unpacked, &entry).context("Removing entry.")?;
+
+        // Written next to the original so that replacing it is a
+        // single rename, leaving either the old or new archive.
+        let temp_name = format!("{}.tmp", archive);
+
+        let rewritten = rewrite_archive(&unpacked, &temp_name, header.as_ref(), footer.is_some(), recovery.map(|r| r.percent))
+            .and_then(|()| replace_file(Path::new(&temp_name), Path::new(&archive)).context("Replacing archive"));
+
+        // The scratch directory goes with it when it's dropped.
+        if rewritten.is_err() {
+            remove_partial(&[temp_name]);
+        }
+
+        rewritten?;
+
+        println!("Removed '{}' from: {}", entry.display(), archive);
+
+        Ok(())
+    }
+
+    fn list_algorithms() -> Result<(), anyhow::Error> {
+        println!("Compression:");
+
+        for algorithm in BinCompressionType::value_variants() {
+            let name = algorithm.to_possible_value().expect("No hidden compression algorithms");
+
+            let compression: CompressionType = algorithm.clone().into();
+
+            let levels = match compression.level_range() {
+                Some(range) => format!("levels {} to {}, default {}", range.min, range.max, range.default),
+                None => "no levels".into(),
+            };
+
+            let seeking = match compression.is_streaming_seekable() {
+                true => "seekable",
+                false => "not seekable",
+            };
+
+            println!("  {:<12}  {:<32}  {}", name.get_name(), levels, seeking);
+        }
+
+        println!("Encryption:");
+
+        for algorithm in BinEncryptionType::value_variants() {
+            println!("  {}", algorithm.to_possible_value().expect("No hidden encryption algorithms").get_name());
+        }
+
+        println!("Signing:");
+
+        for algorithm in BinSigningType::value_variants() {
+            println!("  {}", algorithm.to_possible_value().expect("No hidden signing algorithms").get_name());
+        }
+
+        Ok(())
+    }
+
+    fn repair(archive: String, lock_file: Option<PathBuf>, verbosity: Verbosity) -> Result<(), anyhow::Error> {
+        preamble(verbosity).context("Running preamble")?;
+
+        info!("Repairing archive: {}", archive);
+
+        if !Path::new(&archive).is_file() {
+            return Err(RuntimeError::FileNotFound(archive).into());
+        }
+
+        let _lock = ArchiveLock::acquire(&archive, lock_file.as_deref())?;
+
+        let report = repair(Path::new(&archive)).context("Repairing archive.")?;
+
+        match (report.repaired.len(), report.rebuilt_parity.len()) {
+            (0, 0) => println!("No damage found in {} block(s) of: {}", report.checked_blocks, archive),
+            (repaired, rebuilt) => println!(
+                "Repaired {} damaged block(s) and {} parity block(s) in: {}",
+                repaired,
+                rebuilt,
+                archive
+            ),
+        }
+
+        Ok(())
+    }
+
+    fn rotate(archive: String, lock_file: Option<PathBuf>, verbosity: Verbosity) -> Result<(), anyhow::Error> {
         preamble(verbosity).context("Running preamble")?;
 
         info!("Rotating archive secrets: {}", archive);
 
+        if !Path::new(&archive).is_file() {
+            return Err(RuntimeError::FileNotFound(archive).into());
+        }
+
+        let _lock = ArchiveLock::acquire(&archive, lock_file.as_deref())?;
+
         Err(RuntimeError::NotYetImplemented("Rotating secrets").into())
     }
+
+    fn verify(archive: String, verbosity: Verbosity) -> Result<(), anyhow::Error> {
+        preamble(verbosity).context("Running preamble")?;
+
+        info!("Verifying archive digest: {}", archive);
+
+        if !Path::new(&archive).is_file() {
+            return Err(RuntimeError::FileNotFound(archive).into());
+        }
+
+        let footer = verify_footer(Path::new(&archive)).context("Verifying digest.")?;
+
+        println!("Digest valid for: {} (sha256:{})", archive, footer.hex_digest());
+
+        Ok(())
+    }
+
+    fn verify_signature(archive: String, pubkey: Option<String>, secret: &dyn SecretProvider, verbosity: Verbosity) -> Result<(), anyhow::Error> {
+        preamble(verbosity).context("Running preamble")?;
+
+        let archive_path = PathBuf::from(&archive);
+
+        if !archive_path.is_file() {
+            return Err(RuntimeError::FileNotFound(archive).into());
+        }
+
+        let scratch = ScratchDir::create("verify-signature").context("Creating scratch directory.")?;
+        let unpacked = scratch.join("unpacked");
+
+        let header = unpack_archive(&archive, &unpacked)?;
+
+        // Only archives without a manifest or header are identified by
+        // their extension, which is lost when they're renamed.
+        let (encryption_algorithm, signing): (EncryptionType, SigningType) = match (Manifest::read_from(Path::new(&unpacked)).context("Reading manifest.")?, header) {
+            (Some(manifest), _) => (manifest.encryption, manifest.signing),
+            (None, Some(header)) => (header.encryption, SigningType::default()),
+            (None, None) => {
+                let (encryption, _, signing) = parse_extensions(&archive_path);
+                (encryption.into(), signing)
+            },
+        };
+
+        let encryption_secret = resolve_secret(&encryption_algorithm, secret)?;
+
+        // TODO: Pass the public key through once a key based SigningType exists.
+        if let Some(pubkey) = pubkey {
+            warn!("No signing method uses keys yet, ignoring public key: {}", pubkey);
+        }
+
+        let verified = zap::verify_directory(
+            &unpacked,
+            encryption_algorithm,
+            encryption_secret,
+            CompressionType::Auto,
+            signing,
+        )
+        .context("Verifying signatures.")?;
+
+        if verified.is_empty() || verified.iter().any(|f| f.signature.is_none()) {
+            return Err(RuntimeError::NotSigned(archive).into());
+        }
+
+        println!("Signatures valid for {} file(s) in: {}", verified.len(), archive);
+
+        Ok(())
+    }
+}
+
+// Runs writing to the same place share a staging directory, so one that
+// already exists is in use by another run, or was left by one that was
+// killed. Reusing it would mix the files of both, so it's only removed if
+// asked. The guard returned removes it again however this run ends.
+fn prepare_staging_dir(path: &str, force: bool) -> Result<StagingDir, anyhow::Error> {
+    // Along with the parts of an archive split with --max-archive-size.
+    let staged = staged_dirs(path);
+
+    if let (Some(first), false) = (staged.first(), force) {
+        return Err(RuntimeError::StagingDirExists(first.to_string_lossy().into()).into());
+    }
+
+    for dir in staged.iter() {
+        fs::remove_dir_all(dir).context("Removing staging directory.")?;
+    }
+
+    Ok(StagingDir::new(path))
+}
+
+// Packs 'staging' into 'out_name', or into parts named after it with
+// --max-archive-size, and returns the names written. The staging
+// directory is removed once they're all in place.
+#[allow(clippy::too_many_arguments)]
+fn pack_staged(
+    staging: &str,
+    out_name: &str,
+    header: &ArchiveHeader,
+    digest: bool,
+    recovery_percent: Option<u32>,
+    max_archive_size: Option<u64>,
+    write_buffer_size: usize,
+    checksum: Option<&ChecksumAlgorithm>,
+    progress: Option<Arc<Progress>>,
+    progress_interval: Duration,
+    progress_format: ProgressFormat,
+) -> Result<Vec<String>, anyhow::Error> {
+    // Each part is an archive of its own, named after the output.
+    let staged = match max_archive_size {
+        Some(size) => zap::split_staged(staging, size).context("Splitting archive.")?,
+        None => vec![PathBuf::from(staging)],
+    };
+
+    let out_names: Vec<String> = match staged.len() {
+        1 => vec![out_name.to_string()],
+        _ => (1..=staged.len()).map(|n| part_name(out_name, n)).collect(),
+    };
+
+    // Shown as a phase of its own, as packing a large archive can take
+    // a while after every file is compressed. Counted across every part.
+    let display = progress.clone().map(|p| {
+        p.start_packing(staged.iter().map(|dir| staged_bytes(dir)).sum());
+        ProgressDisplay::start(p, progress_interval, progress_format)
+    });
+
+    // Parts are archives of their own, so they're packed in parallel.
+    // Every part is written before any is moved into place, so that a
+    // failure part way leaves none of them looking like an archive.
+    let packed: Vec<Result<String, anyhow::Error>> = staged.par_iter()
+        .zip(out_names.par_iter())
+        .map(|(dir, name)| pack_archive(
+            &dir.to_string_lossy(),
+            name,
+            header,
+            digest,
+            recovery_percent,
+            write_buffer_size,
+            progress.clone(),
+        ))
+        .collect();
+
+    if let Some(display) = display {
+        display.finish();
+    }
+
+    let written: Vec<String> = packed.iter().filter_map(|p| p.as_ref().ok().cloned()).collect();
+
+    if let Some(e) = packed.into_iter().find_map(|p| p.err()) {
+        remove_partial(&written);
+        return Err(e);
+    }
+
+    for (n, (temp_name, name)) in written.iter().zip(out_names.iter()).enumerate() {
+        let promoted = promote_archive(temp_name, name, checksum);
+
+        if promoted.is_err() {
+            remove_partial(&written[n + 1..]);
+        }
+
+        promoted?;
+    }
+
+    for dir in staged.iter() {
+        fs::remove_dir_all(dir).context("Cleaning up...")?;
+    }
+
+    Ok(out_names)
+}
+
+// Packs 'unpacked' into 'temp_name' as remove rewrites an archive, with
+// the same header, digest and recovery record as the original.
+fn rewrite_archive(
+    unpacked: &str,
+    temp_name: &str,
+    header: Option<&ArchiveHeader>,
+    digest: bool,
+    recovery_percent: Option<u32>,
+) -> Result<(), anyhow::Error> {
+    let mut out_writer = BufWriter::new(File::create(temp_name).context("Creating output file")?);
+
+    if let Some(header) = header {
+        write_header(&mut out_writer, header).context("Writing header")?;
+    }
+
+    pack_files(unpacked, &mut out_writer).context("Packing files")?;
+
+    out_writer.flush().context("Writing output file")?;
+    drop(out_writer);
+
+    if digest {
+        append_footer(Path::new(temp_name)).context("Appending digest")?;
+    }
+
+    if let Some(percent) = recovery_percent {
+        append_recovery(Path::new(temp_name), percent).context("Appending recovery record")?;
+    }
+
+    Ok(())
+}
+
+// Packs the files staged in 'dir' into a temporary file next to
+// 'out_name', with the digest and recovery record if asked for, and
+// returns its name for promote_archive. Nothing is left behind on failure.
+fn pack_archive(
+    dir: &str,
+    out_name: &str,
+    header: &ArchiveHeader,
+    digest: bool,
+    recovery_percent: Option<u32>,
+    write_buffer_size: usize,
+    progress: Option<Arc<Progress>>,
+) -> Result<String, anyhow::Error> {
+    // Written next to the destination so that it only appears once
+    // complete, see replace_file.
+    let temp_name = format!("{}.tmp", out_name);
+
+    let written = write_archive(
+        dir,
+        &temp_name,
+        header,
+        digest,
+        recovery_percent,
+        write_buffer_size,
+        progress,
+    );
+
+    match written {
+        Ok(()) => Ok(temp_name),
+        Err(e) => {
+            remove_partial(&[temp_name]);
+            Err(e)
+        },
+    }
+}
+
+fn write_archive(
+    dir: &str,
+    temp_name: &str,
+    header: &ArchiveHeader,
+    digest: bool,
+    recovery_percent: Option<u32>,
+    write_buffer_size: usize,
+    progress: Option<Arc<Progress>>,
+) -> Result<(), anyhow::Error> {
+    let out_file = File::create(temp_name).context("Creating output file")?;
+
+    // Under the buffer, so that progress is counted and the digest
+    // updated once per buffer rather than for every small write.
+    let out_file = HashingWriter::new(ProgressWriter::new(out_file, progress), digest);
+
+    let mut out_writer = BufWriter::with_capacity(write_buffer_size, out_file);
+
+    write_header(&mut out_writer, header).context("Writing header")?;
+
+    let started = Instant::now();
+
+    let packed = pack_files(dir, &mut out_writer)
+        .context("Packing files")
+        .and_then(|_| out_writer.into_inner().map_err(|e| e.into_error()).context("Writing output file"));
+
+    // Closed before the footer is appended to it.
+    let (_, footer) = packed?.finalise();
+
+    debug!("Packed {} in {:?}", temp_name, started.elapsed());
+
+    // Hashed as it was written, so the archive isn't read again.
+    if let Some(footer) = footer {
+        write_footer(Path::new(temp_name), &footer).context("Appending digest")?;
+        info!("Archive digest: sha256:{}", footer.hex_digest());
+    }
+
+    // Applied last so that the digest is covered too.
+    if let Some(percent) = recovery_percent {
+        let record = append_recovery(Path::new(temp_name), percent).context("Appending recovery record")?;
+        info!("Recovery record: {} parity block(s) for {} block(s)", record.parity_blocks, record.data_blocks());
+    }
+
+    Ok(())
+}
+
+// Moves an archive written by pack_archive into place, then writes its
+// checksum if asked for.
+fn promote_archive(temp_name: &str, out_name: &str, checksum: Option<&ChecksumAlgorithm>) -> Result<(), anyhow::Error> {
+    replace_file(Path::new(temp_name), Path::new(out_name)).context("Moving archive into place")?;
+
+    // After everything else is appended, as it covers the final file.
+    if let Some(algorithm) = checksum {
+        let sidecar = write_sidecar(Path::new(out_name), algorithm).context("Writing checksum")?;
+        info!("Checksum written to: {}", sidecar.display());
+    }
+
+    Ok(())
+}
+
+// Already failing, so a file that can't be removed is only warned about.
+fn remove_partial(temp_names: &[String]) {
+    for temp_name in temp_names.iter() {
+        if let Err(e) = fs::remove_file(temp_name) {
+            warn!("Unable to remove incomplete archive '{}': {}", temp_name, e);
+        }
+    }
+}
+
+// As remove_partial, for the directory of --output-format dir, along with
+// its checkpoint, which would otherwise resume into the missing directory.
+fn remove_partial_dir(out_name: &str, checkpoint: &Path) {
+    if let Err(e) = fs::remove_dir_all(out_name) {
+        warn!("Unable to remove incomplete output '{}': {}", out_name, e);
+    }
+
+    if checkpoint.exists() {
+        if let Err(e) = fs::remove_file(checkpoint) {
+            warn!("Unable to remove checkpoint '{}': {}", checkpoint.display(), e);
+        }
+    }
+}
+
+fn is_part_extension(ext: &str) -> bool {
+    ext.strip_prefix("part").is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+}
+
+// 'dir.lz4.zap' becomes 'dir.lz4.part1.zap', so that each part keeps the
+// extensions it is extracted by.
+fn part_name(out_name: &str, n: usize) -> String {
+    match out_name.strip_suffix(".zap") {
+        Some(stem) => format!("{}.part{}.zap", stem, n),
+        None => format!("{}.part{}", out_name, n),
+    }
+}
+
+// Unpacks 'archive' into 'destination', returning its header, which older
+// archives don't have. Archives with a header, digest footer or recovery
+// record are unpacked from a copy without them, so that the container
+// reader never sees any. The copy is made in a scratch directory of its
+// own, so that concurrent runs each have theirs.
+fn unpack_archive(archive: &str, destination: &str) -> Result<Option<ArchiveHeader>, anyhow::Error> {
+    check_archive_size(archive)?;
+
+    let header = read_header(Path::new(archive)).context("Reading header.")?;
+    let footer = read_footer(Path::new(archive)).context("Reading digest.")?;
+    let recovery = read_recovery(Path::new(archive)).context("Reading recovery record.")?;
+
+    let start = match header {
+        Some(_) => HEADER_SIZE,
+        None => 0,
+    };
+
+    // The footer sits before the recovery record, so its payload
+    // excludes both.
+    let end = match (footer, recovery) {
+        (None, None) if start == 0 => {
+            unpack_payload(archive, destination, archive)?;
+            return Ok(header);
+        },
+        (Some(footer), _) => footer.payload_len,
+        (None, Some(record)) => record.data_len,
+        (None, None) => fs::metadata(archive).context("Reading archive.")?.len(),
+    };
+
+    if end <= start {
+        return Err(RuntimeError::InvalidArchive(archive.into(), "it has a header but no contents".into()).into());
+    }
+
+    let scratch = ScratchDir::create("payload").context("Creating scratch directory.")?;
+    let payload = scratch.join("payload");
+
+    copy_range(Path::new(archive), start, end, Path::new(&payload)).context("Removing header and trailers.")?;
+
+    unpack_payload(&payload, destination, archive)?;
+
+    Ok(header)
+}
+
+// zapf panics on a payload too short or damaged to hold its index, rather
+// than returning an error, so the panic is caught and reported as an
+// invalid archive. Its message is kept out of the output meanwhile.
+fn unpack_payload(payload: &str, destination: &str, archive: &str) -> Result<(), anyhow::Error> {
+    // The hook is the process's, so it's only swapped by one at a time.
+    static HOOK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    let unpacked = {
+        let _swapping = HOOK.lock().unwrap_or_else(|e| e.into_inner());
+
+        let hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(|_| {}));
+
+        let unpacked = std::panic::catch_unwind(|| unpack_files(payload, destination));
+
+        std::panic::set_hook(hook);
+
+        unpacked
+    };
+
+    match unpacked {
+        Ok(unpacked) => unpacked.context("Unpacking files."),
+        Err(_) => Err(RuntimeError::InvalidArchive(archive.into(), "its index is missing or damaged".into()).into()),
+    }
+}
+
+// The smallest files are refused before they get to zapf at all, with a
+// clearer reason than unpack_payload's.
+fn check_archive_size(archive: &str) -> Result<(), anyhow::Error> {
+    let len = fs::metadata(archive).context("Reading archive.")?.len();
+
+    match len {
+        0 => Err(RuntimeError::InvalidArchive(archive.into(), "the file is empty".into()).into()),
+        len if len < HEADER_SIZE => Err(RuntimeError::InvalidArchive(
+            archive.into(),
+            format!("{} byte(s) is too small to be an archive", len),
+        ).into()),
+        _ => Ok(()),
+    }
+}
+
+// By the paths entries are stored under, before any stripping or mapping.
+fn has_single_top_level_dir(listing: &ArchiveListing) -> bool {
+    let Some(first) = listing.entries.first().and_then(|entry| entry.path.components().next()) else {
+        return false;
+    };
+
+    // An entry directly at the top level is a file, not a directory.
+    listing.entries.iter().all(|entry| {
+        entry.path.components().next() == Some(first) && entry.path.components().count() > 1
+    })
 }
 
 fn preamble(verbosity: Verbosity) -> Result<(), anyhow::Error> {
@@ -346,3 +1874,104 @@ fn preamble(verbosity: Verbosity) -> Result<(), anyhow::Error> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unpack(scratch: &ScratchDir, contents: &[u8]) -> Result<Option<ArchiveHeader>, anyhow::Error> {
+        let archive = scratch.join("archive.zap");
+        fs::write(&archive, contents).unwrap();
+
+        unpack_archive(&archive, &scratch.join("unpacked"))
+    }
+
+    fn is_invalid(result: Result<Option<ArchiveHeader>, anyhow::Error>) -> bool {
+        matches!(result.map_err(|e| e.downcast::<RuntimeError>()), Err(Ok(RuntimeError::InvalidArchive(_, _))))
+    }
+
+    fn header() -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_header(&mut buf, &ArchiveHeader { encryption: EncryptionType::Passthrough, compression: CompressionType::Lz4 }).unwrap();
+
+        buf
+    }
+
+    #[test]
+    fn failed_archive_leaves_nothing() {
+        let scratch = ScratchDir::create("test").unwrap();
+        let staged = scratch.join("staged");
+
+        fs::create_dir(&staged).unwrap();
+        fs::write(Path::new(&staged).join("entry"), b"entry").unwrap();
+
+        let out_name = scratch.join("out.zap");
+        let header = ArchiveHeader { encryption: EncryptionType::Passthrough, compression: CompressionType::Lz4 };
+
+        // Fails once the payload and digest are written, on the recovery record.
+        assert!(pack_archive(&staged, &out_name, &header, true, Some(0), DEFAULT_WRITE_BUFFER_SIZE, None).is_err());
+
+        assert!(!Path::new(&out_name).exists());
+        assert!(!Path::new(&format!("{}.tmp", out_name)).exists());
+    }
+
+    #[test]
+    fn unpacks_concurrently() {
+        let input = ScratchDir::create("test").unwrap();
+        fs::write(input.join("entry"), b"entry").unwrap();
+
+        let mut contents = header();
+        pack_files(input.path(), &mut contents).unwrap();
+
+        // Each from a payload of its own, whatever the archive is named.
+        let scratches: Vec<ScratchDir> = (0..4).map(|_| ScratchDir::create("test").unwrap()).collect();
+
+        std::thread::scope(|scope| {
+            for scratch in scratches.iter() {
+                let contents = &contents;
+
+                scope.spawn(move || {
+                    let archive = scratch.join("renamed.dat");
+                    fs::write(&archive, contents).unwrap();
+
+                    let unpacked = scratch.join("unpacked");
+                    assert!(unpack_archive(&archive, &unpacked).unwrap().is_some());
+                    assert_eq!(fs::read(Path::new(&unpacked).join("entry")).unwrap(), b"entry");
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn rejects_empty_archive() {
+        let scratch = ScratchDir::create("test").unwrap();
+
+        assert!(is_invalid(unpack(&scratch, b"")));
+    }
+
+    #[test]
+    fn rejects_one_byte_archive() {
+        let scratch = ScratchDir::create("test").unwrap();
+
+        assert!(is_invalid(unpack(&scratch, b"z")));
+    }
+
+    #[test]
+    fn rejects_header_without_payload() {
+        let scratch = ScratchDir::create("test").unwrap();
+
+        assert!(is_invalid(unpack(&scratch, &header())));
+    }
+
+    #[test]
+    fn rejects_short_payload() {
+        let scratch = ScratchDir::create("test").unwrap();
+
+        // Too short for zapf's index, with a header and without.
+        let mut contents = header();
+        contents.extend_from_slice(b"zap");
+
+        assert!(unpack(&scratch, &contents).is_err());
+        assert!(unpack(&scratch, &[7; HEADER_SIZE as usize + 3]).is_err());
+    }
+}
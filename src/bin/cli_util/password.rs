@@ -1,39 +1,185 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, ErrorKind},
+    path::Path,
+};
+
+use log::warn;
 use rpassword::prompt_password;
 use sha2::{Digest, Sha256, Sha512};
 
 use zap::error::{HashingError, InputError, PasswordError};
 
-pub fn get_password_confirm(key_len: usize) -> Result<Vec<u8>, PasswordError> {
-    let pass = match prompt_password("Enter a password for encryption: ") {
-        Ok(val) => val,
-        Err(e) => return Err(InputError::from(e).into()),
-    };
+/// Estimated entropy below which a password is considered weak.
+pub const MIN_PASSWORD_ENTROPY_BITS: f64 = 60.0;
 
-    let confirm_pass = match prompt_password("Repeat encryption password: ") {
-        Ok(val) => val,
-        Err(e) => return Err(InputError::from(e).into()),
-    };
+/// How to treat weak passwords when creating an archive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PasswordCheck {
+    Skip,
+    Warn,
+    Require,
+}
+
+pub fn get_password_confirm(key_len: usize, check: PasswordCheck) -> Result<Vec<u8>, PasswordError> {
+    Ok(convert_pw_to_key(read_password_confirm(check)?, key_len)?)
+}
+
+// As get_password_confirm, giving the password rather than the key.
+pub fn read_password_confirm(check: PasswordCheck) -> Result<String, PasswordError> {
+    confirm_with(check, |message| prompt_password(message))
+}
 
+// 'ask' prompts for a line, the terminal outside of tests.
+fn confirm_with(check: PasswordCheck, mut ask: impl FnMut(&str) -> io::Result<String>) -> Result<String, PasswordError> {
+    let pass = prompt(&mut ask, "Enter a password for encryption: ")?;
+
+    // Before asking for it again, there is nothing to repeat.
     if pass.is_empty() {
         return Err(PasswordError::PasswordEmpty);
     }
 
+    let confirm_pass = prompt(&mut ask, "Repeat encryption password: ")?;
+
     if pass.ne(&confirm_pass) {
         return Err(PasswordError::PasswordsDoNotMatch);
     }
 
+    // Checked before the password is turned into a key so that a
+    // rejected password never reaches the key derivation.
+    check_password_strength(&pass, check)?;
+
+    Ok(pass)
+}
+
+// Reads the password from the first line of 'path', for when it can't be
+// entered interactively. There is nothing to confirm it against, but it
+// is still strength checked.
+pub fn get_password_from_file(path: &Path, key_len: usize, check: PasswordCheck) -> Result<Vec<u8>, PasswordError> {
+    let file_error = |e| PasswordError::PasswordFileError(path.display().to_string(), e);
+
+    let mut pass = String::new();
+
+    BufReader::new(File::open(path).map_err(file_error)?)
+        .read_line(&mut pass)
+        .map_err(file_error)?;
+
+    let pass = pass.trim_end_matches(['\r', '\n']).to_string();
+
+    if pass.is_empty() {
+        return Err(PasswordError::PasswordEmpty);
+    }
+
+    check_password_strength(&pass, check)?;
+
     Ok(convert_pw_to_key(pass, key_len)?)
 }
 
-pub fn get_password_noconf(key_len: usize) -> Result<Vec<u8>, PasswordError> {
-    let pass = match prompt_password("Enter a password for encryption: ") {
-        Ok(val) => val,
-        Err(e) => return Err(InputError::from(e).into()),
+// None when 'var' isn't set, so that the caller can say which variable.
+pub fn get_password_from_env(var: &str, key_len: usize, check: PasswordCheck) -> Result<Option<Vec<u8>>, PasswordError> {
+    let Ok(pass) = std::env::var(var) else {
+        return Ok(None);
     };
 
+    if pass.is_empty() {
+        return Err(PasswordError::PasswordEmpty);
+    }
+
+    check_password_strength(&pass, check)?;
+
+    Ok(Some(convert_pw_to_key(pass, key_len)?))
+}
+
+pub fn get_password_noconf(key_len: usize) -> Result<Vec<u8>, PasswordError> {
+    Ok(convert_pw_to_key(read_password_noconf()?, key_len)?)
+}
+
+pub fn read_password_noconf() -> Result<String, PasswordError> {
+    noconf_with(|message| prompt_password(message))
+}
+
+fn noconf_with(mut ask: impl FnMut(&str) -> io::Result<String>) -> Result<String, PasswordError> {
+    let pass = prompt(&mut ask, "Enter a password for encryption: ")?;
+
+    // No archive can have been encrypted with one.
+    if pass.is_empty() {
+        return Err(PasswordError::PasswordEmpty);
+    }
+
+    Ok(pass)
+}
+
+// rpassword reports input that ends without a newline, eg: Ctrl-D at the
+// prompt, as UnexpectedEof. That is taken as giving up on the password
+// rather than as an empty one, which only pressing enter gives.
+fn prompt(ask: &mut impl FnMut(&str) -> io::Result<String>, message: &str) -> Result<String, PasswordError> {
+    match ask(message) {
+        Ok(pass) => Ok(pass),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Err(PasswordError::PasswordEntryCancelled),
+        Err(e) => Err(InputError::from(e).into()),
+    }
+}
+
+// For a password that was stored rather than entered, eg: in the keyring.
+#[cfg(feature = "keyring")]
+pub fn get_password_from_str(pass: String, key_len: usize, check: PasswordCheck) -> Result<Vec<u8>, PasswordError> {
+    if pass.is_empty() {
+        return Err(PasswordError::PasswordEmpty);
+    }
+
+    check_password_strength(&pass, check)?;
+
     Ok(convert_pw_to_key(pass, key_len)?)
 }
 
+fn check_password_strength(pass: &str, check: PasswordCheck) -> Result<(), PasswordError> {
+    if check == PasswordCheck::Skip {
+        return Ok(());
+    }
+
+    let bits = estimate_entropy(pass);
+
+    if bits >= MIN_PASSWORD_ENTROPY_BITS {
+        return Ok(());
+    }
+
+    match check {
+        PasswordCheck::Require => Err(PasswordError::PasswordTooWeak(bits, MIN_PASSWORD_ENTROPY_BITS)),
+        _ => {
+            warn!(
+                "Password is weak: estimated {:.0} bits of entropy, {:.0} recommended",
+                bits, MIN_PASSWORD_ENTROPY_BITS
+            );
+            Ok(())
+        }
+    }
+}
+
+// A rough estimate based on the character classes used. Repeated
+// characters only count once towards the length so that 'aaaaaaaa'
+// isn't treated as a long password.
+fn estimate_entropy(pass: &str) -> f64 {
+    let mut pool = 0;
+
+    if pass.chars().any(|c| c.is_ascii_lowercase()) { pool += 26; }
+    if pass.chars().any(|c| c.is_ascii_uppercase()) { pool += 26; }
+    if pass.chars().any(|c| c.is_ascii_digit()) { pool += 10; }
+    if pass.chars().any(|c| c.is_ascii_punctuation() || c == ' ') { pool += 33; }
+    if pass.chars().any(|c| !c.is_ascii()) { pool += 100; }
+
+    if pool == 0 {
+        return 0.0;
+    }
+
+    let mut distinct: Vec<char> = pass.chars().collect();
+    distinct.sort_unstable();
+    distinct.dedup();
+
+    let length = pass.chars().count().min(distinct.len() * 2);
+
+    length as f64 * (pool as f64).log2()
+}
+
 // This will need to be reworked later as more encryption algorithms are
 // brought in. May also need to be moved to 'bin'.
 pub fn convert_pw_to_key(pw: String, len: usize) -> Result<Vec<u8>, HashingError> {
@@ -42,4 +188,37 @@ pub fn convert_pw_to_key(pw: String, len: usize) -> Result<Vec<u8>, HashingError
         512 => Ok(Vec::from(&Sha512::digest(pw.as_bytes())[..])),
         _ => Err(HashingError::UnrecognisedAlgorithmLength(len)),
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Answers each prompt with the next line of 'input', as the terminal would.
+    fn answers(mut input: &'static [u8]) -> impl FnMut(&str) -> io::Result<String> {
+        move |_| rpassword::read_password_from_bufread(&mut input)
+    }
+
+    #[test]
+    fn eof_cancels() {
+        assert!(matches!(noconf_with(answers(b"")), Err(PasswordError::PasswordEntryCancelled)));
+        assert!(matches!(confirm_with(PasswordCheck::Skip, answers(b"")), Err(PasswordError::PasswordEntryCancelled)));
+        // Typed, then Ctrl-D rather than enter.
+        assert!(matches!(noconf_with(answers(b"secret")), Err(PasswordError::PasswordEntryCancelled)));
+        // At the second prompt.
+        assert!(matches!(confirm_with(PasswordCheck::Skip, answers(b"secret\n")), Err(PasswordError::PasswordEntryCancelled)));
+    }
+
+    #[test]
+    fn empty_passwords_are_rejected() {
+        assert!(matches!(noconf_with(answers(b"\n")), Err(PasswordError::PasswordEmpty)));
+        assert!(matches!(confirm_with(PasswordCheck::Skip, answers(b"\n\n")), Err(PasswordError::PasswordEmpty)));
+    }
+
+    #[test]
+    fn confirmed_passwords_must_match() {
+        assert_eq!(confirm_with(PasswordCheck::Skip, answers(b"secret\nsecret\n")).unwrap(), "secret");
+        assert!(matches!(confirm_with(PasswordCheck::Skip, answers(b"secret\nsecrets\n")), Err(PasswordError::PasswordsDoNotMatch)));
+        assert_eq!(noconf_with(answers(b"secret\r\n")).unwrap(), "secret");
+    }
+}
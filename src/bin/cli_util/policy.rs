@@ -0,0 +1,58 @@
+use clap::ValueEnum;
+use zap::options::{ChangePolicy, ConflictPolicy, SymlinkPolicy};
+
+#[derive(Debug, Default, Clone, ValueEnum)]
+pub enum BinChangePolicy {
+    #[default]
+    Skip,
+    Retry,
+    Error,
+}
+
+impl From<BinChangePolicy> for ChangePolicy {
+    fn from(p: BinChangePolicy) -> Self {
+        match p {
+            BinChangePolicy::Skip => ChangePolicy::Skip,
+            BinChangePolicy::Retry => ChangePolicy::Retry,
+            BinChangePolicy::Error => ChangePolicy::Error,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, ValueEnum)]
+pub enum BinConflictPolicy {
+    #[default]
+    Rename,
+    Skip,
+    Overwrite,
+    Error,
+}
+
+impl From<BinConflictPolicy> for ConflictPolicy {
+    fn from(p: BinConflictPolicy) -> Self {
+        match p {
+            BinConflictPolicy::Rename => ConflictPolicy::Rename,
+            BinConflictPolicy::Skip => ConflictPolicy::Skip,
+            BinConflictPolicy::Overwrite => ConflictPolicy::Overwrite,
+            BinConflictPolicy::Error => ConflictPolicy::Error,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, ValueEnum)]
+pub enum BinSymlinkPolicy {
+    #[default]
+    DenyEscaping,
+    RelativeOnly,
+    AllowAbsolute,
+}
+
+impl From<BinSymlinkPolicy> for SymlinkPolicy {
+    fn from(p: BinSymlinkPolicy) -> Self {
+        match p {
+            BinSymlinkPolicy::DenyEscaping => SymlinkPolicy::DenyEscaping,
+            BinSymlinkPolicy::RelativeOnly => SymlinkPolicy::RelativeOnly,
+            BinSymlinkPolicy::AllowAbsolute => SymlinkPolicy::AllowAbsolute,
+        }
+    }
+}
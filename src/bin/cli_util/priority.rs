@@ -0,0 +1,54 @@
+use std::io;
+
+use log::debug;
+
+// Both are per thread on Linux and inherited by the threads created after,
+// so this is called before any are started, eg: by rayon.
+pub fn lower_priority(nice: Option<i32>, ionice: bool) -> Result<(), io::Error> {
+    if let Some(nice) = nice {
+        set_nice(nice)?;
+        debug!("Running at niceness {}", nice);
+    }
+
+    if ionice {
+        set_idle_io()?;
+        debug!("Running in the idle IO class");
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_nice(nice: i32) -> Result<(), io::Error> {
+    match unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(unix))]
+fn set_nice(_nice: i32) -> Result<(), io::Error> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+// There's no wrapper for ioprio_set in libc, see ioprio_set(2).
+#[cfg(target_os = "linux")]
+fn set_idle_io() -> Result<(), io::Error> {
+    const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+    const IOPRIO_CLASS_IDLE: libc::c_long = 3;
+    const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+
+    // 0 is the calling thread. Passed as a long like the others, as the
+    // arguments of syscall are variadic.
+    let who: libc::c_long = 0;
+
+    match unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, who, IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_idle_io() -> Result<(), io::Error> {
+    Err(io::ErrorKind::Unsupported.into())
+}
@@ -0,0 +1,200 @@
+use std::{
+    io::{self, stderr, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use serde::Serialize;
+use walkdir::WalkDir;
+use zap::progress::{Progress, ProgressPhase, ProgressSnapshot};
+
+/// Version of the --json-progress events. Bumped when a field is removed
+/// or changes meaning, fields may be added without bumping it.
+pub const JSON_PROGRESS_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProgressFormat {
+    /// A single line, redrawn in place.
+    Human,
+    /// A JSON object per line, for other programs to display.
+    Json,
+}
+
+#[derive(Serialize)]
+struct JsonProgressEvent {
+    version: u32,
+    /// "progress" while running, "finished" once for each phase.
+    event: &'static str,
+    phase: &'static str,
+    files_done: u64,
+    files_total: u64,
+    bytes_done: u64,
+    bytes_total: u64,
+    current_path: Option<String>,
+}
+
+/// Writes progress to stderr every 'interval' until finished.
+pub struct ProgressDisplay {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+impl ProgressDisplay {
+    pub fn start(progress: Arc<Progress>, interval: Duration, format: ProgressFormat) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+
+        let handle = thread::spawn(move || {
+            while !stopped.load(Ordering::Relaxed) {
+                format.write(&progress.snapshot(), false);
+                // Woken early by finish, so that exiting isn't held up
+                // by a long interval.
+                thread::park_timeout(interval);
+            }
+
+            format.write(&progress.snapshot(), true);
+        });
+
+        ProgressDisplay { stop, handle }
+    }
+
+    pub fn finish(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.handle.thread().unpark();
+
+        // A panic while drawing shouldn't fail the operation itself.
+        let _ = self.handle.join();
+    }
+}
+
+/// Counts the bytes written through it as packed, so that packing the
+/// archive can be shown as progress too.
+pub struct ProgressWriter<W: Write> {
+    inner: W,
+    progress: Option<Arc<Progress>>,
+}
+
+impl<W: Write> ProgressWriter<W> {
+    pub fn new(inner: W, progress: Option<Arc<Progress>>) -> Self {
+        ProgressWriter { inner, progress }
+    }
+}
+
+impl<W: Write> Write for ProgressWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+
+        if let Some(progress) = &self.progress {
+            progress.pack_bytes(written as u64);
+        }
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl ProgressFormat {
+    fn write(self, snapshot: &ProgressSnapshot, finished: bool) {
+        match self {
+            ProgressFormat::Human => {
+                draw(snapshot);
+
+                if finished {
+                    eprintln!();
+                }
+            },
+            ProgressFormat::Json => write_json(snapshot, finished),
+        }
+    }
+}
+
+// What will be packed, the archive itself is a little larger for its index.
+pub fn staged_bytes(dir: &Path) -> u64 {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.metadata().ok())
+        .filter(|m| m.is_file())
+        .map(|m| m.len())
+        .sum()
+}
+
+fn draw(snapshot: &ProgressSnapshot) {
+    let percent = match snapshot.total_bytes {
+        0 => 100.0,
+        total => snapshot.done_bytes as f64 / total as f64 * 100.0,
+    };
+
+    let throughput = snapshot.throughput
+        .map(|t| format!("{}/s", format_bytes(t as u64)))
+        .unwrap_or_else(|| "-".into());
+
+    let eta = snapshot.eta
+        .map(|eta| humantime::format_duration(Duration::from_secs(eta.as_secs())).to_string())
+        .unwrap_or_else(|| "-".into());
+
+    // Packing only counts bytes, the files are written by zapf.
+    let files = match snapshot.phase {
+        ProgressPhase::Processing => format!("{}/{} files", snapshot.done_files, snapshot.total_files),
+        ProgressPhase::Packing => "packing".into(),
+    };
+
+    // Padded so that a shorter line fully covers the previous one.
+    eprint!(
+        "\r{:>5.1}%  {}  {}/{}  {}  ETA {:<16}",
+        percent,
+        files,
+        format_bytes(snapshot.done_bytes),
+        format_bytes(snapshot.total_bytes),
+        throughput,
+        eta,
+    );
+
+    let _ = stderr().flush();
+}
+
+fn write_json(snapshot: &ProgressSnapshot, finished: bool) {
+    let event = JsonProgressEvent {
+        version: JSON_PROGRESS_VERSION,
+        event: if finished { "finished" } else { "progress" },
+        phase: match snapshot.phase {
+            ProgressPhase::Processing => "processing",
+            ProgressPhase::Packing => "packing",
+        },
+        files_done: snapshot.done_files,
+        files_total: snapshot.total_files,
+        bytes_done: snapshot.done_bytes,
+        bytes_total: snapshot.total_bytes,
+        current_path: snapshot.current_path.as_ref().map(|p| p.to_string_lossy().into_owned()),
+    };
+
+    // Only fails for maps with non-string keys, which this has none of.
+    if let Ok(line) = serde_json::to_string(&event) {
+        eprintln!("{}", line);
+    }
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    match unit {
+        0 => format!("{} {}", bytes, UNITS[0]),
+        _ => format!("{:.1} {}", value, UNITS[unit]),
+    }
+}
@@ -0,0 +1,212 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use log::warn;
+
+use super::is_part_extension;
+
+// Numbers the directories of one run, as several can be open at once.
+static NEXT: AtomicU32 = AtomicU32::new(0);
+
+/// A directory of its own for a command to unpack an archive into, eg:
+/// list, removed with everything in it when dropped, however the command
+/// ends. Named after the command and the process, so that concurrent runs
+/// and the staging directory of archive and extract are never touched.
+pub struct ScratchDir {
+    // As the library's directory functions take it.
+    path: String,
+}
+
+impl ScratchDir {
+    // Created rather than reused, so anything already at the path, eg: a
+    // symlink planted by another user, fails it instead of being followed.
+    pub fn create(name: &str) -> io::Result<ScratchDir> {
+        loop {
+            let n = NEXT.fetch_add(1, Ordering::Relaxed);
+            let path = std::env::temp_dir()
+                .join(format!("zap-{}-{}-{}", name, std::process::id(), n))
+                .to_string_lossy()
+                .into_owned();
+
+            match fs::create_dir(&path) {
+                Ok(()) => return Ok(ScratchDir { path }),
+                // Left behind by an earlier process with the same id.
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn join(&self, name: &str) -> String {
+        Path::new(&self.path).join(name).to_string_lossy().into_owned()
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.path) {
+            warn!("Unable to remove '{}': {}", self.path, e);
+        }
+    }
+}
+
+/// The directory archive or extract stages its files in, removed with the
+/// parts split from it when dropped, so that a run that fails isn't taken
+/// for one still using it by prepare_staging_dir. Unlike a ScratchDir it's
+/// named after the output, see staging_path, so that the check and
+/// --resume can find it.
+pub struct StagingDir {
+    path: String,
+    keep: bool,
+}
+
+impl StagingDir {
+    pub fn new(path: &str) -> StagingDir {
+        StagingDir { path: path.into(), keep: false }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    // For a run given --resume, which leaves its files for the next one.
+    pub fn keep(&mut self) {
+        self.keep = true;
+    }
+}
+
+impl Drop for StagingDir {
+    fn drop(&mut self) {
+        if self.keep {
+            return;
+        }
+
+        // Already removed once a run finishes.
+        for dir in staged_dirs(&self.path) {
+            if let Err(e) = fs::remove_dir_all(&dir) {
+                warn!("Unable to remove '{}': {}", dir.display(), e);
+            }
+        }
+    }
+}
+
+/// Where files are staged for 'destination', next to it rather than in the
+/// temporary directory, so that they're on the same filesystem and only
+/// runs writing to the same place share it, eg: 'out.zap.staging'. A
+/// destination without a name, eg: '.', holds it instead.
+pub fn staging_path(destination: &str) -> String {
+    let destination = Path::new(destination);
+
+    let path = match (destination.parent(), destination.file_name()) {
+        (Some(parent), Some(name)) => parent.join(format!("{}.staging", name.to_string_lossy())),
+        _ => destination.join(".zap-staging"),
+    };
+
+    path.to_string_lossy().into_owned()
+}
+
+/// 'path' and the parts of an archive split from it with
+/// --max-archive-size, eg: 'path.part1', that exist, in order.
+pub fn staged_dirs(path: &str) -> Vec<PathBuf> {
+    let path = Path::new(path);
+    let (Some(parent), Some(name)) = (path.parent(), path.file_name().and_then(|n| n.to_str())) else {
+        return Vec::new();
+    };
+
+    // An empty parent is the current directory.
+    let parent = match parent.as_os_str().is_empty() {
+        true => Path::new("."),
+        false => parent,
+    };
+
+    let mut staged: Vec<PathBuf> = fs::read_dir(parent)
+        .map(|entries| entries
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_name().to_str().and_then(|n| n.strip_prefix(name)).is_some_and(|rest| {
+                rest.is_empty() || rest.strip_prefix('.').is_some_and(is_part_extension)
+            }))
+            .map(|e| path.with_file_name(e.file_name()))
+            .collect())
+        .unwrap_or_default();
+
+    staged.sort();
+
+    staged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_is_new_and_empty() {
+        let first = ScratchDir::create("test").unwrap();
+        let second = ScratchDir::create("test").unwrap();
+
+        assert_ne!(first.path(), second.path());
+        assert_eq!(fs::read_dir(first.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn removed_when_dropped() {
+        let scratch = ScratchDir::create("test").unwrap();
+        let path = scratch.path().to_string();
+
+        fs::create_dir(scratch.join("unpacked")).unwrap();
+        fs::write(Path::new(&scratch.join("unpacked")).join("entry"), b"entry").unwrap();
+
+        drop(scratch);
+
+        assert!(!Path::new(&path).exists());
+    }
+
+    #[test]
+    fn staging_is_removed_with_its_parts() {
+        let scratch = ScratchDir::create("test").unwrap();
+        let path = scratch.join("staging");
+
+        for dir in ["staging", "staging.part1", "staging.part2", "staging.old", "staging-other"] {
+            fs::create_dir(scratch.join(dir)).unwrap();
+        }
+        fs::write(Path::new(&path).join("entry"), b"entry").unwrap();
+
+        assert_eq!(
+            staged_dirs(&path),
+            ["staging", "staging.part1", "staging.part2"].map(|dir| PathBuf::from(scratch.join(dir))),
+        );
+
+        drop(StagingDir::new(&path));
+
+        assert!(staged_dirs(&path).is_empty());
+        assert!(Path::new(&scratch.join("staging.old")).exists() && Path::new(&scratch.join("staging-other")).exists());
+    }
+
+    #[test]
+    fn staged_next_to_destination() {
+        assert_eq!(staging_path("out.zap"), "out.zap.staging");
+        assert_eq!(staging_path("/backups/out.zap"), "/backups/out.zap.staging");
+        assert_eq!(staging_path("restored/"), "restored.staging");
+        assert_eq!(staging_path("."), "./.zap-staging");
+    }
+
+    #[test]
+    fn kept_staging_is_left() {
+        let scratch = ScratchDir::create("test").unwrap();
+        let path = scratch.join("staging");
+        fs::create_dir(&path).unwrap();
+
+        let mut staging = StagingDir::new(&path);
+        staging.keep();
+        drop(staging);
+
+        assert!(Path::new(&path).exists());
+    }
+}
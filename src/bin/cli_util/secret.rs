@@ -0,0 +1,318 @@
+use std::{
+    cell::OnceCell,
+    path::{Path, PathBuf},
+};
+
+use zap::{
+    encryption::{secret::SecretProvider, EncryptionSecret, SecretType},
+    error::{EncryptionKeyError, SecretError},
+};
+#[cfg(feature = "keyring")]
+use zap::error::{InputError, PasswordError};
+
+use super::{
+    error::RuntimeError,
+    password::{get_password_confirm, get_password_from_env, get_password_from_file, get_password_noconf, PasswordCheck},
+};
+#[cfg(feature = "keyring")]
+use super::password::{get_password_from_str, read_password_confirm, read_password_noconf};
+
+/// Asks for the password on the terminal, twice when 'confirm' is set.
+pub struct PromptProvider {
+    pub confirm: bool,
+    pub check: PasswordCheck,
+}
+
+impl SecretProvider for PromptProvider {
+    fn secret_type(&self) -> SecretType {
+        SecretType::Password
+    }
+
+    fn resolve(&self) -> Result<EncryptionSecret, SecretError> {
+        let key = match self.confirm {
+            true => get_password_confirm(256, self.check)?,
+            false => get_password_noconf(256)?,
+        };
+
+        Ok(EncryptionSecret::Password(key))
+    }
+}
+
+/// Reads the password from the first line of a file.
+pub struct PasswordFileProvider {
+    pub path: PathBuf,
+    pub check: PasswordCheck,
+}
+
+impl SecretProvider for PasswordFileProvider {
+    fn secret_type(&self) -> SecretType {
+        SecretType::Password
+    }
+
+    fn resolve(&self) -> Result<EncryptionSecret, SecretError> {
+        Ok(EncryptionSecret::Password(get_password_from_file(&self.path, 256, self.check)?))
+    }
+}
+
+/// Reads the password from an environment variable.
+pub struct EnvProvider {
+    pub var: String,
+    pub check: PasswordCheck,
+}
+
+impl SecretProvider for EnvProvider {
+    fn secret_type(&self) -> SecretType {
+        SecretType::Password
+    }
+
+    fn resolve(&self) -> Result<EncryptionSecret, SecretError> {
+        match get_password_from_env(&self.var, 256, self.check)? {
+            Some(key) => Ok(EncryptionSecret::Password(key)),
+            None => Err(SecretError::MissingEnvVar(self.var.clone())),
+        }
+    }
+}
+
+pub struct KeyFileProvider {
+    pub path: String,
+}
+
+impl SecretProvider for KeyFileProvider {
+    fn secret_type(&self) -> SecretType {
+        SecretType::Key
+    }
+
+    fn resolve(&self) -> Result<EncryptionSecret, SecretError> {
+        if !Path::new(&self.path).is_file() {
+            return Err(EncryptionKeyError::FailedToFindKeyfile(self.path.clone()).into());
+        }
+
+        Ok(EncryptionSecret::Key(self.path.clone()))
+    }
+}
+
+/// Reads the password from the OS keyring. When there is none stored it
+/// is asked for instead, and may then be stored.
+#[cfg(feature = "keyring")]
+pub struct KeyringProvider {
+    pub service: String,
+    pub account: String,
+    pub confirm: bool,
+    pub check: PasswordCheck,
+}
+
+#[cfg(feature = "keyring")]
+impl SecretProvider for KeyringProvider {
+    fn secret_type(&self) -> SecretType {
+        SecretType::Password
+    }
+
+    fn resolve(&self) -> Result<EncryptionSecret, SecretError> {
+        let provider_failed = |e: ::keyring::Error| SecretError::ProviderFailed(Box::new(e));
+
+        let entry = ::keyring::Entry::new(&self.service, &self.account).map_err(provider_failed)?;
+
+        let pass = match entry.get_password() {
+            Ok(pass) => pass,
+            Err(::keyring::Error::NoEntry) => {
+                let pass = match self.confirm {
+                    true => read_password_confirm(self.check)?,
+                    false => read_password_noconf()?,
+                };
+
+                if confirm_store(&self.service, &self.account).map_err(PasswordError::from)? {
+                    entry.set_password(&pass).map_err(provider_failed)?;
+                }
+
+                pass
+            },
+            Err(e) => return Err(provider_failed(e)),
+        };
+
+        Ok(EncryptionSecret::Password(get_password_from_str(pass, 256, self.check)?))
+    }
+}
+
+// Only asked on a terminal, anything but yes leaves the keyring as it was.
+#[cfg(feature = "keyring")]
+fn confirm_store(service: &str, account: &str) -> Result<bool, InputError> {
+    use std::io::{self, BufRead, IsTerminal, Write};
+
+    if !io::stdin().is_terminal() {
+        return Ok(false);
+    }
+
+    eprint!("Store the password in the keyring as '{}' for {}? [y/N] ", account, service);
+    io::stderr().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+#[cfg(feature = "keyring")]
+fn keyring_provider(account: String, confirm: bool, check: PasswordCheck) -> Result<Box<dyn SecretProvider>, RuntimeError> {
+    Ok(Box::new(KeyringProvider { service: KEYRING_SERVICE.into(), account, confirm, check }))
+}
+
+#[cfg(not(feature = "keyring"))]
+fn keyring_provider(_account: String, _confirm: bool, _check: PasswordCheck) -> Result<Box<dyn SecretProvider>, RuntimeError> {
+    Err(RuntimeError::InvalidArgument("--keyring needs zap to be built with the 'keyring' feature".into()))
+}
+
+/// The service passwords are stored under in the keyring.
+#[cfg(feature = "keyring")]
+pub const KEYRING_SERVICE: &str = "zap";
+
+// Archives are stored under their name without directories or extensions,
+// so that 'photos', 'photos.xchacha.zap' and 'backup/photos.xchacha.zap'
+// share a password.
+pub fn keyring_account(archive: &str) -> String {
+    let name = Path::new(archive)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| archive.into());
+
+    name.trim_start_matches('.').split('.').next().unwrap_or_default().to_string()
+}
+
+// The password is prompted for when no source is given. clap already
+// rejects more than one, this is checked again rather than picking one
+// of them should a command be missing the conflict. 'keyring' is the
+// account given with --keyring, if any, or the one for 'archive'.
+pub fn secret_provider(
+    keypath: Option<String>,
+    password_file: Option<PathBuf>,
+    password_env: Option<String>,
+    keyring: Option<Option<String>>,
+    archive: &str,
+    confirm: bool,
+    check: PasswordCheck,
+) -> Result<Box<dyn SecretProvider>, RuntimeError> {
+    let given: Vec<&str> = [
+        ("--keypath", keypath.is_some()),
+        ("--password-file", password_file.is_some()),
+        ("--password-env", password_env.is_some()),
+        ("--keyring", keyring.is_some()),
+    ]
+    .into_iter()
+    .filter_map(|(flag, set)| set.then_some(flag))
+    .collect();
+
+    if given.len() > 1 {
+        return Err(RuntimeError::ConflictingSecrets(given.join(", ")));
+    }
+
+    Ok(match (keypath, password_file, password_env, keyring) {
+        (Some(path), _, _, _) => Box::new(KeyFileProvider { path }),
+        (_, Some(path), _, _) => Box::new(PasswordFileProvider { path, check }),
+        (_, _, Some(var), _) => Box::new(EnvProvider { var, check }),
+        (_, _, _, Some(account)) => keyring_provider(account.unwrap_or_else(|| keyring_account(archive)), confirm, check)?,
+        (None, None, None, None) => Box::new(PromptProvider { confirm, check }),
+    })
+}
+
+/// Resolves 'inner' once and gives the same secret after, so that several
+/// archives can be read with a single prompt.
+pub struct CachedProvider<'a> {
+    inner: &'a dyn SecretProvider,
+    secret: OnceCell<EncryptionSecret>,
+}
+
+impl<'a> CachedProvider<'a> {
+    pub fn new(inner: &'a dyn SecretProvider) -> Self {
+        CachedProvider { inner, secret: OnceCell::new() }
+    }
+}
+
+impl SecretProvider for CachedProvider<'_> {
+    fn secret_type(&self) -> SecretType {
+        self.inner.secret_type()
+    }
+
+    fn resolve(&self) -> Result<EncryptionSecret, SecretError> {
+        if let Some(secret) = self.secret.get() {
+            return Ok(secret.clone());
+        }
+
+        let secret = self.inner.resolve()?;
+
+        Ok(self.secret.get_or_init(|| secret).clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    // Gives a new password each time, as a prompt could.
+    struct CountingProvider(Cell<u8>);
+
+    impl SecretProvider for CountingProvider {
+        fn secret_type(&self) -> SecretType {
+            SecretType::Password
+        }
+
+        fn resolve(&self) -> Result<EncryptionSecret, SecretError> {
+            self.0.set(self.0.get() + 1);
+
+            Ok(EncryptionSecret::Password(vec![self.0.get()]))
+        }
+    }
+
+    #[test]
+    fn cached_resolves_once() {
+        let inner = CountingProvider(Cell::new(0));
+        let cached = CachedProvider::new(&inner);
+
+        for _ in 0..3 {
+            assert!(matches!(cached.resolve(), Ok(EncryptionSecret::Password(pass)) if pass == [1]));
+        }
+
+        assert_eq!(inner.0.get(), 1);
+        assert_eq!(cached.secret_type(), SecretType::Password);
+    }
+
+    // Which of --keypath, --password-file, --password-env and --keyring are given.
+    fn provider(given: [bool; 4]) -> Result<Box<dyn SecretProvider>, RuntimeError> {
+        secret_provider(
+            given[0].then(|| "key".to_string()),
+            given[1].then(|| PathBuf::from("password.txt")),
+            given[2].then(|| "ZAP_PASSWORD".to_string()),
+            given[3].then_some(None),
+            "archive.zap",
+            false,
+            PasswordCheck::Skip,
+        )
+    }
+
+    #[test]
+    fn one_source_of_secret() {
+        assert_eq!(provider([false; 4]).unwrap().secret_type(), SecretType::Password);
+        assert_eq!(provider([true, false, false, false]).unwrap().secret_type(), SecretType::Key);
+        assert_eq!(provider([false, false, true, false]).unwrap().secret_type(), SecretType::Password);
+    }
+
+    #[test]
+    fn conflicting_sources_are_rejected() {
+        let flags = ["--keypath", "--password-file", "--password-env", "--keyring"];
+
+        for first in 0..4 {
+            for second in first + 1..4 {
+                let mut given = [false; 4];
+                given[first] = true;
+                given[second] = true;
+
+                match provider(given) {
+                    Err(RuntimeError::ConflictingSecrets(named)) => assert_eq!(named, format!("{}, {}", flags[first], flags[second])),
+                    _ => panic!("{} and {} weren't rejected", flags[first], flags[second]),
+                }
+            }
+        }
+
+        assert!(matches!(provider([true; 4]), Err(RuntimeError::ConflictingSecrets(named)) if named == flags.join(", ")));
+    }
+}
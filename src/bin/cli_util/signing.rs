@@ -0,0 +1,16 @@
+use clap::ValueEnum;
+use zap::signing::SigningType;
+
+#[derive(Debug, Default, Clone, ValueEnum)]
+pub enum BinSigningType {
+    #[default]
+    Passthrough,
+}
+
+impl From<BinSigningType> for SigningType {
+    fn from(s: BinSigningType) -> Self {
+        match s {
+            BinSigningType::Passthrough => SigningType::Passthrough,
+        }
+    }
+}
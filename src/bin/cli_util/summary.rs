@@ -0,0 +1,59 @@
+use std::time::Duration;
+
+use super::progress::format_bytes;
+
+/// What a run did and where its time went, printed once it finishes.
+#[derive(Debug, Default)]
+pub struct RunSummary {
+    pub files: usize,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub phases: Vec<(&'static str, Duration)>,
+    pub elapsed: Duration,
+}
+
+impl RunSummary {
+    // Phases of the same name are added together, eg: packing each part.
+    pub fn add_phase(&mut self, name: &'static str, elapsed: Duration) {
+        match self.phases.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, total)) => *total += elapsed,
+            None => self.phases.push((name, elapsed)),
+        }
+    }
+
+    /// Adds another run to this one, for archives extracted one after another.
+    pub fn merge(&mut self, other: RunSummary) {
+        self.files += other.files;
+        self.bytes_in += other.bytes_in;
+        self.bytes_out += other.bytes_out;
+        self.elapsed += other.elapsed;
+
+        for (name, elapsed) in other.phases {
+            self.add_phase(name, elapsed);
+        }
+    }
+
+    // eg: 'Archived 120 file(s), 3.2 MiB to 1.1 MiB (34.0%) in 2.41s: walk 50.12ms, compress 1.90s, pack 460.03ms'
+    pub fn print(&self, action: &str) {
+        let ratio = match self.bytes_in {
+            0 => String::new(),
+            bytes_in => format!(" ({:.1}%)", self.bytes_out as f64 / bytes_in as f64 * 100.0),
+        };
+
+        let phases: Vec<String> = self.phases
+            .iter()
+            .map(|(name, elapsed)| format!("{} {:.2?}", name, elapsed))
+            .collect();
+
+        println!(
+            "{} {} file(s), {} to {}{} in {:.2?}: {}",
+            action,
+            self.files,
+            format_bytes(self.bytes_in),
+            format_bytes(self.bytes_out),
+            ratio,
+            self.elapsed,
+            phases.join(", ")
+        );
+    }
+}
@@ -1,9 +1,135 @@
-use zap::signing::SigningType;
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use clap::ValueEnum;
+use zap::{compression::CompressionType, encryption::EncryptionType, manifest::MAX_COMMENT_LEN, options::CompressionRule, signing::SigningType};
 
 use super::{encryption::BinEncryptionType, compression::BinCompressionType};
 
+// Falls back to passthrough for anything not named in the extensions.
+pub fn parse_extensions(path: &Path) -> (BinEncryptionType, BinCompressionType, SigningType) {
+    let mut encryption = BinEncryptionType::Passthrough;
+    let mut compression = BinCompressionType::Passthrough;
+
+    let name = path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    for ext in name.split('.') {
+        if let Some(found) = encryption_for_extension(ext) {
+            encryption = found;
+        }
+
+        if let Some(found) = compression_for_extension(ext) {
+            compression = found;
+        }
+    }
+
+    // No signing method has an extension of its own yet.
+    (encryption, compression, SigningType::default())
+}
+
+// Through the library's extensions, so that they're named in one place.
+pub fn encryption_for_extension(ext: &str) -> Option<BinEncryptionType> {
+    EncryptionType::from_extension(ext).map(BinEncryptionType::from)
+}
+
+pub fn compression_for_extension(ext: &str) -> Option<BinCompressionType> {
+    CompressionType::from_extension(ext).map(BinCompressionType::from)
+}
+
+// Rejected here too so that a long comment fails before asking for a password.
+pub fn parse_comment(s: &str) -> Result<String, String> {
+    match s.len() {
+        len if len > MAX_COMMENT_LEN => Err(format!("comment is {} bytes, the limit is {}", len, MAX_COMMENT_LEN)),
+        _ => Ok(s.into()),
+    }
+}
+
+// Parses a '--rule' of the form 'ext=algorithm', eg: 'jpg=passthrough'.
+pub fn parse_rule(s: &str) -> Result<CompressionRule, String> {
+    let (extension, algorithm) = s.split_once('=')
+        .ok_or_else(|| format!("expected 'ext=algorithm', got '{}'", s))?;
+
+    let extension = extension.trim().trim_start_matches('.');
+
+    if extension.is_empty() {
+        return Err(format!("missing extension in rule '{}'", s));
+    }
+
+    let compression: CompressionType = match BinCompressionType::from_str(algorithm.trim(), true)? {
+        BinCompressionType::Auto => return Err("'auto' compression is only valid for extraction".into()),
+        c => c.into(),
+    };
+
+    Ok(CompressionRule {
+        extension: extension.into(),
+        compression,
+    })
+}
+
+// Temporary files are created next to their destination, on the same
+// filesystem, so that moving them into place is a single rename that leaves
+// either the old or the new file. If the destination is still on another
+// filesystem, eg: it is a mount point itself, the file is copied instead,
+// which isn't atomic.
+pub fn replace_file(temp: &Path, destination: &Path) -> io::Result<()> {
+    match fs::rename(temp, destination) {
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+            fs::copy(temp, destination)?;
+            fs::remove_file(temp)
+        },
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::MetadataExt;
+
+    use super::*;
+    use crate::cli_util::scratch::ScratchDir;
+
+    #[test]
+    fn replaces_on_the_same_filesystem() {
+        let scratch = ScratchDir::create("test").unwrap();
+        let temp = scratch.join("out.zap.tmp");
+        let destination = scratch.join("out.zap");
+
+        fs::write(&temp, b"new").unwrap();
+        fs::write(&destination, b"old").unwrap();
+
+        replace_file(Path::new(&temp), Path::new(&destination)).unwrap();
+
+        assert_eq!(fs::read(&destination).unwrap(), b"new");
+        assert!(!Path::new(&temp).exists());
+    }
+
+    // /dev/shm is a tmpfs on most Linux systems, so a rename from it to
+    // the temporary directory crosses filesystems.
+    #[test]
+    fn copies_across_filesystems() {
+        let shm = Path::new("/dev/shm");
+        let scratch = ScratchDir::create("test").unwrap();
+
+        let crosses = fs::metadata(shm).is_ok_and(|m| m.dev() != fs::metadata(scratch.path()).unwrap().dev());
+
+        if !crosses {
+            return;
+        }
+
+        let temp = shm.join(format!("zap-test-{}.tmp", std::process::id()));
+        let destination = scratch.join("out.zap");
+
+        fs::write(&temp, b"new").unwrap();
+        fs::write(&destination, b"old").unwrap();
 
+        replace_file(&temp, Path::new(&destination)).unwrap();
 
-pub fn parse_extensions() -> (BinEncryptionType, BinCompressionType, SigningType) {
-    (BinEncryptionType::Passthrough, BinCompressionType::Passthrough, SigningType::Passthrough)
-}
\ No newline at end of file
+        assert_eq!(fs::read(&destination).unwrap(), b"new");
+        assert!(!temp.exists());
+    }
+}
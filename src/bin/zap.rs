@@ -1,9 +1,24 @@
+use std::process::ExitCode;
+
 use zap::error::ZapError;
-use crate::cli_util::Args;
+use crate::cli_util::{exit_code, Args, EXIT_DISK_FULL};
 use clap::Parser;
 
 mod cli_util;
 
-fn main() -> Result<(), anyhow::Error> {
-    Args::parse().execute()
+fn main() -> ExitCode {
+    match Args::parse().execute() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+
+            let code = exit_code(&e);
+
+            if code == EXIT_DISK_FULL {
+                eprintln!("The destination ran out of disk space, free some space or choose another output location.");
+            }
+
+            ExitCode::from(code)
+        }
+    }
 }
@@ -0,0 +1,295 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    compression::CompressionType,
+    encryption::{EncryptionSecret, EncryptionType},
+    error::{CheckpointError, EncryptionKeyError},
+    fs::FileInfo,
+    report::FileReport,
+    signing::SigningType,
+};
+
+// Entries compressed before 2 were encrypted differently, so can't be
+// resumed into an archive that records them as streams.
+const CHECKPOINT_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize)]
+struct CheckpointHeader {
+    version: u32,
+    settings: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct CheckpointEntry {
+    source: PathBuf,
+    destination: PathBuf,
+    len: u64,
+    modified: Option<SystemTime>,
+    pub(crate) written_bytes: u64,
+}
+
+/// Records each file as it is compressed, so that an interrupted run can
+/// be resumed without compressing them again. Stored as a line of JSON
+/// per file after a header, so that a run stopped mid write only loses
+/// the last line.
+pub struct Checkpoint {
+    file: Mutex<File>,
+    done: HashMap<PathBuf, CheckpointEntry>,
+}
+
+impl Checkpoint {
+    /// Starts a new checkpoint at 'path', or with 'resume' continues the
+    /// one there if any. 'settings' is from settings_digest, and must be
+    /// the same as the checkpoint's to resume it.
+    pub(crate) fn open(path: &Path, settings: &str, resume: bool) -> Result<Self, CheckpointError> {
+        let done = match resume {
+            true => Self::read(path, settings)?,
+            false => None,
+        };
+
+        let Some(done) = done else {
+            let mut file = File::create(path)?;
+            let header = CheckpointHeader { version: CHECKPOINT_VERSION, settings: settings.into() };
+
+            writeln!(file, "{}", serde_json::to_string(&header)?)?;
+
+            return Ok(Checkpoint { file: Mutex::new(file), done: HashMap::new() });
+        };
+
+        debug!("Resuming with {} file(s) already compressed", done.len());
+
+        Ok(Checkpoint {
+            file: Mutex::new(OpenOptions::new().append(true).open(path)?),
+            done,
+        })
+    }
+
+    // None when there is no checkpoint to resume.
+    fn read(path: &Path, settings: &str) -> Result<Option<HashMap<PathBuf, CheckpointEntry>>, CheckpointError> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut lines = BufReader::new(file).lines();
+
+        let header: CheckpointHeader = match lines.next() {
+            Some(line) => serde_json::from_str(&line?)?,
+            None => return Ok(None),
+        };
+
+        if header.version != CHECKPOINT_VERSION {
+            return Err(CheckpointError::UnsupportedVersion(header.version));
+        }
+
+        if header.settings != settings {
+            return Err(CheckpointError::SettingsChanged(path.to_path_buf()));
+        }
+
+        let mut done = HashMap::new();
+
+        for (n, line) in lines.enumerate() {
+            match serde_json::from_str::<CheckpointEntry>(&line?) {
+                Ok(entry) => {
+                    done.insert(entry.source.clone(), entry);
+                },
+                // Anything after it would have been written later, so
+                // this can only be the line the run stopped during.
+                Err(e) => {
+                    warn!("Ignoring the rest of the checkpoint from line {}: {}", n + 2, e);
+                    break;
+                },
+            }
+        }
+
+        Ok(Some(done))
+    }
+
+    /// The entry for 'source' when it was compressed to 'destination' and
+    /// hasn't changed since.
+    pub(crate) fn done(&self, source: &Path, scanned: &FileInfo, destination: &Path) -> Option<&CheckpointEntry> {
+        self.done
+            .get(source)
+            .filter(|e| e.destination == destination && e.len == scanned.len && e.modified == scanned.modified)
+    }
+
+    pub(crate) fn record(&self, file: &FileReport, scanned: &FileInfo) -> Result<(), CheckpointError> {
+        let entry = CheckpointEntry {
+            source: file.source.clone(),
+            destination: file.destination.clone(),
+            len: scanned.len,
+            modified: scanned.modified,
+            written_bytes: file.written_bytes,
+        };
+
+        let line = serde_json::to_string(&entry)?;
+
+        writeln!(self.file.lock().expect("Checkpoint lock poisoned"), "{}", line)?;
+
+        Ok(())
+    }
+}
+
+/// Identifies everything that changes what a file is compressed to, so
+/// that a checkpoint isn't resumed with different algorithms or secret.
+/// The secret is only stored hashed together with the rest. For a keyfile
+/// that's the key it holds, as another key may be written to the path.
+pub(crate) fn settings_digest(
+    encryption: &EncryptionType,
+    encryption_secret: &EncryptionSecret,
+    compression: &CompressionType,
+    compression_level: &flate2::Compression,
+    signing: &SigningType,
+) -> Result<String, CheckpointError> {
+    let mut hasher = Sha256::new();
+
+    hasher.update(format!("{:?} {:?} {} {:?}", encryption, compression, compression_level.level(), signing));
+
+    match encryption_secret {
+        EncryptionSecret::None => hasher.update([0]),
+        EncryptionSecret::Password(key) => {
+            hasher.update([1]);
+            hasher.update(key);
+        },
+        EncryptionSecret::Key(path) => {
+            let key = std::fs::read(path).map_err(|_| EncryptionKeyError::FailedToFindKeyfile(path.clone()))?;
+
+            hasher.update([2]);
+            hasher.update(key);
+        },
+    }
+
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::fs::FileKind;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("zap-checkpoint-{}-{}", name, std::process::id()))
+    }
+
+    fn settings(secret: &EncryptionSecret) -> String {
+        settings_digest(
+            &EncryptionType::XChaCha,
+            secret,
+            &CompressionType::Lz4,
+            &flate2::Compression::default(),
+            &SigningType::default(),
+        )
+        .unwrap()
+    }
+
+    fn scanned(len: u64) -> FileInfo {
+        FileInfo { kind: FileKind::File, len, modified: Some(SystemTime::UNIX_EPOCH), created: None }
+    }
+
+    fn report(name: &str, len: u64) -> FileReport {
+        FileReport {
+            source: PathBuf::from("in").join(name),
+            destination: PathBuf::from("out").join(name),
+            bytes: len,
+            written_bytes: len / 2,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn resumes_what_was_recorded() {
+        let path = temp_path("resumed");
+        let settings = settings(&EncryptionSecret::Password(b"password".to_vec()));
+
+        let checkpoint = Checkpoint::open(&path, &settings, false).unwrap();
+        checkpoint.record(&report("a", 10), &scanned(10)).unwrap();
+        checkpoint.record(&report("b", 20), &scanned(20)).unwrap();
+        drop(checkpoint);
+
+        let resumed = Checkpoint::open(&path, &settings, true);
+        let started_again = Checkpoint::open(&path, &settings, false);
+        std::fs::remove_file(&path).unwrap();
+
+        let resumed = resumed.unwrap();
+        let (a, b) = (report("a", 10), report("b", 20));
+
+        assert_eq!(resumed.done(&a.source, &scanned(10), &a.destination).map(|e| e.written_bytes), Some(5));
+        assert!(resumed.done(&b.source, &scanned(20), &b.destination).is_some());
+
+        // Changed since, or compressed somewhere else.
+        assert!(resumed.done(&a.source, &scanned(11), &a.destination).is_none());
+        assert!(resumed.done(&a.source, &FileInfo { modified: None, ..scanned(10) }, &a.destination).is_none());
+        assert!(resumed.done(&a.source, &scanned(10), &b.destination).is_none());
+
+        assert!(started_again.unwrap().done.is_empty());
+    }
+
+    #[test]
+    fn truncated_last_line_is_ignored() {
+        let path = temp_path("truncated");
+        let settings = settings(&EncryptionSecret::None);
+
+        let checkpoint = Checkpoint::open(&path, &settings, false).unwrap();
+        checkpoint.record(&report("a", 10), &scanned(10)).unwrap();
+        checkpoint.record(&report("b", 20), &scanned(20)).unwrap();
+        drop(checkpoint);
+
+        // As a run stopped part way through writing the last line leaves it.
+        let contents = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &contents[..contents.len() - 10]).unwrap();
+
+        let resumed = Checkpoint::open(&path, &settings, true);
+        std::fs::remove_file(&path).unwrap();
+
+        let done = resumed.unwrap().done;
+
+        assert_eq!(done.len(), 1);
+        assert!(done.contains_key(Path::new("in/a")));
+    }
+
+    #[test]
+    fn different_settings_are_not_resumed() {
+        let path = temp_path("settings");
+
+        Checkpoint::open(&path, &settings(&EncryptionSecret::Password(b"password".to_vec())), false).unwrap();
+
+        let resumed = Checkpoint::open(&path, &settings(&EncryptionSecret::Password(b"other".to_vec())), true);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(resumed, Err(CheckpointError::SettingsChanged(p)) if p == path));
+    }
+
+    #[test]
+    fn keyfiles_are_compared_by_their_key() {
+        let (first, second) = (temp_path("first.key"), temp_path("second.key"));
+        let key = |path: &Path| EncryptionSecret::Key(path.to_string_lossy().into_owned());
+
+        std::fs::write(&first, [1; 32]).unwrap();
+        std::fs::write(&second, [1; 32]).unwrap();
+        let (same, moved) = (settings(&key(&first)), settings(&key(&second)));
+
+        // Another key written over the first.
+        std::fs::write(&first, [2; 32]).unwrap();
+        let replaced = settings(&key(&first));
+
+        std::fs::remove_file(&first).unwrap();
+        std::fs::remove_file(&second).unwrap();
+
+        assert_eq!(same, moved);
+        assert_ne!(same, replaced);
+    }
+}
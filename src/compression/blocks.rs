@@ -0,0 +1,175 @@
+use std::io::{Error, Read};
+
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::{
+    encryption::{EncryptionSecret, EncryptionType},
+    signing::SigningType,
+};
+
+use super::CompressionType;
+
+// Compresses a single large file on several threads. The file is read a
+// block at a time and each block is compressed on its own, as a gzip
+// member, an lz4 frame or a snappy stream, 'threads' blocks at a time on
+// rayon's pool. Their output is read back in order, as one entry that the
+// decompressors read through, carrying on into the next member or frame
+// wherever one ends. Blocks don't refer back to each other, so the output
+// is a little larger than compressing the file in one go.
+//
+// Blocks run on the pool the files are compressed on, so a file's blocks
+// take turns with the other files rather than starting threads of their own.
+
+/// Size of the blocks a file is split into.
+pub const BLOCK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Whether 'compression' can be split into blocks, which have to be
+/// decompressed as one by its decompressor.
+pub fn splits_into_blocks(compression: &CompressionType) -> bool {
+    match compression {
+        CompressionType::Lz4 | CompressionType::Gzip | CompressionType::Snappy => true,
+        // Nothing to compress, or to spread over threads.
+        CompressionType::Passthrough | CompressionType::Auto => false,
+    }
+}
+
+/// Reads 'input' compressed with 'compression', see splits_into_blocks,
+/// to be passed through the rest of a pipeline as it is.
+pub struct BlockCompressor<'a, R> {
+    input: R,
+    compression: &'a CompressionType,
+    compression_level: flate2::Compression,
+    threads: usize,
+    output: Vec<u8>,
+    position: usize,
+    // Set once the input is used up, or after the first block of an empty
+    // input, which is compressed too so that it decompresses.
+    finished: bool,
+    started: bool,
+}
+
+impl<'a, R> BlockCompressor<'a, R>
+where
+    R: Read,
+{
+    pub fn new(input: R, compression: &'a CompressionType, compression_level: flate2::Compression, threads: usize) -> Self {
+        BlockCompressor {
+            input,
+            compression,
+            compression_level,
+            threads: std::cmp::max(threads, 1),
+            output: Vec::new(),
+            position: 0,
+            finished: false,
+            started: false,
+        }
+    }
+
+    // Reads the next 'threads' blocks and compresses them together.
+    fn fill(&mut self) -> Result<(), Error> {
+        let mut blocks: Vec<Vec<u8>> = Vec::with_capacity(self.threads);
+
+        while blocks.len() < self.threads {
+            let mut block = Vec::with_capacity(BLOCK_SIZE);
+            (&mut self.input).take(BLOCK_SIZE as u64).read_to_end(&mut block)?;
+
+            let last = block.len() < BLOCK_SIZE;
+
+            if !block.is_empty() || !self.started {
+                blocks.push(block);
+                self.started = true;
+            }
+
+            if last {
+                self.finished = true;
+                break;
+            }
+        }
+
+        let (compression, compression_level) = (self.compression, self.compression_level);
+
+        let compressed: Vec<Vec<u8>> = blocks.par_iter()
+            .map(|block| crate::compress_bytes(
+                block,
+                &EncryptionType::Passthrough,
+                &EncryptionSecret::None,
+                compression,
+                &compression_level,
+                &SigningType::Passthrough,
+            ))
+            .collect::<Result<_, _>>()
+            .map_err(Error::other)?;
+
+        self.output = compressed.concat();
+        self.position = 0;
+
+        Ok(())
+    }
+}
+
+impl<'a, R> Read for BlockCompressor<'a, R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // Blocks compressed to nothing are skipped rather than read as the end.
+        while self.position == self.output.len() && !self.finished {
+            self.fill()?;
+        }
+
+        let len = std::cmp::min(buf.len(), self.output.len() - self.position);
+        buf[..len].copy_from_slice(&self.output[self.position..self.position + len]);
+        self.position += len;
+
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(input: &[u8], compression: &CompressionType, threads: usize) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        BlockCompressor::new(input, compression, flate2::Compression::fast(), threads)
+            .read_to_end(&mut compressed)
+            .unwrap();
+
+        crate::decompress_bytes(
+            &compressed,
+            &EncryptionType::Passthrough,
+            &EncryptionSecret::None,
+            compression,
+            &SigningType::Passthrough,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn blocks_decompress_as_one() {
+        // Ends part way into a block, after several batches of them.
+        let input: Vec<u8> = (0..BLOCK_SIZE * 5 + 1234).map(|i| (i % 251) as u8).collect();
+
+        for compression in CompressionType::ALL.iter().filter(|c| splits_into_blocks(c)) {
+            for threads in [1, 2, 4] {
+                assert_eq!(round_trip(&input, compression, threads), input, "{:?} on {} threads", compression, threads);
+            }
+        }
+    }
+
+    #[test]
+    fn ends_on_a_block() {
+        let input = vec![3; BLOCK_SIZE * 2];
+
+        for compression in CompressionType::ALL.iter().filter(|c| splits_into_blocks(c)) {
+            assert_eq!(round_trip(&input, compression, 2), input, "{:?}", compression);
+        }
+    }
+
+    #[test]
+    fn empty_input_decompresses() {
+        for compression in CompressionType::ALL.iter().filter(|c| splits_into_blocks(c)) {
+            assert!(round_trip(&[], compression, 2).is_empty(), "{:?}", compression);
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use std::{
+    io::{Read, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use crate::{
+    encryption::{DecryptionModule, EncryptionModule, EncryptionType},
+    error::CompressorInitError,
+};
+
+use super::{Compress, CompressionAlgorithm, CompressionType, Decompress, DecompressionAlgorithm};
+
+/// Bytes counted by a CountingAlgorithm, shared with every compressor
+/// and decompressor it creates so that they can still be read once
+/// those have been finalised.
+#[derive(Debug, Clone, Default)]
+pub struct ByteCounts {
+    uncompressed: Arc<AtomicU64>,
+    compressed: Arc<AtomicU64>,
+}
+
+impl ByteCounts {
+    /// Bytes written to a compressor, or read from a decompressor.
+    pub fn uncompressed(&self) -> u64 {
+        self.uncompressed.load(Ordering::Relaxed)
+    }
+
+    /// Bytes written by a compressor, or read by a decompressor.
+    pub fn compressed(&self) -> u64 {
+        self.compressed.load(Ordering::Relaxed)
+    }
+
+    /// Compressed size over uncompressed size, None before anything is counted.
+    pub fn ratio(&self) -> Option<f64> {
+        match self.uncompressed() {
+            0 => None,
+            uncompressed => Some(self.compressed() as f64 / uncompressed as f64),
+        }
+    }
+}
+
+/// Wraps another algorithm, counting the bytes on either side of it as
+/// they pass through, so that ratios don't need a second pass.
+pub struct CountingAlgorithm<A> {
+    inner: A,
+    counts: ByteCounts,
+}
+
+impl<A> CountingAlgorithm<A> {
+    pub fn new(inner: A) -> Self {
+        CountingAlgorithm {
+            inner,
+            counts: ByteCounts::default(),
+        }
+    }
+
+    pub fn counts(&self) -> ByteCounts {
+        self.counts.clone()
+    }
+}
+
+impl<A, T> CompressionAlgorithm<T> for CountingAlgorithm<A>
+where
+    T: EncryptionModule,
+    A: CompressionAlgorithm<CountingWriter<T>>,
+{
+    type Compressor = CountingCompressor<A::Compressor>;
+
+    fn compressor(&self, io: T) -> Result<Self::Compressor, CompressorInitError> {
+        let io = CountingWriter {
+            inner: io,
+            count: self.counts.compressed.clone(),
+        };
+
+        Ok(CountingCompressor {
+            inner: self.inner.compressor(io)?,
+            count: self.counts.uncompressed.clone(),
+        })
+    }
+}
+
+impl<A, T> DecompressionAlgorithm<T> for CountingAlgorithm<A>
+where
+    T: DecryptionModule,
+    A: DecompressionAlgorithm<CountingReader<T>>,
+{
+    type Decompressor = CountingDecompressor<A::Decompressor>;
+
+    fn decompressor(&self, io: T) -> Result<Self::Decompressor, CompressorInitError> {
+        let io = CountingReader {
+            inner: io,
+            count: self.counts.compressed.clone(),
+        };
+
+        Ok(CountingDecompressor {
+            inner: self.inner.decompressor(io)?,
+            count: self.counts.uncompressed.clone(),
+        })
+    }
+}
+
+pub struct CountingCompressor<C> {
+    inner: C,
+    count: Arc<AtomicU64>,
+}
+
+impl<C: Compress> Compress for CountingCompressor<C> {
+    fn algorithm(&self) -> CompressionType {
+        self.inner.algorithm()
+    }
+
+    fn finalise(self) -> Result<(), std::io::Error> {
+        self.inner.finalise()
+    }
+}
+
+impl<C: Compress> Write for CountingCompressor<C> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.count.fetch_add(len as u64, Ordering::Relaxed);
+
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub struct CountingDecompressor<D> {
+    inner: D,
+    count: Arc<AtomicU64>,
+}
+
+impl<D: Decompress> Decompress for CountingDecompressor<D> {
+    fn algorithm(&self) -> CompressionType {
+        self.inner.algorithm()
+    }
+
+    fn finalise(self) -> Result<(), std::io::Error> {
+        self.inner.finalise()
+    }
+}
+
+impl<D: Decompress> Read for CountingDecompressor<D> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.count.fetch_add(len as u64, Ordering::Relaxed);
+
+        Ok(len)
+    }
+}
+
+/// Sits between the wrapped compressor and the encryptor it writes to.
+pub struct CountingWriter<T> {
+    inner: T,
+    count: Arc<AtomicU64>,
+}
+
+impl<T: EncryptionModule> EncryptionModule for CountingWriter<T> {
+    fn algorithm(&self) -> EncryptionType {
+        self.inner.algorithm()
+    }
+
+    fn finalise(self) -> Result<(), std::io::Error> {
+        self.inner.finalise()
+    }
+}
+
+impl<T: EncryptionModule> Write for CountingWriter<T> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.count.fetch_add(len as u64, Ordering::Relaxed);
+
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Sits between the wrapped decompressor and the decryptor it reads from.
+pub struct CountingReader<T> {
+    inner: T,
+    count: Arc<AtomicU64>,
+}
+
+impl<T: DecryptionModule> DecryptionModule for CountingReader<T> {
+    fn algorithm(&self) -> EncryptionType {
+        self.inner.algorithm()
+    }
+
+    fn finalise(self) -> Result<(), std::io::Error> {
+        self.inner.finalise()
+    }
+}
+
+impl<T: DecryptionModule> Read for CountingReader<T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.count.fetch_add(len as u64, Ordering::Relaxed);
+
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        compression::lz4::Lz4Algorithm,
+        encryption::passthrough::{DecryptorPassthrough, EncryptorPassthrough},
+    };
+
+    #[test]
+    fn counts_match_both_sides() {
+        let input: Vec<u8> = b"counted on either side\n".repeat(1000);
+
+        let compressing = CountingAlgorithm::new(Lz4Algorithm::new());
+        let mut output = Vec::new();
+
+        let mut compressor = compressing.compressor(EncryptorPassthrough::new(&mut output)).unwrap();
+        compressor.write_all(&input).unwrap();
+        compressor.finalise().unwrap();
+
+        let counts = compressing.counts();
+        assert_eq!(counts.uncompressed(), input.len() as u64);
+        assert_eq!(counts.compressed(), output.len() as u64);
+        assert_eq!(counts.ratio(), Some(output.len() as f64 / input.len() as f64));
+
+        let decompressing = CountingAlgorithm::new(Lz4Algorithm::new());
+        let mut restored = Vec::new();
+
+        let mut decompressor = decompressing.decompressor(DecryptorPassthrough::new(&output[..])).unwrap();
+        decompressor.read_to_end(&mut restored).unwrap();
+        decompressor.finalise().unwrap();
+
+        assert_eq!(restored, input);
+
+        let counts = decompressing.counts();
+        assert_eq!(counts.uncompressed(), input.len() as u64);
+        assert_eq!(counts.compressed(), output.len() as u64);
+    }
+
+    #[test]
+    fn nothing_counted_has_no_ratio() {
+        assert_eq!(CountingAlgorithm::new(Lz4Algorithm::new()).counts().ratio(), None);
+    }
+}
@@ -1,4 +1,4 @@
-use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
 use std::io::{Read, Write};
 
 use crate::{
@@ -6,7 +6,7 @@ use crate::{
     error::CompressorInitError,
 };
 
-use super::{Compress, CompressionAlgorithm, Decompress, DecompressionAlgorithm};
+use super::{Compress, CompressionAlgorithm, Decompress, DecompressionAlgorithm, CompressionType};
 
 pub struct GzipAlgorithm {
     level: Compression,
@@ -76,13 +76,14 @@ impl<T> Compress for GzipCompressor<T>
 where
     T: EncryptionModule,
 {
+    fn algorithm(&self) -> CompressionType {
+        CompressionType::Gzip
+    }
+
     fn finalise(self) -> Result<(), std::io::Error> {
         match self.encoder.finish() {
             Ok(w) => w.finalise(),
-            Err(e) => Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("Encryption failed: {}", e), // TODO: better error handling
-            )),
+            Err(e) => Err(e),
         }
     }
 }
@@ -106,7 +107,8 @@ pub struct GzipDeompressor<T>
 where
     T: DecryptionModule,
 {
-    decoder: GzDecoder<T>,
+    // Files compressed in blocks are a member per block, see blocks.
+    decoder: MultiGzDecoder<T>,
 }
 
 impl<T> GzipDeompressor<T>
@@ -115,7 +117,7 @@ where
 {
     pub fn new(io: T) -> Self {
         GzipDeompressor {
-            decoder: GzDecoder::new(io),
+            decoder: MultiGzDecoder::new(io),
         }
     }
 }
@@ -124,6 +126,10 @@ impl<T> Decompress for GzipDeompressor<T>
 where
     T: DecryptionModule,
 {
+    fn algorithm(&self) -> CompressionType {
+        CompressionType::Gzip
+    }
+
     fn finalise(self) -> Result<(), std::io::Error> {
         self.decoder.into_inner().finalise()
     }
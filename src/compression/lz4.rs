@@ -6,7 +6,7 @@ use crate::{
     error::CompressorInitError,
 };
 
-use super::{Compress, CompressionAlgorithm, Decompress, DecompressionAlgorithm};
+use super::{Compress, CompressionAlgorithm, Decompress, DecompressionAlgorithm, CompressionType};
 
 pub struct Lz4Algorithm {}
 
@@ -66,9 +66,15 @@ impl<T> Compress for Lz4Compressor<T>
 where
     T: EncryptionModule,
 {
+    fn algorithm(&self) -> CompressionType {
+        CompressionType::Lz4
+    }
+
     fn finalise(self) -> Result<(), std::io::Error> {
         match self.encoder.finish() {
             Ok(w) => w.finalise(),
+            // Keep the original error so that its kind, eg: StorageFull, isn't lost.
+            Err(lz4_flex::frame::Error::IoError(e)) => Err(e),
             Err(e) => Err(std::io::Error::new(
                 std::io::ErrorKind::Other,
                 format!("Encryption failed: {}", e), // TODO: better error handling
@@ -114,6 +120,10 @@ impl<T> Decompress for Lz4Deompressor<T>
 where
     T: DecryptionModule,
 {
+    fn algorithm(&self) -> CompressionType {
+        CompressionType::Lz4
+    }
+
     fn finalise(self) -> Result<(), std::io::Error> {
         self.decoder.into_inner().finalise()
     }
@@ -124,6 +134,13 @@ where
     T: DecryptionModule,
 {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        self.decoder.read(buf)
+        // Files compressed in blocks are a frame per block, see blocks. The
+        // decoder may stop at the end of a frame, reading on into the next
+        // only when read again, so an empty read is tried once more before
+        // it's taken as the end. Blocks are never empty frames.
+        match self.decoder.read(buf)? {
+            0 if !buf.is_empty() => self.decoder.read(buf),
+            read => Ok(read),
+        }
     }
 }
@@ -1,3 +1,5 @@
+pub mod blocks;
+pub mod counting;
 pub mod gzip;
 pub mod lz4;
 pub mod passthrough;
@@ -6,15 +8,23 @@ pub mod snappy;
 use crate::error::CompressorInitError;
 
 // External
+use serde::{Deserialize, Serialize};
 use std::io::{Error, Read, Write};
 
 pub struct CompressionMode;
 pub struct DecompressionMode;
 
 pub trait Compress: Write {
+    /// The algorithm this compressor implements.
+    fn algorithm(&self) -> CompressionType;
+    /// Writes out anything still buffered, eg: the last frame, into the
+    /// encryptor and then finalises it. Dropping a compressor instead
+    /// silently loses whatever it buffered.
     fn finalise(self) -> Result<(), Error>;
 }
 pub trait Decompress: Read {
+    /// The algorithm this decompressor implements.
+    fn algorithm(&self) -> CompressionType;
     fn finalise(self) -> Result<(), Error>;
 }
 
@@ -36,13 +46,91 @@ where
     fn decompressor(&self, reader: T) -> Result<Self::Decompressor, CompressorInitError>;
 }
 
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum CompressionType {
     #[default]
     Passthrough,
     Lz4,
     Gzip,
     Snappy,
+    /// Only valid when decompressing, the algorithm is taken from the
+    /// archive manifest, or from each entry's extension when the archive
+    /// has no manifest.
+    Auto,
+}
+
+/// The levels accepted by an algorithm, inclusive, and the level used
+/// when none is asked for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LevelRange {
+    pub min: u32,
+    pub max: u32,
+    pub default: u32,
+}
+
+impl LevelRange {
+    pub fn contains(&self, level: u32) -> bool {
+        (self.min..=self.max).contains(&level)
+    }
+}
+
+impl CompressionType {
+    /// Every algorithm, which a new one has to be added to as well.
+    pub const ALL: [CompressionType; 5] = [
+        CompressionType::Passthrough,
+        CompressionType::Lz4,
+        CompressionType::Gzip,
+        CompressionType::Snappy,
+        CompressionType::Auto,
+    ];
+
+    /// The algorithm 'ext' is the extension of, see extension.
+    pub fn from_extension(ext: &str) -> Option<CompressionType> {
+        CompressionType::ALL.into_iter().find(|c| c.extension() == Some(ext))
+    }
+
+    /// Part of an archive's name identifying the algorithm, without the dot.
+    pub fn extension(&self) -> Option<&'static str> {
+        match self {
+            CompressionType::Passthrough | CompressionType::Auto => None,
+            CompressionType::Lz4 => Some("lz4"),
+            CompressionType::Gzip => Some("gz"),
+            CompressionType::Snappy => Some("sz"),
+        }
+    }
+
+    /// None for algorithms that don't take a level, which ignore it.
+    pub fn level_range(&self) -> Option<LevelRange> {
+        match self {
+            // The same default as gzip itself, a balance of size and speed.
+            CompressionType::Gzip => Some(LevelRange {
+                min: flate2::Compression::none().level(),
+                max: flate2::Compression::best().level(),
+                default: 6,
+            }),
+            CompressionType::Passthrough
+            | CompressionType::Lz4
+            | CompressionType::Snappy
+            | CompressionType::Auto => None,
+        }
+    }
+
+    /// Whether an entry compressed with this can be decoded from the start
+    /// of any of its blocks, without decoding everything before it. Every
+    /// entry is compressed on its own, so any entry can always be extracted
+    /// without the others, this is only about reading part of one.
+    pub fn is_streaming_seekable(&self) -> bool {
+        match self {
+            // Frames of independent blocks, lz4_flex's default block mode.
+            CompressionType::Passthrough
+            | CompressionType::Lz4
+            | CompressionType::Snappy => true,
+            // A single deflate stream, whose blocks refer back to earlier ones.
+            CompressionType::Gzip => false,
+            CompressionType::Auto => false,
+        }
+    }
 }
 
 impl From<String> for CompressionType {
@@ -52,7 +140,43 @@ impl From<String> for CompressionType {
             "lz4" => Self::Lz4,
             "gzip" => Self::Gzip,
             "snappy" => Self::Snappy,
+            "auto" => Self::Auto,
             _ => Self::Passthrough,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Stops compiling when a variant is added, until it is given a place
+    // here and in ALL.
+    fn position(compression: &CompressionType) -> usize {
+        match compression {
+            CompressionType::Passthrough => 0,
+            CompressionType::Lz4 => 1,
+            CompressionType::Gzip => 2,
+            CompressionType::Snappy => 3,
+            CompressionType::Auto => 4,
+        }
+    }
+
+    #[test]
+    fn all_holds_every_variant_once() {
+        for (i, compression) in CompressionType::ALL.iter().enumerate() {
+            assert_eq!(position(compression), i, "{:?} is out of place in ALL", compression);
+        }
+    }
+
+    #[test]
+    fn extensions_round_trip() {
+        for compression in CompressionType::ALL {
+            if let Some(ext) = compression.extension() {
+                assert_eq!(CompressionType::from_extension(ext), Some(compression));
+            }
+        }
+
+        assert_eq!(CompressionType::from_extension("zap"), None);
+    }
+}
@@ -5,7 +5,7 @@ use crate::{
     error::CompressorInitError,
 };
 
-use super::{Compress, CompressionAlgorithm, Decompress, DecompressionAlgorithm};
+use super::{Compress, CompressionAlgorithm, Decompress, DecompressionAlgorithm, CompressionType};
 
 pub struct PassthroughAlgorithm {}
 
@@ -65,21 +65,32 @@ impl<T> Compress for PassthroughCompressor<T>
 where
     T: EncryptionModule,
 {
+    fn algorithm(&self) -> CompressionType {
+        CompressionType::Passthrough
+    }
+
     fn finalise(self) -> Result<(), std::io::Error> {
         self.inner.finalise()
     }
 }
 
+// Calls are passed straight to the encryptor, inlined so that encrypting
+// without compressing costs nothing over encrypting alone.
 impl<T> Write for PassthroughCompressor<T>
 where
     T: EncryptionModule,
 {
+    #[inline]
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        let len = self.inner.write(buf)?;
+        self.inner.write(buf)
+    }
 
-        Ok(len)
+    #[inline]
+    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        self.inner.write_all(buf)
     }
 
+    #[inline]
     fn flush(&mut self) -> std::io::Result<()> {
         self.inner.flush()
     }
@@ -107,6 +118,10 @@ impl<T> Decompress for PassthroughDeompressor<T>
 where
     T: DecryptionModule,
 {
+    fn algorithm(&self) -> CompressionType {
+        CompressionType::Passthrough
+    }
+
     fn finalise(self) -> Result<(), std::io::Error> {
         self.inner.finalise()
     }
@@ -116,6 +131,7 @@ impl<T> Read for PassthroughDeompressor<T>
 where
     T: DecryptionModule,
 {
+    #[inline]
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         self.inner.read(buf)
     }
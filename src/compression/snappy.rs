@@ -7,7 +7,7 @@ use crate::{
     error::CompressorInitError,
 };
 
-use super::{Compress, CompressionAlgorithm, Decompress, DecompressionAlgorithm};
+use super::{Compress, CompressionAlgorithm, Decompress, DecompressionAlgorithm, CompressionType};
 
 pub struct SnappyAlgorithm {}
 
@@ -67,11 +67,15 @@ impl<T> Compress for SnappyCompressor<T>
 where
     T: EncryptionModule,
 {
+    fn algorithm(&self) -> CompressionType {
+        CompressionType::Snappy
+    }
+
     fn finalise(self) -> Result<(), std::io::Error> {
         match self.encoder.into_inner() {
             Ok(w) => w.finalise(),
             Err(e) => Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
+                e.error().kind(),
                 format!("Encryption failed: {}", e), // TODO: better error handling
             )),
         }
@@ -115,6 +119,10 @@ impl<T> Decompress for SnappyDeompressor<T>
 where
     T: DecryptionModule,
 {
+    fn algorithm(&self) -> CompressionType {
+        CompressionType::Snappy
+    }
+
     fn finalise(self) -> Result<(), std::io::Error> {
         self.decoder.into_inner().finalise()
     }
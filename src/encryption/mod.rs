@@ -1,26 +1,51 @@
 pub mod aes_gcm_256;
 pub mod chachapoly;
 pub mod passthrough;
+pub mod secret;
+pub(crate) mod stream;
 pub mod xchachapoly;
 
 //Internal
-use crate::error::EncryptorInitError;
+use crate::error::{EncryptionKeyError, EncryptorInitError};
+
+use self::{
+    aes_gcm_256::AesGcmAlgorithm, chachapoly::ChaChaPolyAlgorithm, passthrough::EncryptionPassthrough,
+    xchachapoly::XChaChaPolyAlgorithm,
+};
 
 // External
 
-use std::io::{Error, Read, Write};
+use serde::{Deserialize, Serialize};
+use std::io::{Error, ErrorKind, Read, Write};
 
 pub struct EncryptorMode;
 pub struct DecryptorMode;
 
 pub trait EncryptionModule: Write {
+    /// The algorithm this encryptor implements.
+    fn algorithm(&self) -> EncryptionType;
+    /// Encrypts and writes the last, possibly partial, block and flushes
+    /// the writer beneath. flush alone leaves a partial block buffered,
+    /// as writing it early would change where blocks are split.
     fn finalise(self) -> Result<(), Error>;
 }
 pub trait DecryptionModule: Read {
+    /// The algorithm this decryptor implements.
+    fn algorithm(&self) -> EncryptionType;
     fn finalise(self) -> Result<(), Error>;
 }
 
-pub trait EncryptionAlgorithm<T>
+/// Sizes in bytes of what an algorithm is keyed and sealed with, all 0
+/// for passthrough.
+pub trait CipherSizes {
+    const KEY_SIZE: usize;
+    /// Of the cipher's nonce, which streams number rather than store.
+    const NONCE_SIZE: usize;
+    /// Added to every chunk of an entry.
+    const TAG_SIZE: usize;
+}
+
+pub trait EncryptionAlgorithm<T>: CipherSizes
 where
     T: Write,
 {
@@ -29,7 +54,7 @@ where
     fn encryptor(&self, writer: T) -> Result<Self::Encryptor, EncryptorInitError>;
 }
 
-pub trait DecryptionAlgorithm<T>
+pub trait DecryptionAlgorithm<T>: CipherSizes
 where
     T: Read,
 {
@@ -38,6 +63,27 @@ where
     fn decryptor(&self, reader: T) -> Result<Self::Decryptor, EncryptorInitError>;
 }
 
+// Reads from 'io' until 'buf' is full or the reader is exhausted.
+// A single read is not guaranteed to return a whole block, so the
+// block based decryptors use this to avoid splitting blocks apart.
+pub(crate) fn read_block<T>(io: &mut T, buf: &mut [u8]) -> Result<usize, Error>
+where
+    T: Read,
+{
+    let mut filled = 0;
+
+    while filled < buf.len() {
+        match io.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(filled)
+}
+
 #[derive(Default, Clone)]
 pub enum EncryptionSecret {
     #[default]
@@ -46,7 +92,48 @@ pub enum EncryptionSecret {
     Key(String),
 }
 
-#[derive(Default, Clone, Debug)]
+impl EncryptionSecret {
+    /// What 'encryption' is keyed with, None without a secret. Keyfiles
+    /// hold the raw key, so one of any length but the algorithm's key size
+    /// is the wrong file or a key for another algorithm.
+    pub fn key(&self, encryption: &EncryptionType) -> Result<Option<Vec<u8>>, EncryptionKeyError> {
+        match self {
+            EncryptionSecret::None => Ok(None),
+            EncryptionSecret::Password(p) => Ok(Some(p.clone())),
+            EncryptionSecret::Key(path) => {
+                let key = std::fs::read(path).map_err(|_| EncryptionKeyError::FailedToFindKeyfile(path.clone()))?;
+
+                match key.len() == encryption.key_size() {
+                    true => Ok(Some(key)),
+                    false => Err(EncryptionKeyError::WrongLength(path.clone(), encryption.key_size(), key.len() as u64)),
+                }
+            },
+        }
+    }
+}
+
+/// The kind of secret an archive was encrypted with, without the secret.
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SecretType {
+    #[default]
+    None,
+    Password,
+    Key,
+}
+
+impl From<&EncryptionSecret> for SecretType {
+    fn from(secret: &EncryptionSecret) -> Self {
+        match secret {
+            EncryptionSecret::None => SecretType::None,
+            EncryptionSecret::Password(_) => SecretType::Password,
+            EncryptionSecret::Key(_) => SecretType::Key,
+        }
+    }
+}
+
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum EncryptionType {
     #[default]
     Passthrough,
@@ -55,6 +142,54 @@ pub enum EncryptionType {
     ChaCha,
 }
 
+/// How an entry encrypted with one of the AEAD algorithms is laid out.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EncryptionFraming {
+    /// Chunks sealed in order with a key derived for the entry, so that
+    /// a truncated or reordered entry fails to decrypt.
+    #[default]
+    Stream,
+    /// Blocks sealed with a random nonce each, as archives were
+    /// encrypted before Stream. Only read, never written.
+    Blocks,
+}
+
+impl EncryptionType {
+    /// Every algorithm, which a new one has to be added to as well.
+    pub const ALL: [EncryptionType; 4] = [
+        EncryptionType::Passthrough,
+        EncryptionType::XChaCha,
+        EncryptionType::AesGcm,
+        EncryptionType::ChaCha,
+    ];
+
+    /// The algorithm 'ext' is the extension of, see extension.
+    pub fn from_extension(ext: &str) -> Option<EncryptionType> {
+        EncryptionType::ALL.into_iter().find(|e| e.extension() == Some(ext))
+    }
+
+    /// Part of an archive's name identifying the algorithm, without the dot.
+    pub fn extension(&self) -> Option<&'static str> {
+        match self {
+            EncryptionType::Passthrough => None,
+            EncryptionType::XChaCha => Some("xcha"),
+            EncryptionType::AesGcm => Some("aes"),
+            EncryptionType::ChaCha => Some("cha"),
+        }
+    }
+
+    /// Length of the key the algorithm takes, which keyfiles must be.
+    pub fn key_size(&self) -> usize {
+        match self {
+            EncryptionType::Passthrough => EncryptionPassthrough::KEY_SIZE,
+            EncryptionType::XChaCha => XChaChaPolyAlgorithm::<()>::KEY_SIZE,
+            EncryptionType::AesGcm => AesGcmAlgorithm::<(), ()>::KEY_SIZE,
+            EncryptionType::ChaCha => ChaChaPolyAlgorithm::<()>::KEY_SIZE,
+        }
+    }
+}
+
 impl From<String> for EncryptionType {
     fn from(s: String) -> Self {
         match s.as_str() {
@@ -65,4 +200,87 @@ impl From<String> for EncryptionType {
             _ => Self::Passthrough,
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Stops compiling when a variant is added, until it is given a place
+    // here and in ALL.
+    fn position(encryption: &EncryptionType) -> usize {
+        match encryption {
+            EncryptionType::Passthrough => 0,
+            EncryptionType::XChaCha => 1,
+            EncryptionType::AesGcm => 2,
+            EncryptionType::ChaCha => 3,
+        }
+    }
+
+    #[test]
+    fn all_holds_every_variant_once() {
+        for (i, encryption) in EncryptionType::ALL.iter().enumerate() {
+            assert_eq!(position(encryption), i, "{:?} is out of place in ALL", encryption);
+        }
+    }
+
+    #[test]
+    fn extensions_round_trip() {
+        for encryption in EncryptionType::ALL {
+            if let Some(ext) = encryption.extension() {
+                assert_eq!(EncryptionType::from_extension(ext), Some(encryption));
+            }
+        }
+
+        assert_eq!(EncryptionType::from_extension("zap"), None);
+    }
+
+    #[test]
+    fn algorithms_report_their_sizes() {
+        assert_eq!(
+            (XChaChaPolyAlgorithm::<()>::KEY_SIZE, XChaChaPolyAlgorithm::<()>::NONCE_SIZE, XChaChaPolyAlgorithm::<()>::TAG_SIZE),
+            (32, 24, 16),
+        );
+        assert_eq!(
+            (ChaChaPolyAlgorithm::<()>::KEY_SIZE, ChaChaPolyAlgorithm::<()>::NONCE_SIZE, ChaChaPolyAlgorithm::<()>::TAG_SIZE),
+            (32, 12, 16),
+        );
+        assert_eq!(
+            (AesGcmAlgorithm::<(), ()>::KEY_SIZE, AesGcmAlgorithm::<(), ()>::NONCE_SIZE, AesGcmAlgorithm::<(), ()>::TAG_SIZE),
+            (32, 12, 16),
+        );
+        assert_eq!(
+            (EncryptionPassthrough::KEY_SIZE, EncryptionPassthrough::NONCE_SIZE, EncryptionPassthrough::TAG_SIZE),
+            (0, 0, 0),
+        );
+    }
+
+    fn keyfile(name: &str, len: usize) -> String {
+        let path = std::env::temp_dir().join(format!("zap-test-{}-{}.key", name, std::process::id()));
+        std::fs::write(&path, vec![9; len]).unwrap();
+
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn keyfiles_must_be_the_key_size() {
+        let path = keyfile("short", 31);
+        let key = EncryptionSecret::Key(path.clone()).key(&EncryptionType::XChaCha);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(key, Err(EncryptionKeyError::WrongLength(_, 32, 31))));
+
+        let path = keyfile("exact", 32);
+        let key = EncryptionSecret::Key(path.clone()).key(&EncryptionType::AesGcm);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(key.unwrap(), Some(vec![9; 32]));
+    }
+
+    #[test]
+    fn missing_keyfile_is_reported() {
+        let key = EncryptionSecret::Key("/nonexistent/zap.key".into()).key(&EncryptionType::ChaCha);
+
+        assert!(matches!(key, Err(EncryptionKeyError::FailedToFindKeyfile(_))));
+    }
+}
@@ -5,7 +5,7 @@ use std::io::{
     Error
 };
 
-use super::{EncryptionModule, DecryptionModule, EncryptionAlgorithm, DecryptionAlgorithm};
+use super::{CipherSizes, EncryptionModule, DecryptionModule, EncryptionAlgorithm, DecryptionAlgorithm, EncryptionType};
 
 pub struct EncryptionPassthrough {
 
@@ -25,6 +25,12 @@ impl Default for EncryptionPassthrough {
     }
 }
 
+impl CipherSizes for EncryptionPassthrough {
+    const KEY_SIZE: usize = 0;
+    const NONCE_SIZE: usize = 0;
+    const TAG_SIZE: usize = 0;
+}
+
 pub struct EncryptorPassthrough<T> {
     inner: T
 }
@@ -50,6 +56,10 @@ where T: Write
 impl <T> EncryptionModule for EncryptorPassthrough<T>
 where T: Write
 {
+    fn algorithm(&self) -> EncryptionType {
+        EncryptionType::Passthrough
+    }
+
     fn finalise(mut self) -> Result<(), Error> {
         self.flush()
     }
@@ -93,6 +103,10 @@ where T: Read
 impl <T> DecryptionModule for DecryptorPassthrough<T>
 where T: Read
 {
+    fn algorithm(&self) -> EncryptionType {
+        EncryptionType::Passthrough
+    }
+
     fn finalise(self) -> Result<(), Error> {
         Ok(())
     }
@@ -0,0 +1,110 @@
+use crate::error::{EncryptionKeyError, SecretError};
+
+use super::{EncryptionSecret, EncryptionType, SecretType};
+
+/// Where the secret for an archive comes from, eg: a prompt, a file or a
+/// secret manager, so that acquiring it is kept apart from the pipeline.
+pub trait SecretProvider {
+    /// The kind of secret 'resolve' gives, known without resolving it so
+    /// that it can be checked against how an archive was encrypted first.
+    fn secret_type(&self) -> SecretType;
+    fn resolve(&self) -> Result<EncryptionSecret, SecretError>;
+}
+
+/// Resolves the secret for 'encryption', without asking the provider
+/// for one when the archive isn't encrypted.
+pub fn resolve_secret(encryption: &EncryptionType, provider: &dyn SecretProvider) -> Result<EncryptionSecret, SecretError> {
+    match encryption {
+        EncryptionType::Passthrough => Ok(EncryptionSecret::None),
+        _ => match provider.resolve()? {
+            EncryptionSecret::Key(path) => {
+                check_key_length(encryption, &path)?;
+                Ok(EncryptionSecret::Key(path))
+            },
+            secret => Ok(secret),
+        },
+    }
+}
+
+// Keyfiles hold the raw key, so a file of any other length is the wrong
+// file or a key for another algorithm.
+fn check_key_length(encryption: &EncryptionType, path: &str) -> Result<(), EncryptionKeyError> {
+    let len = std::fs::metadata(path)
+        .map_err(|_| EncryptionKeyError::FailedToFindKeyfile(path.into()))?
+        .len();
+
+    match len == encryption.key_size() as u64 {
+        true => Ok(()),
+        false => Err(EncryptionKeyError::WrongLength(path.into(), encryption.key_size(), len)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    // Gives 'secret', or fails as a sealed secret manager would without one.
+    struct MockProvider {
+        secret: Option<EncryptionSecret>,
+        calls: Cell<usize>,
+    }
+
+    impl MockProvider {
+        fn new(secret: Option<EncryptionSecret>) -> Self {
+            MockProvider { secret, calls: Cell::new(0) }
+        }
+    }
+
+    impl SecretProvider for MockProvider {
+        fn secret_type(&self) -> SecretType {
+            match &self.secret {
+                Some(EncryptionSecret::Password(_)) => SecretType::Password,
+                Some(EncryptionSecret::Key(_)) => SecretType::Key,
+                Some(EncryptionSecret::None) | None => SecretType::None,
+            }
+        }
+
+        fn resolve(&self) -> Result<EncryptionSecret, SecretError> {
+            self.calls.set(self.calls.get() + 1);
+
+            self.secret.clone().ok_or_else(|| SecretError::ProviderFailed("vault is sealed".into()))
+        }
+    }
+
+    #[test]
+    fn unencrypted_archives_never_ask() {
+        let provider = MockProvider::new(None);
+
+        assert!(matches!(resolve_secret(&EncryptionType::Passthrough, &provider), Ok(EncryptionSecret::None)));
+        assert_eq!(provider.calls.get(), 0);
+    }
+
+    #[test]
+    fn secrets_come_from_the_provider() {
+        let provider = MockProvider::new(Some(EncryptionSecret::Password(b"hunter2".to_vec())));
+
+        match resolve_secret(&EncryptionType::XChaCha, &provider) {
+            Ok(EncryptionSecret::Password(pass)) => assert_eq!(pass, b"hunter2"),
+            _ => panic!("expected the provider's password"),
+        }
+        assert_eq!(provider.calls.get(), 1);
+
+        let failing = MockProvider::new(None);
+        assert!(matches!(resolve_secret(&EncryptionType::XChaCha, &failing), Err(SecretError::ProviderFailed(_))));
+    }
+
+    #[test]
+    fn provided_keyfiles_are_checked() {
+        let path = std::env::temp_dir().join(format!("zap-secret-short-key-{}", std::process::id()));
+        std::fs::write(&path, [0; 8]).unwrap();
+
+        let provider = MockProvider::new(Some(EncryptionSecret::Key(path.to_string_lossy().into_owned())));
+        let result = resolve_secret(&EncryptionType::XChaCha, &provider);
+
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(result, Err(SecretError::EncryptionKeyError(EncryptionKeyError::WrongLength(_, 32, 8)))));
+    }
+}
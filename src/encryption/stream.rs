@@ -0,0 +1,552 @@
+use std::io::{Error, ErrorKind, Read, Write};
+
+// External
+use aes_gcm::{
+    aead::{generic_array::typenum::Unsigned, rand_core::RngCore, Aead, AeadCore, KeyInit, Nonce, OsRng},
+    Aes256Gcm,
+};
+use chacha20poly1305::{ChaCha20Poly1305, XChaCha20Poly1305};
+
+use crate::error::{DecryptError, EncryptorInitError};
+
+use super::{read_block, EncryptionFraming, EncryptionType};
+
+// Entries are encrypted as a STREAM (Hoang, Reyhanitabar, Rogaway and
+// Vizár), as in the age format:
+//
+//   [salt: 16][chunk 0]...[chunk n][last chunk]
+//
+// Each chunk is CHUNK_SIZE bytes of plaintext sealed with a tag, the last
+// is shorter and may be empty. A key is derived from the secret and a random
+// salt for every entry, so the nonce of each chunk can be its position:
+//
+//   [0 ...][counter: u32 BE][last: u8]
+//
+// The last chunk is sealed with the last byte set, so that a stream cut
+// short on a chunk boundary, or with chunks dropped or moved, fails to
+// decrypt rather than pass as complete. Chunks can be opened on their own
+// given their position, without decrypting anything before them.
+pub(crate) const CHUNK_SIZE: usize = 8192;
+const SALT_SIZE: usize = 16;
+const KEY_CONTEXT: &str = "zap 2024-01-01 entry stream key";
+
+/// Encrypts or decrypts an entry a chunk at a time, shared by the AEAD
+/// algorithms which only differ in their cipher.
+pub(crate) struct Chunked<A, T> {
+    // Kept to derive the stream's key from once the salt is read, then
+    // replaced by it to check other algorithms against if the first fails.
+    key: Vec<u8>,
+    algorithm: EncryptionType,
+    // The legacy cipher when reading blocks, or the entry's once derived.
+    cipher: Option<A>,
+    framing: EncryptionFraming,
+    counter: u32,
+    finished: bool,
+    // Plaintext waiting to be sealed, or opened but not yet read.
+    buffer: Vec<u8>,
+    // Bytes to write before the first chunk, or read past the last chunk.
+    pending: Vec<u8>,
+    io: T,
+}
+
+impl<A, T> Chunked<A, T>
+where
+    A: Aead + KeyInit,
+{
+    const NONCE_SIZE: usize = <A as AeadCore>::NonceSize::USIZE;
+    const TAG_SIZE: usize = <A as AeadCore>::TagSize::USIZE;
+
+    /// 'name' identifies the algorithm in errors.
+    pub(crate) fn writer(name: &str, key: &[u8], io: T) -> Result<Self, EncryptorInitError> {
+        new_cipher::<A>(name, key)?;
+
+        let mut salt = vec![0; SALT_SIZE];
+        OsRng.fill_bytes(&mut salt);
+
+        Ok(Chunked {
+            cipher: Some(new_cipher(name, &derive_key(key, &salt))?),
+            key: vec![],
+            // Only used when reading.
+            algorithm: EncryptionType::Passthrough,
+            framing: EncryptionFraming::Stream,
+            counter: 0,
+            finished: false,
+            buffer: vec![],
+            pending: salt,
+            io,
+        })
+    }
+
+    /// 'algorithm' is the one A implements, named if another opens the entry.
+    pub(crate) fn reader(
+        name: &str,
+        algorithm: EncryptionType,
+        key: &[u8],
+        io: T,
+        framing: EncryptionFraming,
+    ) -> Result<Self, EncryptorInitError> {
+        let cipher = match framing {
+            EncryptionFraming::Blocks => Some(new_cipher(name, key)?),
+            // Checked now so a bad key fails the same way for either.
+            EncryptionFraming::Stream => new_cipher::<A>(name, key).map(|_| None)?,
+        };
+
+        Ok(Chunked {
+            key: key.to_vec(),
+            algorithm,
+            cipher,
+            framing,
+            counter: 0,
+            finished: false,
+            buffer: vec![],
+            pending: vec![],
+            io,
+        })
+    }
+
+    fn nonce(&self, last: bool) -> Nonce<A> {
+        let mut nonce = Nonce::<A>::default();
+        let len = nonce.len();
+
+        nonce[len - 5..len - 1].copy_from_slice(&self.counter.to_be_bytes());
+        nonce[len - 1] = last as u8;
+
+        nonce
+    }
+
+    // Moves on to the next chunk, which there can only be 2^32 of.
+    fn advance(&mut self) -> Result<(), Error> {
+        self.counter = self.counter.checked_add(1).ok_or_else(|| Error::new(
+            ErrorKind::Other,
+            format!("Entry is longer than {} chunks", u32::MAX),
+        ))?;
+
+        Ok(())
+    }
+}
+
+impl<A, T> Chunked<A, T>
+where
+    A: Aead + KeyInit,
+    T: Write,
+{
+    pub(crate) fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        // At most a chunk is taken per call so that the buffer stays
+        // bounded however large the write, write_all passes the rest.
+        let buf = &buf[..std::cmp::min(buf.len(), CHUNK_SIZE)];
+
+        self.buffer.extend_from_slice(buf);
+
+        // A full chunk is only sealed once there is more after it, as
+        // the last chunk has to be sealed as such.
+        while self.buffer.len() > CHUNK_SIZE {
+            self.seal(CHUNK_SIZE, false)?;
+        }
+
+        Ok(buf.len())
+    }
+
+    pub(crate) fn flush(&mut self) -> Result<(), Error> {
+        self.io.flush()
+    }
+
+    /// Seals what is left as the last chunk, even if that is nothing.
+    pub(crate) fn finalise(mut self) -> Result<(), Error> {
+        self.seal(self.buffer.len(), true)?;
+
+        self.io.flush()
+    }
+
+    // Sealed from the buffer in place, rather than copied out first.
+    fn seal(&mut self, len: usize, last: bool) -> Result<(), Error> {
+        let nonce = self.nonce(last);
+        let cipher = self.cipher.as_ref().expect("Writers always have a cipher");
+
+        let sealed = cipher.encrypt(&nonce, &self.buffer[..len]).map_err(|e| Error::new(
+            ErrorKind::Other,
+            format!("Failed to encrypt: {}", e),
+        ))?;
+
+        self.buffer.drain(..len);
+
+        if !self.pending.is_empty() {
+            self.io.write_all(&self.pending)?;
+            self.pending.clear();
+        }
+
+        self.io.write_all(&sealed)?;
+
+        match last {
+            true => Ok(()),
+            false => self.advance(),
+        }
+    }
+}
+
+impl<A, T> Chunked<A, T>
+where
+    A: Aead + KeyInit,
+    T: Read,
+{
+    pub(crate) fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        // The last chunk is used up before reading another, otherwise
+        // reads smaller than a chunk would buffer the whole stream.
+        if self.buffer.is_empty() && !self.finished {
+            match self.framing {
+                EncryptionFraming::Stream => self.open_chunk()?,
+                EncryptionFraming::Blocks => self.open_block()?,
+            }
+        }
+
+        let cpy_len = std::cmp::min(buf.len(), self.buffer.len());
+        buf[..cpy_len].clone_from_slice(self.buffer.drain(..cpy_len).as_slice());
+
+        Ok(cpy_len)
+    }
+
+    fn open_chunk(&mut self) -> Result<(), Error> {
+        if self.cipher.is_none() {
+            let mut salt = [0; SALT_SIZE];
+
+            let read_len = read_block(&mut self.io, &mut salt)?;
+
+            if read_len < SALT_SIZE {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    format!("Truncated stream: expected a {} byte salt, found {} bytes", SALT_SIZE, read_len),
+                ));
+            }
+
+            self.key = derive_key(&self.key, &salt).to_vec();
+
+            self.cipher = Some(A::new_from_slice(&self.key).map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?);
+        }
+
+        // A byte past a full chunk is read to tell whether it is the last.
+        let wanted = CHUNK_SIZE + Self::TAG_SIZE + 1;
+        let start = self.pending.len();
+
+        self.pending.resize(wanted, 0);
+        let read_len = read_block(&mut self.io, &mut self.pending[start..])?;
+        self.pending.truncate(start + read_len);
+
+        let last = self.pending.len() < wanted;
+        let chunk_len = std::cmp::min(self.pending.len(), CHUNK_SIZE + Self::TAG_SIZE);
+
+        if chunk_len < Self::TAG_SIZE {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("Truncated chunk: expected at least {} bytes, found {}", Self::TAG_SIZE, chunk_len),
+            ));
+        }
+
+        let nonce = self.nonce(last);
+        let cipher = self.cipher.as_ref().expect("Cipher was derived above");
+
+        // A stream cut short ends on a chunk that wasn't sealed as the
+        // last, so truncation fails here too.
+        let plaintext = cipher.decrypt(&nonce, &self.pending[..chunk_len]).map_err(|_| {
+            let opened_by = match self.counter {
+                0 => detect_stream(&self.algorithm, &self.key, &self.pending[..chunk_len], last),
+                _ => None,
+            };
+
+            decrypt_error(&self.algorithm, opened_by, self.counter)
+        })?;
+
+        // Only opened once the buffer is used up, so it can be replaced.
+        self.pending.drain(..chunk_len);
+        self.buffer = plaintext;
+
+        match last {
+            true => {
+                self.finished = true;
+                Ok(())
+            },
+            false => self.advance(),
+        }
+    }
+
+    // Archives from before streams sealed each block with a random nonce
+    // stored in front of it:
+    //
+    //   [nonce][ciphertext: up to CHUNK_SIZE][tag]
+    fn open_block(&mut self) -> Result<(), Error> {
+        let mut raw_buf = vec![0u8; CHUNK_SIZE + Self::TAG_SIZE + Self::NONCE_SIZE];
+
+        let read_len = read_block(&mut self.io, &mut raw_buf)?;
+
+        if read_len == 0 {
+            self.finished = true;
+            return Ok(());
+        }
+
+        if read_len <= Self::NONCE_SIZE + Self::TAG_SIZE {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("Truncated block: expected more than {} bytes, found {}", Self::NONCE_SIZE + Self::TAG_SIZE, read_len),
+            ));
+        }
+
+        let nonce = Nonce::<A>::from_slice(&raw_buf[..Self::NONCE_SIZE]);
+        let cipher = self.cipher.as_ref().expect("Blocks are read with the secret's cipher");
+
+        let plaintext = cipher.decrypt(nonce, &raw_buf[Self::NONCE_SIZE..read_len]).map_err(|_| {
+            let opened_by = match self.counter {
+                0 => detect_block(&self.algorithm, &self.key, &raw_buf[..read_len]),
+                _ => None,
+            };
+
+            decrypt_error(&self.algorithm, opened_by, self.counter)
+        })?;
+
+        self.buffer = plaintext;
+
+        // Only counted to tell the first block apart.
+        self.counter = self.counter.saturating_add(1);
+
+        Ok(())
+    }
+}
+
+fn decrypt_error(algorithm: &EncryptionType, opened_by: Option<EncryptionType>, counter: u32) -> Error {
+    let error = match opened_by {
+        Some(opened_by) => DecryptError::WrongAlgorithm(opened_by, algorithm.clone()),
+        None => DecryptError::Authentication(counter),
+    };
+
+    Error::new(ErrorKind::InvalidData, error)
+}
+
+// When the first chunk fails, the other algorithms are tried on it with the
+// same key. An authenticated chunk can't have come from anything else, so
+// this is only ever wrong in naming no algorithm, eg: for a wrong password.
+fn detect_stream(algorithm: &EncryptionType, key: &[u8], chunk: &[u8], last: bool) -> Option<EncryptionType> {
+    fn opens<B: Aead + KeyInit>(key: &[u8], chunk: &[u8], last: bool) -> bool {
+        let mut nonce = Nonce::<B>::default();
+        let len = nonce.len();
+        nonce[len - 1] = last as u8;
+
+        B::new_from_slice(key).is_ok_and(|cipher| cipher.decrypt(&nonce, chunk).is_ok())
+    }
+
+    [
+        (EncryptionType::XChaCha, opens::<XChaCha20Poly1305> as fn(&[u8], &[u8], bool) -> bool),
+        (EncryptionType::ChaCha, opens::<ChaCha20Poly1305>),
+        (EncryptionType::AesGcm, opens::<Aes256Gcm>),
+    ]
+    .into_iter()
+    .find(|(other, opens)| other != algorithm && opens(key, chunk, last))
+    .map(|(other, _)| other)
+}
+
+// Blocks store their nonce, which differs in length between algorithms.
+fn detect_block(algorithm: &EncryptionType, key: &[u8], block: &[u8]) -> Option<EncryptionType> {
+    fn opens<B: Aead + KeyInit>(key: &[u8], block: &[u8]) -> bool {
+        let nonce_size = <B as AeadCore>::NonceSize::USIZE;
+
+        block.len() > nonce_size
+            && B::new_from_slice(key).is_ok_and(|cipher| {
+                cipher.decrypt(Nonce::<B>::from_slice(&block[..nonce_size]), &block[nonce_size..]).is_ok()
+            })
+    }
+
+    [
+        (EncryptionType::XChaCha, opens::<XChaCha20Poly1305> as fn(&[u8], &[u8]) -> bool),
+        (EncryptionType::ChaCha, opens::<ChaCha20Poly1305>),
+        (EncryptionType::AesGcm, opens::<Aes256Gcm>),
+    ]
+    .into_iter()
+    .find(|(other, opens)| other != algorithm && opens(key, block))
+    .map(|(other, _)| other)
+}
+
+fn new_cipher<A: KeyInit>(name: &str, key: &[u8]) -> Result<A, EncryptorInitError> {
+    A::new_from_slice(key).map_err(|e| EncryptorInitError::AlgorithmError(format!("{}: {}", name, e)))
+}
+
+// Every algorithm takes a 256 bit key, which this always is. Only once
+// the secret itself is known to fit is it derived from.
+fn derive_key(key: &[u8], salt: &[u8]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new_derive_key(KEY_CONTEXT);
+    hasher.update(salt);
+    hasher.update(key);
+
+    *hasher.finalize().as_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    fn input(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn seal<A: Aead + KeyInit>(input: &[u8]) -> Vec<u8> {
+        let mut sealed = Vec::new();
+        let mut writer = Chunked::<A, _>::writer("test", &KEY, &mut sealed).unwrap();
+
+        let mut written = 0;
+        while written < input.len() {
+            written += writer.write(&input[written..]).unwrap();
+        }
+
+        writer.finalise().unwrap();
+
+        sealed
+    }
+
+    fn open<A: Aead + KeyInit>(algorithm: EncryptionType, sealed: &[u8], framing: EncryptionFraming) -> Result<Vec<u8>, Error> {
+        let mut reader = Chunked::<A, _>::reader("test", algorithm, &KEY, sealed, framing).unwrap();
+        let mut opened = Vec::new();
+        let mut buf = [0; 1000];
+
+        loop {
+            match reader.read(&mut buf)? {
+                0 => return Ok(opened),
+                n => opened.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    // The chunk that failed, for an error that is a DecryptError.
+    fn failed_chunk(result: Result<Vec<u8>, Error>) -> Option<u32> {
+        match result.err()?.get_ref()?.downcast_ref::<DecryptError>()? {
+            DecryptError::Authentication(chunk) => Some(*chunk),
+            DecryptError::WrongAlgorithm(..) => None,
+        }
+    }
+
+    fn round_trips<A: Aead + KeyInit>(algorithm: EncryptionType) {
+        for len in [0, 1, CHUNK_SIZE - 1, CHUNK_SIZE, CHUNK_SIZE + 1] {
+            let sealed = seal::<A>(&input(len));
+            // A full last chunk is sealed as the last rather than followed by an empty one.
+            let chunks = std::cmp::max(len.div_ceil(CHUNK_SIZE), 1);
+
+            assert_eq!(sealed.len(), SALT_SIZE + len + chunks * <A as AeadCore>::TagSize::USIZE, "{:?} at {} bytes", algorithm, len);
+            assert_eq!(open::<A>(algorithm.clone(), &sealed, EncryptionFraming::Stream).unwrap(), input(len), "{:?} at {} bytes", algorithm, len);
+        }
+    }
+
+    #[test]
+    fn round_trips_on_chunk_boundaries() {
+        round_trips::<XChaCha20Poly1305>(EncryptionType::XChaCha);
+        round_trips::<ChaCha20Poly1305>(EncryptionType::ChaCha);
+        round_trips::<Aes256Gcm>(EncryptionType::AesGcm);
+    }
+
+    // Bytes of a sealed chunk with its tag, for XChaCha20Poly1305.
+    const SEALED_CHUNK: usize = CHUNK_SIZE + 16;
+
+    #[test]
+    fn tampered_chunk_fails() {
+        let mut sealed = seal::<XChaCha20Poly1305>(&input(CHUNK_SIZE * 2 + 10));
+        sealed[SALT_SIZE + SEALED_CHUNK + 100] ^= 1;
+
+        assert_eq!(failed_chunk(open::<XChaCha20Poly1305>(EncryptionType::XChaCha, &sealed, EncryptionFraming::Stream)), Some(1));
+    }
+
+    #[test]
+    fn truncated_stream_fails() {
+        let sealed = seal::<XChaCha20Poly1305>(&input(CHUNK_SIZE * 2 + 10));
+
+        // On a chunk boundary, with the last chunk dropped, and part way into one.
+        for len in [SALT_SIZE + SEALED_CHUNK * 2, SALT_SIZE + SEALED_CHUNK + 100] {
+            assert_eq!(failed_chunk(open::<XChaCha20Poly1305>(EncryptionType::XChaCha, &sealed[..len], EncryptionFraming::Stream)), Some(1), "cut at {}", len);
+        }
+
+        assert!(open::<XChaCha20Poly1305>(EncryptionType::XChaCha, &sealed[..SALT_SIZE - 1], EncryptionFraming::Stream).is_err());
+    }
+
+    #[test]
+    fn reordered_chunks_fail() {
+        let sealed = seal::<XChaCha20Poly1305>(&input(CHUNK_SIZE * 2 + 10));
+
+        let (salt, chunks) = sealed.split_at(SALT_SIZE);
+        let (first, rest) = chunks.split_at(SEALED_CHUNK);
+        let (second, last) = rest.split_at(SEALED_CHUNK);
+
+        let reordered = [salt, second, first, last].concat();
+
+        assert_eq!(failed_chunk(open::<XChaCha20Poly1305>(EncryptionType::XChaCha, &reordered, EncryptionFraming::Stream)), Some(0));
+    }
+
+    #[test]
+    fn legacy_blocks_decrypt() {
+        let input = input(CHUNK_SIZE + 10);
+        let cipher = XChaCha20Poly1305::new_from_slice(&KEY).unwrap();
+
+        // Each block with a random nonce in front of it, with the secret as the key.
+        let blocks: Vec<u8> = input
+            .chunks(CHUNK_SIZE)
+            .flat_map(|block| {
+                let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+                [nonce.to_vec(), cipher.encrypt(&nonce, block).unwrap()].concat()
+            })
+            .collect();
+
+        assert_eq!(open::<XChaCha20Poly1305>(EncryptionType::XChaCha, &blocks, EncryptionFraming::Blocks).unwrap(), input);
+        assert!(open::<XChaCha20Poly1305>(EncryptionType::XChaCha, &blocks, EncryptionFraming::Stream).is_err());
+    }
+
+    // The algorithms named, for an error saying another one opens the entry.
+    fn wrong_algorithm(result: Result<Vec<u8>, Error>) -> Option<(EncryptionType, EncryptionType)> {
+        match result.err()?.get_ref()?.downcast_ref::<DecryptError>()? {
+            DecryptError::WrongAlgorithm(used, given) => Some((used.clone(), given.clone())),
+            DecryptError::Authentication(_) => None,
+        }
+    }
+
+    type Seal = fn(&[u8]) -> Vec<u8>;
+    type Open = fn(EncryptionType, &[u8], EncryptionFraming) -> Result<Vec<u8>, Error>;
+
+    #[test]
+    fn wrong_algorithm_is_named() {
+        let algorithms: [(EncryptionType, Seal, Open); 3] = [
+            (EncryptionType::XChaCha, seal::<XChaCha20Poly1305>, open::<XChaCha20Poly1305>),
+            (EncryptionType::ChaCha, seal::<ChaCha20Poly1305>, open::<ChaCha20Poly1305>),
+            (EncryptionType::AesGcm, seal::<Aes256Gcm>, open::<Aes256Gcm>),
+        ];
+
+        // A first chunk that is the last, and one that isn't.
+        for len in [100, CHUNK_SIZE + 10] {
+            for (used, seal, _) in algorithms.iter() {
+                let sealed = seal(&input(len));
+
+                for (given, _, open) in algorithms.iter().filter(|(given, _, _)| given != used) {
+                    assert_eq!(
+                        wrong_algorithm(open(given.clone(), &sealed, EncryptionFraming::Stream)),
+                        Some((used.clone(), given.clone())),
+                        "{:?} opened as {:?} at {} bytes",
+                        used,
+                        given,
+                        len,
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn wrong_algorithm_is_named_for_blocks() {
+        let cipher = XChaCha20Poly1305::new_from_slice(&KEY).unwrap();
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let block = [nonce.to_vec(), cipher.encrypt(&nonce, input(100).as_slice()).unwrap()].concat();
+
+        assert_eq!(
+            wrong_algorithm(open::<Aes256Gcm>(EncryptionType::AesGcm, &block, EncryptionFraming::Blocks)),
+            Some((EncryptionType::XChaCha, EncryptionType::AesGcm)),
+        );
+    }
+
+    #[test]
+    fn damaged_entries_name_no_algorithm() {
+        let mut sealed = seal::<XChaCha20Poly1305>(&input(100));
+        sealed[SALT_SIZE] ^= 1;
+
+        assert_eq!(failed_chunk(open::<Aes256Gcm>(EncryptionType::AesGcm, &sealed, EncryptionFraming::Stream)), Some(0));
+    }
+}
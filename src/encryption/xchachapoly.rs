@@ -2,28 +2,28 @@
 use crate::error::EncryptorInitError;
 
 // External
-use aes_gcm::{
-    aead::{Aead, OsRng},
-    KeyInit, AeadCore,
-};
-use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use aes_gcm::aead::{generic_array::typenum::Unsigned, AeadCore, KeySizeUser};
+use chacha20poly1305::XChaCha20Poly1305;
 use std::{
-    io::{Error, ErrorKind, Read, Write},
-    vec, marker::PhantomData,
+    io::{Error, Read, Write},
+    marker::PhantomData,
 };
 
-use super::{EncryptionAlgorithm, EncryptorMode, DecryptionAlgorithm, DecryptorMode, EncryptionModule, DecryptionModule};
-
-const NONCE_SIZE: usize = 24;
+use super::{
+    stream::Chunked, CipherSizes, DecryptionAlgorithm, DecryptionModule, DecryptorMode, EncryptionAlgorithm, EncryptionFraming,
+    EncryptionModule, EncryptionType, EncryptorMode,
+};
 
 pub struct XChaChaPolyAlgorithm<T> {
     key: T,
+    framing: EncryptionFraming,
 }
 
 impl XChaChaPolyAlgorithm<()> {
     pub fn new() -> XChaChaPolyAlgorithm<()> {
         XChaChaPolyAlgorithm {
             key: (),
+            framing: EncryptionFraming::default(),
         }
     }
 }
@@ -34,86 +34,74 @@ impl Default for XChaChaPolyAlgorithm<()> {
     }
 }
 
+impl <T> CipherSizes for XChaChaPolyAlgorithm<T> {
+    const KEY_SIZE: usize = <XChaCha20Poly1305 as KeySizeUser>::KeySize::USIZE;
+    const NONCE_SIZE: usize = <XChaCha20Poly1305 as AeadCore>::NonceSize::USIZE;
+    const TAG_SIZE: usize = <XChaCha20Poly1305 as AeadCore>::TagSize::USIZE;
+}
+
 impl <T> XChaChaPolyAlgorithm<T> {
 
-    pub fn with_key(self, key: Vec<u8>) -> XChaChaPolyAlgorithm<Vec<u8>> {
+    // The key is generic so that it can be borrowed rather
+    // than copied when many encryptors share a secret.
+    pub fn with_key<K>(self, key: K) -> XChaChaPolyAlgorithm<K>
+    where K: AsRef<[u8]>
+    {
         XChaChaPolyAlgorithm {
             key,
+            framing: self.framing,
+        }
+    }
+
+    /// How entries are read, encryptors always write streams.
+    pub fn with_framing(self, framing: EncryptionFraming) -> Self {
+        XChaChaPolyAlgorithm {
+            framing,
+            ..self
         }
     }
 }
 
-impl <T> EncryptionAlgorithm<T> for XChaChaPolyAlgorithm<Vec<u8>>
-where T: Write
+impl <T, K> EncryptionAlgorithm<T> for XChaChaPolyAlgorithm<K>
+where T: Write, K: AsRef<[u8]>
 {
     type Encryptor = XChaChaPoly<T, EncryptorMode>;
 
     fn encryptor(&self, writer: T) -> Result<XChaChaPoly<T, EncryptorMode>, EncryptorInitError> {
         Ok(XChaChaPoly {
-            cipher: match XChaCha20Poly1305::new_from_slice(&self.key[0..]) {
-                Ok(k) => k,
-                Err(e) => return Err(EncryptorInitError::AlgorithmError(format!("XChaChaPoly: {}", e))),
-            },
-            internal_buffer: vec![],
-            io: writer,
+            inner: Chunked::writer("XChaChaPoly", self.key.as_ref(), writer)?,
             mode: PhantomData
         })
     }
 }
 
-impl<T> DecryptionAlgorithm<T> for XChaChaPolyAlgorithm<Vec<u8>>
-where T: Read
+impl<T, K> DecryptionAlgorithm<T> for XChaChaPolyAlgorithm<K>
+where T: Read, K: AsRef<[u8]>
 {
     type Decryptor = XChaChaPoly<T, DecryptorMode>;
 
     fn decryptor(&self, reader: T) -> Result<XChaChaPoly<T, DecryptorMode>, EncryptorInitError> {
         Ok(XChaChaPoly {
-            cipher: match XChaCha20Poly1305::new_from_slice(&self.key[0..]) {
-                Ok(k) => k,
-                Err(e) => return Err(EncryptorInitError::AlgorithmError(format!("XChaChaPoly: {}", e))),
-            },
-            internal_buffer: vec![],
-            io: reader,
+            inner: Chunked::reader("XChaChaPoly", EncryptionType::XChaCha, self.key.as_ref(), reader, self.framing)?,
             mode: PhantomData
         })
     }
 }
 
 pub struct XChaChaPoly<T, M> {
-    cipher: XChaCha20Poly1305,
-    // Temporarily stored as Vec<u8> until it is decided how
-    // How the nonce will be stored as in zap metadata
-    internal_buffer: Vec<u8>,
-    io: T,
+    inner: Chunked<XChaCha20Poly1305, T>,
     mode: PhantomData<M>,
-} 
+}
 
-impl <T> EncryptionModule for XChaChaPoly<T, EncryptorMode> 
+impl <T> EncryptionModule for XChaChaPoly<T, EncryptorMode>
 where T: Write
 {
-    fn finalise(mut self) -> Result<(), Error> {
-        while !self.internal_buffer.is_empty() {
-            let drain_len = std::cmp::min(self.internal_buffer.len(), 8192);
-
-            let buf = self.internal_buffer.drain(..drain_len);
-
-            let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
-            
-            match self.cipher.encrypt(&nonce, buf.as_slice()) {
-                Ok(n) => {
-                    self.io.write_all(&nonce)?;
-                    self.io.write_all(&n)?;
-                }
-                Err(e) => return Err(Error::new(
-                    ErrorKind::Other,
-                    format!("Failed to encrypt: {}", e),
-                )),
-            }
-        }
-
-        self.io.flush()?;
+    fn algorithm(&self) -> EncryptionType {
+        EncryptionType::XChaCha
+    }
 
-        Ok(())
+    fn finalise(self) -> Result<(), Error> {
+        self.inner.finalise()
     }
 }
 
@@ -122,43 +110,21 @@ where
     T: Write,
 {
     fn flush(&mut self) -> std::io::Result<()> {
-        self.io.flush()
+        self.inner.flush()
     }
 
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
- 
-        /*
-            Payload for each block is written as:
-            [ nonce ][ ciphertext ][ tag ]
-            [ 24    ][ 8192       ][ 16  ] (Bytes)
-        */
-
-        self.internal_buffer.extend_from_slice(buf);
-
-        while self.internal_buffer.len() > 8192 {
-            let buf = self.internal_buffer.drain(..8192);
-
-            let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
-
-            match self.cipher.encrypt(&nonce, buf.as_slice()) {
-                Ok(n) => {
-                    self.io.write_all(&nonce)?;
-                    self.io.write_all(&n)?;
-                }
-                Err(e) => return Err(Error::new(
-                    ErrorKind::Other,
-                    format!("Failed to encrypt: {}", e),
-                )),
-            }
-        }
-
-        Ok(buf.len())
+        self.inner.write(buf)
     }
 }
 
 impl <T> DecryptionModule for XChaChaPoly<T, DecryptorMode>
 where T: Read
 {
+    fn algorithm(&self) -> EncryptionType {
+        EncryptionType::XChaCha
+    }
+
     fn finalise(self) -> Result<(), Error> {
         Ok(())
     }
@@ -169,43 +135,6 @@ where
     T: Read,
 {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        
-        let mut raw_buf = vec![0u8; 8192+16+NONCE_SIZE];
-
-        let read_len = self.io.read(&mut raw_buf)?;
-
-        if read_len > 0 {
-            let raw_nonce= raw_buf.drain(..NONCE_SIZE).collect::<Vec<u8>>();
-            let nonce = XNonce::from_slice(&raw_nonce);
-
-            match self.cipher.decrypt(
-                nonce,
-                &raw_buf[..(read_len-NONCE_SIZE)], /* Payload {
-                                          msg: &raw_buf[..read_len],
-                                          aad: &self.key
-                                      }*/
-            ) {
-                Ok(plaintext) => {
-                    // May consider changing this so that cipher.update writes
-                    // directly to self.internal_buffer. For now though we can
-                    // extend self.internal_buffer from dec_buf.
-                    self.internal_buffer.extend_from_slice(&plaintext);
-                },
-                Err(e) => {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        format!("Failed to decrypt: {}", e),
-                    ))
-                }
-            }
-        }
-
-        // Copy n bytes where n is the lesser of buf and internal_buf
-        // The copy is super jank but that will hopefully change when
-        // slice.take() take comes out of nightly.
-        let cpy_len = std::cmp::min(buf.len(), self.internal_buffer.len());
-        buf[..cpy_len].clone_from_slice(self.internal_buffer.drain(..cpy_len).as_slice());
-
-        Ok(cpy_len)
+        self.inner.read(buf)
     }
-}
\ No newline at end of file
+}
@@ -1,5 +1,9 @@
+use std::path::PathBuf;
+
 use rayon::ThreadPoolBuildError;
 
+use crate::{encryption::EncryptionType, report::SkipReason};
+
 #[derive(Debug, thiserror::Error)]
 pub enum ZapError {
     #[error("{0}")]
@@ -21,6 +25,20 @@ pub enum ZapError {
     #[error(transparent)]
     EncryptionSecretError(#[from] EncryptionSecretError),
     #[error(transparent)]
+    ManifestError(#[from] ManifestError),
+    #[error(transparent)]
+    FooterError(#[from] FooterError),
+    #[error(transparent)]
+    HeaderError(#[from] HeaderError),
+    #[error(transparent)]
+    RecoveryError(#[from] RecoveryError),
+    #[error(transparent)]
+    PipelineCompressionError(#[from] PipelineCompressionError),
+    #[error(transparent)]
+    PipelineDecompressionError(#[from] PipelineDecompressionError),
+    #[error(transparent)]
+    PipelineBuildError(#[from] PipelineBuildError),
+    #[error(transparent)]
     FailedToInitialiseLogger(#[from] log::SetLoggerError),
 }
 
@@ -31,6 +49,15 @@ pub enum EncryptionError
     InitError(EncryptionSecretError)
 }
 
+/// Why an entry failed to decrypt, carried by the io::Error it is read with.
+#[derive(Debug, thiserror::Error)]
+pub enum DecryptError {
+    #[error("Failed to decrypt chunk {0}, it is damaged, out of place or the entry is truncated")]
+    Authentication(u32),
+    #[error("Failed to decrypt, the archive appears to use {0:?}, not {1:?}")]
+    WrongAlgorithm(EncryptionType, EncryptionType),
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum EncryptorInitError {
     #[error("Failed to init algorithm: {0}")]
@@ -54,7 +81,9 @@ pub enum EncryptionKeyError
     #[error("Keyfile not provided")]
     KeyfileNotProvided,
     #[error("Keyfile not found: {0}")]
-    FailedToFindKeyfile(String)
+    FailedToFindKeyfile(String),
+    #[error("Keyfile '{0}' should be a {1} byte key for the algorithm, but is {2} bytes")]
+    WrongLength(String, usize, u64),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -63,12 +92,31 @@ pub enum PasswordError {
     PasswordsDoNotMatch,
     #[error("Password is empty")]
     PasswordEmpty,
+    #[error("Password entry was cancelled")]
+    PasswordEntryCancelled,
+    #[error("Failed to read password file '{0}': {1}")]
+    PasswordFileError(String, std::io::Error),
+    #[error("Password is too weak: estimated {0:.0} bits of entropy, at least {1:.0} required")]
+    PasswordTooWeak(f64, f64),
     #[error(transparent)]
     HashingError(#[from] HashingError),
     #[error(transparent)]
     InputError(#[from] InputError),
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum SecretError {
+    #[error(transparent)]
+    PasswordError(#[from] PasswordError),
+    #[error(transparent)]
+    EncryptionKeyError(#[from] EncryptionKeyError),
+    #[error("Environment variable '{0}' is not set or isn't valid unicode")]
+    MissingEnvVar(String),
+    /// For providers outside of zap, eg: a secret manager.
+    #[error("Failed to fetch secret: {0}")]
+    ProviderFailed(Box<dyn std::error::Error + Send + Sync>),
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum HashingError {
     #[error("UnrecognisedAlgorithm: {0}")]
@@ -92,7 +140,35 @@ pub enum CompressionError {
     #[error("Failed to compress file: {0}")]
     IOError(#[from] std::io::Error),
     #[error(transparent)]
-    PathRewriteError(#[from] PathRewriteError)
+    PathRewriteError(#[from] PathRewriteError),
+    #[error(transparent)]
+    ManifestError(#[from] ManifestError),
+    #[error("Failed to compress '{}': {}", .0.display(), .1)]
+    FileFailed(PathBuf, Box<PipelineCompressionError>),
+    #[error("Source changed while compressing '{}': {}", .0.display(), .1)]
+    SourceChanged(PathBuf, SkipReason),
+    #[error("Ran out of disk space while writing '{}'", .0.display())]
+    DiskFull(PathBuf),
+    #[error("Comment is {0} bytes, longer than the limit of {1}")]
+    CommentTooLong(usize, usize),
+    #[error("Options can't be combined: {0}")]
+    IncompatibleOptions(&'static str),
+    #[error(transparent)]
+    CheckpointError(#[from] CheckpointError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CheckpointError {
+    #[error("Failed to access checkpoint: {0}")]
+    IOError(#[from] std::io::Error),
+    #[error("Invalid checkpoint: {0}")]
+    InvalidFormat(#[from] serde_json::Error),
+    #[error("Unsupported checkpoint version: {0}")]
+    UnsupportedVersion(u32),
+    #[error("Checkpoint '{}' was made with different algorithms or secret, remove it to start again", .0.display())]
+    SettingsChanged(PathBuf),
+    #[error(transparent)]
+    Key(#[from] EncryptionKeyError),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -111,7 +187,121 @@ pub enum DecompressionError {
     #[error("Failed to walk directory: {0}")]
     FailedToWalkDirectory(#[from] walkdir::Error),
     #[error("Failed to decompress file: {0}")]
-    IOError(#[from] std::io::Error)
+    IOError(#[from] std::io::Error),
+    #[error(transparent)]
+    PathRewriteError(#[from] PathRewriteError),
+    #[error(transparent)]
+    ManifestError(#[from] ManifestError),
+    #[error("Failed to decompress '{}': {}", .0.display(), .1)]
+    FileFailed(PathBuf, Box<PipelineDecompressionError>),
+    #[error("'{}' and '{}' would be extracted to the same path", .0.display(), .1.display())]
+    PathConflict(PathBuf, PathBuf),
+    #[error("Refusing to extract '{}', it would be written outside of the output directory", .0.display())]
+    UnsafePath(PathBuf),
+    #[error("Refusing to extract '{}', it links to '{}' which the symlink policy doesn't allow", .0.display(), .1.display())]
+    UnsafeSymlink(PathBuf, PathBuf),
+    #[error("No such entry in archive: {}", .0.display())]
+    EntryNotFound(PathBuf),
+    #[error("Cannot write to output directory '{}': {}", .0.display(), .1)]
+    OutputNotWritable(PathBuf, std::io::Error),
+    #[error("'{}' is a duplicate of '{}', which is missing from the archive", .0.display(), .1.display())]
+    MissingDuplicateSource(PathBuf, PathBuf),
+    #[error("Asked for {0} but the archive records {1}")]
+    AlgorithmMismatch(String, String),
+    #[error("Ran out of disk space while writing '{}'", .0.display())]
+    DiskFull(PathBuf),
+    #[error("Stopped extracting '{}': {}", .0.display(), .1)]
+    SizeLimitExceeded(PathBuf, SizeLimitError),
+    #[error("Entries of this archive are each encrypted with a secret of their own, extracting them needs a secret resolver")]
+    SecretResolverRequired,
+}
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum TemplateError {
+    #[error("Template is empty")]
+    Empty,
+    #[error("Unknown placeholder '{{{0}}}', expected one of {{dir}}, {{name}}, {{stem}}, {{ext}} or {{n}}")]
+    UnknownPlaceholder(String),
+    #[error("Unclosed '{{' in template")]
+    Unclosed,
+    #[error("Template would write outside of the output directory: {0}")]
+    UnsafePath(String),
+}
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum GlobError {
+    #[error("Pattern is empty")]
+    Empty,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PathMapError {
+    #[error("Failed to read path map: {0}")]
+    IOError(#[from] std::io::Error),
+    #[error("Line {0} of the path map isn't 'archived/path => destination': {1}")]
+    InvalidLine(usize, String),
+    #[error("Line {} of the path map could write outside of where it points: {}", .0, .1.display())]
+    UnsafePath(usize, PathBuf),
+}
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum SizeLimitError {
+    #[error("entry is larger than the limit of {0} bytes")]
+    Entry(u64),
+    #[error("extracted files are larger than the limit of {0} bytes in total")]
+    Total(u64),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum HeaderError {
+    #[error("Failed to access archive: {0}")]
+    IOError(#[from] std::io::Error),
+    #[error("Archive format version {0} is not supported, this version of zap reads versions {1} to {2}")]
+    UnsupportedVersion(u8, u8, u8),
+    #[error("Archive header names an unknown algorithm: {0}")]
+    UnknownAlgorithm(u8),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum FooterError {
+    #[error("Failed to access archive: {0}")]
+    IOError(#[from] std::io::Error),
+    #[error("Archive has no digest: {0}")]
+    Missing(String),
+    #[error("Archive already has a digest: {0}")]
+    AlreadyPresent(String),
+    #[error("Archive does not match its digest: {0}")]
+    Mismatch(String),
+    #[error("Archive '{0}' is {2} bytes, but {1} bytes were hashed")]
+    LengthMismatch(String, u64, u64),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RecoveryError {
+    #[error("Failed to access archive: {0}")]
+    IOError(#[from] std::io::Error),
+    #[error("Recovery percentage must be between 1 and 100, got: {0}")]
+    InvalidPercent(u32),
+    #[error("Archive has no recovery record: {0}")]
+    Missing(String),
+    #[error("Archive already has a recovery record: {0}")]
+    AlreadyPresent(String),
+    #[error("Too many damaged blocks to repair in parity group {1}: {0}")]
+    Unrecoverable(String, u64),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ManifestError {
+    #[error("Failed to access manifest: {0}")]
+    IOError(#[from] std::io::Error),
+    #[error("Failed to parse manifest: {0}")]
+    FormatError(#[from] serde_json::Error),
+    #[error("Input contains a file using the reserved manifest name: {0}")]
+    ReservedName(String),
+    #[error("Failed to compress manifest: {0}")]
+    CompressionFailed(Box<PipelineCompressionError>),
+    #[error("Failed to decompress manifest: {0}")]
+    DecompressionFailed(Box<PipelineDecompressionError>),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -140,6 +330,8 @@ pub enum PipelineCompressionError {
 pub enum PipelineDecompressionError {
     #[error("Generic Error: {0}")]
     Generic(String),
+    #[error("Unexpected end of stream, the input may be truncated: {0}")]
+    UnexpectedEof(String),
     #[error(transparent)]
     HashingError(HashingError),
     #[error(transparent)]
@@ -156,6 +348,35 @@ pub enum PipelineDecompressionError {
     DecryptorInitError(EncryptorInitError),
     #[error(transparent)]
     CompressionInitError(CompressorInitError),
+    #[error(transparent)]
+    DecryptError(DecryptError),
+}
+
+impl PipelineCompressionError {
+    /// Whether the destination ran out of space while being written.
+    pub fn is_disk_full(&self) -> bool {
+        matches!(self, PipelineCompressionError::IOError(e) if e.kind() == std::io::ErrorKind::StorageFull)
+    }
+}
+
+impl PipelineDecompressionError {
+    /// Whether the destination ran out of space while being written.
+    pub fn is_disk_full(&self) -> bool {
+        matches!(self, PipelineDecompressionError::IOError(e) if e.kind() == std::io::ErrorKind::StorageFull)
+    }
+
+    /// Whether the entry was encrypted with another algorithm or key.
+    pub fn is_wrong_algorithm(&self) -> bool {
+        matches!(self, PipelineDecompressionError::DecryptError(DecryptError::WrongAlgorithm(..)))
+    }
+
+    /// The limit that stopped the destination from being written, if any.
+    pub fn size_limit(&self) -> Option<&SizeLimitError> {
+        match self {
+            PipelineDecompressionError::IOError(e) => e.get_ref().and_then(|e| e.downcast_ref()),
+            _ => None,
+        }
+    }
 }
 
 impl From<CompressorInitError> for PipelineDecompressionError {
@@ -242,10 +463,44 @@ impl From<EncryptorInitError> for PipelineBuildError {
 
 #[derive(Debug, thiserror::Error)]
 pub enum CompressorInitError {
-
+    #[error("Compression algorithm must be resolved before building a pipeline")]
+    UnresolvedAlgorithm,
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum SignerInitError {
     
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        compression::CompressionType,
+        encryption::EncryptionSecret,
+        pipeline::PipelineConfig,
+        signing::SigningType,
+    };
+
+    fn config(secret: EncryptionSecret) -> PipelineConfig {
+        PipelineConfig::new(EncryptionType::XChaCha, secret, CompressionType::Lz4, flate2::Compression::default(), SigningType::default())
+    }
+
+    // Both only have to compile and fail, through '?' alone.
+    fn compress() -> Result<(), ZapError> {
+        config(EncryptionSecret::Key("/nonexistent/zap.key".into())).builder().build().compress_stream(&mut &b"input"[..], Vec::new())?;
+
+        Ok(())
+    }
+
+    fn decompress() -> Result<(), ZapError> {
+        config(EncryptionSecret::Password(vec![7; 32])).builder().build().decompress_stream(&[0_u8; 64][..], &mut Vec::new())?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn pipeline_errors_propagate() {
+        assert!(matches!(compress(), Err(ZapError::PipelineCompressionError(_))));
+        assert!(matches!(decompress(), Err(ZapError::PipelineDecompressionError(_))));
+    }
+}
@@ -0,0 +1,481 @@
+// C interface for embedding zap, enabled with the 'ffi' feature.
+// The matching declarations are in include/zap.h.
+//
+// Every function returns a ZapStatus, or NULL for constructors, rather
+// than panicking, as unwinding across the boundary is undefined.
+// Anything allocated by zap must be freed by zap.
+
+use std::{
+    ffi::{c_char, CStr},
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::PathBuf,
+    ptr, slice,
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    compression::CompressionType,
+    encryption::{EncryptionSecret, EncryptionType},
+    signing::SigningType,
+};
+
+pub const ZAP_COMPRESSION_PASSTHROUGH: u32 = 0;
+pub const ZAP_COMPRESSION_LZ4: u32 = 1;
+pub const ZAP_COMPRESSION_GZIP: u32 = 2;
+pub const ZAP_COMPRESSION_SNAPPY: u32 = 3;
+
+pub const ZAP_ENCRYPTION_PASSTHROUGH: u32 = 0;
+pub const ZAP_ENCRYPTION_XCHACHA: u32 = 1;
+pub const ZAP_ENCRYPTION_AES_GCM: u32 = 2;
+pub const ZAP_ENCRYPTION_CHACHA: u32 = 3;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ZapStatus {
+    Ok = 0,
+    /// A pointer was NULL, a path wasn't valid UTF-8 or a value was out of range.
+    InvalidArgument = 1,
+    /// A file couldn't be opened, created or written.
+    IOError = 2,
+    CompressionFailed = 3,
+    /// Includes input that was tampered with or the wrong password.
+    DecompressionFailed = 4,
+    Panicked = 5,
+}
+
+/// The algorithms and secret used by the other functions. Opaque to C.
+pub struct ZapConfig {
+    encryption: EncryptionType,
+    encryption_secret: EncryptionSecret,
+    compression: CompressionType,
+    compression_level: flate2::Compression,
+    signing: SigningType,
+}
+
+/// Bytes allocated by zap, freed with zap_buffer_free.
+#[repr(C)]
+pub struct ZapBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+fn guard(f: impl FnOnce() -> ZapStatus) -> ZapStatus {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or(ZapStatus::Panicked)
+}
+
+unsafe fn path_arg(path: *const c_char) -> Option<PathBuf> {
+    if path.is_null() {
+        return None;
+    }
+
+    CStr::from_ptr(path).to_str().ok().map(PathBuf::from)
+}
+
+unsafe fn bytes_arg<'a>(data: *const u8, len: usize) -> Option<&'a [u8]> {
+    match (data.is_null(), len) {
+        (_, 0) => Some(&[]),
+        (true, _) => None,
+        (false, len) => Some(slice::from_raw_parts(data, len)),
+    }
+}
+
+/// Creates a config using one of the ZAP_COMPRESSION_* and ZAP_ENCRYPTION_*
+/// constants, and a gzip level from 0 to 9. Returns NULL if any are out of
+/// range. Encrypted configs need a password set before use.
+#[no_mangle]
+pub extern "C" fn zap_config_new(compression: u32, encryption: u32, level: u32) -> *mut ZapConfig {
+    let compression = match compression {
+        ZAP_COMPRESSION_PASSTHROUGH => CompressionType::Passthrough,
+        ZAP_COMPRESSION_LZ4 => CompressionType::Lz4,
+        ZAP_COMPRESSION_GZIP => CompressionType::Gzip,
+        ZAP_COMPRESSION_SNAPPY => CompressionType::Snappy,
+        _ => return ptr::null_mut(),
+    };
+
+    let encryption = match encryption {
+        ZAP_ENCRYPTION_PASSTHROUGH => EncryptionType::Passthrough,
+        ZAP_ENCRYPTION_XCHACHA => EncryptionType::XChaCha,
+        ZAP_ENCRYPTION_AES_GCM => EncryptionType::AesGcm,
+        ZAP_ENCRYPTION_CHACHA => EncryptionType::ChaCha,
+        _ => return ptr::null_mut(),
+    };
+
+    if CompressionType::Gzip.level_range().is_some_and(|range| !range.contains(level)) {
+        return ptr::null_mut();
+    }
+
+    Box::into_raw(Box::new(ZapConfig {
+        encryption,
+        encryption_secret: EncryptionSecret::None,
+        compression,
+        compression_level: flate2::Compression::new(level),
+        signing: SigningType::default(),
+    }))
+}
+
+/// Sets the password used to encrypt and decrypt, which is turned into a
+/// key the same way as the zap command line does.
+///
+/// # Safety
+///
+/// 'config' must come from zap_config_new and 'password' must point to
+/// 'len' readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn zap_config_set_password(config: *mut ZapConfig, password: *const u8, len: usize) -> ZapStatus {
+    guard(|| {
+        let (Some(config), Some(password)) = (config.as_mut(), bytes_arg(password, len)) else {
+            return ZapStatus::InvalidArgument;
+        };
+
+        if password.is_empty() {
+            return ZapStatus::InvalidArgument;
+        }
+
+        config.encryption_secret = EncryptionSecret::Password(Sha256::digest(password).to_vec());
+
+        ZapStatus::Ok
+    })
+}
+
+/// # Safety
+///
+/// 'config' must come from zap_config_new, or be NULL, and not be used after.
+#[no_mangle]
+pub unsafe extern "C" fn zap_config_free(config: *mut ZapConfig) {
+    if !config.is_null() {
+        drop(Box::from_raw(config));
+    }
+}
+
+/// Compresses 'len' bytes of 'input' into a new buffer written to 'output'.
+///
+/// # Safety
+///
+/// 'config' must come from zap_config_new, 'input' must point to 'len'
+/// readable bytes and 'output' must point to a writable ZapBuffer.
+#[no_mangle]
+pub unsafe extern "C" fn zap_compress_buffer(
+    config: *const ZapConfig,
+    input: *const u8,
+    len: usize,
+    output: *mut ZapBuffer,
+) -> ZapStatus {
+    guard(|| {
+        let (Some(config), Some(mut input), false) = (config.as_ref(), bytes_arg(input, len), output.is_null()) else {
+            return ZapStatus::InvalidArgument;
+        };
+
+        let mut buffer = Vec::new();
+
+        let result = crate::compress_dyn(
+            &mut input,
+            &mut buffer,
+            &config.encryption,
+            &config.encryption_secret,
+            &config.compression,
+            &config.compression_level,
+            &config.signing,
+        );
+
+        match result {
+            Ok(()) => {
+                output.write(into_buffer(buffer));
+                ZapStatus::Ok
+            },
+            Err(_) => ZapStatus::CompressionFailed,
+        }
+    })
+}
+
+/// Decompresses 'len' bytes of 'input' into a new buffer written to 'output'.
+///
+/// # Safety
+///
+/// As for zap_compress_buffer.
+#[no_mangle]
+pub unsafe extern "C" fn zap_decompress_buffer(
+    config: *const ZapConfig,
+    input: *const u8,
+    len: usize,
+    output: *mut ZapBuffer,
+) -> ZapStatus {
+    guard(|| {
+        let (Some(config), Some(mut input), false) = (config.as_ref(), bytes_arg(input, len), output.is_null()) else {
+            return ZapStatus::InvalidArgument;
+        };
+
+        let mut buffer = Vec::new();
+
+        let result = crate::decompress_dyn(
+            &mut input,
+            &mut buffer,
+            &config.encryption,
+            &config.encryption_secret,
+            &config.compression,
+            &config.signing,
+        );
+
+        match result {
+            Ok(()) => {
+                output.write(into_buffer(buffer));
+                ZapStatus::Ok
+            },
+            Err(_) => ZapStatus::DecompressionFailed,
+        }
+    })
+}
+
+/// # Safety
+///
+/// 'buffer' must have been written by zap and not freed already.
+#[no_mangle]
+pub unsafe extern "C" fn zap_buffer_free(buffer: ZapBuffer) {
+    if !buffer.data.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(buffer.data, buffer.len)));
+    }
+}
+
+/// Compresses the file at 'input' into a new file at 'output', both
+/// NUL terminated UTF-8 paths.
+///
+/// # Safety
+///
+/// 'config' must come from zap_config_new and both paths must be NUL terminated.
+#[no_mangle]
+pub unsafe extern "C" fn zap_compress_file(config: *const ZapConfig, input: *const c_char, output: *const c_char) -> ZapStatus {
+    guard(|| {
+        let (Some(config), Some(input), Some(output)) = (config.as_ref(), path_arg(input), path_arg(output)) else {
+            return ZapStatus::InvalidArgument;
+        };
+
+        let (Ok(source), Ok(destination)) = (File::open(input), File::create(&output)) else {
+            return ZapStatus::IOError;
+        };
+
+        let mut destination = BufWriter::new(destination);
+
+        let result = crate::compress_dyn(
+            &mut BufReader::new(source),
+            &mut destination,
+            &config.encryption,
+            &config.encryption_secret,
+            &config.compression,
+            &config.compression_level,
+            &config.signing,
+        );
+
+        match (result, destination.flush()) {
+            (Ok(()), Ok(())) => ZapStatus::Ok,
+            (Ok(()), Err(_)) => ZapStatus::IOError,
+            (Err(_), _) => {
+                drop(destination);
+                let _ = std::fs::remove_file(&output);
+                ZapStatus::CompressionFailed
+            },
+        }
+    })
+}
+
+/// Decompresses the file at 'input' into a new file at 'output'.
+///
+/// # Safety
+///
+/// As for zap_compress_file.
+#[no_mangle]
+pub unsafe extern "C" fn zap_decompress_file(config: *const ZapConfig, input: *const c_char, output: *const c_char) -> ZapStatus {
+    guard(|| {
+        let (Some(config), Some(input), Some(output)) = (config.as_ref(), path_arg(input), path_arg(output)) else {
+            return ZapStatus::InvalidArgument;
+        };
+
+        let (Ok(source), Ok(destination)) = (File::open(input), File::create(&output)) else {
+            return ZapStatus::IOError;
+        };
+
+        let mut destination = BufWriter::new(destination);
+
+        let result = crate::decompress_dyn(
+            &mut BufReader::new(source),
+            &mut destination,
+            &config.encryption,
+            &config.encryption_secret,
+            &config.compression,
+            &config.signing,
+        );
+
+        match (result, destination.flush()) {
+            (Ok(()), Ok(())) => ZapStatus::Ok,
+            (Ok(()), Err(_)) => ZapStatus::IOError,
+            (Err(_), _) => {
+                // Nothing partially decrypted is left behind.
+                drop(destination);
+                let _ = std::fs::remove_file(&output);
+                ZapStatus::DecompressionFailed
+            },
+        }
+    })
+}
+
+fn into_buffer(data: Vec<u8>) -> ZapBuffer {
+    let data = data.into_boxed_slice();
+    let len = data.len();
+
+    ZapBuffer {
+        data: Box::into_raw(data) as *mut u8,
+        len,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+
+    use super::*;
+
+    fn temp_path(name: &str) -> CString {
+        let path = std::env::temp_dir().join(format!("zap-ffi-{}-{}", name, std::process::id()));
+
+        CString::new(path.to_str().unwrap()).unwrap()
+    }
+
+    fn config(compression: u32, password: &[u8]) -> *mut ZapConfig {
+        let config = zap_config_new(compression, ZAP_ENCRYPTION_XCHACHA, 6);
+        assert!(!config.is_null());
+
+        assert_eq!(unsafe { zap_config_set_password(config, password.as_ptr(), password.len()) }, ZapStatus::Ok);
+
+        config
+    }
+
+    // Copied out of the buffer zap wrote, which is then freed.
+    fn call(
+        f: unsafe extern "C" fn(*const ZapConfig, *const u8, usize, *mut ZapBuffer) -> ZapStatus,
+        config: *const ZapConfig,
+        input: &[u8],
+    ) -> Result<Vec<u8>, ZapStatus> {
+        let mut output = ZapBuffer { data: ptr::null_mut(), len: 0 };
+
+        match unsafe { f(config, input.as_ptr(), input.len(), &mut output) } {
+            ZapStatus::Ok => unsafe {
+                let data = slice::from_raw_parts(output.data, output.len).to_vec();
+                zap_buffer_free(output);
+
+                Ok(data)
+            },
+            status => Err(status),
+        }
+    }
+
+    #[test]
+    fn buffers_round_trip() {
+        let input = b"through the c interface".repeat(100);
+
+        for compression in [ZAP_COMPRESSION_PASSTHROUGH, ZAP_COMPRESSION_LZ4, ZAP_COMPRESSION_GZIP, ZAP_COMPRESSION_SNAPPY] {
+            let config = config(compression, b"password");
+
+            let compressed = call(zap_compress_buffer, config, &input).unwrap();
+            let decompressed = call(zap_decompress_buffer, config, &compressed).unwrap();
+
+            unsafe { zap_config_free(config) };
+
+            assert_ne!(compressed, input);
+            assert_eq!(decompressed, input, "{}", compression);
+        }
+    }
+
+    #[test]
+    fn files_round_trip() {
+        let (input, compressed, output) = (temp_path("input"), temp_path("compressed"), temp_path("output"));
+        let data = b"through the c interface".repeat(100);
+        std::fs::write(input.to_str().unwrap(), &data).unwrap();
+
+        let config = config(ZAP_COMPRESSION_LZ4, b"password");
+
+        let statuses = unsafe {
+            [
+                zap_compress_file(config, input.as_ptr(), compressed.as_ptr()),
+                zap_decompress_file(config, compressed.as_ptr(), output.as_ptr()),
+            ]
+        };
+
+        unsafe { zap_config_free(config) };
+        let decompressed = std::fs::read(output.to_str().unwrap());
+
+        for path in [&input, &compressed, &output] {
+            let _ = std::fs::remove_file(path.to_str().unwrap());
+        }
+
+        assert_eq!(statuses, [ZapStatus::Ok, ZapStatus::Ok]);
+        assert_eq!(decompressed.unwrap(), data);
+    }
+
+    #[test]
+    fn wrong_password_fails_and_leaves_no_output() {
+        let (input, compressed, output) = (temp_path("secret"), temp_path("encrypted"), temp_path("decrypted"));
+        std::fs::write(input.to_str().unwrap(), b"secret".repeat(100)).unwrap();
+
+        let (right, wrong) = (config(ZAP_COMPRESSION_LZ4, b"password"), config(ZAP_COMPRESSION_LZ4, b"other"));
+
+        let statuses = unsafe {
+            [
+                zap_compress_file(right, input.as_ptr(), compressed.as_ptr()),
+                zap_decompress_file(wrong, compressed.as_ptr(), output.as_ptr()),
+            ]
+        };
+
+        let buffer = call(zap_compress_buffer, right, b"secret").and_then(|data| call(zap_decompress_buffer, wrong, &data));
+
+        unsafe {
+            zap_config_free(right);
+            zap_config_free(wrong);
+        }
+
+        let left = std::path::Path::new(output.to_str().unwrap()).exists();
+
+        for path in [&input, &compressed, &output] {
+            let _ = std::fs::remove_file(path.to_str().unwrap());
+        }
+
+        assert_eq!(statuses, [ZapStatus::Ok, ZapStatus::DecompressionFailed]);
+        assert_eq!(buffer, Err(ZapStatus::DecompressionFailed));
+        assert!(!left);
+    }
+
+    #[test]
+    fn invalid_arguments_are_rejected() {
+        // Out of range constants and levels.
+        assert!(zap_config_new(4, ZAP_ENCRYPTION_PASSTHROUGH, 6).is_null());
+        assert!(zap_config_new(ZAP_COMPRESSION_LZ4, 4, 6).is_null());
+        assert!(zap_config_new(ZAP_COMPRESSION_GZIP, ZAP_ENCRYPTION_PASSTHROUGH, 10).is_null());
+
+        let config = zap_config_new(ZAP_COMPRESSION_LZ4, ZAP_ENCRYPTION_PASSTHROUGH, 6);
+        let path = temp_path("unused");
+        let mut output = ZapBuffer { data: ptr::null_mut(), len: 0 };
+
+        let statuses = unsafe {
+            [
+                zap_config_set_password(ptr::null_mut(), b"password".as_ptr(), 8),
+                zap_config_set_password(config, ptr::null(), 8),
+                zap_config_set_password(config, b"".as_ptr(), 0),
+                zap_compress_buffer(ptr::null(), b"input".as_ptr(), 5, &mut output),
+                zap_compress_buffer(config, ptr::null(), 5, &mut output),
+                zap_compress_buffer(config, b"input".as_ptr(), 5, ptr::null_mut()),
+                zap_decompress_buffer(ptr::null(), b"input".as_ptr(), 5, &mut output),
+                zap_compress_file(ptr::null(), path.as_ptr(), path.as_ptr()),
+                zap_compress_file(config, ptr::null(), path.as_ptr()),
+                zap_decompress_file(config, path.as_ptr(), ptr::null()),
+            ]
+        };
+
+        unsafe {
+            zap_config_free(config);
+            zap_config_free(ptr::null_mut());
+        }
+
+        assert!(statuses.iter().all(|s| *s == ZapStatus::InvalidArgument), "{:?}", statuses);
+        assert!(output.data.is_null());
+    }
+}
@@ -0,0 +1,251 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, copy, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::{error::FooterError, recovery};
+
+// An archive may end with an optional footer holding a digest of
+// everything before it:
+//
+//   [payload][sha256 digest: 32][payload length: u64 le][FOOTER_MAGIC]
+//
+// The magic and the payload length must both agree with the size of the
+// file for a footer to be found, so a payload that happens to end with
+// the magic isn't mistaken for one. When the archive also has a recovery
+// record, the footer sits just before it.
+pub const FOOTER_MAGIC: &[u8; 8] = b"ZAPFOOT1";
+pub const FOOTER_SIZE: u64 = 48;
+
+/// Digest covering the whole container, as written by the archiver.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveFooter {
+    pub digest: [u8; 32],
+    pub payload_len: u64,
+}
+
+impl ArchiveFooter {
+    pub fn hex_digest(&self) -> String {
+        self.digest.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn to_bytes(&self) -> [u8; FOOTER_SIZE as usize] {
+        let mut bytes = [0; FOOTER_SIZE as usize];
+
+        bytes[..32].copy_from_slice(&self.digest);
+        bytes[32..40].copy_from_slice(&self.payload_len.to_le_bytes());
+        bytes[40..].copy_from_slice(FOOTER_MAGIC);
+
+        bytes
+    }
+}
+
+/// Hashes the archive at 'path' and appends a footer holding the digest.
+/// This must be done before a recovery record is appended.
+pub fn append_footer(path: &Path) -> Result<ArchiveFooter, FooterError> {
+    if read_footer(path)?.is_some() {
+        return Err(FooterError::AlreadyPresent(path.display().to_string()));
+    }
+
+    let mut file = OpenOptions::new().read(true).append(true).open(path)?;
+    let payload_len = file.metadata()?.len();
+
+    let footer = ArchiveFooter {
+        digest: hash_payload(&mut file, payload_len)?,
+        payload_len,
+    };
+
+    file.write_all(&footer.to_bytes())?;
+    file.sync_all()?;
+
+    Ok(footer)
+}
+
+/// Hashes everything written through it, so that the footer can be made
+/// while the archive is written rather than by reading it back after.
+/// Passes writes straight through when not hashing.
+pub struct HashingWriter<W: Write> {
+    inner: W,
+    hasher: Option<Sha256>,
+    len: u64,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W, hash: bool) -> Self {
+        HashingWriter {
+            inner,
+            hasher: hash.then(Sha256::new),
+            len: 0,
+        }
+    }
+
+    /// The footer for everything written, None when not hashing.
+    pub fn finalise(self) -> (W, Option<ArchiveFooter>) {
+        let footer = self.hasher.map(|hasher| ArchiveFooter {
+            digest: hasher.finalize().into(),
+            payload_len: self.len,
+        });
+
+        (self.inner, footer)
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..written]);
+            self.len += written as u64;
+        }
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Appends 'footer', from a HashingWriter, to the archive at 'path'. The
+/// archive must be exactly what was hashed, which is checked by length.
+pub fn write_footer(path: &Path, footer: &ArchiveFooter) -> Result<(), FooterError> {
+    let mut file = OpenOptions::new().append(true).open(path)?;
+    let len = file.metadata()?.len();
+
+    if len != footer.payload_len {
+        return Err(FooterError::LengthMismatch(path.display().to_string(), footer.payload_len, len));
+    }
+
+    file.write_all(&footer.to_bytes())?;
+    file.sync_all()?;
+
+    Ok(())
+}
+
+/// Reads the footer of the archive at 'path', None when it has none.
+pub fn read_footer(path: &Path) -> Result<Option<ArchiveFooter>, FooterError> {
+    let mut file = File::open(path)?;
+    let len = recovery::payload_len(&mut file)?;
+
+    if len < FOOTER_SIZE {
+        return Ok(None);
+    }
+
+    let mut bytes = [0; FOOTER_SIZE as usize];
+    file.seek(SeekFrom::Start(len - FOOTER_SIZE))?;
+    file.read_exact(&mut bytes)?;
+
+    let payload_len = u64::from_le_bytes(bytes[32..40].try_into().expect("Slice is 8 bytes"));
+
+    if &bytes[40..] != FOOTER_MAGIC || payload_len.checked_add(FOOTER_SIZE) != Some(len) {
+        return Ok(None);
+    }
+
+    Ok(Some(ArchiveFooter {
+        digest: bytes[..32].try_into().expect("Slice is 32 bytes"),
+        payload_len,
+    }))
+}
+
+/// Checks the payload of the archive at 'path' against its footer.
+pub fn verify_footer(path: &Path) -> Result<ArchiveFooter, FooterError> {
+    let footer = read_footer(path)?
+        .ok_or_else(|| FooterError::Missing(path.display().to_string()))?;
+
+    let digest = hash_payload(&mut File::open(path)?, footer.payload_len)?;
+
+    if digest != footer.digest {
+        return Err(FooterError::Mismatch(path.display().to_string()));
+    }
+
+    Ok(footer)
+}
+
+/// Copies the archive at 'path', without its footer, to 'destination'.
+pub fn copy_payload(path: &Path, footer: &ArchiveFooter, destination: &Path) -> Result<(), FooterError> {
+    let mut reader = BufReader::new(File::open(path)?).take(footer.payload_len);
+    let mut writer = BufWriter::new(File::create(destination)?);
+
+    copy(&mut reader, &mut writer)?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn hash_payload(file: &mut File, payload_len: u64) -> Result<[u8; 32], FooterError> {
+    file.seek(SeekFrom::Start(0))?;
+
+    let mut hasher = Sha256::new();
+    copy(&mut BufReader::new(file).take(payload_len), &mut hasher)?;
+
+    Ok(hasher.finalize().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("zap-footer-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn inline_digest_matches_reading_back() {
+        let payload: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let (inline, read_back) = (temp_path("inline"), temp_path("read-back"));
+
+        // Through a buffer, as the archive is written.
+        let mut writer = BufWriter::new(HashingWriter::new(File::create(&inline).unwrap(), true));
+        writer.write_all(&payload).unwrap();
+        let (_, footer) = writer.into_inner().map_err(|e| e.into_error()).unwrap().finalise();
+        let footer = footer.unwrap();
+        write_footer(&inline, &footer).unwrap();
+
+        std::fs::write(&read_back, &payload).unwrap();
+        let expected = append_footer(&read_back).unwrap();
+
+        let (inline_bytes, read_back_bytes) = (std::fs::read(&inline).unwrap(), std::fs::read(&read_back).unwrap());
+        let verified = verify_footer(&inline);
+
+        std::fs::remove_file(&inline).unwrap();
+        std::fs::remove_file(&read_back).unwrap();
+
+        assert_eq!(footer, expected);
+        assert_eq!(footer.payload_len, payload.len() as u64);
+        assert_eq!(inline_bytes, read_back_bytes);
+        assert_eq!(verified.unwrap(), expected);
+    }
+
+    #[test]
+    fn footer_must_match_the_file() {
+        let path = temp_path("grown");
+
+        let mut writer = HashingWriter::new(File::create(&path).unwrap(), true);
+        writer.write_all(b"hashed").unwrap();
+        let (mut file, footer) = writer.finalise();
+
+        // Written after hashing, so the digest no longer covers the file.
+        file.write_all(b" and not").unwrap();
+        drop(file);
+
+        let result = write_footer(&path, &footer.unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(result, Err(FooterError::LengthMismatch(_, 6, 14))));
+    }
+
+    #[test]
+    fn nothing_hashed_without_a_digest() {
+        let mut writer = HashingWriter::new(Vec::new(), false);
+        writer.write_all(b"unhashed").unwrap();
+
+        let (written, footer) = writer.finalise();
+
+        assert_eq!(written, b"unhashed");
+        assert!(footer.is_none());
+    }
+}
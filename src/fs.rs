@@ -0,0 +1,501 @@
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fs::{File, FileTimes, OpenOptions},
+    io::{Cursor, Error, ErrorKind, Read, Write},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use walkdir::WalkDir;
+
+/// Longest path, in bytes, that RealFs can open. Long paths on Windows are
+/// opened with the '\\?\' prefix, which raises the limit from 260.
+#[cfg(windows)]
+pub const MAX_PATH_LEN: usize = 32_767;
+#[cfg(unix)]
+pub const MAX_PATH_LEN: usize = libc::PATH_MAX as usize;
+#[cfg(not(any(unix, windows)))]
+pub const MAX_PATH_LEN: usize = 4096;
+
+/// Longest file or directory name, in bytes, on the common filesystems.
+pub const MAX_NAME_LEN: usize = 255;
+
+/// The operations the directory functions need from a filesystem, so
+/// that they can be run against something other than the disk.
+pub trait FileSystem: Sync {
+    type Reader: Read;
+    type Writer: Write;
+
+    fn open(&self, path: &Path) -> Result<Self::Reader, Error>;
+    fn create(&self, path: &Path) -> Result<Self::Writer, Error>;
+    fn metadata(&self, path: &Path) -> Result<FileInfo, Error>;
+    /// Every path under 'root', including 'root' itself. Parents are
+    /// listed before their children. Symlinks are not followed.
+    fn walk(&self, root: &Path) -> Result<Vec<PathBuf>, Error>;
+    fn create_dir_all(&self, path: &Path) -> Result<(), Error>;
+    fn remove_file(&self, path: &Path) -> Result<(), Error>;
+
+    /// As walk, but leaves out any path 'prune' returns true for, along
+    /// with everything under it. 'root' itself is never pruned.
+    fn walk_pruned(&self, root: &Path, prune: &mut dyn FnMut(&Path) -> bool) -> Result<Vec<PathBuf>, Error> {
+        let mut pruned: Vec<PathBuf> = Vec::new();
+
+        Ok(self.walk(root)?
+            .into_iter()
+            .filter(|path| {
+                if pruned.iter().any(|p| path.starts_with(p)) {
+                    return false;
+                }
+
+                if path != root && prune(path) {
+                    pruned.push(path.clone());
+                    return false;
+                }
+
+                true
+            })
+            .collect())
+    }
+
+    /// As open, but without updating the file's access time where the
+    /// filesystem supports it. Falls back to open anywhere else.
+    fn open_noatime(&self, path: &Path) -> Result<Self::Reader, Error> {
+        self.open(path)
+    }
+
+    /// The target of 'path' when it is a symlink, None otherwise or where
+    /// the filesystem has no symlinks.
+    fn read_link(&self, _path: &Path) -> Result<Option<PathBuf>, Error> {
+        Ok(None)
+    }
+
+    /// Sets the times of the file at 'path', leaving out any that are None.
+    /// Times the filesystem can't set are ignored.
+    fn set_times(&self, _path: &Path, _modified: Option<SystemTime>, _created: Option<SystemTime>) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Hints that 'path' is about to be read, so that reading it can start
+    /// in the background. Does nothing where that isn't supported.
+    fn prefetch(&self, _path: &Path) {}
+
+    /// Whether 'path' is short enough to be opened, checked before it is
+    /// so that it can be skipped rather than failing part way through.
+    fn fits_path(&self, _path: &Path) -> bool {
+        true
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok()
+    }
+
+    /// 'path' with links resolved, to tell whether two paths are the same
+    /// or one is within the other. By default only '.' and '..' are
+    /// resolved, from the path itself.
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf, Error> {
+        let mut canonical = PathBuf::new();
+
+        for component in path.components() {
+            match component {
+                Component::CurDir => (),
+                Component::ParentDir => {
+                    canonical.pop();
+                },
+                c => canonical.push(c),
+            }
+        }
+
+        Ok(canonical)
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>, Error> {
+        let mut data = Vec::new();
+        self.open(path)?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> Result<(), Error> {
+        let mut writer = self.create(path)?;
+        writer.write_all(data)?;
+        writer.flush()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileKind {
+    File,
+    Dir,
+    /// FIFOs, sockets, device nodes and the like.
+    Other,
+}
+
+/// The parts of a file's metadata used to detect changes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileInfo {
+    pub kind: FileKind,
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+    /// When the file was created, where the platform and filesystem
+    /// record it.
+    pub created: Option<SystemTime>,
+}
+
+/// The filesystem of the host, used unless another is given.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RealFs;
+
+impl FileSystem for RealFs {
+    type Reader = File;
+    type Writer = File;
+
+    fn open(&self, path: &Path) -> Result<Self::Reader, Error> {
+        File::open(long_path(path))
+    }
+
+    // O_NOATIME is only allowed for the owner of a file, or with
+    // CAP_FOWNER, so anyone else opens it normally.
+    #[cfg(target_os = "linux")]
+    fn open_noatime(&self, path: &Path) -> Result<Self::Reader, Error> {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        match std::fs::OpenOptions::new().read(true).custom_flags(libc::O_NOATIME).open(path) {
+            Err(e) if e.kind() == ErrorKind::PermissionDenied => File::open(path),
+            result => result,
+        }
+    }
+
+    fn create(&self, path: &Path) -> Result<Self::Writer, Error> {
+        File::create(long_path(path))
+    }
+
+    // The file only needs to be open to give the hint, what is read into
+    // the page cache stays there once it is closed.
+    #[cfg(target_os = "linux")]
+    fn prefetch(&self, path: &Path) {
+        use std::os::fd::AsRawFd;
+
+        if let Ok(file) = File::open(long_path(path)) {
+            unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_WILLNEED) };
+        }
+    }
+
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf, Error> {
+        std::fs::canonicalize(long_path(path))
+    }
+
+    fn read_link(&self, path: &Path) -> Result<Option<PathBuf>, Error> {
+        let path = long_path(path);
+
+        match path.symlink_metadata()?.file_type().is_symlink() {
+            true => std::fs::read_link(&path).map(Some),
+            false => Ok(None),
+        }
+    }
+
+    fn metadata(&self, path: &Path) -> Result<FileInfo, Error> {
+        let metadata = long_path(path).metadata()?;
+
+        let kind = match (metadata.is_file(), metadata.is_dir()) {
+            (true, _) => FileKind::File,
+            (_, true) => FileKind::Dir,
+            _ => FileKind::Other,
+        };
+
+        Ok(FileInfo {
+            kind,
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            // From statx on Linux, which older kernels and some
+            // filesystems don't support.
+            created: metadata.created().ok(),
+        })
+    }
+
+    fn walk(&self, root: &Path) -> Result<Vec<PathBuf>, Error> {
+        WalkDir::new(root)
+            .into_iter()
+            .map(|e| e.map(|e| e.into_path()).map_err(Error::from))
+            .collect()
+    }
+
+    // Pruned directories aren't read at all.
+    fn walk_pruned(&self, root: &Path, prune: &mut dyn FnMut(&Path) -> bool) -> Result<Vec<PathBuf>, Error> {
+        WalkDir::new(root)
+            .into_iter()
+            .filter_entry(|e| e.depth() == 0 || !prune(e.path()))
+            .map(|e| e.map(|e| e.into_path()).map_err(Error::from))
+            .collect()
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<(), Error> {
+        std::fs::create_dir_all(long_path(path))
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<(), Error> {
+        std::fs::remove_file(long_path(path))
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>, Error> {
+        std::fs::read(long_path(path))
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> Result<(), Error> {
+        std::fs::write(long_path(path), data)
+    }
+
+    // Only Windows and macOS can set the creation time, elsewhere it is
+    // always the time the file was extracted.
+    fn set_times(&self, path: &Path, modified: Option<SystemTime>, created: Option<SystemTime>) -> Result<(), Error> {
+        let mut times = FileTimes::new();
+
+        if let Some(modified) = modified {
+            times = times.set_modified(modified);
+        }
+
+        #[cfg(windows)]
+        if let Some(created) = created {
+            use std::os::windows::fs::FileTimesExt;
+            times = times.set_created(created);
+        }
+
+        #[cfg(target_os = "macos")]
+        if let Some(created) = created {
+            use std::os::macos::fs::FileTimesExt;
+            times = times.set_created(created);
+        }
+
+        #[cfg(not(any(windows, target_os = "macos")))]
+        let _ = created;
+
+        OpenOptions::new().write(true).open(long_path(path))?.set_times(times)
+    }
+
+    fn fits_path(&self, path: &Path) -> bool {
+        let path = long_path(path);
+
+        path.as_os_str().len() <= MAX_PATH_LEN
+            && path.components().all(|c| c.as_os_str().len() <= MAX_NAME_LEN)
+    }
+}
+
+// Windows only opens paths of 260 characters or more with the '\\?\'
+// prefix, which needs an absolute path as it turns off resolving '.' and
+// '..'. Network paths take the '\\?\UNC\' prefix instead.
+#[cfg(windows)]
+fn long_path(path: &Path) -> Cow<'_, Path> {
+    const MAX_PATH: usize = 260;
+
+    if path.as_os_str().len() < MAX_PATH {
+        return Cow::Borrowed(path);
+    }
+
+    let Ok(absolute) = std::path::absolute(path) else {
+        return Cow::Borrowed(path);
+    };
+
+    let absolute = absolute.to_string_lossy();
+
+    let prefixed = match absolute.strip_prefix(r"\\") {
+        Some(_) if absolute.starts_with(r"\\?\") => return Cow::Borrowed(path),
+        Some(network) => format!(r"\\?\UNC\{}", network),
+        None => format!(r"\\?\{}", absolute),
+    };
+
+    Cow::Owned(PathBuf::from(prefixed))
+}
+
+#[cfg(not(windows))]
+fn long_path(path: &Path) -> Cow<'_, Path> {
+    Cow::Borrowed(path)
+}
+
+#[derive(Debug, Clone)]
+enum MemoryNode {
+    File(Arc<Mutex<Vec<u8>>>),
+    Dir,
+}
+
+/// A filesystem held entirely in memory. Paths are case-sensitive and
+/// files have no modification time.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryFs {
+    nodes: Arc<Mutex<BTreeMap<PathBuf, MemoryNode>>>,
+}
+
+impl MemoryFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a file, creating its parent directories.
+    pub fn insert<P: AsRef<Path>>(&self, path: P, data: &[u8]) -> Result<(), Error> {
+        self.write(path.as_ref(), data)
+    }
+
+    /// Every file in the filesystem with its contents.
+    pub fn files(&self) -> BTreeMap<PathBuf, Vec<u8>> {
+        self.nodes
+            .lock()
+            .expect("MemoryFs lock poisoned")
+            .iter()
+            .filter_map(|(path, node)| match node {
+                MemoryNode::File(data) => Some((path.clone(), data.lock().expect("MemoryFs lock poisoned").clone())),
+                MemoryNode::Dir => None,
+            })
+            .collect()
+    }
+
+    fn node(&self, path: &Path) -> Option<MemoryNode> {
+        self.nodes.lock().expect("MemoryFs lock poisoned").get(path).cloned()
+    }
+}
+
+/// Writes to a file in a MemoryFs as they are made.
+pub struct MemoryWriter {
+    data: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Write for MemoryWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.data.lock().expect("MemoryFs lock poisoned").extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl FileSystem for MemoryFs {
+    type Reader = Cursor<Vec<u8>>;
+    type Writer = MemoryWriter;
+
+    fn open(&self, path: &Path) -> Result<Self::Reader, Error> {
+        match self.node(path) {
+            Some(MemoryNode::File(data)) => Ok(Cursor::new(data.lock().expect("MemoryFs lock poisoned").clone())),
+            Some(MemoryNode::Dir) => Err(Error::other(format!("Is a directory: {}", path.display()))),
+            None => Err(ErrorKind::NotFound.into()),
+        }
+    }
+
+    fn create(&self, path: &Path) -> Result<Self::Writer, Error> {
+        if let Some(parent) = path.parent() {
+            if !matches!(self.node(parent), Some(MemoryNode::Dir)) && !parent.as_os_str().is_empty() {
+                return Err(ErrorKind::NotFound.into());
+            }
+        }
+
+        let data = Arc::new(Mutex::new(Vec::new()));
+
+        let mut nodes = self.nodes.lock().expect("MemoryFs lock poisoned");
+
+        if let Some(MemoryNode::Dir) = nodes.get(path) {
+            return Err(Error::other(format!("Is a directory: {}", path.display())));
+        }
+
+        nodes.insert(path.to_path_buf(), MemoryNode::File(data.clone()));
+
+        Ok(MemoryWriter { data })
+    }
+
+    fn metadata(&self, path: &Path) -> Result<FileInfo, Error> {
+        match self.node(path) {
+            Some(MemoryNode::File(data)) => Ok(FileInfo {
+                kind: FileKind::File,
+                len: data.lock().expect("MemoryFs lock poisoned").len() as u64,
+                modified: None,
+                created: None,
+            }),
+            Some(MemoryNode::Dir) => Ok(FileInfo {
+                kind: FileKind::Dir,
+                len: 0,
+                modified: None,
+                created: None,
+            }),
+            None => Err(ErrorKind::NotFound.into()),
+        }
+    }
+
+    fn walk(&self, root: &Path) -> Result<Vec<PathBuf>, Error> {
+        if self.node(root).is_none() {
+            return Err(ErrorKind::NotFound.into());
+        }
+
+        // BTreeMap ordering already places parents before children.
+        Ok(self.nodes
+            .lock()
+            .expect("MemoryFs lock poisoned")
+            .keys()
+            .filter(|path| path.starts_with(root))
+            .cloned()
+            .collect())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<(), Error> {
+        let mut nodes = self.nodes.lock().expect("MemoryFs lock poisoned");
+
+        for dir in path.ancestors().filter(|dir| !dir.as_os_str().is_empty()) {
+            match nodes.get(dir) {
+                Some(MemoryNode::File(_)) => {
+                    return Err(Error::other(format!("Not a directory: {}", dir.display())))
+                },
+                Some(MemoryNode::Dir) => (),
+                None => {
+                    nodes.insert(dir.to_path_buf(), MemoryNode::Dir);
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<(), Error> {
+        let mut nodes = self.nodes.lock().expect("MemoryFs lock poisoned");
+
+        match nodes.get(path) {
+            Some(MemoryNode::File(_)) => {
+                nodes.remove(path);
+                Ok(())
+            },
+            Some(MemoryNode::Dir) => Err(Error::other(format!("Is a directory: {}", path.display()))),
+            None => Err(ErrorKind::NotFound.into()),
+        }
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> Result<(), Error> {
+        if let Some(parent) = path.parent() {
+            self.create_dir_all(parent)?;
+        }
+
+        let mut writer = self.create(path)?;
+        writer.write_all(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_walk_lists_parents_first() {
+        let fs = MemoryFs::new();
+        fs.insert("/root/b/file", b"b").unwrap();
+        fs.insert("/root/a", b"a").unwrap();
+
+        assert_eq!(fs.walk(Path::new("/root")).unwrap(), ["/root", "/root/a", "/root/b", "/root/b/file"].map(PathBuf::from));
+        assert_eq!(fs.walk(Path::new("/missing")).unwrap_err().kind(), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn memory_files_are_not_directories() {
+        let fs = MemoryFs::new();
+        fs.insert("/root/file", b"file").unwrap();
+
+        assert!(fs.create(Path::new("/root/file/child")).is_err());
+        assert!(fs.create_dir_all(Path::new("/root/file/child")).is_err());
+        assert!(fs.remove_file(Path::new("/root")).is_err());
+        assert_eq!(fs.metadata(Path::new("/root/file")).unwrap().len, 4);
+    }
+}
@@ -0,0 +1,121 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{copy, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write},
+    ops::RangeInclusive,
+    path::Path,
+};
+
+use crate::{compression::CompressionType, encryption::EncryptionType, error::HeaderError};
+
+// Archives start with a header so that they can be recognised, and their
+// algorithms found, whatever they are named:
+//
+//   [HEADER_MAGIC][version: u8][encryption: u8][compression: u8][reserved: 5]
+//
+// Archives from before the header were only recognised by their extension.
+// The manifest inside the archive takes precedence over the header, which
+// is only what the archive was created with.
+pub const HEADER_MAGIC: &[u8; 8] = b"ZAPARCH1";
+pub const HEADER_SIZE: u64 = 16;
+// 2 from when entries were encrypted as streams.
+const HEADER_VERSION: u8 = 2;
+
+/// Header versions this version of zap can extract. Archives written by a
+/// later version are refused rather than read as if they were this one.
+pub const SUPPORTED_VERSIONS: RangeInclusive<u8> = 1..=HEADER_VERSION;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveHeader {
+    pub encryption: EncryptionType,
+    pub compression: CompressionType,
+}
+
+impl ArchiveHeader {
+    fn to_bytes(&self) -> [u8; HEADER_SIZE as usize] {
+        let mut bytes = [0; HEADER_SIZE as usize];
+
+        bytes[..8].copy_from_slice(HEADER_MAGIC);
+        bytes[8] = HEADER_VERSION;
+        bytes[9] = match self.encryption {
+            EncryptionType::Passthrough => 0,
+            EncryptionType::XChaCha => 1,
+            EncryptionType::AesGcm => 2,
+            EncryptionType::ChaCha => 3,
+        };
+        bytes[10] = match self.compression {
+            CompressionType::Passthrough | CompressionType::Auto => 0,
+            CompressionType::Lz4 => 1,
+            CompressionType::Gzip => 2,
+            CompressionType::Snappy => 3,
+        };
+
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8; HEADER_SIZE as usize]) -> Result<Self, HeaderError> {
+        if !SUPPORTED_VERSIONS.contains(&bytes[8]) {
+            return Err(HeaderError::UnsupportedVersion(
+                bytes[8],
+                *SUPPORTED_VERSIONS.start(),
+                *SUPPORTED_VERSIONS.end(),
+            ));
+        }
+
+        let encryption = match bytes[9] {
+            0 => EncryptionType::Passthrough,
+            1 => EncryptionType::XChaCha,
+            2 => EncryptionType::AesGcm,
+            3 => EncryptionType::ChaCha,
+            n => return Err(HeaderError::UnknownAlgorithm(n)),
+        };
+
+        let compression = match bytes[10] {
+            0 => CompressionType::Passthrough,
+            1 => CompressionType::Lz4,
+            2 => CompressionType::Gzip,
+            3 => CompressionType::Snappy,
+            n => return Err(HeaderError::UnknownAlgorithm(n)),
+        };
+
+        Ok(ArchiveHeader { encryption, compression })
+    }
+}
+
+/// Written before the container, as the first bytes of the archive.
+pub fn write_header<W: Write>(writer: &mut W, header: &ArchiveHeader) -> Result<(), HeaderError> {
+    Ok(writer.write_all(&header.to_bytes())?)
+}
+
+/// Returns None for archives created before the header was added.
+pub fn read_header(path: &Path) -> Result<Option<ArchiveHeader>, HeaderError> {
+    let mut bytes = [0; HEADER_SIZE as usize];
+
+    match File::open(path)?.read_exact(&mut bytes) {
+        Ok(()) => (),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+
+    if &bytes[..8] != HEADER_MAGIC {
+        return Ok(None);
+    }
+
+    ArchiveHeader::from_bytes(&bytes).map(Some)
+}
+
+/// Copies bytes 'start' to 'end' of the archive at 'path' to 'destination',
+/// eg: to leave out the header, footer and recovery record of an archive.
+/// 'destination' is created, failing if anything is already there rather
+/// than writing through it, eg: a symlink.
+pub fn copy_range(path: &Path, start: u64, end: u64, destination: &Path) -> Result<(), HeaderError> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(start))?;
+
+    let mut reader = BufReader::new(file).take(end.saturating_sub(start));
+    let mut writer = BufWriter::new(OpenOptions::new().write(true).create_new(true).open(destination)?);
+
+    copy(&mut reader, &mut writer)?;
+    writer.flush()?;
+
+    Ok(())
+}
@@ -1,5 +1,10 @@
 
-use std::io::Error;
+use std::{
+    io::{Error, Read, Write},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{error::SizeLimitError, options::RetryPolicy};
 
 // Cleanup is a function that signals for all nested
 // writers/readers that no more will be read/written
@@ -31,3 +36,99 @@ pub fn bind_io_constructors<T, U, V, W>(
     move | x | c(b(a(x)))
 }
 
+// Fails writes that would take the file past 'max_file_size', or every
+// file sharing 'total' past 'max_total_size', so that an entry that
+// expands far beyond its stored size is stopped before it is written.
+pub(crate) struct SizeLimitedWriter<'a, W> {
+    inner: W,
+    written: u64,
+    max_file_size: Option<u64>,
+    total: &'a AtomicU64,
+    max_total_size: Option<u64>,
+}
+
+impl<'a, W> SizeLimitedWriter<'a, W> {
+    pub(crate) fn new(inner: W, max_file_size: Option<u64>, total: &'a AtomicU64, max_total_size: Option<u64>) -> Self {
+        SizeLimitedWriter {
+            inner,
+            written: 0,
+            max_file_size,
+            total,
+            max_total_size,
+        }
+    }
+}
+
+impl<W: Write> Write for SizeLimitedWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let len = buf.len() as u64;
+
+        if let Some(max) = self.max_file_size.filter(|max| self.written + len > *max) {
+            return Err(Error::other(SizeLimitError::Entry(max)));
+        }
+
+        // Reserved before writing so that concurrent writers can't
+        // all pass the check at once.
+        let total = self.total.fetch_add(len, Ordering::Relaxed) + len;
+
+        if let Some(max) = self.max_total_size.filter(|max| total > *max) {
+            return Err(Error::other(SizeLimitError::Total(max)));
+        }
+
+        let written = self.inner.write(buf).inspect_err(|_| {
+            self.total.fetch_sub(len, Ordering::Relaxed);
+        })?;
+
+        self.total.fetch_sub(len - written as u64, Ordering::Relaxed);
+        self.written += written as u64;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.inner.flush()
+    }
+}
+
+// A read or write that fails hasn't consumed or written anything, so
+// it's safe to try it again on a transient error.
+pub(crate) struct RetryReader<R> {
+    inner: R,
+    policy: RetryPolicy,
+}
+
+impl<R> RetryReader<R> {
+    pub(crate) fn new(inner: R, policy: RetryPolicy) -> Self {
+        RetryReader { inner, policy }
+    }
+}
+
+impl<R: Read> Read for RetryReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let inner = &mut self.inner;
+        self.policy.run(|| inner.read(buf))
+    }
+}
+
+pub(crate) struct RetryWriter<W> {
+    inner: W,
+    policy: RetryPolicy,
+}
+
+impl<W> RetryWriter<W> {
+    pub(crate) fn new(inner: W, policy: RetryPolicy) -> Self {
+        RetryWriter { inner, policy }
+    }
+}
+
+impl<W: Write> Write for RetryWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let inner = &mut self.inner;
+        self.policy.run(|| inner.write(buf))
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        let inner = &mut self.inner;
+        self.policy.run(|| inner.flush())
+    }
+}
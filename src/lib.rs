@@ -1,48 +1,69 @@
+#[cfg(feature = "async")]
+pub mod async_io;
+pub mod checkpoint;
 pub mod compression;
 pub mod encryption;
 pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod footer;
+pub mod fs;
+pub mod header;
 pub mod internal;
+pub mod manifest;
+pub mod options;
 pub mod pipeline;
 pub mod prelude;
+pub mod processor;
+pub mod progress;
+pub mod recovery;
+pub mod report;
 pub mod signing;
+// Public only for benches/zero_runs.rs, see the module comment.
+#[doc(hidden)]
+pub mod sparse;
+mod trace;
 
-use core::panic;
 use std::{
-    backtrace,
-    path::{self, Path, PathBuf},
+    collections::{BTreeMap, HashMap, HashSet},
+    io::{BufWriter, ErrorKind, Read, Write},
+    path::{self, Component, Path, PathBuf},
     sync::Arc,
+    sync::atomic::AtomicU64,
+    time::{Duration, Instant},
 };
 
-use crate::pipeline::ProcessingPipeline;
-use compression::CompressionType;
+use crate::pipeline::{PipelineConfig, ProcessingPipeline};
+use checkpoint::{settings_digest, Checkpoint};
+use compression::{blocks::{splits_into_blocks, BlockCompressor, BLOCK_SIZE}, CompressionType};
 use crossbeam::sync::WaitGroup;
-use encryption::{EncryptionSecret, EncryptionType};
-use error::{CompressionError, DecompressionError, PathRewriteError};
-use log::{debug, error, info};
-use rayon::{ThreadPoolBuilder, prelude::{IntoParallelRefIterator, IntoParallelIterator}, prelude::ParallelIterator};
+use encryption::{
+    aes_gcm_256::AesGcmAlgorithm, chachapoly::ChaChaPolyAlgorithm, xchachapoly::XChaChaPolyAlgorithm, DecryptionAlgorithm,
+    EncryptionFraming, EncryptionSecret, EncryptionType, SecretType,
+};
+use fs::{FileInfo, FileKind, FileSystem, RealFs};
+use internal::{RetryReader, RetryWriter, SizeLimitedWriter};
+use error::{
+    CompressionError, DecompressionError, DecryptError, EncryptionSecretError, EncryptorInitError, PathRewriteError,
+    PipelineCompressionError, PipelineDecompressionError,
+};
+use log::{debug, error, info, warn};
+use manifest::{is_manifest_path, FileRecord, Manifest, MAX_COMMENT_LEN};
+use options::{ChangePolicy, CompressOptions, ConflictPolicy, DecompressOptions, Glob, CHANGE_RETRIES, VCS_NAMES};
+use report::{ArchiveComparison, ArchiveListing, DirectoryReport, DuplicateFile, FileOutcome, FileReport, ListedEntry, SkipReason, SkippedFile, VerifiedFile};
+use rayon::{prelude::{IntoParallelRefIterator, IntoParallelIterator, IndexedParallelIterator}, prelude::ParallelIterator};
+use sha2::{Digest, Sha256};
 use signing::SigningType;
-use walkdir::WalkDir;
+use trace::FileSpan;
 
-pub struct Processor {}
+pub use processor::{Processor, ProcessorBuilder};
 
 pub fn build_common_extension(enc: &EncryptionType, comp: &CompressionType) -> String {
-    let mut ext = String::new();
-
-    match enc {
-        EncryptionType::Passthrough => (),
-        EncryptionType::XChaCha => ext.push_str(".xcha"),
-        EncryptionType::AesGcm => ext.push_str(".aes"),
-        EncryptionType::ChaCha => ext.push_str(".cha"),
-    }
-
-    match comp {
-        CompressionType::Passthrough => (),
-        CompressionType::Lz4 => ext.push_str(".lz4"),
-        CompressionType::Gzip => ext.push_str(".gz"),
-        CompressionType::Snappy => ext.push_str(".sz"),
-    }
-    
-    ext
+    [enc.extension(), comp.extension()]
+        .into_iter()
+        .flatten()
+        .map(|ext| format!(".{}", ext))
+        .collect()
 }
 
 fn rewrite_ext(path: &Path, extension: &str) -> Result<PathBuf, CompressionError> {
@@ -62,7 +83,7 @@ fn rewrite_ext(path: &Path, extension: &str) -> Result<PathBuf, CompressionError
     }
 }
 
-fn clear_ext(path: &Path) -> Result<PathBuf, CompressionError> {
+fn clear_ext(path: &Path) -> Result<PathBuf, PathRewriteError> {
     let input_file_path: PathBuf = path.to_path_buf();
 
     let mut input_file_extensions: Vec<&str> = match input_file_path
@@ -71,11 +92,11 @@ fn clear_ext(path: &Path) -> Result<PathBuf, CompressionError> {
                 .expect("Unable to convert extension to string.")
                 .split('.')
                 .rev().collect(),
-            None => return Err(PathRewriteError::FileNameError("Failed to get file name".into()).into()),
+            None => return Err(PathRewriteError::FileNameError("Failed to get file name".into())),
         };
 
     input_file_extensions
-        .retain(|ext| !["xcha", "aes", "cha", "lz4", "gz", "sz"].contains(ext));
+        .retain(|ext| EncryptionType::from_extension(ext).is_none() && CompressionType::from_extension(ext).is_none());
 
     input_file_extensions.reverse();
 
@@ -90,36 +111,62 @@ fn clear_ext(path: &Path) -> Result<PathBuf, CompressionError> {
         ))
 }
 
-fn get_types_from_extensions(path: &Path) -> Result<(EncryptionType, CompressionType), CompressionError> {
+fn get_types_from_extensions(path: &Path) -> Result<(EncryptionType, CompressionType), PathRewriteError> {
     let input_file_path: PathBuf = path.to_path_buf();
 
-    let mut input_file_extensions: Vec<&str> = match input_file_path
+    let input_file_extensions: Vec<&str> = match input_file_path
         .file_name() {
             Some(ext) => ext.to_str()
                 .expect("Unable to convert extension to string.")
                 .split('.')
                 .rev().collect(),
-            None => return Err(PathRewriteError::FileNameError("Failed to get file name".into()).into()),
+            None => return Err(PathRewriteError::FileNameError("Failed to get file name".into())),
         };
 
     let mut encryption_algorithm: EncryptionType = EncryptionType::Passthrough;
     let mut compression_algorithm: CompressionType = CompressionType::Passthrough;
 
     for ext in input_file_extensions.iter() {
-        match *ext {
-            "xcha" => encryption_algorithm = EncryptionType::XChaCha,
-            "aes" => encryption_algorithm = EncryptionType::AesGcm,
-            "cha" => encryption_algorithm = EncryptionType::ChaCha,
-            "lz4" => compression_algorithm = CompressionType::Lz4,
-            "gz" => compression_algorithm = CompressionType::Gzip,
-            "sz" => compression_algorithm = CompressionType::Snappy,
-            _ => (),
+        if let Some(found) = EncryptionType::from_extension(ext) {
+            encryption_algorithm = found;
+        }
+
+        if let Some(found) = CompressionType::from_extension(ext) {
+            compression_algorithm = found;
         }
     }
 
     Ok((encryption_algorithm, compression_algorithm))
 }
 
+// The manifest takes precedence over the requested algorithm, which in turn
+// is only inferred from the entry's extension when left as Auto.
+// 'relative' is the path of the entry within the archive, used to look up
+// entries the manifest records a different algorithm for. When 'force' is
+// set a requested algorithm is used for every entry instead.
+fn resolve_compression(
+    compression: &CompressionType,
+    manifest: Option<&Manifest>,
+    relative: &Path,
+    path: &Path,
+    force: bool,
+) -> Result<CompressionType, PathRewriteError> {
+    if force && *compression != CompressionType::Auto {
+        return Ok(compression.clone());
+    }
+
+    if let Some(c) = manifest.and_then(|m| m.entries.get(relative)) {
+        return Ok(c.clone());
+    }
+
+    match (manifest, compression) {
+        (Some(m), _) if m.compression != CompressionType::Auto => Ok(m.compression.clone()),
+        (_, CompressionType::Auto) => Ok(get_types_from_extensions(path)?.1),
+        (_, c) => Ok(c.clone()),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn compress_directory(
     input_folder_path: &str,
     output_folder_path: &str,
@@ -128,199 +175,2638 @@ pub fn compress_directory(
     compression: CompressionType,
     compression_level: flate2::Compression,
     signing: SigningType,
-) -> Result<(), CompressionError> {
+    options: &CompressOptions,
+) -> Result<DirectoryReport, CompressionError> {
+    compress_directory_in(
+        &RealFs,
+        input_folder_path,
+        output_folder_path,
+        encryption,
+        encryption_secret,
+        compression,
+        compression_level,
+        signing,
+        options,
+    )
+}
 
-    info!("Compressing directory: {:?} -> {:?}", input_folder_path, output_folder_path);
-    info!("Encryption: {:?}", encryption);
-    info!("Compression: {:?}", compression);
-    info!("Compression level: {:?}", compression_level);
-    info!("Signing: {:?}", signing);
+/// Same as compress_directory, but reads and writes through 'fs'.
+#[allow(clippy::too_many_arguments)]
+pub fn compress_directory_in<F: FileSystem>(
+    fs: &F,
+    input_folder_path: &str,
+    output_folder_path: &str,
+    encryption: EncryptionType,
+    encryption_secret: EncryptionSecret,
+    compression: CompressionType,
+    compression_level: flate2::Compression,
+    signing: SigningType,
+    options: &CompressOptions,
+) -> Result<DirectoryReport, CompressionError> {
+    compress_roots_in(
+        fs,
+        &[(PathBuf::from(input_folder_path), PathBuf::new())],
+        output_folder_path,
+        encryption,
+        encryption_secret,
+        compression,
+        compression_level,
+        signing,
+        options,
+    )
+}
+
+/// Compresses several directories into one, each under a directory named
+/// after it. Directories with the same name are told apart as 'name (1)',
+/// 'name (2)' and so on, in the order given.
+#[allow(clippy::too_many_arguments)]
+pub fn compress_directories(
+    input_folder_paths: &[&str],
+    output_folder_path: &str,
+    encryption: EncryptionType,
+    encryption_secret: EncryptionSecret,
+    compression: CompressionType,
+    compression_level: flate2::Compression,
+    signing: SigningType,
+    options: &CompressOptions,
+) -> Result<DirectoryReport, CompressionError> {
+    compress_directories_in(
+        &RealFs,
+        input_folder_paths,
+        output_folder_path,
+        encryption,
+        encryption_secret,
+        compression,
+        compression_level,
+        signing,
+        options,
+    )
+}
 
-    let common_extension = build_common_extension(&encryption, &compression);
+/// Same as compress_directories, but reads and writes through 'fs'.
+#[allow(clippy::too_many_arguments)]
+pub fn compress_directories_in<F: FileSystem>(
+    fs: &F,
+    input_folder_paths: &[&str],
+    output_folder_path: &str,
+    encryption: EncryptionType,
+    encryption_secret: EncryptionSecret,
+    compression: CompressionType,
+    compression_level: flate2::Compression,
+    signing: SigningType,
+    options: &CompressOptions,
+) -> Result<DirectoryReport, CompressionError> {
+    compress_roots_in(
+        fs,
+        &root_prefixes(&without_overlaps(fs, input_folder_paths)?),
+        output_folder_path,
+        encryption,
+        encryption_secret,
+        compression,
+        compression_level,
+        signing,
+        options,
+    )
+}
 
-    let input_paths: Vec<PathBuf> = WalkDir::new(input_folder_path)
-        .into_iter()
-        .map(|e| e.unwrap_or_else(|e| panic!("Error: {:?}", e)))
-        .map(|e| e.into_path())
-        // TODO : Explore allow follow symlnks option
-        .filter(|e|e.is_file())
-        .collect();
+// Inputs that are the same as, or within, another are left out, with a
+// warning, so that no file is archived twice. Their files are archived
+// under the input holding them. Of the same directory given twice, the
+// first is kept.
+fn without_overlaps<'a, F: FileSystem>(fs: &F, input_folder_paths: &[&'a str]) -> Result<Vec<&'a str>, std::io::Error> {
+    let canonical: Vec<PathBuf> = input_folder_paths
+        .iter()
+        .map(|input| fs.canonicalize(Path::new(input)))
+        .collect::<Result<_, _>>()?;
 
-    let output_paths: Vec<PathBuf> = input_paths
+    let kept = input_folder_paths
         .iter()
-        .filter_map(|e| match e.strip_prefix(input_folder_path) {
-            Ok(p) => rewrite_ext(p, &common_extension).ok(),
-            Err(e) => {
-                panic!("Error: {:?}", e);
-                // None
+        .enumerate()
+        .filter(|(n, input)| {
+            let path = &canonical[*n];
+
+            let holding = canonical.iter()
+                .enumerate()
+                .find(|(m, other)| m != n && path.starts_with(other) && (path != *other || m < n));
+
+            match holding {
+                Some((m, other)) if path == other => {
+                    warn!("'{}' is the same directory as '{}', archiving it once", input, input_folder_paths[m]);
+                    false
+                },
+                Some((m, _)) => {
+                    warn!("'{}' is within '{}', archiving its files under '{}' only", input, input_folder_paths[m], input_folder_paths[m]);
+                    false
+                },
+                None => true,
             }
         })
-        .map(
-            |p| Path::new(output_folder_path).join(p)
-        ).collect();
-
-    let jobs: Vec<(PathBuf, PathBuf)> = input_paths
-        .into_iter()
-        .zip(output_paths)
+        .map(|(_, input)| *input)
         .collect();
 
-    jobs.iter().for_each(
-        |(input, output)| debug!(
-            "Compressing: {:?} -> {:?}",
-            input.display(),
-            output.display()
-        )
-    );
+    Ok(kept)
+}
 
-    jobs.par_iter()
-        .map(
-            |(_, output)| output.parent().unwrap()
-        )
-        .for_each(
-        |parent| 
-            std::fs::create_dir_all(parent)
-                .expect("Failed to create all the required directories/subdirectories")
-        );
-    
-    jobs.into_par_iter()
-        .for_each(
-            |(input, output)| {
-                let pipeline = ProcessingPipeline::builder()
-                    .with_source(input.clone())
-                    .with_destination(output)
-                    .with_compression(&compression)
-                    .with_compression_level(&compression_level)
-                    .with_encryption(&encryption)
-                    .with_encryption_secret(&encryption_secret)
-                    .with_signing(&signing)
-                    .build();
-
-                    match pipeline.compress_dir() {
-                        Ok(_) => debug!(
-                            "Finished compressing '{:?}' successfully",
-                            input.display()
-                        ),
-                        Err(e) => {
-                            
-                            let bt = backtrace::Backtrace::capture();
-            
-                            error!(
-                                "Error while compressing '{}': {:?}",
-                                input.display(),
-                                e
-                            );
-                            log::trace!(
-                                "Error while compressing '{}': {:?}",
-                                input.display(),
-                                bt
-                            );
-            
-                            error!("Error compressing file {:?}", e);
-                            panic!();
-        }}});
+// Each input paired with the directory it is stored under. Inputs
+// without a name of their own, eg: '/', are stored under 'root'.
+fn root_prefixes(input_folder_paths: &[&str]) -> Vec<(PathBuf, PathBuf)> {
+    let mut used: HashMap<String, usize> = HashMap::new();
 
-    Ok(())
+    input_folder_paths
+        .iter()
+        .map(|input| {
+            let name = Path::new(input)
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "root".into());
+
+            let count = used.entry(name.clone()).or_insert(0);
+
+            let prefix = match *count {
+                0 => name,
+                n => format!("{} ({})", name, n),
+            };
+
+            *count += 1;
+
+            (PathBuf::from(input), PathBuf::from(prefix))
+        })
+        .collect()
 }
 
-// todo: This function will alter the filename of binary files eg:
-// a binary called 'someBinary' will end up as 'someBinary.'
-pub fn decompress_directory(
-    input_folder_path: &str,
+// Every file under each root is stored under the root's prefix, which is
+// empty when there is only the one root.
+#[allow(clippy::too_many_arguments)]
+fn compress_roots_in<F: FileSystem>(
+    fs: &F,
+    roots: &[(PathBuf, PathBuf)],
     output_folder_path: &str,
     encryption: EncryptionType,
     encryption_secret: EncryptionSecret,
     compression: CompressionType,
+    compression_level: flate2::Compression,
     signing: SigningType,
-) -> Result<(), DecompressionError> {
-    
-    info!("Decompressing directory: {:?} -> {:?}", input_folder_path, output_folder_path);
+    options: &CompressOptions,
+) -> Result<DirectoryReport, CompressionError> {
+
+    for (root, prefix) in roots.iter() {
+        info!("Compressing directory: {:?} -> {:?}", root, Path::new(output_folder_path).join(prefix));
+    }
+
     info!("Encryption: {:?}", encryption);
     info!("Compression: {:?}", compression);
+    info!("Compression level: {:?}", compression_level);
     info!("Signing: {:?}", signing);
+    info!("On change: {:?}", options.on_change);
+    info!("Rules: {:?}", options.rules);
+    info!("In-memory threshold: {} bytes", options.in_memory_threshold);
+
+    // Only algorithms that take a level are given it, the rest ignore it.
+    for algorithm in std::iter::once(&compression).chain(options.rules.iter().map(|r| &r.compression)) {
+        if algorithm.level_range().is_some() {
+            debug!("Effective compression level for {:?}: {}", algorithm, compression_level.level());
+        }
+    }
+
+    info!("Exclude hidden: {}", options.exclude_hidden);
+    info!("Exclude VCS: {}", options.exclude_vcs);
+    info!("No atime: {}", options.no_atime);
+    info!("Dedup: {}", options.dedup);
+    info!("Prefetch: {} file(s)", options.prefetch);
+
+    // Checked before any work is done rather than when the manifest is written.
+    if let Some(comment) = options.comment.as_ref().filter(|c| c.len() > MAX_COMMENT_LEN) {
+        return Err(CompressionError::CommentTooLong(comment.len(), MAX_COMMENT_LEN));
+    }
+
+    // A duplicate would be stored once, under its original's secret, and
+    // could only be extracted by whoever holds that.
+    if options.dedup && options.secret_resolver.is_some() {
+        return Err(CompressionError::IncompatibleOptions("dedup can't be used with a secret resolver"));
+    }
+
+    let mut report = DirectoryReport::default();
 
-    let input_paths: Vec<PathBuf> = WalkDir::new(input_folder_path)
+    // Only the pruned file or directory itself is reported, not
+    // everything beneath a pruned directory.
+    let mut pruned = Vec::new();
+
+    let mut prune = |path: &Path| {
+        let Some(name) = path.file_name().map(|n| n.to_string_lossy()) else {
+            return false;
+        };
+
+        let reason = if options.exclude_vcs && VCS_NAMES.contains(&name.as_ref()) {
+            SkipReason::VersionControl
+        } else if options.exclude_hidden && name.starts_with('.') {
+            SkipReason::Hidden
+        } else {
+            return false;
+        };
+
+        pruned.push(SkippedFile { path: path.to_path_buf(), reason });
+
+        true
+    };
+
+    let walking = Instant::now();
+
+    // Paired with the path each is stored under, relative to the output.
+    let mut walked: Vec<(PathBuf, PathBuf)> = Vec::new();
+
+    for (root, prefix) in roots.iter() {
+        let root = resolve_root(fs, root)?;
+
+        for path in fs.walk_pruned(&root, &mut prune)? {
+            let relative = path.strip_prefix(&root)
+                .map_err(|e| PathRewriteError::FileNameError(e.to_string()))?;
+            let archived = prefix.join(relative);
+
+            walked.push((path, archived));
+        }
+    }
+
+    for skipped in pruned {
+        debug!("Skipping '{}': {}", skipped.path.display(), skipped.reason);
+        report.skipped.push(skipped);
+    }
+
+    // Metadata is captured at scan time so that files changing
+    // before or during compression can be detected.
+    // Anything that isn't a regular file or directory (FIFOs, sockets,
+    // device nodes) is skipped, as reading them may block forever
+    // or never reach EOF.
+    let input_paths: Vec<(PathBuf, PathBuf, FileInfo)> = walked
         .into_iter()
-        .map(|e| e.unwrap_or_else(|e| panic!("Error: {:?}", e)))
-        .map(|e| e.into_path())
         // TODO : Explore allow follow symlnks option
-        .filter(|e|e.is_file())
+        .filter_map(|(e, archived)| match fs.metadata(&e) {
+            Ok(m) if m.kind == FileKind::File => Some((e, archived, m)),
+            Ok(m) if m.kind == FileKind::Other => {
+                warn!("Skipping '{}': {}", e.display(), SkipReason::NotRegularFile);
+                report.skipped.push(SkippedFile { path: e, reason: SkipReason::NotRegularFile });
+                None
+            },
+            _ => None,
+        })
         .collect();
 
-    let output_paths: Vec<PathBuf> = input_paths
-        .iter()
-        .filter_map(|e| match e.strip_prefix(input_folder_path) {
-            Ok(p) => self::clear_ext(p).ok(),
-            Err(e) => {
-                panic!("Error: {:?}", e);
-                // None
-            }
-        })
-        .map(
-            |p| Path::new(output_folder_path).join(p)
-        ).collect();
+    // Looked up again for the manifest, to record duplicates by the path
+    // they're extracted to.
+    let archived_paths: HashMap<PathBuf, PathBuf> = input_paths.iter()
+        .map(|(input, archived, _)| (input.clone(), archived.clone()))
+        .collect();
 
-    let jobs: Vec<(PathBuf, PathBuf)> = input_paths
+    let mut jobs: Vec<(PathBuf, PathBuf, FileInfo, CompressionType)> = input_paths
         .into_iter()
-        .zip(output_paths)
-        .collect();
+        .map(|(input, relative, metadata)| -> Result<(PathBuf, PathBuf, FileInfo, CompressionType), CompressionError> {
+            let file_compression = options.compression_for(&relative, &compression).clone();
+            let extension = build_common_extension(&encryption, &file_compression);
+            let output = Path::new(output_folder_path).join(rewrite_ext(&relative, &extension)?);
+
+            Ok((input, output, metadata, file_compression))
+        })
+        .collect::<Result<_, CompressionError>>()?;
+
+    // Sorted so that the report, and which failure is returned when
+    // several files fail, don't depend on the order the directory was
+    // walked in or the files were finished in.
+    jobs.sort_by(|a, b| a.0.cmp(&b.0));
+
+    jobs.retain(|(input, output, _, _)| {
+        let fits = fs.fits_path(output);
+
+        if !fits {
+            warn!("Skipping '{}': {}", input.display(), SkipReason::PathTooLong);
+            report.skipped.push(SkippedFile { path: input.clone(), reason: SkipReason::PathTooLong });
+        }
+
+        fits
+    });
+
+    report.walk_elapsed = walking.elapsed();
+
+    let processing = Instant::now();
 
     jobs.iter().for_each(
-        |(input, output)| debug!(
-            "Compressing: {:?} -> {:?}",
+        |(input, output, _, compression)| debug!(
+            "Compressing: {:?} -> {:?} ({:?})",
             input.display(),
-            output.display()
+            output.display(),
+            compression
         )
     );
 
+    // The first of each set of identical files, by path, is the one
+    // stored so that the same file is picked on every run. Files that
+    // can't be read are left to fail, or be skipped, as usual.
+    let mut duplicates: Vec<(PathBuf, FileInfo, PathBuf, PathBuf)> = Vec::new();
+
+    if options.dedup {
+        let digests: Vec<Option<[u8; 32]>> = jobs.par_iter()
+            .map(|(input, _, _, _)| hash_file(fs, input, options).ok())
+            .collect();
+
+        let mut stored: HashMap<[u8; 32], usize> = HashMap::new();
+        let mut unique: Vec<(PathBuf, PathBuf, FileInfo, CompressionType)> = Vec::with_capacity(jobs.len());
+
+        for (job, digest) in jobs.into_iter().zip(digests) {
+            match digest.and_then(|d| stored.get(&d).copied()) {
+                Some(i) => {
+                    let (original, original_output, _, _) = &unique[i];
+                    debug!("'{}' is a duplicate of '{}'", job.0.display(), original.display());
+                    duplicates.push((job.0, job.2, original.clone(), original_output.clone()));
+                },
+                None => {
+                    if let Some(d) = digest {
+                        stored.insert(d, unique.len());
+                    }
+                    unique.push(job);
+                },
+            }
+        }
+
+        jobs = unique;
+    }
+
     jobs.par_iter()
+        .filter_map(
+            |(_, output, _, _)| output.parent()
+        )
+        .try_for_each(
+            |parent| fs.create_dir_all(parent)
+        )?;
+
+    let checkpoint = match &options.checkpoint {
+        Some(path) => {
+            let settings = settings_digest(&encryption, &encryption_secret, &compression, &compression_level, &signing)?;
+            Some(Checkpoint::open(path, &settings, options.resume)?)
+        },
+        None => None,
+    };
+
+    let config = PipelineConfig::new(encryption, encryption_secret, compression, compression_level, signing);
+
+    // Each resolved once, by the path it's extracted to, and kept for
+    // the manifest. Only encrypted archives have secrets to resolve.
+    let resolved: HashMap<PathBuf, EncryptionSecret> = match &options.secret_resolver {
+        Some(resolver) if *config.encryption() != EncryptionType::Passthrough => jobs.iter()
+            .map(|(input, _, _, _)| (input.clone(), resolver.resolve(&archived_paths[input])))
+            .collect(),
+        _ => HashMap::new(),
+    };
+
+    let compress_file = |input: &Path, output: &Path, compression: &CompressionType, len: u64| -> Result<(), PipelineCompressionError> {
+        let builder = || match resolved.get(input) {
+            Some(secret) => config.builder().with_encryption_secret(secret),
+            None => config.builder(),
+        };
+
+        // For small files the cost of streaming through file IO
+        // outweighs the compression itself, so they're done in one go.
+        let retry = options.io_retry;
+
+        if len < options.in_memory_threshold {
+            let data = retry.run(|| read_source(fs, input, options))?;
+            let mut buffer = Vec::with_capacity(data.len());
+
+            builder()
+                .with_compression(compression)
+                .with_size_hint(len)
+                .build()
+                .compress_stream(&mut transformed(options, input, &mut data.as_slice()), &mut buffer)?;
+
+            // Written from the start again on each attempt.
+            return Ok(retry.run(|| fs.write(output, &buffer))?);
+        }
+
+        let mut reader = RetryReader::new(retry.run(|| open_source(fs, input, options))?, retry);
+        let mut source = transformed(options, input, &mut reader);
+        let destination = RetryWriter::new(retry.run(|| fs.create(output))?, retry);
+
+        let pipeline = builder()
+            .with_source(input.to_path_buf())
+            .with_destination(output.to_path_buf())
+            .with_size_hint(len);
+
+        // Compressed in blocks on several threads ahead of the pipeline,
+        // which is left to pass them through. Signing is of what it's given,
+        // which only matters once there is a signing method that signs.
+        match block_parallel(options, compression, len) {
+            true => pipeline
+                .with_compression(&CompressionType::Passthrough)
+                .build()
+                .compress_stream(
+                    &mut BlockCompressor::new(&mut source, compression, *config.compression_level(), options.threads_per_file),
+                    destination,
+                ),
+            false => pipeline
+                .with_compression(compression)
+                .build()
+                .compress_stream(&mut source, destination),
+        }
+    };
+
+    if let Some(progress) = &options.progress {
+        progress.start(jobs.len() as u64, jobs.iter().map(|(_, _, m, _)| m.len).sum());
+    }
+
+    // Skipped files are counted as finished too, so that the
+    // progress still reaches the total.
+    let finish_file = |bytes: u64| if let Some(progress) = &options.progress {
+        progress.finish_file(bytes)
+    };
+
+    // Each worker compresses its share of the jobs in order, so the file
+    // 'prefetch' ahead of the one it starts is likely to be its next but
+    // some. The first few are started here, before any worker gets to them.
+    let upcoming: Vec<PathBuf> = match options.prefetch {
+        0 => Vec::new(),
+        _ => jobs.iter().map(|(input, _, _, _)| input.clone()).collect(),
+    };
+
+    upcoming.iter().take(options.prefetch).for_each(|input| fs.prefetch(input));
+
+    // Paired with the metadata of the source when it was read.
+    let outcomes: Vec<Result<(FileOutcome, CompressionType, Option<FileRecord>), CompressionError>> = jobs.into_par_iter()
+        .enumerate()
         .map(
-            |(_, output)| output.parent().unwrap()
+            |(n, (input, output, mut scanned, compression))| {
+                let span = FileSpan::enter("compress", &input, &compression, scanned.len);
+                let started = Instant::now();
+
+                if let Some(next) = upcoming.get(n + options.prefetch) {
+                    fs.prefetch(next);
+                }
+
+                if let Some(progress) = &options.progress {
+                    progress.start_file(&input);
+                }
+
+                let resumed = checkpoint.as_ref()
+                    .and_then(|c| c.done(&input, &scanned, &output))
+                    .filter(|_| fs.exists(&output));
+
+                if let Some(done) = resumed {
+                    debug!("'{}' was compressed by an earlier run", input.display());
+                    finish_file(scanned.len);
+                    span.outcome("resumed");
+
+                    return Ok((FileOutcome::Processed(FileReport {
+                        written_bytes: done.written_bytes,
+                        source: input,
+                        destination: output,
+                        bytes: scanned.len,
+                        elapsed: Duration::ZERO,
+                    }), compression, Some(FileRecord::from(&scanned))))
+                }
+
+                let mut attempt = 0;
+
+                loop {
+                    attempt += 1;
+
+                    let result = compress_file(&input, &output, &compression, scanned.len);
+
+                    let reason = match (result, detect_change(fs, &input, &scanned)) {
+                        (Ok(()), None) => {
+                            finish_file(scanned.len);
+                            span.outcome("processed");
+
+                            let file = FileReport {
+                                written_bytes: fs.metadata(&output).map(|m| m.len).unwrap_or_default(),
+                                source: input,
+                                destination: output,
+                                bytes: scanned.len,
+                                elapsed: started.elapsed(),
+                            };
+
+                            // Only costs compressing the file again on resume.
+                            if let Some(Err(e)) = checkpoint.as_ref().map(|c| c.record(&file, &scanned)) {
+                                warn!("Failed to record '{}' in the checkpoint: {}", file.source.display(), e);
+                            }
+
+                            return Ok((FileOutcome::Processed(file), compression, Some(FileRecord::from(&scanned))))
+                        },
+                        (Err(e), None) => {
+                            // Don't leave a partially written entry behind.
+                            let _ = fs.remove_file(&output);
+                            span.failed(&e);
+
+                            return Err(match e.is_disk_full() {
+                                true => CompressionError::DiskFull(output),
+                                false => CompressionError::FileFailed(input, Box::new(e)),
+                            })
+                        },
+                        (_, Some(reason)) => reason,
+                    };
+
+                    match options.on_change {
+                        ChangePolicy::Retry if reason == SkipReason::Changed && attempt < CHANGE_RETRIES => {
+                            debug!("Retrying '{}' after it changed (attempt {})", input.display(), attempt);
+
+                            if let Ok(m) = fs.metadata(&input) {
+                                scanned = m;
+                            }
+                        },
+                        ChangePolicy::Error => {
+                            span.failed(&reason);
+                            return Err(CompressionError::SourceChanged(input, reason))
+                        },
+                        _ => {
+                            // The output may be partially written, or be a mix
+                            // of old and new content.
+                            let _ = fs.remove_file(&output);
+
+                            finish_file(scanned.len);
+                            span.outcome("skipped");
+
+                            return Ok((FileOutcome::Skipped(SkippedFile { path: input, reason }), compression, None))
+                        },
+                    }
+                }
+            }
         )
-        .for_each(
-        |parent| 
-            std::fs::create_dir_all(parent)
-                .expect("Failed to create all the required directories/subdirectories")
-        );
+        .collect();
 
-    let _compression_level = flate2::Compression::default(); // Needs to be deleted at some point
-    
-    jobs.into_par_iter()
-        .for_each(
-            |(input, output)| {
-                let pipeline = ProcessingPipeline::builder()
-                    .with_source(input.clone())
-                    .with_destination(output)
-                    .with_compression(&compression)
-                    .with_compression_level(&_compression_level) // TODO: Make compression level optional
-                    .with_encryption(&encryption)
-                    .with_encryption_secret(&encryption_secret)
-                    .with_signing(&signing)
-                    .build();
-
-                    match pipeline.decompress_dir() {
-                        Ok(_) => debug!(
-                            "Finished compressing '{:?}' successfully",
-                            input.display()
-                        ),
-                        Err(e) => {
-                            
-                            let bt = backtrace::Backtrace::capture();
-            
-                            error!(
-                                "Error while compressing '{}': {:?}",
-                                input.display(),
-                                e
-                            );
-                            log::trace!(
-                                "Error while compressing '{}': {:?}",
-                                input.display(),
-                                bt
-                            );
-            
-                            error!("Error compressing file {:?}", e);
-                            panic!();
-        }}});
+    let mut manifest = Manifest::new(config.compression().clone(), config.encryption().clone());
+    manifest.secret = Some(config.secret_type());
+    manifest.signing = config.signing().clone();
+    manifest.comment = options.comment.clone();
 
-    Ok(())
+    let mut first_error = None;
+
+    for outcome in outcomes {
+        match outcome {
+            Ok((FileOutcome::Processed(file), file_compression, record)) => {
+                debug!("Finished compressing '{:?}' successfully", file.source.display());
+
+                let archived = archived_paths.get(&file.source).expect("Processed files are walked files");
+
+                if let Some(record) = record {
+                    manifest.files.insert(archived.clone(), record);
+                }
+
+                if let Some(secret) = resolved.get(&file.source) {
+                    manifest.entry_secrets.insert(archived.clone(), SecretType::from(secret));
+                }
+
+                if file_compression != *config.compression() {
+                    let relative = file.destination.strip_prefix(output_folder_path)
+                        .map_err(|e| PathRewriteError::FileNameError(e.to_string()))?;
+                    manifest.entries.insert(relative.to_path_buf(), file_compression);
+                }
+
+                report.processed.push(file)
+            },
+            Ok((FileOutcome::Skipped(file), _, _)) => {
+                warn!("Skipping '{}': {}", file.path.display(), file.reason);
+                report.skipped.push(file)
+            },
+            Err(e) => {
+                error!("Error while compressing: {:?}", e);
+                if first_error.is_none() {
+                    first_error = Some(e);
+                }
+            },
+        }
+    }
+
+    if let Some(e) = first_error {
+        return Err(e);
+    }
+
+    for (path, scanned, original, original_output) in duplicates {
+        // Nothing was stored for a skipped original, so neither is its duplicate.
+        if let Some(reason) = report.skipped.iter().find(|s| s.path == original).map(|s| s.reason.clone()) {
+            warn!("Skipping '{}': {}", path.display(), reason);
+            report.skipped.push(SkippedFile { path, reason });
+            continue;
+        }
+
+        let relative = archived_paths.get(&path).expect("Duplicates are walked files");
+        let stored = original_output.strip_prefix(output_folder_path)
+            .map_err(|e| PathRewriteError::FileNameError(e.to_string()))?;
+
+        manifest.duplicates.insert(relative.clone(), stored.to_path_buf());
+        manifest.files.insert(relative.clone(), FileRecord::from(&scanned));
+        report.duplicates.push(DuplicateFile { path, duplicate_of: original });
+    }
+
+    fs.create_dir_all(Path::new(output_folder_path))?;
+
+    if options.compress_manifest {
+        manifest.write_compressed_to_in(fs, Path::new(output_folder_path), config.compression(), config.compression_level())?;
+    } else {
+        manifest.write_to_in(fs, Path::new(output_folder_path))?;
+    }
+
+    report.process_elapsed = processing.elapsed();
+
+    Ok(report)
+}
+
+// A root given as a symlink is archived as the directory it points to,
+// rather than relying on how the walker treats a symlinked root. Targets
+// are relative to the directory holding the link.
+fn resolve_root<F: FileSystem>(fs: &F, root: &Path) -> Result<PathBuf, std::io::Error> {
+    // As many as Linux follows before giving up with ELOOP.
+    const MAX_LINKS: usize = 40;
+
+    let mut resolved = root.to_path_buf();
+
+    for _ in 0..MAX_LINKS {
+        let Some(target) = fs.read_link(&resolved)? else {
+            if resolved != root {
+                debug!("Archiving '{}' through its link to '{}'", root.display(), resolved.display());
+            }
+
+            return Ok(resolved);
+        };
+
+        resolved = resolved.parent().unwrap_or(Path::new("")).join(target);
+    }
+
+    Err(std::io::Error::other(format!("Too many levels of symbolic links: {}", root.display())))
+}
+
+// Removes everything from 'output_folder_path' that isn't in 'report' as
+// processed, or the manifest, so that packing it only stores the files that
+// were compressed. Left behind by a run that was interrupted, or by a file
+// that failed without its output being removed. Returns the removed files.
+pub fn retain_processed(output_folder_path: &str, report: &DirectoryReport) -> Result<Vec<PathBuf>, CompressionError> {
+    let root = Path::new(output_folder_path);
+
+    let processed: HashSet<&Path> = report.processed
+        .iter()
+        .map(|file| file.destination.as_path())
+        .collect();
+
+    let mut removed = Vec::new();
+
+    // Children first, so that directories are emptied before they're checked.
+    for entry in walkdir::WalkDir::new(root).min_depth(1).contents_first(true) {
+        let entry = entry.map_err(std::io::Error::from)?;
+        let path = entry.path();
+
+        if entry.file_type().is_dir() {
+            // Directories of skipped files are created before compressing.
+            if std::fs::read_dir(path)?.next().is_none() {
+                std::fs::remove_dir(path)?;
+            }
+        } else if !processed.contains(path) && !is_manifest_path(root, path) {
+            warn!("Removing '{}' from staging, it wasn't compressed", path.display());
+            std::fs::remove_file(path)?;
+            removed.push(path.to_path_buf());
+        }
+    }
+
+    Ok(removed)
 }
 
+// Moves the files staged in 'output_folder_path' into directories named
+// '<output>.part1', '<output>.part2' and so on, each holding at most
+// 'max_size' bytes, to be packed as archives of their own. Files are kept
+// in path order, so that a directory's files mostly end up together, and
+// are never split, so a larger file gets a part to itself. Each part has
+// a manifest of its own files, with duplicates kept with the file stored
+// in their place. Files that fit in one part are left where they are.
+pub fn split_staged(output_folder_path: &str, max_size: u64) -> Result<Vec<PathBuf>, CompressionError> {
+    let root = Path::new(output_folder_path);
+
+    let mut staged: Vec<(PathBuf, u64)> = Vec::new();
+    let mut manifest_path = None;
+
+    for entry in walkdir::WalkDir::new(root).sort_by_file_name() {
+        let entry = entry?;
+
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        match is_manifest_path(root, entry.path()) {
+            true => manifest_path = Some(entry.path().to_path_buf()),
+            false => {
+                let relative = entry.path().strip_prefix(root)
+                    .map_err(|e| PathRewriteError::FileNameError(e.to_string()))?;
+
+                staged.push((relative.to_path_buf(), entry.metadata()?.len()));
+            },
+        }
+    }
+
+    let mut parts: Vec<Vec<PathBuf>> = Vec::new();
+    let mut part_size = 0;
+
+    for (relative, len) in staged {
+        match parts.last_mut() {
+            Some(part) if part_size + len <= max_size => {
+                part.push(relative);
+                part_size += len;
+            },
+            _ => {
+                if len > max_size {
+                    warn!("'{}' is larger than the archive size, it is stored in an archive of its own", relative.display());
+                }
+
+                parts.push(vec![relative]);
+                part_size = len;
+            },
+        }
+    }
+
+    if parts.len() <= 1 {
+        return Ok(vec![root.to_path_buf()]);
+    }
+
+    let manifest = Manifest::read_from(root)?;
+
+    let mut dirs = Vec::with_capacity(parts.len());
+
+    for (n, files) in parts.iter().enumerate() {
+        let dir = PathBuf::from(format!("{}.part{}", output_folder_path, n + 1));
+
+        // Would mix in the files of whichever run left it behind.
+        if dir.exists() {
+            return Err(std::io::Error::new(ErrorKind::AlreadyExists, format!("'{}' already exists", dir.display())).into());
+        }
+
+        for relative in files.iter() {
+            if let Some(parent) = dir.join(relative).parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+
+            std::fs::rename(root.join(relative), dir.join(relative))?;
+        }
+
+        if let (Some(manifest), Some(manifest_path)) = (&manifest, &manifest_path) {
+            let stored: HashSet<&PathBuf> = files.iter().collect();
+
+            let mut part = manifest.clone();
+            part.entries.retain(|path, _| stored.contains(path));
+            part.duplicates.retain(|_, original| stored.contains(original));
+
+            let extracted: HashSet<PathBuf> = files.iter()
+                .map(|path| clear_ext(path))
+                .collect::<Result<_, _>>()?;
+
+            part.files.retain(|path, _| extracted.contains(path) || part.duplicates.contains_key(path));
+
+            // Copied first so that the part's manifest is written compressed,
+            // or not, the same as the original.
+            let name = manifest_path.strip_prefix(root)
+                .map_err(|e| PathRewriteError::FileNameError(e.to_string()))?;
+
+            std::fs::copy(manifest_path, dir.join(name))?;
+            part.overwrite(&dir)?;
+        }
+
+        debug!("Staged {} file(s) in '{}'", files.len(), dir.display());
+
+        dirs.push(dir);
+    }
+
+    // Only the manifest and empty directories are left.
+    std::fs::remove_dir_all(root)?;
+
+    Ok(dirs)
+}
+
+fn hash_file<F: FileSystem>(fs: &F, path: &Path, options: &CompressOptions) -> Result<[u8; 32], std::io::Error> {
+    let mut hasher = Sha256::new();
+    // Files are only the same if what is stored for them is.
+    std::io::copy(&mut transformed(options, path, &mut open_source(fs, path, options)?), &mut hasher)?;
+    Ok(hasher.finalize().into())
+}
+
+// Whether a file is split into blocks compressed on several threads, see
+// compression::blocks. Files of a block or two gain little from it.
+fn block_parallel(options: &CompressOptions, compression: &CompressionType, len: u64) -> bool {
+    options.threads_per_file > 1 && len >= 2 * BLOCK_SIZE as u64 && splits_into_blocks(compression)
+}
+
+// The contents of 'path' as they're compressed, after the transform if
+// there is one. The size hint is still the size of the file, which is
+// only used to pick buffers.
+fn transformed<'a>(options: &CompressOptions, path: &Path, source: &'a mut dyn Read) -> Box<dyn Read + 'a> {
+    match &options.transform {
+        Some(transform) => transform.apply(path, source),
+        None => Box::new(source),
+    }
+}
+
+// Files being archived are opened through these, so that they can be
+// read without updating their access time.
+fn open_source<F: FileSystem>(fs: &F, path: &Path, options: &CompressOptions) -> Result<F::Reader, std::io::Error> {
+    match options.no_atime {
+        true => fs.open_noatime(path),
+        false => fs.open(path),
+    }
+}
+
+fn read_source<F: FileSystem>(fs: &F, path: &Path, options: &CompressOptions) -> Result<Vec<u8>, std::io::Error> {
+    if !options.no_atime {
+        return fs.read(path);
+    }
+
+    let mut data = Vec::new();
+    fs.open_noatime(path)?.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+fn detect_change<F: FileSystem>(fs: &F, path: &Path, scanned: &FileInfo) -> Option<SkipReason> {
+    match fs.metadata(path) {
+        Err(_) => Some(SkipReason::Removed),
+        Ok(m) if m.len != scanned.len || m.modified != scanned.modified => Some(SkipReason::Changed),
+        Ok(_) => None,
+    }
+}
+
+// Archives without a manifest predate streams too.
+fn archive_framing(manifest: Option<&Manifest>) -> EncryptionFraming {
+    manifest.map_or(EncryptionFraming::Blocks, Manifest::framing)
+}
+
+// Lists the processed files in an unpacked archive along with the
+// compression algorithm each was processed with.
+fn archive_entries<F: FileSystem>(
+    fs: &F,
+    input_folder_path: &str,
+    compression: &CompressionType,
+    force: bool,
+) -> Result<Vec<(PathBuf, CompressionType)>, DecompressionError> {
+    let manifest = Manifest::read_from_in(fs, Path::new(input_folder_path))?;
+
+    debug!("Manifest: {:?}", manifest);
+
+    fs.walk(Path::new(input_folder_path))?
+        .into_iter()
+        // TODO : Explore allow follow symlnks option
+        .filter(|e| fs.metadata(e).is_ok_and(|m| m.kind == FileKind::File))
+        .filter(|e| !is_manifest_path(Path::new(input_folder_path), e))
+        .map(|e| -> Result<(PathBuf, CompressionType), DecompressionError> {
+            let relative = e.strip_prefix(input_folder_path)
+                .map_err(|e| PathRewriteError::FileNameError(e.to_string()))?;
+            let compression = resolve_compression(compression, manifest.as_ref(), relative, &e, force)?;
+            Ok((e, compression))
+        })
+        .collect()
+}
+
+// todo: This function will alter the filename of binary files eg:
+// a binary called 'someBinary' will end up as 'someBinary.'
+pub fn decompress_directory(
+    input_folder_path: &str,
+    output_folder_path: &str,
+    encryption: EncryptionType,
+    encryption_secret: EncryptionSecret,
+    compression: CompressionType,
+    signing: SigningType,
+    options: &DecompressOptions,
+) -> Result<DirectoryReport, DecompressionError> {
+    decompress_directory_in(
+        &RealFs,
+        input_folder_path,
+        output_folder_path,
+        encryption,
+        encryption_secret,
+        compression,
+        signing,
+        options,
+    )
+}
+
+/// Same as decompress_directory, but reads and writes through 'fs'.
+#[allow(clippy::too_many_arguments)]
+pub fn decompress_directory_in<F: FileSystem>(
+    fs: &F,
+    input_folder_path: &str,
+    output_folder_path: &str,
+    encryption: EncryptionType,
+    encryption_secret: EncryptionSecret,
+    compression: CompressionType,
+    signing: SigningType,
+    options: &DecompressOptions,
+) -> Result<DirectoryReport, DecompressionError> {
+    
+    info!("Decompressing directory: {:?} -> {:?}", input_folder_path, output_folder_path);
+    info!("Encryption: {:?}", encryption);
+    info!("Compression: {:?}", compression);
+    info!("Signing: {:?}", signing);
+
+    let walking = Instant::now();
+
+    let mut report = DirectoryReport::default();
+    let mut jobs: Vec<(PathBuf, PathBuf, CompressionType)> = Vec::new();
+
+    let mut sources: Vec<(PathBuf, PathBuf, CompressionType)> = Vec::new();
+
+    let manifest = Manifest::read_from_in(fs, Path::new(input_folder_path))?;
+
+    // An algorithm that was asked for but differs from the one recorded
+    // would only produce garbage, so it's used only when forced.
+    if let Some(m) = manifest.as_ref().filter(|_| !options.force_algorithm) {
+        if compression != CompressionType::Auto && m.compression != CompressionType::Auto && compression != m.compression {
+            return Err(DecompressionError::AlgorithmMismatch(format!("{:?} compression", compression), format!("{:?}", m.compression)));
+        }
+    }
+
+    // Checked before anything is extracted, including links that aren't
+    // entries themselves, eg: to a directory, as entries may be beneath them.
+    for path in fs.walk(Path::new(input_folder_path))? {
+        if let Some(target) = fs.read_link(&path)? {
+            if !options.symlink_policy.allows(Path::new(input_folder_path), &path, &target) {
+                return Err(DecompressionError::UnsafeSymlink(path, target));
+            }
+        }
+    }
+
+    for (input, compression) in archive_entries(fs, input_folder_path, &compression, options.force_algorithm)? {
+        let relative = input.strip_prefix(input_folder_path)
+            .map_err(|e| PathRewriteError::FileNameError(e.to_string()))?;
+
+        sources.push((clear_ext(relative)?, input, compression));
+    }
+
+    let framing = archive_framing(manifest.as_ref());
+
+    let (duplicates, records, entry_secrets) = manifest
+        .map(|m| (m.duplicates, m.files, m.entry_secrets))
+        .unwrap_or_default();
+
+    // Without the resolver, the archive's secret would only fail to
+    // decrypt every entry.
+    if !entry_secrets.is_empty() && options.secret_resolver.is_none() {
+        return Err(DecompressionError::SecretResolverRequired);
+    }
+
+    // Entries are resolved by the path they were archived under, before
+    // any stripping or renaming.
+    let entry_paths: HashMap<PathBuf, PathBuf> = sources.iter()
+        .filter(|(relative, _, _)| entry_secrets.contains_key(relative))
+        .map(|(relative, input, _)| (input.clone(), relative.clone()))
+        .collect();
+
+    // Duplicates are extracted again from the entry stored in their place.
+    let stored: HashMap<PathBuf, CompressionType> = sources.iter()
+        .map(|(_, input, compression)| (input.clone(), compression.clone()))
+        .collect();
+
+    for (duplicate, original) in duplicates {
+        let input = Path::new(input_folder_path).join(&original);
+
+        match stored.get(&input) {
+            Some(compression) => sources.push((duplicate, input, compression.clone())),
+            None => return Err(DecompressionError::MissingDuplicateSource(duplicate, original)),
+        }
+    }
+
+    // Sorted so that each entry's position, used by rename templates,
+    // is the same on every run.
+    sources.sort_by(|a, b| a.0.cmp(&b.0));
+
+    // Times to restore, by entry and output, from the manifest. Entries
+    // renamed in a conflict keep the time they were extracted at.
+    let mut times: HashMap<(PathBuf, PathBuf), FileRecord> = HashMap::new();
+
+    for (n, (relative, input, compression)) in sources.into_iter().enumerate() {
+        match extracted_path(&relative, n + 1, options)?.map(|output| Path::new(output_folder_path).join(output)) {
+            Some(output) if !fs.fits_path(&output) => {
+                warn!("Skipping '{}': {}", input.display(), SkipReason::PathTooLong);
+                report.skipped.push(SkippedFile { path: input, reason: SkipReason::PathTooLong })
+            },
+            Some(output) => {
+                if let Some(record) = records.get(&relative).filter(|_| options.preserve_timestamps) {
+                    times.insert((input.clone(), output.clone()), record.clone());
+                }
+
+                jobs.push((input, output, compression))
+            },
+            None => {
+                debug!("Skipping '{}': {}", input.display(), SkipReason::TooFewComponents);
+                report.skipped.push(SkippedFile { path: input, reason: SkipReason::TooFewComponents })
+            },
+        }
+    }
+
+    // Sorted so that which of two conflicting entries is kept doesn't
+    // depend on the order the directory was walked in. Duplicates share
+    // an input, so the output breaks ties.
+    jobs.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+
+    check_output_writable_in(fs, Path::new(output_folder_path))?;
+
+    let case_insensitive = is_case_insensitive(fs, Path::new(output_folder_path))?;
+
+    if options.flatten || options.rename.is_some() || options.path_map.is_some() || case_insensitive {
+        debug!("Checking for conflicting entries, case-insensitive: {}", case_insensitive);
+        jobs = resolve_conflicts(jobs, &options.on_conflict, case_insensitive, &mut report.skipped)?;
+    }
+
+    report.walk_elapsed = walking.elapsed();
+
+    let processing = Instant::now();
+
+    jobs.iter().for_each(
+        |(input, output, compression)| debug!(
+            "Decompressing: {:?} -> {:?} ({:?})",
+            input.display(),
+            output.display(),
+            compression
+        )
+    );
+
+    jobs.par_iter()
+        .filter_map(
+            |(_, output, _)| output.parent()
+        )
+        .try_for_each(
+            |parent| fs.create_dir_all(parent)
+        )?;
+
+    // TODO: Make compression level optional, it's unused when decompressing
+    let config = PipelineConfig::new(encryption, encryption_secret, compression, flate2::Compression::default(), signing)
+        .with_framing(framing);
+    
+    // Sizes of the entries rather than of the files they extract to,
+    // which aren't known until they're written.
+    let sizes: Vec<u64> = jobs.iter()
+        .map(|(input, _, _)| fs.metadata(input).map(|m| m.len))
+        .collect::<Result<_, _>>()?;
+
+    if let Some(progress) = &options.progress {
+        progress.start(jobs.len() as u64, sizes.iter().sum());
+    }
+
+    let extracted_total = AtomicU64::new(0);
+
+    let decompress_file = |input: &Path, output: &Path, compression: &CompressionType, len: u64| -> Result<(), PipelineDecompressionError> {
+        let resolved = options.secret_resolver.as_ref()
+            .zip(entry_paths.get(input))
+            .map(|(r, relative)| r.resolve(relative));
+
+        let builder = match &resolved {
+            Some(secret) => config.builder().with_encryption_secret(secret),
+            None => config.builder(),
+        };
+
+        let retry = options.io_retry;
+
+        // Written straight through unless a buffer is asked for, as the
+        // pipeline already buffers by the size of the file.
+        let mut destination = SizeLimitedWriter::new(
+            BufWriter::with_capacity(
+                options.write_buffer_size.unwrap_or(0),
+                RetryWriter::new(retry.run(|| fs.create(output))?, retry),
+            ),
+            options.max_file_size,
+            &extracted_total,
+            options.max_total_size,
+        );
+
+        // The size of the entry is only a rough guide to the size of
+        // the file it extracts to, but is close enough to pick buffers by.
+        builder
+            .with_source(input.to_path_buf())
+            .with_destination(output.to_path_buf())
+            .with_compression(compression)
+            .with_size_hint(len)
+            .build()
+            .decompress_stream(RetryReader::new(retry.run(|| fs.open(input))?, retry), &mut destination)?;
+
+        Ok(())
+    };
+
+    let results: Vec<Result<Duration, DecompressionError>> = jobs.par_iter()
+        .zip(sizes.par_iter())
+        .map(
+            |((input, output, compression), len)| {
+                let span = FileSpan::enter("decompress", input, compression, *len);
+                let started = Instant::now();
+
+                if let Some(progress) = &options.progress {
+                    progress.start_file(output);
+                }
+
+                decompress_file(input, output, compression, *len).map_err(|e| {
+                    // Don't leave a partially written file behind.
+                    let _ = fs.remove_file(output);
+                    span.failed(&e);
+
+                    if let Some(limit) = e.size_limit() {
+                        return DecompressionError::SizeLimitExceeded(input.clone(), limit.clone());
+                    }
+
+                    match e.is_disk_full() {
+                        true => DecompressionError::DiskFull(output.clone()),
+                        false => DecompressionError::FileFailed(input.clone(), Box::new(e)),
+                    }
+                })?;
+
+                // The file is complete either way, only its times are off.
+                if let Some(record) = times.get(&(input.clone(), output.clone())) {
+                    if let Err(e) = fs.set_times(output, record.modified, record.created) {
+                        warn!("Unable to restore the times of '{}': {}", output.display(), e);
+                    }
+                }
+
+                if let Some(progress) = &options.progress {
+                    progress.finish_file(*len);
+                }
+
+                span.outcome("processed");
+
+                Ok(started.elapsed())
+            }
+        )
+        .collect();
+
+    // Every failure is logged, but only the first by entry is returned.
+    // The results are in the same order as the sorted jobs, so that this
+    // is the same on every run whichever thread failed first.
+    let mut first_error = None;
+
+    for ((source, destination, _), (result, bytes)) in jobs.into_iter().zip(results.into_iter().zip(sizes)) {
+        match result {
+            Ok(elapsed) => {
+                debug!("Finished decompressing '{:?}' successfully", source.display());
+                let written_bytes = fs.metadata(&destination).map(|m| m.len).unwrap_or_default();
+                report.processed.push(FileReport { source, destination, bytes, written_bytes, elapsed })
+            },
+            Err(e) => {
+                error!("Error while decompressing: {:?}", e);
+                if first_error.is_none() {
+                    first_error = Some(e);
+                }
+            },
+        }
+    }
+
+    if let Some(e) = first_error {
+        return Err(e);
+    }
+
+    report.process_elapsed = processing.elapsed();
+
+    Ok(report)
+}
+
+// The path an entry is extracted to, relative to the output directory
+// unless a path map sends it elsewhere, or None when stripping components
+// leaves nothing of it. 'n' is the entry's position, from 1, for rename
+// templates.
+fn extracted_path(relative: &Path, n: usize, options: &DecompressOptions) -> Result<Option<PathBuf>, DecompressionError> {
+    // Only plain names are allowed, so that no entry can be written
+    // outside of the output directory, whether it is stripped or not.
+    if !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err(DecompressionError::UnsafePath(relative.to_path_buf()));
+    }
+
+    // Absolute destinations replace the output directory when joined.
+    if let Some(mapped) = options.path_map.as_ref().and_then(|m| m.map(relative)) {
+        return Ok(Some(mapped));
+    }
+
+    let stripped: PathBuf = relative.components().skip(options.strip_components).collect();
+
+    let output = match (options.flatten, stripped.file_name()) {
+        (_, None) => return Ok(None),
+        (true, Some(name)) => PathBuf::from(name),
+        (false, Some(_)) => stripped,
+    };
+
+    let Some(template) = &options.rename else {
+        return Ok(Some(output));
+    };
+
+    let renamed = template.render(&output, n);
+
+    // Checked again as names such as '..' can come from the entry itself.
+    match renamed.components().all(|c| matches!(c, Component::Normal(_))) {
+        true => Ok(Some(renamed)),
+        false => Err(DecompressionError::UnsafePath(renamed)),
+    }
+}
+
+/// Creates 'dir' if needed and checks that files can be written to it,
+/// so that a read-only destination fails before anything is extracted
+/// rather than once for every entry.
+pub fn check_output_writable(dir: &Path) -> Result<(), DecompressionError> {
+    check_output_writable_in(&RealFs, dir)
+}
+
+pub fn check_output_writable_in<F: FileSystem>(fs: &F, dir: &Path) -> Result<(), DecompressionError> {
+    let probe = dir.join(format!(".zap-write-probe-{}", std::process::id()));
+
+    let result = fs.create_dir_all(dir)
+        .and_then(|_| fs.create(&probe))
+        .and_then(|mut writer| writer.flush());
+
+    match result {
+        Ok(()) => Ok(fs.remove_file(&probe)?),
+        Err(e) => Err(DecompressionError::OutputNotWritable(dir.to_path_buf(), e)),
+    }
+}
+
+// Probes 'dir' by creating a file and checking whether it can be
+// found again under a different case.
+fn is_case_insensitive<F: FileSystem>(fs: &F, dir: &Path) -> Result<bool, std::io::Error> {
+    let probe = dir.join(format!(".zap-case-probe-{}", std::process::id()));
+    let folded = dir.join(format!(".ZAP-CASE-PROBE-{}", std::process::id()));
+
+    fs.create(&probe)?;
+    let insensitive = fs.exists(&folded);
+    fs.remove_file(&probe)?;
+
+    Ok(insensitive)
+}
+
+// Entries that only differ by case are written to the same file on a
+// case-insensitive filesystem, as are entries with the same name when
+// flattening, so they are resolved up front rather than letting
+// whichever finishes last silently win.
+fn resolve_conflicts(
+    jobs: Vec<(PathBuf, PathBuf, CompressionType)>,
+    policy: &ConflictPolicy,
+    fold_case: bool,
+    skipped: &mut Vec<SkippedFile>,
+) -> Result<Vec<(PathBuf, PathBuf, CompressionType)>, DecompressionError> {
+    let mut claimed: HashMap<String, usize> = HashMap::new();
+    let mut resolved: Vec<(PathBuf, PathBuf, CompressionType)> = Vec::with_capacity(jobs.len());
+
+    for (input, output, compression) in jobs {
+        let existing = match claimed.get(&conflict_key(&output, fold_case)) {
+            Some(index) => *index,
+            None => {
+                claimed.insert(conflict_key(&output, fold_case), resolved.len());
+                resolved.push((input, output, compression));
+                continue;
+            }
+        };
+
+        warn!(
+            "'{}' conflicts with '{}' when extracted",
+            input.display(),
+            resolved[existing].0.display()
+        );
+
+        match policy {
+            ConflictPolicy::Rename => {
+                let mut n = 1;
+                let renamed = loop {
+                    let candidate = numbered_path(&output, n);
+                    if !claimed.contains_key(&conflict_key(&candidate, fold_case)) {
+                        break candidate;
+                    }
+                    n += 1;
+                };
+
+                claimed.insert(conflict_key(&renamed, fold_case), resolved.len());
+                resolved.push((input, renamed, compression));
+            },
+            ConflictPolicy::Skip => skipped.push(SkippedFile {
+                path: input,
+                reason: SkipReason::Conflict,
+            }),
+            ConflictPolicy::Overwrite => {
+                let (earlier, _, _) = std::mem::replace(&mut resolved[existing], (input, output, compression));
+                skipped.push(SkippedFile {
+                    path: earlier,
+                    reason: SkipReason::Conflict,
+                });
+            },
+            ConflictPolicy::Error => {
+                return Err(DecompressionError::PathConflict(resolved[existing].0.clone(), input));
+            },
+        }
+    }
+
+    Ok(resolved)
+}
+
+fn conflict_key(path: &Path, fold_case: bool) -> String {
+    match fold_case {
+        true => path.to_string_lossy().to_lowercase(),
+        false => path.to_string_lossy().into_owned(),
+    }
+}
+
+// 'dir/foo.txt' -> 'dir/foo (n).txt'
+fn numbered_path(path: &Path, n: usize) -> PathBuf {
+    let stem = path.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+
+    let name = match path.extension() {
+        Some(ext) => format!("{} ({}).{}", stem, n, ext.to_string_lossy()),
+        None => format!("{} ({})", stem, n),
+    };
+
+    path.with_file_name(name)
+}
+
+// In-memory counterpart to compress_directory, processing a single
+// buffer through the same pipeline used for files.
+pub fn compress_bytes(
+    input: &[u8],
+    encryption: &EncryptionType,
+    encryption_secret: &EncryptionSecret,
+    compression: &CompressionType,
+    compression_level: &flate2::Compression,
+    signing: &SigningType,
+) -> Result<Vec<u8>, PipelineCompressionError> {
+    let mut reader = input;
+    let mut output = Vec::new();
+
+    ProcessingPipeline::builder()
+        .with_compression(compression)
+        .with_compression_level(compression_level)
+        .with_encryption(encryption)
+        .with_encryption_secret(encryption_secret)
+        .with_signing(signing)
+        .build()
+        .compress_stream(&mut reader, &mut output)?;
+
+    Ok(output)
+}
+
+pub fn decompress_bytes(
+    input: &[u8],
+    encryption: &EncryptionType,
+    encryption_secret: &EncryptionSecret,
+    compression: &CompressionType,
+    signing: &SigningType,
+) -> Result<Vec<u8>, PipelineDecompressionError> {
+    let compression_level = flate2::Compression::default();
+    let mut output = Vec::new();
+
+    ProcessingPipeline::builder()
+        .with_compression(compression)
+        .with_compression_level(&compression_level)
+        .with_encryption(encryption)
+        .with_encryption_secret(encryption_secret)
+        .with_signing(signing)
+        .build()
+        .decompress_stream(input, &mut output)?;
+
+    Ok(output)
+}
+
+// Counterparts to compress_bytes and decompress_bytes taking trait
+// objects, for callers that can't use the generic pipeline.
+pub fn compress_dyn(
+    input: &mut dyn Read,
+    output: &mut dyn Write,
+    encryption: &EncryptionType,
+    encryption_secret: &EncryptionSecret,
+    compression: &CompressionType,
+    compression_level: &flate2::Compression,
+    signing: &SigningType,
+) -> Result<(), PipelineCompressionError> {
+    ProcessingPipeline::builder()
+        .with_compression(compression)
+        .with_compression_level(compression_level)
+        .with_encryption(encryption)
+        .with_encryption_secret(encryption_secret)
+        .with_signing(signing)
+        .build()
+        .compress_dyn(input, output)
+}
+
+pub fn decompress_dyn(
+    input: &mut dyn Read,
+    output: &mut dyn Write,
+    encryption: &EncryptionType,
+    encryption_secret: &EncryptionSecret,
+    compression: &CompressionType,
+    signing: &SigningType,
+) -> Result<(), PipelineDecompressionError> {
+    let compression_level = flate2::Compression::default();
+
+    ProcessingPipeline::builder()
+        .with_compression(compression)
+        .with_compression_level(&compression_level)
+        .with_encryption(encryption)
+        .with_encryption_secret(encryption_secret)
+        .with_signing(signing)
+        .build()
+        .decompress_dyn(input, output)?;
+
+    Ok(())
+}
+
+// Runs every file of an unpacked archive through the decompression
+// pipeline without writing anything, returning each verifier's result.
+pub fn verify_directory(
+    input_folder_path: &str,
+    encryption: EncryptionType,
+    encryption_secret: EncryptionSecret,
+    compression: CompressionType,
+    signing: SigningType,
+) -> Result<Vec<VerifiedFile>, DecompressionError> {
+
+    info!("Verifying directory: {:?}", input_folder_path);
+    info!("Encryption: {:?}", encryption);
+    info!("Compression: {:?}", compression);
+    info!("Signing: {:?}", signing);
+
+    let entries = archive_entries(&RealFs, input_folder_path, &compression, false)?;
+    let manifest = Manifest::read_from(Path::new(input_folder_path))?;
+
+    let config = PipelineConfig::new(encryption, encryption_secret, compression, flate2::Compression::default(), signing)
+        .with_framing(archive_framing(manifest.as_ref()));
+
+    entries
+        .into_par_iter()
+        .map(
+            |(input, compression)| {
+                let result = config.builder()
+                    .with_source(input.clone())
+                    .with_compression(&compression)
+                    .build()
+                    .verify_dir();
+
+                match result {
+                    Ok(signature) => Ok(VerifiedFile { path: input, signature }),
+                    Err(e) => Err(DecompressionError::FileFailed(input, Box::new(e))),
+                }
+            }
+        )
+        .collect()
+}
+
+/// Whether 'encryption_secret' opens the unpacked archive, without
+/// decrypting it. Only the first chunk of its smallest entry is
+/// authenticated. Any secret opens an archive that isn't encrypted or has
+/// no entries.
+pub fn check_secret(
+    input_folder_path: &str,
+    encryption: EncryptionType,
+    encryption_secret: EncryptionSecret,
+) -> Result<bool, DecompressionError> {
+    info!("Checking secret for directory: {:?}", input_folder_path);
+    info!("Encryption: {:?}", encryption);
+
+    let manifest = Manifest::read_from(Path::new(input_folder_path))?;
+    let framing = archive_framing(manifest.as_ref());
+
+    // Empty entries of archives from before streams have nothing to
+    // authenticate, every entry since has at least a tag.
+    let smallest = archive_entries(&RealFs, input_folder_path, &CompressionType::Auto, false)?
+        .into_iter()
+        .filter_map(|(input, _)| RealFs.metadata(&input).ok().map(|m| (m.len, input)))
+        .filter(|(len, _)| *len > 0)
+        .min();
+
+    let Some((_, input)) = smallest else {
+        return Ok(true);
+    };
+
+    debug!("Checking secret against: {:?}", input.display());
+
+    fn opens<D, R>(algorithm: D, reader: R) -> Result<bool, PipelineDecompressionError>
+    where
+        D: DecryptionAlgorithm<R>,
+        R: Read,
+    {
+        match algorithm.decryptor(reader)?.read(&mut [0; 1]) {
+            Ok(_) => Ok(true),
+            Err(e) if e.get_ref().is_some_and(|e| e.is::<DecryptError>()) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    if encryption == EncryptionType::Passthrough {
+        return Ok(true);
+    }
+
+    let key = match encryption_secret.key(&encryption) {
+        Ok(Some(key)) => key,
+        Ok(None) => return Ok(false),
+        Err(e) => {
+            let e = PipelineDecompressionError::from(EncryptorInitError::from(EncryptionSecretError::from(e)));
+            return Err(DecompressionError::FileFailed(input, Box::new(e)));
+        },
+    };
+
+    let opened = match encryption {
+        EncryptionType::XChaCha => opens(XChaChaPolyAlgorithm::new().with_key(key).with_framing(framing), RealFs.open(&input)?),
+        EncryptionType::ChaCha => opens(ChaChaPolyAlgorithm::new().with_key(key).with_framing(framing), RealFs.open(&input)?),
+        EncryptionType::AesGcm => opens(AesGcmAlgorithm::new().with_key(key).with_framing(framing), RealFs.open(&input)?),
+        EncryptionType::Passthrough => return Ok(true),
+    };
+
+    opened.map_err(|e| DecompressionError::FileFailed(input, Box::new(e)))
+}
+
+// Lists the entries of an unpacked archive as they would be extracted.
+// Only entries matching 'filter' are listed, when given. Duplicates are
+// matched by their own path rather than the entry they're extracted from.
+pub fn list_archive(input_folder_path: &str, filter: Option<&Glob>) -> Result<ArchiveListing, DecompressionError> {
+    info!("Listing directory: {:?}", input_folder_path);
+
+    let manifest = Manifest::read_from(Path::new(input_folder_path))?;
+
+    let mut entries: Vec<ListedEntry> = archive_entries(&RealFs, input_folder_path, &CompressionType::Auto, false)?
+        .into_iter()
+        .map(|(input, compression)| -> Result<ListedEntry, DecompressionError> {
+            let relative = input.strip_prefix(input_folder_path)
+                .map_err(|e| PathRewriteError::FileNameError(e.to_string()))?;
+
+            Ok(ListedEntry {
+                path: clear_ext(relative)?,
+                stored_size: input.metadata()?.len(),
+                compression,
+                duplicate_of: None,
+            })
+        })
+        .collect::<Result<_, _>>()?;
+
+    if let Some(manifest) = &manifest {
+        let stored: HashMap<PathBuf, CompressionType> = entries.iter()
+            .map(|e| (e.path.clone(), e.compression.clone()))
+            .collect();
+
+        for (duplicate, original) in manifest.duplicates.iter() {
+            let original_path = clear_ext(original)?;
+
+            let compression = stored.get(&original_path)
+                .cloned()
+                .ok_or_else(|| DecompressionError::MissingDuplicateSource(duplicate.clone(), original.clone()))?;
+
+            entries.push(ListedEntry {
+                path: duplicate.clone(),
+                stored_size: 0,
+                compression,
+                duplicate_of: Some(original_path),
+            });
+        }
+    }
+
+    if let Some(glob) = filter {
+        entries.retain(|entry| glob.matches(&entry.path));
+    }
+
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(ArchiveListing { manifest, entries })
+}
+
+// Compares an unpacked archive with 'directory' by the size and modification
+// time recorded for each file, without decompressing anything. Files are
+// matched by their path relative to 'directory'.
+pub fn compare_archive(input_folder_path: &str, directory: &str) -> Result<ArchiveComparison, DecompressionError> {
+    info!("Comparing directory: {:?} with {:?}", directory, input_folder_path);
+
+    let listing = list_archive(input_folder_path, None)?;
+    let records = listing.manifest.map(|m| m.files).unwrap_or_default();
+
+    let root = resolve_root(&RealFs, Path::new(directory))?;
+
+    // Only regular files are archived, anything else is left out as it
+    // would be when archiving.
+    let mut current: BTreeMap<PathBuf, FileInfo> = BTreeMap::new();
+
+    for path in RealFs.walk(&root)? {
+        let Some(metadata) = RealFs.metadata(&path).ok().filter(|m| m.kind == FileKind::File) else {
+            continue;
+        };
+
+        let relative = path.strip_prefix(&root)
+            .map_err(|e| PathRewriteError::FileNameError(e.to_string()))?;
+
+        current.insert(relative.to_path_buf(), metadata);
+    }
+
+    let mut comparison = ArchiveComparison::default();
+
+    for entry in listing.entries {
+        let Some(metadata) = current.remove(&entry.path) else {
+            comparison.removed.push(entry.path);
+            continue;
+        };
+
+        match records.get(&entry.path) {
+            Some(record) if record.size != metadata.len || record.modified != metadata.modified => {
+                comparison.modified.push(entry.path)
+            },
+            Some(_) => {},
+            None => comparison.unchecked.push(entry.path),
+        }
+    }
+
+    comparison.added = current.into_keys().collect();
+
+    Ok(comparison)
+}
+
+// Removes an entry from an unpacked archive, where 'entry' is the path it
+// is extracted to, as shown by list_archive. Returns the removed file.
+pub fn remove_entry(input_folder_path: &str, entry: &Path) -> Result<PathBuf, DecompressionError> {
+    info!("Removing entry: {:?} from {:?}", entry, input_folder_path);
+
+    let root = Path::new(input_folder_path);
+
+    let mut manifest = Manifest::read_from(root)?;
+
+    // Duplicates have nothing stored of their own, only a manifest record.
+    if let Some(manifest) = manifest.as_mut() {
+        if manifest.duplicates.remove(entry).is_some() {
+            manifest.files.remove(entry);
+            manifest.overwrite(root)?;
+            return Ok(root.join(entry));
+        }
+    }
+
+    let mut staged = None;
+
+    for (input, _) in archive_entries(&RealFs, input_folder_path, &CompressionType::Auto, false)? {
+        let relative = input.strip_prefix(input_folder_path)
+            .map_err(|e| PathRewriteError::FileNameError(e.to_string()))?;
+
+        if clear_ext(relative)? == entry {
+            staged = Some(input);
+            break;
+        }
+    }
+
+    let staged = staged.ok_or_else(|| DecompressionError::EntryNotFound(entry.to_path_buf()))?;
+    let relative = staged.strip_prefix(input_folder_path)
+        .map_err(|e| PathRewriteError::FileNameError(e.to_string()))?
+        .to_path_buf();
+
+    let promoted = match manifest.as_mut() {
+        Some(manifest) => promote_duplicate(root, manifest, &relative)?,
+        None => false,
+    };
+
+    if !promoted {
+        std::fs::remove_file(&staged)?;
+    }
+
+    // Directories only holding the removed entry would otherwise be
+    // packed, and extracted, as empty directories.
+    let mut parent = staged.parent();
+
+    while let Some(dir) = parent.filter(|dir| *dir != root) {
+        if std::fs::read_dir(dir)?.next().is_some() {
+            break;
+        }
+
+        std::fs::remove_dir(dir)?;
+        parent = dir.parent();
+    }
+
+    if let Some(mut manifest) = manifest {
+        let recorded = manifest.files.remove(entry).is_some();
+
+        if promoted || manifest.entries.remove(&relative).is_some() || recorded {
+            manifest.overwrite(root)?;
+        }
+    }
+
+    Ok(staged)
+}
+
+// Duplicates of a removed entry would be left without any contents, so
+// the stored file is moved to the first of them instead of being removed.
+// Returns whether there was a duplicate to move it to.
+fn promote_duplicate(root: &Path, manifest: &mut Manifest, relative: &Path) -> Result<bool, DecompressionError> {
+    let Some(duplicate) = manifest.duplicates
+        .iter()
+        .find(|(_, original)| *original == relative)
+        .map(|(duplicate, _)| duplicate.clone()) else {
+        return Ok(false);
+    };
+
+    manifest.duplicates.remove(&duplicate);
+
+    let compression = manifest.entries.get(relative).unwrap_or(&manifest.compression);
+
+    let mut name = duplicate.file_name()
+        .ok_or_else(|| PathRewriteError::FileNameError("Failed to get file name".into()))?
+        .to_os_string();
+    name.push(build_common_extension(&manifest.encryption, compression));
+
+    let moved = duplicate.with_file_name(name);
+
+    debug!("Moving '{}' to its duplicate '{}'", relative.display(), moved.display());
+
+    if let Some(parent) = root.join(&moved).parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    std::fs::rename(root.join(relative), root.join(&moved))?;
+
+    if let Some(compression) = manifest.entries.remove(relative) {
+        manifest.entries.insert(moved.clone(), compression);
+    }
+
+    manifest.duplicates
+        .values_mut()
+        .filter(|original| *original == relative)
+        .for_each(|original| *original = moved.clone());
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use options::{ContentTransform, RenameTemplate, DEFAULT_IN_MEMORY_THRESHOLD};
+
+    // A directory of the test's own under the temp directory, removed
+    // with everything in it when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("zap-lib-{}-{}", name, std::process::id()));
+
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+
+            TempDir(path)
+        }
+
+        fn join(&self, name: &str) -> String {
+            self.0.join(name).to_string_lossy().into_owned()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn write_files(root: &str, files: &[(&str, &[u8])]) {
+        for (name, data) in files {
+            let path = Path::new(root).join(name);
+
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, data).unwrap();
+        }
+    }
+
+    fn compressed(dir: &TempDir, encryption: EncryptionType, secret: EncryptionSecret) -> String {
+        let (input, output) = (dir.join("input"), dir.join("output"));
+
+        write_files(&input, &[("a.txt", b"first file"), ("nested/b.txt", &b"second file".repeat(1000))]);
+
+        compress_directory(
+            &input,
+            &output,
+            encryption,
+            secret,
+            CompressionType::Lz4,
+            flate2::Compression::default(),
+            SigningType::default(),
+            &CompressOptions::default(),
+        )
+        .unwrap();
+
+        output
+    }
+
+    // Compresses 'files' from '/in' to '/out' in memory.
+    fn memory_archive(files: &[(&str, &[u8])], options: &CompressOptions) -> (fs::MemoryFs, DirectoryReport) {
+        let fs = fs::MemoryFs::new();
+
+        for (name, data) in files {
+            fs.insert(Path::new("/in").join(name), data).unwrap();
+        }
+
+        let report = compress_directory_in(
+            &fs,
+            "/in",
+            "/out",
+            EncryptionType::Passthrough,
+            EncryptionSecret::None,
+            CompressionType::Lz4,
+            flate2::Compression::default(),
+            SigningType::default(),
+            options,
+        )
+        .unwrap();
+
+        (fs, report)
+    }
+
+    fn memory_extract(fs: &fs::MemoryFs, options: &DecompressOptions) -> Result<DirectoryReport, DecompressionError> {
+        decompress_directory_in(
+            fs,
+            "/out",
+            "/restored",
+            EncryptionType::Passthrough,
+            EncryptionSecret::None,
+            CompressionType::Auto,
+            SigningType::default(),
+            options,
+        )
+    }
+
+    fn restored(fs: &fs::MemoryFs) -> BTreeMap<PathBuf, Vec<u8>> {
+        fs.files().into_iter().filter(|(path, _)| path.starts_with("/restored")).collect()
+    }
+
+    #[test]
+    fn memory_fs_round_trips_in_order() {
+        let files: [(&str, &[u8]); 3] = [("b.txt", b"second"), ("a.txt", b"first"), ("dir/c.txt", b"third")];
+        let (fs, report) = memory_archive(&files, &CompressOptions::default());
+
+        // By path, whatever order they were given or finished in.
+        let sources: Vec<PathBuf> = report.processed.iter().map(|f| f.source.clone()).collect();
+        assert_eq!(sources, ["/in/a.txt", "/in/b.txt", "/in/dir/c.txt"].map(PathBuf::from));
+        assert_eq!(report.bytes(), 16);
+        assert!(report.skipped.is_empty() && report.duplicates.is_empty());
+
+        assert!(fs.exists(Path::new("/out/dir/c.txt.lz4")));
+
+        let extracted = memory_extract(&fs, &DecompressOptions::default()).unwrap();
+        assert_eq!(extracted.processed.len(), 3);
+
+        assert_eq!(
+            restored(&fs),
+            files.iter().map(|(name, data)| (Path::new("/restored").join(name), data.to_vec())).collect(),
+        );
+    }
+
+    #[test]
+    fn memory_fs_filters_hidden_and_vcs() {
+        let options = CompressOptions { exclude_hidden: true, exclude_vcs: true, ..CompressOptions::default() };
+        let (fs, report) = memory_archive(&[(".git/config", b"vcs"), (".hidden", b"hidden"), ("kept.txt", b"kept")], &options);
+
+        assert_eq!(report.processed.len(), 1);
+        assert_eq!(
+            report.skipped.iter().map(|f| (f.path.clone(), f.reason.clone())).collect::<Vec<_>>(),
+            vec![(PathBuf::from("/in/.git"), SkipReason::VersionControl), (PathBuf::from("/in/.hidden"), SkipReason::Hidden)],
+        );
+
+        memory_extract(&fs, &DecompressOptions::default()).unwrap();
+
+        assert_eq!(restored(&fs).into_keys().collect::<Vec<_>>(), vec![PathBuf::from("/restored/kept.txt")]);
+    }
+
+    #[test]
+    fn hidden_directories_are_pruned() {
+        let files: [(&str, &[u8]); 4] = [
+            (".cache/deep/.nested/file", b"cached"),
+            ("src/.hidden/x.txt", b"x"),
+            ("src/.env", b"env"),
+            ("src/visible.txt", b"visible"),
+        ];
+
+        let options = CompressOptions { exclude_hidden: true, ..CompressOptions::default() };
+        let (_, report) = memory_archive(&files, &options);
+
+        assert_eq!(report.processed.iter().map(|f| f.source.clone()).collect::<Vec<_>>(), vec![PathBuf::from("/in/src/visible.txt")]);
+
+        // Only what was pruned, not everything beneath it.
+        assert_eq!(
+            report.skipped.iter().map(|f| (f.path.clone(), f.reason.clone())).collect::<Vec<_>>(),
+            ["/in/.cache", "/in/src/.env", "/in/src/.hidden"].map(|p| (PathBuf::from(p), SkipReason::Hidden)),
+        );
+
+        let (_, report) = memory_archive(&files, &CompressOptions::default());
+        assert_eq!(report.processed.len(), 4);
+        assert!(report.skipped.is_empty());
+    }
+
+    #[test]
+    fn duplicates_are_stored_once() {
+        let names: Vec<String> = (0..10).map(|n| format!("copies/{}.txt", n)).collect();
+        let mut files: Vec<(&str, &[u8])> = names.iter().map(|name| (name.as_str(), &b"the same contents"[..])).collect();
+        files.push(("unique.txt", b"different contents"));
+
+        let options = CompressOptions { dedup: true, ..CompressOptions::default() };
+        let (fs, report) = memory_archive(&files, &options);
+
+        assert_eq!(report.processed.len(), 2);
+        assert_eq!(report.duplicates.len(), 9);
+        assert!(report.duplicates.iter().all(|d| d.duplicate_of == Path::new("/in/copies/0.txt")));
+
+        let stored: Vec<PathBuf> = fs.files()
+            .into_keys()
+            .filter(|path| path.starts_with("/out") && !manifest::is_manifest_path(Path::new("/out"), path))
+            .collect();
+        assert_eq!(stored, ["/out/copies/0.txt.lz4", "/out/unique.txt.lz4"].map(PathBuf::from));
+
+        let manifest = Manifest::read_from_in(&fs, Path::new("/out")).unwrap().unwrap();
+        assert_eq!(manifest.duplicates.len(), 9);
+
+        memory_extract(&fs, &DecompressOptions::default()).unwrap();
+
+        assert_eq!(
+            restored(&fs),
+            files.iter().map(|(name, data)| (Path::new("/restored").join(name), data.to_vec())).collect(),
+        );
+    }
+
+    #[test]
+    fn memory_fs_resolves_conflicts() {
+        let (fs, _) = memory_archive(&[("a/x.txt", b"from a"), ("b/x.txt", b"from b")], &CompressOptions::default());
+
+        let extract = |on_conflict: ConflictPolicy| {
+            // What the last policy extracted is removed first.
+            for path in restored(&fs).keys() {
+                fs.remove_file(path).unwrap();
+            }
+
+            let options = DecompressOptions { flatten: true, on_conflict, ..DecompressOptions::default() };
+            memory_extract(&fs, &options).map(|report| (report, restored(&fs)))
+        };
+
+        let (report, files) = extract(ConflictPolicy::Rename).unwrap();
+        assert!(report.skipped.is_empty());
+        assert_eq!(
+            files,
+            BTreeMap::from([
+                (PathBuf::from("/restored/x (1).txt"), b"from b".to_vec()),
+                (PathBuf::from("/restored/x.txt"), b"from a".to_vec()),
+            ]),
+        );
+
+        for (policy, kept) in [(ConflictPolicy::Skip, b"from a"), (ConflictPolicy::Overwrite, b"from b")] {
+            let (report, files) = extract(policy.clone()).unwrap();
+
+            assert_eq!(report.processed.len(), 1, "{:?}", policy);
+            assert_eq!(report.skipped.iter().map(|f| f.reason.clone()).collect::<Vec<_>>(), vec![SkipReason::Conflict], "{:?}", policy);
+            assert_eq!(files, BTreeMap::from([(PathBuf::from("/restored/x.txt"), kept.to_vec())]), "{:?}", policy);
+        }
+
+        assert!(matches!(extract(ConflictPolicy::Error), Err(DecompressionError::PathConflict(..))));
+    }
+
+    #[test]
+    fn first_failing_entry_is_reported() {
+        let files: [(&str, &[u8]); 4] = [("a.txt", b"a"), ("b.txt", b"b"), ("c.txt", b"c"), ("d.txt", b"d")];
+        let (fs, _) = memory_archive(&files, &CompressOptions::default());
+
+        // Neither is a valid lz4 frame.
+        fs.insert("/out/d.txt.lz4", b"corrupt d").unwrap();
+        fs.insert("/out/b.txt.lz4", b"corrupt b").unwrap();
+
+        for _ in 0..10 {
+            match memory_extract(&fs, &DecompressOptions::default()) {
+                Err(DecompressionError::FileFailed(path, _)) => assert_eq!(path, Path::new("/out/b.txt.lz4")),
+                other => panic!("Expected b.txt to fail, got {:?}", other.map(|r| r.processed.len())),
+            }
+        }
+    }
+
+    #[test]
+    fn strips_leading_components() {
+        let (fs, _) = memory_archive(&[("top/a.txt", b"a"), ("top/dir/b.txt", b"b"), ("loose.txt", b"loose")], &CompressOptions::default());
+
+        let options = DecompressOptions { strip_components: 1, ..DecompressOptions::default() };
+        let report = memory_extract(&fs, &options).unwrap();
+
+        assert_eq!(
+            restored(&fs),
+            BTreeMap::from([
+                (PathBuf::from("/restored/a.txt"), b"a".to_vec()),
+                (PathBuf::from("/restored/dir/b.txt"), b"b".to_vec()),
+            ]),
+        );
+        assert_eq!(
+            report.skipped.iter().map(|f| f.reason.clone()).collect::<Vec<_>>(),
+            vec![SkipReason::TooFewComponents],
+        );
+    }
+
+    #[test]
+    fn extracted_paths_stay_in_the_output() {
+        let strip = |n: usize| DecompressOptions { strip_components: n, ..DecompressOptions::default() };
+
+        assert_eq!(extracted_path(Path::new("top/a.txt"), 1, &strip(1)).unwrap(), Some(PathBuf::from("a.txt")));
+        assert_eq!(extracted_path(Path::new("top/a.txt"), 1, &strip(2)).unwrap(), None);
+        assert_eq!(extracted_path(Path::new("a.txt"), 1, &strip(5)).unwrap(), None);
+
+        // Refused before stripping, which would otherwise drop the '..'.
+        for unsafe_path in ["../a.txt", "top/../../a.txt", "/etc/passwd", "./a.txt"] {
+            assert!(
+                matches!(extracted_path(Path::new(unsafe_path), 1, &strip(1)), Err(DecompressionError::UnsafePath(_))),
+                "{}",
+                unsafe_path,
+            );
+        }
+    }
+
+    #[test]
+    fn renames_extracted_files() {
+        let (fs, _) = memory_archive(&[("a.txt", b"a"), ("docs/b.md", b"b"), ("docs/b.txt", b"c")], &CompressOptions::default());
+
+        let rename = |template: &str| {
+            for path in restored(&fs).keys() {
+                fs.remove_file(path).unwrap();
+            }
+
+            let options = DecompressOptions { rename: Some(RenameTemplate::parse(template).unwrap()), ..DecompressOptions::default() };
+            memory_extract(&fs, &options).unwrap();
+
+            restored(&fs)
+        };
+
+        assert_eq!(
+            rename("{dir}/{stem}_{n}.{ext}"),
+            BTreeMap::from([
+                (PathBuf::from("/restored/a_1.txt"), b"a".to_vec()),
+                (PathBuf::from("/restored/docs/b_2.md"), b"b".to_vec()),
+                (PathBuf::from("/restored/docs/b_3.txt"), b"c".to_vec()),
+            ]),
+        );
+
+        // Renamed onto the same path, so resolved with the conflict policy.
+        assert_eq!(
+            rename("{stem}.txt"),
+            BTreeMap::from([
+                (PathBuf::from("/restored/a.txt"), b"a".to_vec()),
+                (PathBuf::from("/restored/b (1).txt"), b"c".to_vec()),
+                (PathBuf::from("/restored/b.txt"), b"b".to_vec()),
+            ]),
+        );
+    }
+
+    #[test]
+    fn mismatched_algorithms_are_rejected() {
+        let (fs, _) = memory_archive(&[("a.txt", b"a")], &CompressOptions::default());
+
+        let extract = |compression: CompressionType, force_algorithm: bool| {
+            decompress_directory_in(
+                &fs,
+                "/out",
+                "/restored",
+                EncryptionType::Passthrough,
+                EncryptionSecret::None,
+                compression,
+                SigningType::default(),
+                &DecompressOptions { force_algorithm, ..DecompressOptions::default() },
+            )
+        };
+
+        assert!(matches!(extract(CompressionType::Gzip, false), Err(DecompressionError::AlgorithmMismatch(..))));
+        assert!(restored(&fs).is_empty());
+
+        // Forced, the archive's lz4 entries are read as gzip and fail.
+        assert!(extract(CompressionType::Gzip, true).is_err());
+
+        for path in restored(&fs).keys() {
+            fs.remove_file(path).unwrap();
+        }
+
+        extract(CompressionType::Lz4, false).unwrap();
+        assert_eq!(restored(&fs), BTreeMap::from([(PathBuf::from("/restored/a.txt"), b"a".to_vec())]));
+    }
+
+    #[test]
+    fn transforms_contents_before_compressing() {
+        // Upcases text files, leaving the rest as they are.
+        let upcase = ContentTransform::new(|path, contents| {
+            if path.extension().is_some_and(|ext| ext == "txt") {
+                let mut data = Vec::new();
+                contents.read_to_end(&mut data).unwrap();
+
+                return Box::new(std::io::Cursor::new(data.to_ascii_uppercase()));
+            }
+
+            Box::new(contents)
+        });
+
+        // Read in memory, and streamed.
+        for in_memory_threshold in [DEFAULT_IN_MEMORY_THRESHOLD, 0] {
+            let options = CompressOptions { transform: Some(upcase.clone()), dedup: true, in_memory_threshold, ..CompressOptions::default() };
+            let (fs, report) = memory_archive(&[("a.txt", b"abc"), ("b.txt", b"ABC"), ("c.md", b"abc")], &options);
+
+            // Found to be the same once transformed.
+            assert_eq!(report.duplicates.len(), 1, "threshold {}", in_memory_threshold);
+
+            memory_extract(&fs, &DecompressOptions::default()).unwrap();
+
+            assert_eq!(
+                restored(&fs),
+                BTreeMap::from([
+                    (PathBuf::from("/restored/a.txt"), b"ABC".to_vec()),
+                    (PathBuf::from("/restored/b.txt"), b"ABC".to_vec()),
+                    (PathBuf::from("/restored/c.md"), b"abc".to_vec()),
+                ]),
+                "threshold {}",
+                in_memory_threshold,
+            );
+        }
+    }
+
+    // Folds the case of every path under /restored, as macOS and Windows
+    // do, so that names differing only in case are the same file there.
+    struct FoldingFs(fs::MemoryFs);
+
+    impl FoldingFs {
+        fn fold(path: &Path) -> PathBuf {
+            match path.starts_with("/restored") {
+                true => PathBuf::from(path.to_string_lossy().to_lowercase()),
+                false => path.to_path_buf(),
+            }
+        }
+    }
+
+    impl FileSystem for FoldingFs {
+        type Reader = <fs::MemoryFs as FileSystem>::Reader;
+        type Writer = <fs::MemoryFs as FileSystem>::Writer;
+
+        fn open(&self, path: &Path) -> Result<Self::Reader, std::io::Error> {
+            self.0.open(&Self::fold(path))
+        }
+
+        fn create(&self, path: &Path) -> Result<Self::Writer, std::io::Error> {
+            self.0.create(&Self::fold(path))
+        }
+
+        fn metadata(&self, path: &Path) -> Result<FileInfo, std::io::Error> {
+            self.0.metadata(&Self::fold(path))
+        }
+
+        fn walk(&self, root: &Path) -> Result<Vec<PathBuf>, std::io::Error> {
+            self.0.walk(&Self::fold(root))
+        }
+
+        fn create_dir_all(&self, path: &Path) -> Result<(), std::io::Error> {
+            self.0.create_dir_all(&Self::fold(path))
+        }
+
+        fn remove_file(&self, path: &Path) -> Result<(), std::io::Error> {
+            self.0.remove_file(&Self::fold(path))
+        }
+    }
+
+    #[test]
+    fn case_conflicts_are_resolved_when_folding() {
+        let (memory, _) = memory_archive(&[("Foo.txt", b"upper"), ("foo.txt", b"lower")], &CompressOptions::default());
+        let fs = FoldingFs(memory);
+
+        assert!(!is_case_insensitive(&fs.0, Path::new("/out")).unwrap());
+
+        fs.create_dir_all(Path::new("/restored")).unwrap();
+        assert!(is_case_insensitive(&fs, Path::new("/restored")).unwrap());
+
+        let report = decompress_directory_in(
+            &fs,
+            "/out",
+            "/restored",
+            EncryptionType::Passthrough,
+            EncryptionSecret::None,
+            CompressionType::Auto,
+            SigningType::default(),
+            &DecompressOptions::default(),
+        )
+        .unwrap();
+
+        // Renamed rather than one overwriting the other.
+        assert_eq!(report.processed.len(), 2);
+        assert_eq!(
+            restored(&fs.0),
+            BTreeMap::from([
+                (PathBuf::from("/restored/foo (1).txt"), b"lower".to_vec()),
+                (PathBuf::from("/restored/foo.txt"), b"upper".to_vec()),
+            ]),
+        );
+
+        let options = DecompressOptions { on_conflict: ConflictPolicy::Error, ..DecompressOptions::default() };
+        let failed = decompress_directory_in(
+            &fs,
+            "/out",
+            "/restored",
+            EncryptionType::Passthrough,
+            EncryptionSecret::None,
+            CompressionType::Auto,
+            SigningType::default(),
+            &options,
+        );
+
+        assert!(matches!(failed, Err(DecompressionError::PathConflict(..))));
+    }
+
+    #[test]
+    fn overlapping_inputs_are_archived_once() {
+        let fs = fs::MemoryFs::new();
+        fs.insert("/in/a/x.txt", b"x").unwrap();
+        fs.insert("/in/a/b/y.txt", b"y").unwrap();
+        fs.insert("/in/c/z.txt", b"z").unwrap();
+
+        // Nested in another, given twice, and the same by another path.
+        let report = compress_directories_in(
+            &fs,
+            &["/in/a/b", "/in/a", "/in/c", "/in/a", "/in/./c/../a"],
+            "/out",
+            EncryptionType::Passthrough,
+            EncryptionSecret::None,
+            CompressionType::Lz4,
+            flate2::Compression::default(),
+            SigningType::default(),
+            &CompressOptions::default(),
+        )
+        .unwrap();
+
+        let sources: Vec<PathBuf> = report.processed.iter().map(|f| f.source.clone()).collect();
+        assert_eq!(sources, ["/in/a/b/y.txt", "/in/a/x.txt", "/in/c/z.txt"].map(PathBuf::from));
+
+        let written: Vec<PathBuf> = fs.files()
+            .into_keys()
+            .filter(|path| path.starts_with("/out") && !manifest::is_manifest_path(Path::new("/out"), path))
+            .collect();
+        assert_eq!(written, ["/out/a/b/y.txt.lz4", "/out/a/x.txt.lz4", "/out/c/z.txt.lz4"].map(PathBuf::from));
+    }
+
+    #[test]
+    fn entries_open_only_with_their_own_password() {
+        let (alice, bob, archive) = (vec![1; 32], vec![2; 32], vec![9; 32]);
+
+        let resolver = |alice: Vec<u8>, bob: Vec<u8>| options::SecretResolver::new(move |path| match path.starts_with("alice") {
+            true => EncryptionSecret::Password(alice.clone()),
+            false => EncryptionSecret::Password(bob.clone()),
+        });
+
+        let fs = fs::MemoryFs::new();
+        fs.insert("/in/alice/a.txt", b"for alice").unwrap();
+        fs.insert("/in/bob/b.txt", b"for bob").unwrap();
+
+        let options = CompressOptions { secret_resolver: Some(resolver(alice.clone(), bob.clone())), ..CompressOptions::default() };
+
+        compress_directory_in(
+            &fs,
+            "/in",
+            "/out",
+            EncryptionType::XChaCha,
+            EncryptionSecret::Password(archive.clone()),
+            CompressionType::Lz4,
+            flate2::Compression::default(),
+            SigningType::default(),
+            &options,
+        )
+        .unwrap();
+
+        let files = fs.files();
+        let extension = build_common_extension(&EncryptionType::XChaCha, &CompressionType::Lz4);
+
+        for (name, own, others) in [("alice/a.txt", &alice, [&bob, &archive]), ("bob/b.txt", &bob, [&alice, &archive])] {
+            let entry = &files[&Path::new("/out").join(format!("{}{}", name, extension))];
+
+            let open = |password: &Vec<u8>| decompress_bytes(
+                entry,
+                &EncryptionType::XChaCha,
+                &EncryptionSecret::Password(password.clone()),
+                &CompressionType::Lz4,
+                &SigningType::default(),
+            );
+
+            assert!(open(own).is_ok(), "{}", name);
+
+            for other in others {
+                assert!(open(other).is_err(), "{}", name);
+            }
+        }
+
+        let extract = |resolver: options::SecretResolver| decompress_directory_in(
+            &fs,
+            "/out",
+            "/restored",
+            EncryptionType::XChaCha,
+            EncryptionSecret::Password(archive.clone()),
+            CompressionType::Auto,
+            SigningType::default(),
+            &DecompressOptions { secret_resolver: Some(resolver), ..DecompressOptions::default() },
+        );
+
+        extract(resolver(alice.clone(), bob.clone())).unwrap();
+
+        assert_eq!(fs.open(Path::new("/restored/alice/a.txt")).unwrap().into_inner(), b"for alice");
+        assert_eq!(fs.open(Path::new("/restored/bob/b.txt")).unwrap().into_inner(), b"for bob");
+
+        assert!(extract(resolver(bob.clone(), alice.clone())).is_err());
+    }
+
+    #[test]
+    fn stored_extensions_are_passed_through() {
+        let dir = TempDir::new("stored-extensions");
+        let (input, output, restored) = (dir.join("input"), dir.join("output"), dir.join("restored"));
+
+        let text = b"compressible text ".repeat(1000);
+        let files: [(&str, &[u8]); 3] = [("photo.jpg", &text), ("nested/clip.MP4", &text), ("notes.txt", &text)];
+
+        write_files(&input, &files);
+
+        let options = CompressOptions {
+            rules: options::STORED_EXTENSIONS
+                .iter()
+                .map(|ext| options::CompressionRule { extension: ext.to_string(), compression: CompressionType::Passthrough })
+                .collect(),
+            ..CompressOptions::default()
+        };
+
+        compress_directory(
+            &input,
+            &output,
+            EncryptionType::Passthrough,
+            EncryptionSecret::None,
+            CompressionType::Lz4,
+            flate2::Compression::default(),
+            SigningType::default(),
+            &options,
+        )
+        .unwrap();
+
+        let manifest = Manifest::read_from(Path::new(&output)).unwrap().unwrap();
+
+        assert_eq!(manifest.compression, CompressionType::Lz4);
+        assert_eq!(
+            manifest.entries,
+            BTreeMap::from([
+                (PathBuf::from("nested/clip.MP4"), CompressionType::Passthrough),
+                (PathBuf::from("photo.jpg"), CompressionType::Passthrough),
+            ]),
+        );
+
+        // Stored as they are, while the rest are smaller.
+        assert_eq!(std::fs::read(Path::new(&output).join("photo.jpg")).unwrap(), text);
+        assert!(std::fs::metadata(Path::new(&output).join("notes.txt.lz4")).unwrap().len() < text.len() as u64);
+
+        decompress_directory(
+            &output,
+            &restored,
+            EncryptionType::Passthrough,
+            EncryptionSecret::None,
+            CompressionType::Auto,
+            SigningType::default(),
+            &DecompressOptions::default(),
+        )
+        .unwrap();
+
+        for (name, data) in files {
+            assert_eq!(std::fs::read(Path::new(&restored).join(name)).unwrap(), data, "{}", name);
+        }
+    }
+
+    #[test]
+    fn legacy_blocks_decrypt_without_framing() {
+        use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+        use chacha20poly1305::XChaCha20Poly1305;
+
+        let dir = TempDir::new("legacy-blocks");
+        let (input, output) = (dir.join("input"), dir.join("output"));
+
+        // As archives were encrypted before streams, a block at a time with
+        // the secret as the key and a random nonce in front of each block.
+        let text = b"encrypted before streams ".repeat(1000);
+        let cipher = XChaCha20Poly1305::new_from_slice(&[7; 32]).unwrap();
+
+        let blocks: Vec<u8> = text
+            .chunks(8192)
+            .flat_map(|block| {
+                let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+                [nonce.to_vec(), cipher.encrypt(&nonce, block).unwrap()].concat()
+            })
+            .collect();
+
+        let name = format!("a.txt{}", build_common_extension(&EncryptionType::XChaCha, &CompressionType::Passthrough));
+        write_files(&input, &[(&name, &blocks)]);
+
+        let mut manifest = serde_json::to_value(Manifest::new(CompressionType::Passthrough, EncryptionType::XChaCha)).unwrap();
+        manifest.as_object_mut().unwrap().remove("framing");
+        std::fs::write(Path::new(&input).join(manifest::MANIFEST_NAME), serde_json::to_vec(&manifest).unwrap()).unwrap();
+
+        decompress_directory(
+            &input,
+            &output,
+            EncryptionType::XChaCha,
+            EncryptionSecret::Password(vec![7; 32]),
+            CompressionType::Auto,
+            SigningType::default(),
+            &DecompressOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(std::fs::read(Path::new(&output).join("a.txt")).unwrap(), text);
+    }
+
+    #[test]
+    fn only_compressed_files_are_left_to_pack() {
+        let dir = TempDir::new("retain-processed");
+        let (input, output) = (dir.join("input"), dir.join("output"));
+
+        write_files(&input, &[("a.txt", b"a"), ("nested/b.txt", b"b")]);
+
+        let report = compress_directory(
+            &input,
+            &output,
+            EncryptionType::Passthrough,
+            EncryptionSecret::None,
+            CompressionType::Lz4,
+            flate2::Compression::default(),
+            SigningType::default(),
+            &CompressOptions::default(),
+        )
+        .unwrap();
+
+        // Left behind by a file that was skipped, or an earlier run.
+        write_files(&output, &[("stale.txt.lz4", b"stale"), ("partial/left.txt.lz4", b"left")]);
+        std::fs::create_dir(Path::new(&output).join("empty")).unwrap();
+
+        let mut stray = retain_processed(&output, &report).unwrap();
+        stray.sort();
+        assert_eq!(stray, [Path::new(&output).join("partial/left.txt.lz4"), Path::new(&output).join("stale.txt.lz4")]);
+
+        let mut left: Vec<PathBuf> = walkdir::WalkDir::new(&output)
+            .into_iter()
+            .map(|e| e.unwrap())
+            .filter(|e| e.file_type().is_file())
+            .map(|e| e.into_path())
+            .collect();
+        left.sort();
+
+        let mut expected: Vec<PathBuf> = report.processed.iter().map(|f| f.destination.clone()).collect();
+        expected.push(Path::new(&output).join(manifest::MANIFEST_NAME));
+        expected.sort();
+
+        assert_eq!(left, expected);
+        assert!(!Path::new(&output).join("partial").exists() && !Path::new(&output).join("empty").exists());
+    }
+
+    #[test]
+    fn lists_only_matching_entries() {
+        let dir = TempDir::new("list-filter");
+        let (input, output) = (dir.join("input"), dir.join("output"));
+
+        write_files(
+            &input,
+            &[("src/main.rs", b"main"), ("src/util/io.rs", b"io"), ("src/notes.md", b"notes"), ("benches/run.rs", b"run"), ("Readme.md", b"readme")],
+        );
+
+        compress_directory(
+            &input,
+            &output,
+            EncryptionType::Passthrough,
+            EncryptionSecret::None,
+            CompressionType::Lz4,
+            flate2::Compression::default(),
+            SigningType::default(),
+            &CompressOptions::default(),
+        )
+        .unwrap();
+
+        let listed = |pattern: &str| {
+            let mut paths: Vec<PathBuf> = list_archive(&output, Some(&Glob::parse(pattern).unwrap()))
+                .unwrap()
+                .entries
+                .into_iter()
+                .map(|entry| entry.path)
+                .collect();
+            paths.sort();
+
+            paths
+        };
+
+        assert_eq!(listed("src/**/*.rs"), [PathBuf::from("src/main.rs"), PathBuf::from("src/util/io.rs")]);
+        assert_eq!(listed("*.md"), [PathBuf::from("Readme.md")]);
+        assert!(listed("*.toml").is_empty());
+        assert_eq!(list_archive(&output, None).unwrap().entries.len(), 5);
+    }
+
+    #[test]
+    fn removes_a_middle_entry() {
+        let dir = TempDir::new("remove-entry");
+        let (input, output, restored) = (dir.join("input"), dir.join("output"), dir.join("restored"));
+
+        write_files(&input, &[("a.txt", b"a"), ("b.txt", b"b"), ("c.txt", b"c"), ("dir/only.txt", b"only")]);
+
+        compress_directory(
+            &input,
+            &output,
+            EncryptionType::XChaCha,
+            EncryptionSecret::Password(vec![7; 32]),
+            CompressionType::Lz4,
+            flate2::Compression::default(),
+            SigningType::default(),
+            &CompressOptions::default(),
+        )
+        .unwrap();
+
+        remove_entry(&output, Path::new("b.txt")).unwrap();
+        remove_entry(&output, Path::new("dir/only.txt")).unwrap();
+
+        assert!(matches!(remove_entry(&output, Path::new("b.txt")), Err(DecompressionError::EntryNotFound(_))));
+        // Not left to be packed as an empty directory.
+        assert!(!Path::new(&output).join("dir").exists());
+
+        decompress_directory(
+            &output,
+            &restored,
+            EncryptionType::XChaCha,
+            EncryptionSecret::Password(vec![7; 32]),
+            CompressionType::Auto,
+            SigningType::default(),
+            &DecompressOptions::default(),
+        )
+        .unwrap();
+
+        let mut names: Vec<String> = std::fs::read_dir(&restored)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+
+        assert_eq!(names, ["a.txt", "c.txt"]);
+        assert_eq!(std::fs::read(Path::new(&restored).join("c.txt")).unwrap(), b"c");
+    }
+
+    #[test]
+    fn check_secret_tells_passwords_apart() {
+        let dir = TempDir::new("check-password");
+
+        for encryption in EncryptionType::ALL.into_iter().filter(|e| *e != EncryptionType::Passthrough) {
+            let output = compressed(&dir, encryption.clone(), EncryptionSecret::Password(vec![7; 32]));
+
+            assert!(check_secret(&output, encryption.clone(), EncryptionSecret::Password(vec![7; 32])).unwrap());
+            assert!(!check_secret(&output, encryption.clone(), EncryptionSecret::Password(vec![8; 32])).unwrap(), "{:?}", encryption);
+
+            std::fs::remove_dir_all(&output).unwrap();
+        }
+    }
+
+    #[test]
+    fn check_secret_tells_keyfiles_apart() {
+        let dir = TempDir::new("check-keyfile");
+        let (key, other, short) = (dir.join("right.key"), dir.join("wrong.key"), dir.join("short.key"));
+
+        std::fs::write(&key, [1; 32]).unwrap();
+        std::fs::write(&other, [2; 32]).unwrap();
+        std::fs::write(&short, [1; 16]).unwrap();
+
+        let output = compressed(&dir, EncryptionType::XChaCha, EncryptionSecret::Key(key.clone()));
+
+        assert!(check_secret(&output, EncryptionType::XChaCha, EncryptionSecret::Key(key)).unwrap());
+        assert!(!check_secret(&output, EncryptionType::XChaCha, EncryptionSecret::Key(other)).unwrap());
+        assert!(check_secret(&output, EncryptionType::XChaCha, EncryptionSecret::Key(short)).is_err());
+    }
+
+    #[test]
+    fn check_secret_opens_unencrypted() {
+        let dir = TempDir::new("check-unencrypted");
+        let output = compressed(&dir, EncryptionType::Passthrough, EncryptionSecret::None);
+
+        assert!(check_secret(&output, EncryptionType::Passthrough, EncryptionSecret::Password(vec![8; 32])).unwrap());
+    }
+
+    #[test]
+    fn resume_skips_only_unchanged_recorded_files() {
+        let dir = TempDir::new("resume");
+        let (input, output) = (dir.join("input"), dir.join("output"));
+        let names = ["a.txt", "b.txt", "c.txt", "d.txt", "e.txt", "f.txt"];
+
+        for (n, name) in names.iter().enumerate() {
+            write_files(&input, &[(name, format!("file {}", n).repeat(100).as_bytes())]);
+        }
+
+        let run = |resume: bool| compress_directory(
+            &input,
+            &output,
+            EncryptionType::Passthrough,
+            EncryptionSecret::None,
+            CompressionType::Lz4,
+            flate2::Compression::default(),
+            SigningType::default(),
+            &CompressOptions { checkpoint: Some(dir.0.join("checkpoint")), resume, ..CompressOptions::default() },
+        )
+        .unwrap();
+
+        let first = run(false);
+
+        // As if the run were stopped after compressing four files, part
+        // way through recording the fifth.
+        let checkpoint = std::fs::read_to_string(dir.join("checkpoint")).unwrap();
+        let lines: Vec<&str> = checkpoint.lines().collect();
+        std::fs::write(dir.join("checkpoint"), format!("{}\n{}", lines[..5].join("\n"), &lines[5][..10])).unwrap();
+
+        let recorded: Vec<PathBuf> = lines[1..5].iter()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["source"].as_str().unwrap().into())
+            .collect();
+
+        // Only what's written again loses this.
+        for file in first.processed.iter() {
+            std::fs::write(&file.destination, b"stale").unwrap();
+        }
+
+        let destination = |source: &Path| first.processed.iter().find(|f| f.source == source).unwrap().destination.clone();
+
+        // Grown, touched, and lost its output since.
+        std::fs::write(&recorded[0], b"grown").unwrap();
+        std::fs::File::options().write(true).open(&recorded[1]).unwrap()
+            .set_modified(std::time::SystemTime::UNIX_EPOCH + Duration::from_secs(1000)).unwrap();
+        std::fs::remove_file(destination(&recorded[2])).unwrap();
+
+        let resumed = run(true);
+
+        assert_eq!(resumed.processed.len(), names.len());
+
+        for file in resumed.processed.iter() {
+            let skipped = std::fs::read(&file.destination).unwrap() == b"stale";
+
+            assert_eq!(skipped, file.source == recorded[3], "{}", file.source.display());
+        }
+    }
+}
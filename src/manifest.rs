@@ -0,0 +1,236 @@
+use std::{
+    collections::BTreeMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    compression::CompressionType,
+    encryption::{EncryptionFraming, EncryptionSecret, EncryptionType, SecretType},
+    error::ManifestError,
+    fs::{FileInfo, FileSystem, RealFs},
+    signing::SigningType,
+};
+
+// The manifest is written to the root of the staging directory
+// so that it is packed alongside the processed files.
+pub const MANIFEST_NAME: &str = ".zap-manifest.json";
+
+/// Longest comment that can be attached to an archive, in bytes.
+pub const MAX_COMMENT_LEN: usize = 4096;
+
+// A compressed manifest is named after its algorithm, eg:
+// '.zap-manifest.json.lz4'. It is never encrypted, as the manifest
+// is read before a secret is asked for.
+const MANIFEST_COMPRESSIONS: [CompressionType; 4] = [
+    CompressionType::Passthrough,
+    CompressionType::Lz4,
+    CompressionType::Gzip,
+    CompressionType::Snappy,
+];
+
+fn manifest_name(compression: &CompressionType) -> String {
+    format!("{}{}", MANIFEST_NAME, crate::build_common_extension(&EncryptionType::Passthrough, compression))
+}
+
+/// Whether 'path' is where the manifest of 'dir' is stored, compressed or not.
+pub fn is_manifest_path(dir: &Path, path: &Path) -> bool {
+    MANIFEST_COMPRESSIONS
+        .iter()
+        .any(|c| dir.join(manifest_name(c)) == path)
+}
+
+/// Describes how the entries of an archive were processed, allowing
+/// extraction to pick the right algorithms without user input.
+/// Fields added after the first manifest version are optional so
+/// that older archives can still be read.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub compression: CompressionType,
+    pub encryption: EncryptionType,
+    /// Seconds since the unix epoch at which the archive was created.
+    #[serde(default)]
+    pub created_at: Option<u64>,
+    /// Version of zap that created the archive.
+    #[serde(default)]
+    pub zap_version: Option<String>,
+    /// Entries compressed with something other than 'compression', keyed
+    /// by their path relative to the root of the archive.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub entries: BTreeMap<PathBuf, CompressionType>,
+    /// Whether the archive was encrypted with a password or a key.
+    #[serde(default)]
+    pub secret: Option<SecretType>,
+    /// Archives from before signing was recorded were never signed.
+    #[serde(default)]
+    pub signing: SigningType,
+    /// Files that weren't stored because their contents are the same as
+    /// a stored entry. Keyed by the path the file is extracted to, both
+    /// relative to the root of the archive.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub duplicates: BTreeMap<PathBuf, PathBuf>,
+    /// Free text attached when the archive was created, never encrypted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    /// Each file as it was when archived, keyed by the path it is
+    /// extracted to, duplicates included. Used to compare the archive
+    /// with the directory it was made from.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub files: BTreeMap<PathBuf, FileRecord>,
+    /// How encrypted entries are laid out, see framing.
+    #[serde(default)]
+    pub framing: Option<EncryptionFraming>,
+    /// Entries encrypted with a secret of their own, from a SecretResolver,
+    /// and the kind of secret. Keyed by the path the entry is extracted to.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub entry_secrets: BTreeMap<PathBuf, SecretType>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileRecord {
+    /// Size before compression.
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    /// Only recorded where the platform provides it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<SystemTime>,
+}
+
+impl From<&FileInfo> for FileRecord {
+    fn from(info: &FileInfo) -> Self {
+        FileRecord {
+            size: info.len,
+            modified: info.modified,
+            created: info.created,
+        }
+    }
+}
+
+impl Manifest {
+    pub fn new(compression: CompressionType, encryption: EncryptionType) -> Self {
+        Manifest {
+            compression,
+            encryption,
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|d| d.as_secs()),
+            zap_version: Some(env!("CARGO_PKG_VERSION").into()),
+            entries: BTreeMap::new(),
+            secret: None,
+            signing: SigningType::default(),
+            duplicates: BTreeMap::new(),
+            comment: None,
+            files: BTreeMap::new(),
+            framing: Some(EncryptionFraming::Stream),
+            entry_secrets: BTreeMap::new(),
+        }
+    }
+
+    /// Archives from before the framing was recorded were all encrypted
+    /// block by block.
+    pub fn framing(&self) -> EncryptionFraming {
+        self.framing.unwrap_or(EncryptionFraming::Blocks)
+    }
+
+    pub fn write_to(&self, dir: &Path) -> Result<(), ManifestError> {
+        self.write_to_in(&RealFs, dir)
+    }
+
+    pub fn write_to_in<F: FileSystem>(&self, fs: &F, dir: &Path) -> Result<(), ManifestError> {
+        self.write_compressed_to_in(fs, dir, &CompressionType::Passthrough, &flate2::Compression::default())
+    }
+
+    /// Writes the manifest compressed with 'compression', which pays off
+    /// for archives with many entries recorded. Auto is written as is.
+    pub fn write_compressed_to_in<F: FileSystem>(
+        &self,
+        fs: &F,
+        dir: &Path,
+        compression: &CompressionType,
+        level: &flate2::Compression,
+    ) -> Result<(), ManifestError> {
+        // Any of the names would be read back as the manifest.
+        for c in MANIFEST_COMPRESSIONS.iter() {
+            let path = dir.join(manifest_name(c));
+
+            if fs.exists(&path) {
+                return Err(ManifestError::ReservedName(path.to_string_lossy().into()));
+            }
+        }
+
+        self.store(fs, dir, compression, level)
+    }
+
+    /// Replaces the manifest in 'dir', for when an archive is edited.
+    /// It is kept compressed if it was before.
+    pub fn overwrite(&self, dir: &Path) -> Result<(), ManifestError> {
+        self.overwrite_in(&RealFs, dir)
+    }
+
+    pub fn overwrite_in<F: FileSystem>(&self, fs: &F, dir: &Path) -> Result<(), ManifestError> {
+        let compression = MANIFEST_COMPRESSIONS
+            .iter()
+            .find(|c| fs.exists(&dir.join(manifest_name(c))))
+            .unwrap_or(&CompressionType::Passthrough);
+
+        self.store(fs, dir, compression, &flate2::Compression::default())
+    }
+
+    fn store<F: FileSystem>(
+        &self,
+        fs: &F,
+        dir: &Path,
+        compression: &CompressionType,
+        level: &flate2::Compression,
+    ) -> Result<(), ManifestError> {
+        let data = match compression {
+            CompressionType::Passthrough | CompressionType::Auto => {
+                return Ok(fs.write(&dir.join(MANIFEST_NAME), &serde_json::to_vec_pretty(self)?)?)
+            },
+            c => crate::compress_bytes(
+                &serde_json::to_vec(self)?,
+                &EncryptionType::Passthrough,
+                &EncryptionSecret::None,
+                c,
+                level,
+                &SigningType::default(),
+            ).map_err(|e| ManifestError::CompressionFailed(Box::new(e)))?,
+        };
+
+        Ok(fs.write(&dir.join(manifest_name(compression)), &data)?)
+    }
+
+    /// Returns None for archives that were created without a manifest.
+    pub fn read_from(dir: &Path) -> Result<Option<Manifest>, ManifestError> {
+        Self::read_from_in(&RealFs, dir)
+    }
+
+    pub fn read_from_in<F: FileSystem>(fs: &F, dir: &Path) -> Result<Option<Manifest>, ManifestError> {
+        for compression in MANIFEST_COMPRESSIONS.iter() {
+            let data = match fs.read(&dir.join(manifest_name(compression))) {
+                Ok(d) => d,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            let data = match compression {
+                CompressionType::Passthrough => data,
+                c => crate::decompress_bytes(
+                    &data,
+                    &EncryptionType::Passthrough,
+                    &EncryptionSecret::None,
+                    c,
+                    &SigningType::default(),
+                ).map_err(|e| ManifestError::DecompressionFailed(Box::new(e)))?,
+            };
+
+            return Ok(Some(serde_json::from_slice(&data)?));
+        }
+
+        Ok(None)
+    }
+}
@@ -0,0 +1,692 @@
+use std::{
+    io::{Error, ErrorKind, Read},
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use log::warn;
+
+use crate::{
+    compression::CompressionType,
+    encryption::EncryptionSecret,
+    error::{GlobError, PathMapError, TemplateError},
+    progress::Progress,
+};
+
+/// Settings that control how a directory is walked and processed,
+/// separate from the algorithms applied to each file.
+#[derive(Debug, Clone)]
+pub struct CompressOptions {
+    pub on_change: ChangePolicy,
+    /// Per-extension compression, consulted before the default algorithm.
+    pub rules: Vec<CompressionRule>,
+    /// Files smaller than this many bytes are read and processed in
+    /// memory instead of being streamed. 0 streams every file.
+    pub in_memory_threshold: u64,
+    /// Updated as each file is finished.
+    pub progress: Option<Arc<Progress>>,
+    /// Compress the manifest with the archive's algorithm.
+    pub compress_manifest: bool,
+    /// Skip files and directories whose name starts with '.'.
+    /// Hidden directories aren't descended into.
+    pub exclude_hidden: bool,
+    /// Skip the files and directories named in VCS_NAMES, without
+    /// descending into the directories.
+    pub exclude_vcs: bool,
+    /// Read files without updating their access time, where supported.
+    pub no_atime: bool,
+    /// Store files with the same contents once, at the cost of hashing
+    /// every file before compressing.
+    pub dedup: bool,
+    pub io_retry: RetryPolicy,
+    /// Stored in the manifest, up to MAX_COMMENT_LEN bytes.
+    pub comment: Option<String>,
+    /// Where each compressed file is recorded, see Checkpoint.
+    pub checkpoint: Option<PathBuf>,
+    /// Skip the files recorded in 'checkpoint' by an earlier run, when
+    /// unchanged and still in the output directory.
+    pub resume: bool,
+    /// How many files ahead of the one being compressed to start reading,
+    /// for storage where each read waits a long time. 0 to not.
+    pub prefetch: usize,
+    /// Applied to the contents of every file before it is compressed.
+    pub transform: Option<ContentTransform>,
+    /// Encrypts each file with a secret of its own, in place of the one
+    /// given for the archive. Can't be combined with 'dedup'.
+    pub secret_resolver: Option<SecretResolver>,
+    /// How many blocks of a large file are compressed at once, on the same
+    /// threads as the other files, see compression::blocks. 1 compresses
+    /// every file on one thread.
+    pub threads_per_file: usize,
+}
+
+impl Default for CompressOptions {
+    fn default() -> Self {
+        CompressOptions {
+            on_change: ChangePolicy::default(),
+            rules: Vec::new(),
+            in_memory_threshold: DEFAULT_IN_MEMORY_THRESHOLD,
+            progress: None,
+            compress_manifest: false,
+            exclude_hidden: false,
+            exclude_vcs: false,
+            no_atime: false,
+            dedup: false,
+            io_retry: RetryPolicy::default(),
+            comment: None,
+            checkpoint: None,
+            resume: false,
+            prefetch: 0,
+            transform: None,
+            secret_resolver: None,
+            threads_per_file: 1,
+        }
+    }
+}
+
+type TransformFn = dyn for<'a> Fn(&Path, &'a mut dyn Read) -> Box<dyn Read + 'a> + Send + Sync;
+
+/// Rewrites the contents of each file before it is compressed, eg: to
+/// redact secrets. Called with the path of the file and its contents, and
+/// returns the contents to compress in their place. The size and times
+/// recorded for the file are those of the original.
+#[derive(Clone)]
+pub struct ContentTransform(Arc<TransformFn>);
+
+impl ContentTransform {
+    pub fn new(transform: impl for<'a> Fn(&Path, &'a mut dyn Read) -> Box<dyn Read + 'a> + Send + Sync + 'static) -> Self {
+        ContentTransform(Arc::new(transform))
+    }
+
+    pub(crate) fn apply<'a>(&self, path: &Path, contents: &'a mut dyn Read) -> Box<dyn Read + 'a> {
+        (self.0)(path, contents)
+    }
+}
+
+impl std::fmt::Debug for ContentTransform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ContentTransform")
+    }
+}
+
+type SecretFn = dyn Fn(&Path) -> EncryptionSecret + Send + Sync;
+
+/// Picks the secret each entry is encrypted with, eg: a key per user of a
+/// service, so that one leaked secret only opens the entries encrypted
+/// with it. Called with the path the entry is extracted to, relative to
+/// the root of the archive, when compressing and again when extracting.
+#[derive(Clone)]
+pub struct SecretResolver(Arc<SecretFn>);
+
+impl SecretResolver {
+    pub fn new(resolver: impl Fn(&Path) -> EncryptionSecret + Send + Sync + 'static) -> Self {
+        SecretResolver(Arc::new(resolver))
+    }
+
+    pub(crate) fn resolve(&self, path: &Path) -> EncryptionSecret {
+        (self.0)(path)
+    }
+}
+
+impl std::fmt::Debug for SecretResolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SecretResolver")
+    }
+}
+
+pub const DEFAULT_IN_MEMORY_THRESHOLD: u64 = 64 * 1024;
+
+/// Large enough that a slow disk, eg: a spinning one, is written to in
+/// long runs rather than many small writes.
+pub const DEFAULT_WRITE_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Names of the directories and files used by version control systems,
+/// the same as tar's --exclude-vcs.
+pub const VCS_NAMES: [&str; 21] = [
+    // Git
+    ".git", ".gitignore", ".gitattributes", ".gitmodules",
+    // Subversion
+    ".svn",
+    // Mercurial
+    ".hg", ".hgignore", ".hgtags",
+    // Bazaar
+    ".bzr", ".bzrignore", ".bzrtags",
+    // CVS, RCS and SCCS
+    "CVS", ".cvsignore", "RCS", "SCCS",
+    // Arch
+    ".arch-ids", "{arch}", "=RELEASE-ID", "=meta-update", "=update",
+    // Darcs
+    "_darcs",
+];
+
+/// Extensions of formats that are compressed already, that the command
+/// line stores as they are unless given others. Zap's own, eg: gz, are
+/// left out, as an entry named with one would be extracted with it.
+pub const STORED_EXTENSIONS: [&str; 22] = [
+    // Images
+    "jpg", "jpeg", "png", "gif", "webp", "heic", "avif",
+    // Audio and video
+    "mp3", "aac", "ogg", "flac", "opus", "mp4", "mkv", "mov", "webm",
+    // Archives
+    "zip", "bz2", "xz", "zst", "7z", "rar",
+];
+
+impl CompressOptions {
+    /// The compression to use for 'path', the first matching rule or
+    /// 'default' when none match.
+    pub fn compression_for<'a>(&'a self, path: &Path, default: &'a CompressionType) -> &'a CompressionType {
+        let extension = match path.extension() {
+            Some(ext) => ext.to_string_lossy(),
+            None => return default,
+        };
+
+        self.rules
+            .iter()
+            .find(|rule| rule.extension.eq_ignore_ascii_case(&extension))
+            .map(|rule| &rule.compression)
+            .unwrap_or(default)
+    }
+}
+
+/// Compresses files with the given extension using 'compression'.
+/// The extension is matched without the leading '.' and ignoring case.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressionRule {
+    pub extension: String,
+    pub compression: CompressionType,
+}
+
+/// What to do when a source file is modified or removed between
+/// scanning the directory and finishing its compression.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub enum ChangePolicy {
+    /// Leave the file out of the archive and record it in the report.
+    #[default]
+    Skip,
+    /// Compress a modified file again, up to CHANGE_RETRIES times,
+    /// before skipping it. Removed files are skipped.
+    Retry,
+    /// Fail the whole operation.
+    Error,
+}
+
+pub const CHANGE_RETRIES: usize = 3;
+
+/// Settings that control how an unpacked archive is extracted.
+#[derive(Debug, Default, Clone)]
+pub struct DecompressOptions {
+    pub on_conflict: ConflictPolicy,
+    /// Extract every file directly into the output directory, dropping
+    /// the directory structure of the archive. Files with the same name
+    /// are resolved with 'on_conflict'.
+    pub flatten: bool,
+    /// Number of leading components removed from each entry's path, like
+    /// tar's --strip-components. Entries left with no path are skipped.
+    pub strip_components: usize,
+    /// Largest size any one file may be extracted to, in bytes.
+    pub max_file_size: Option<u64>,
+    /// Largest size all files may be extracted to together, in bytes.
+    pub max_total_size: Option<u64>,
+    /// Updated as each file is finished.
+    pub progress: Option<Arc<Progress>>,
+    pub io_retry: RetryPolicy,
+    /// Applied to the path of each file after stripping and flattening.
+    /// Files renamed to the same path are resolved with 'on_conflict'.
+    pub rename: Option<RenameTemplate>,
+    /// Use the requested compression algorithm for every entry, even when
+    /// the manifest records a different one.
+    pub force_algorithm: bool,
+    /// Entries under a mapped prefix are extracted to its destination,
+    /// without stripping, flattening or renaming them.
+    pub path_map: Option<PathMap>,
+    /// Which symlinks in the archive may be extracted through.
+    pub symlink_policy: SymlinkPolicy,
+    /// Restore the modification time, and creation time where the platform
+    /// allows, recorded for each file when archived.
+    pub preserve_timestamps: bool,
+    /// Capacity of the buffer each file is written to disk through, by
+    /// default one picked from the size of the file.
+    pub write_buffer_size: Option<usize>,
+    /// The same resolver the archive was created with, for archives whose
+    /// entries were each encrypted with a secret of their own.
+    pub secret_resolver: Option<SecretResolver>,
+}
+
+/// An output path built from parts of an entry's path, eg:
+/// '{dir}/{stem}_{n}.{ext}'. The placeholders are:
+///
+/// - {dir}: the directories holding the entry, empty at the top level
+/// - {name}: the file name
+/// - {stem}: the file name without its last extension
+/// - {ext}: the last extension, a '.' before it is dropped when there is none
+/// - {n}: the entry's position, from 1, when sorted by path
+///
+/// Empty directories in the result, eg: from an empty {dir}, are dropped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenameTemplate {
+    parts: Vec<TemplatePart>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TemplatePart {
+    Literal(String),
+    Dir,
+    Name,
+    Stem,
+    Ext,
+    N,
+}
+
+impl RenameTemplate {
+    pub fn parse(template: &str) -> Result<Self, TemplateError> {
+        if template.is_empty() {
+            return Err(TemplateError::Empty);
+        }
+
+        if template.starts_with('/') || template.contains('\\') {
+            return Err(TemplateError::UnsafePath(template.into()));
+        }
+
+        let mut parts = Vec::new();
+        let mut rest = template;
+
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(TemplatePart::Literal(rest[..start].into()));
+            }
+
+            let end = rest[start..].find('}').ok_or(TemplateError::Unclosed)? + start;
+
+            parts.push(match &rest[start + 1..end] {
+                "dir" => TemplatePart::Dir,
+                "name" => TemplatePart::Name,
+                "stem" => TemplatePart::Stem,
+                "ext" => TemplatePart::Ext,
+                "n" => TemplatePart::N,
+                other => return Err(TemplateError::UnknownPlaceholder(other.into())),
+            });
+
+            rest = &rest[end + 1..];
+        }
+
+        if !rest.is_empty() {
+            parts.push(TemplatePart::Literal(rest.into()));
+        }
+
+        // The placeholders can only produce plain names, so only the
+        // literal parts could lead outside of the output directory.
+        let template = RenameTemplate { parts };
+        let example = template.render(Path::new("dir/name.ext"), 1);
+
+        if !example.components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(TemplateError::UnsafePath(example.display().to_string()));
+        }
+
+        Ok(template)
+    }
+
+    pub(crate) fn render(&self, path: &Path, n: usize) -> PathBuf {
+        let dir = path.parent().map(|d| d.to_string_lossy()).unwrap_or_default();
+        let name = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+        let stem = path.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+        let ext = path.extension().map(|e| e.to_string_lossy()).unwrap_or_default();
+
+        let mut rendered = String::new();
+
+        for part in self.parts.iter() {
+            match part {
+                TemplatePart::Literal(l) => rendered.push_str(l),
+                TemplatePart::Dir => rendered.push_str(&dir),
+                TemplatePart::Name => rendered.push_str(&name),
+                TemplatePart::Stem => rendered.push_str(&stem),
+                TemplatePart::Ext if ext.is_empty() => {
+                    if rendered.ends_with('.') {
+                        rendered.pop();
+                    }
+                },
+                TemplatePart::Ext => rendered.push_str(&ext),
+                TemplatePart::N => rendered.push_str(&n.to_string()),
+            }
+        }
+
+        rendered.split('/').filter(|c| !c.is_empty()).collect()
+    }
+}
+
+/// A pattern matched against the whole path of an entry, eg: 'src/**/*.rs'.
+/// '*' matches any part of a name and '?' any one character, '**' matches
+/// any number of directories, including none.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Glob {
+    segments: Vec<Vec<char>>,
+}
+
+impl Glob {
+    pub fn parse(pattern: &str) -> Result<Self, GlobError> {
+        let segments: Vec<Vec<char>> = pattern
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(|s| s.chars().collect())
+            .collect();
+
+        if segments.is_empty() {
+            return Err(GlobError::Empty);
+        }
+
+        Ok(Glob { segments })
+    }
+
+    pub fn matches(&self, path: &Path) -> bool {
+        let names: Vec<Vec<char>> = path
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().chars().collect())
+            .collect();
+
+        match_segments(&self.segments, &names)
+    }
+}
+
+fn match_segments(segments: &[Vec<char>], names: &[Vec<char>]) -> bool {
+    match (segments.split_first(), names.split_first()) {
+        (None, _) => names.is_empty(),
+        (Some((segment, rest)), _) if segment.as_slice() == ['*', '*'] => {
+            (0..=names.len()).any(|skipped| match_segments(rest, &names[skipped..]))
+        },
+        (Some((segment, rest)), Some((name, names))) => match_name(segment, name) && match_segments(rest, names),
+        (Some(_), None) => false,
+    }
+}
+
+fn match_name(pattern: &[char], name: &[char]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, _) => name.is_empty(),
+        (Some(('*', rest)), _) => (0..=name.len()).any(|skipped| match_name(rest, &name[skipped..])),
+        (Some(('?', rest)), Some((_, name))) => match_name(rest, name),
+        (Some((p, rest)), Some((c, name))) => p == c && match_name(rest, name),
+        (Some(_), None) => false,
+    }
+}
+
+/// Extracts entries under an archived path somewhere else instead, eg:
+/// 'etc => /mnt/restore/etc'. Each line of the file maps one prefix, the
+/// longest matching prefix is used. Blank lines and lines starting with
+/// '#' are ignored. Relative destinations are within the output directory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathMap {
+    // Longest prefix first, so that the first match is the one used.
+    mappings: Vec<(PathBuf, PathBuf)>,
+}
+
+impl PathMap {
+    pub fn read(path: &Path) -> Result<Self, PathMapError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, PathMapError> {
+        let mut mappings = Vec::new();
+
+        for (n, line) in text.lines().enumerate().map(|(n, l)| (n + 1, l.trim())) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let Some((prefix, destination)) = line.split_once("=>") else {
+                return Err(PathMapError::InvalidLine(n, line.into()));
+            };
+
+            // Archived paths are relative, so a leading '/' is allowed to
+            // match the path the files were archived from.
+            let prefix = PathBuf::from(prefix.trim().trim_start_matches('/'));
+            let destination = PathBuf::from(destination.trim());
+
+            if prefix.as_os_str().is_empty() || destination.as_os_str().is_empty() {
+                return Err(PathMapError::InvalidLine(n, line.into()));
+            }
+
+            if !prefix.components().all(|c| matches!(c, Component::Normal(_))) {
+                return Err(PathMapError::UnsafePath(n, prefix));
+            }
+
+            // Absolute destinations are allowed, they are what the map is
+            // for, but not ones that climb out of where they point to.
+            if destination.components().any(|c| c == Component::ParentDir) {
+                return Err(PathMapError::UnsafePath(n, destination));
+            }
+
+            mappings.push((prefix, destination));
+        }
+
+        mappings.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.components().count()));
+
+        Ok(PathMap { mappings })
+    }
+
+    /// Where 'path', relative to the root of the archive, is extracted to
+    /// when it is under one of the prefixes.
+    pub(crate) fn map(&self, path: &Path) -> Option<PathBuf> {
+        self.mappings.iter().find_map(|(prefix, destination)| {
+            path.strip_prefix(prefix).ok().map(|rest| match rest.as_os_str().is_empty() {
+                true => destination.clone(),
+                false => destination.join(rest),
+            })
+        })
+    }
+}
+
+/// How many times a file operation failing with a transient error, eg:
+/// a timeout on a network filesystem, is tried again. The delay doubles
+/// after each attempt.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub delay: Duration,
+}
+
+pub const DEFAULT_IO_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: 0,
+            delay: DEFAULT_IO_RETRY_DELAY,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub(crate) fn run<T>(&self, mut op: impl FnMut() -> Result<T, Error>) -> Result<T, Error> {
+        let mut attempt = 0;
+
+        loop {
+            match op() {
+                Err(e) if attempt < self.retries && is_transient(&e) => {
+                    let delay = self.delay.saturating_mul(1 << attempt.min(16));
+                    warn!("Retrying in {:?} after a transient error: {}", delay, e);
+
+                    std::thread::sleep(delay);
+                    attempt += 1;
+                },
+                result => return result,
+            }
+        }
+    }
+}
+
+// Errors that may go away if tried again. Anything else, eg: NotFound or
+// PermissionDenied, is returned straight away.
+fn is_transient(e: &Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::WouldBlock
+            | ErrorKind::TimedOut
+            | ErrorKind::Interrupted
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+    )
+}
+
+/// What to do when two entries would be extracted to the same path,
+/// e.g. 'Foo.txt' and 'foo.txt' on a case-insensitive filesystem, or
+/// 'a/foo.txt' and 'b/foo.txt' when flattening.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub enum ConflictPolicy {
+    /// Extract the later entry under a new name, e.g. 'foo (1).txt'.
+    #[default]
+    Rename,
+    /// Keep the first entry and leave the later one out.
+    Skip,
+    /// Keep the last entry and leave the earlier one out.
+    Overwrite,
+    /// Fail the whole operation.
+    Error,
+}
+
+/// Which symlink targets an archive may contain. Entries are read through
+/// their links, so a link pointing elsewhere would extract whatever it
+/// points to. Targets are resolved from the directory holding the link,
+/// without following any other links, which are checked on their own.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub enum SymlinkPolicy {
+    /// Only targets within the archive, whether absolute or relative.
+    #[default]
+    DenyEscaping,
+    /// Only relative targets within the archive.
+    RelativeOnly,
+    /// Relative targets within the archive, and any absolute target.
+    AllowAbsolute,
+}
+
+impl SymlinkPolicy {
+    /// Whether 'link', under 'root', may point to 'target'.
+    pub(crate) fn allows(&self, root: &Path, link: &Path, target: &Path) -> bool {
+        if target.is_absolute() {
+            return match self {
+                SymlinkPolicy::DenyEscaping => target.strip_prefix(root).is_ok_and(|rest| stays_within(0, rest)),
+                SymlinkPolicy::RelativeOnly => false,
+                SymlinkPolicy::AllowAbsolute => true,
+            };
+        }
+
+        let Some(dir) = link.parent().and_then(|dir| dir.strip_prefix(root).ok()) else {
+            return false;
+        };
+
+        stays_within(dir.components().count(), target)
+    }
+}
+
+// Whether 'path', followed from 'depth' directories below a root, never
+// goes above it.
+fn stays_within(mut depth: usize, path: &Path) -> bool {
+    for component in path.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {},
+            Component::ParentDir if depth > 0 => depth -= 1,
+            _ => return false,
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICIES: [SymlinkPolicy; 3] = [SymlinkPolicy::DenyEscaping, SymlinkPolicy::RelativeOnly, SymlinkPolicy::AllowAbsolute];
+
+    // Whether each of POLICIES allows a link at 'link' under /archive to 'target'.
+    fn allowed(link: &str, target: &str) -> [bool; 3] {
+        POLICIES.map(|policy| policy.allows(Path::new("/archive"), Path::new(link), Path::new(target)))
+    }
+
+    #[test]
+    fn inside_links_are_allowed() {
+        assert_eq!(allowed("/archive/link", "file"), [true; 3]);
+        assert_eq!(allowed("/archive/dir/link", "../file"), [true; 3]);
+        assert_eq!(allowed("/archive/dir/link", "./sub/../../other/file"), [true; 3]);
+    }
+
+    #[test]
+    fn escaping_links_are_denied() {
+        assert_eq!(allowed("/archive/link", "../secret"), [false; 3]);
+        assert_eq!(allowed("/archive/dir/link", "../../secret"), [false; 3]);
+        // Back inside afterwards, but only by way of the outside.
+        assert_eq!(allowed("/archive/link", "../archive/file"), [false; 3]);
+    }
+
+    #[test]
+    fn absolute_links_follow_the_policy() {
+        // [deny escaping, relative only, allow absolute]
+        assert_eq!(allowed("/archive/link", "/etc/passwd"), [false, false, true]);
+        assert_eq!(allowed("/archive/link", "/archive/dir/file"), [true, false, true]);
+        assert_eq!(allowed("/archive/link", "/archive/../etc/passwd"), [false, false, true]);
+    }
+
+    #[test]
+    fn stays_within_counts_from_depth() {
+        assert!(stays_within(0, Path::new("a/b/../c")));
+        assert!(!stays_within(0, Path::new("..")));
+        assert!(stays_within(1, Path::new("..")));
+        assert!(!stays_within(1, Path::new("../..")));
+        assert!(!stays_within(3, Path::new("/absolute")));
+    }
+
+    #[test]
+    fn stored_extensions_are_not_zaps_own() {
+        for ext in STORED_EXTENSIONS {
+            assert_eq!(CompressionType::from_extension(ext), None, "{}", ext);
+        }
+    }
+
+    #[test]
+    fn templates_render_each_placeholder() {
+        let render = |template: &str, path: &str, n: usize| {
+            RenameTemplate::parse(template).unwrap().render(Path::new(path), n)
+        };
+
+        assert_eq!(render("{dir}/{stem}_{n}.{ext}", "docs/report.pdf", 3), PathBuf::from("docs/report_3.pdf"));
+        assert_eq!(render("{name}", "docs/report.pdf", 1), PathBuf::from("report.pdf"));
+        assert_eq!(render("archive/{n}/{name}", "report.pdf", 2), PathBuf::from("archive/2/report.pdf"));
+        // No extension drops the '.', and no directory the empty one.
+        assert_eq!(render("{stem}.{ext}", "Makefile", 1), PathBuf::from("Makefile"));
+        assert_eq!(render("{dir}/{stem}", "report.pdf", 1), PathBuf::from("report"));
+    }
+
+    #[test]
+    fn bad_templates_are_rejected() {
+        assert_eq!(RenameTemplate::parse(""), Err(TemplateError::Empty));
+        assert_eq!(RenameTemplate::parse("{stem}.{nope}"), Err(TemplateError::UnknownPlaceholder("nope".into())));
+        assert_eq!(RenameTemplate::parse("{name"), Err(TemplateError::Unclosed));
+
+        for template in ["/tmp/{name}", "../{name}", "{dir}/../../{name}", "./{name}", "a\\{name}"] {
+            assert!(matches!(RenameTemplate::parse(template), Err(TemplateError::UnsafePath(_))), "{}", template);
+        }
+    }
+
+    #[test]
+    fn globs_match_whole_paths() {
+        let matches = |pattern: &str, path: &str| Glob::parse(pattern).unwrap().matches(Path::new(path));
+
+        assert!(matches("src/**/*.rs", "src/main.rs"));
+        assert!(matches("src/**/*.rs", "src/util/io/read.rs"));
+        assert!(!matches("src/**/*.rs", "lib/src/main.rs"));
+        assert!(!matches("src/**/*.rs", "src/main.rsx"));
+        assert!(!matches("src/**/*.rs", "src"));
+
+        assert!(matches("a?.txt", "ab.txt"));
+        assert!(!matches("a?.txt", "a.txt"));
+        assert!(matches("*.md", "Readme.md"));
+        // '*' stays within a name.
+        assert!(!matches("*.md", "docs/Readme.md"));
+        assert!(matches("**", "docs/Readme.md"));
+    }
+
+    #[test]
+    fn empty_globs_are_rejected() {
+        assert_eq!(Glob::parse(""), Err(GlobError::Empty));
+        assert_eq!(Glob::parse("//"), Err(GlobError::Empty));
+    }
+}
@@ -1,9 +1,11 @@
 use std::{
     fs::File,
-    io::{copy, Read, Write},
+    io::{copy, sink, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write},
     path::PathBuf
 };
 
+use log::debug;
+
 use crate::{
     compression::{
         gzip::GzipAlgorithm, lz4::Lz4Algorithm, snappy::SnappyAlgorithm, Compress,
@@ -12,14 +14,47 @@ use crate::{
     encryption::{
         aes_gcm_256::AesGcmAlgorithm, chachapoly::ChaChaPolyAlgorithm,
         passthrough::{EncryptorPassthrough, DecryptorPassthrough}, xchachapoly::XChaChaPolyAlgorithm, DecryptionAlgorithm,
-        EncryptionAlgorithm, EncryptionModule, EncryptionSecret, EncryptionType, DecryptionModule,
+        EncryptionAlgorithm, EncryptionFraming, EncryptionModule, EncryptionSecret, EncryptionType, DecryptionModule, SecretType,
+    },
+    error::{
+        CompressorInitError, DecryptError, EncryptionSecretError, EncryptorInitError, PipelineBuildError,
+        PipelineCompressionError, PipelineDecompressionError,
     },
-    error::{PipelineBuildError, PipelineCompressionError, PipelineDecompressionError},
     signing::{
         passthrough::{SignerPassthrough, VerifierPassthrough}, Sign, SignerMethod, SigningType, VerifierMethod, Verify,
     },
 };
 
+// Length of an entry, as a little endian u64.
+const ENTRY_HEADER_SIZE: usize = 8;
+
+// Buffer sizes used to move data through a pipeline, picked from the size
+// of the file being processed when it is known. Files up to
+// SMALL_FILE_SIZE are buffered whole so that they are written in one go,
+// files from LARGE_FILE_SIZE up are moved in large chunks to cut down on
+// the number of writes.
+const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+const SMALL_FILE_SIZE: u64 = 256 * 1024;
+const LARGE_FILE_SIZE: u64 = 64 * 1024 * 1024;
+const LARGE_BUFFER_SIZE: usize = 1024 * 1024;
+
+fn buffer_size(size_hint: Option<u64>) -> usize {
+    match size_hint {
+        Some(len) if len <= SMALL_FILE_SIZE => (len as usize).max(1),
+        Some(len) if len >= LARGE_FILE_SIZE => LARGE_BUFFER_SIZE,
+        _ => DEFAULT_BUFFER_SIZE,
+    }
+}
+
+/// Location of an entry written by ProcessingPipeline::compress_entry.
+/// 'offset' is the position of the entry's header, 'length' the size of
+/// the entry following it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EntrySpan {
+    pub offset: u64,
+    pub length: u64,
+}
+
 pub struct ProcessingPipelineBuilder<'a, E, ES, C, CL, SV, S, D> {
     encryption: E,
     encryption_secret: ES,
@@ -28,6 +63,8 @@ pub struct ProcessingPipelineBuilder<'a, E, ES, C, CL, SV, S, D> {
     signing: SV,
     source: S,
     destination: D,
+    size_hint: Option<u64>,
+    framing: EncryptionFraming,
     phantom: std::marker::PhantomData<&'a ()>,
 }
 
@@ -42,6 +79,8 @@ impl <'a, E, ES, C, CL, SV, S, D> ProcessingPipelineBuilder<'a, E, ES, C, CL, SV
             signing: (),
             source: (),
             destination: (),
+            size_hint: None,
+            framing: EncryptionFraming::default(),
             phantom: std::marker::PhantomData,
         }
     }
@@ -55,6 +94,8 @@ impl <'a, E, ES, C, CL, SV, S, D> ProcessingPipelineBuilder<'a, E, ES, C, CL, SV
             signing: self.signing,
             source: self.source,
             destination: self.destination,
+            size_hint: self.size_hint,
+            framing: self.framing,
             phantom: self.phantom,
         }
     }
@@ -68,6 +109,8 @@ impl <'a, E, ES, C, CL, SV, S, D> ProcessingPipelineBuilder<'a, E, ES, C, CL, SV
             signing: self.signing,
             source: self.source,
             destination: self.destination,
+            size_hint: self.size_hint,
+            framing: self.framing,
             phantom: self.phantom,
         }
     }
@@ -81,6 +124,8 @@ impl <'a, E, ES, C, CL, SV, S, D> ProcessingPipelineBuilder<'a, E, ES, C, CL, SV
             signing: self.signing,
             source: self.source,
             destination: self.destination,
+            size_hint: self.size_hint,
+            framing: self.framing,
             phantom: self.phantom,
         }
     }
@@ -94,6 +139,8 @@ impl <'a, E, ES, C, CL, SV, S, D> ProcessingPipelineBuilder<'a, E, ES, C, CL, SV
             signing: self.signing,
             source: self.source,
             destination: self.destination,
+            size_hint: self.size_hint,
+            framing: self.framing,
             phantom: self.phantom,
         }
     }
@@ -107,6 +154,8 @@ impl <'a, E, ES, C, CL, SV, S, D> ProcessingPipelineBuilder<'a, E, ES, C, CL, SV
             signing,
             source: self.source,
             destination: self.destination,
+            size_hint: self.size_hint,
+            framing: self.framing,
             phantom: self.phantom,
         }
     }
@@ -120,6 +169,8 @@ impl <'a, E, ES, C, CL, SV, S, D> ProcessingPipelineBuilder<'a, E, ES, C, CL, SV
             signing: self.signing,
             source,
             destination: self.destination,
+            size_hint: self.size_hint,
+            framing: self.framing,
             phantom: self.phantom,
         }
     }
@@ -133,22 +184,42 @@ impl <'a, E, ES, C, CL, SV, S, D> ProcessingPipelineBuilder<'a, E, ES, C, CL, SV
             signing: self.signing,
             source: self.source,
             destination,
+            size_hint: self.size_hint,
+            framing: self.framing,
             phantom: self.phantom,
         }
     }
+
+    /// The size of the file being processed, if known, used to size
+    /// the buffers data is moved through.
+    pub fn with_size_hint(self, size_hint: u64) -> Self {
+        ProcessingPipelineBuilder {
+            size_hint: Some(size_hint),
+            ..self
+        }
+    }
+
+    /// How encrypted entries being decrypted are laid out, entries are
+    /// always encrypted as streams.
+    pub fn with_framing(self, framing: EncryptionFraming) -> Self {
+        ProcessingPipelineBuilder {
+            framing,
+            ..self
+        }
+    }
 }
 
-impl <'a> ProcessingPipelineBuilder<
+impl <'a, S, D> ProcessingPipelineBuilder<
     'a,
     &'a EncryptionType,
     &'a EncryptionSecret,
     &'a CompressionType,
     &'a flate2::Compression,
     &'a SigningType,
-    PathBuf,
-    PathBuf
+    S,
+    D
 > {
-    pub fn build(self) -> ProcessingPipeline<'a> {
+    pub fn build(self) -> ProcessingPipeline<'a, S, D> {
         ProcessingPipeline {
             encryption: self.encryption,
             encryption_secret: self.encryption_secret,
@@ -157,18 +228,108 @@ impl <'a> ProcessingPipelineBuilder<
             signing: self.signing,
             source: self.source,
             destination: self.destination,
+            size_hint: self.size_hint,
+            framing: self.framing,
+        }
+    }
+}
+
+/// Holds the settings shared by every file in a run, so that per-file
+/// pipelines can be created from it without copying the secret.
+pub struct PipelineConfig {
+    encryption: EncryptionType,
+    encryption_secret: EncryptionSecret,
+    compression: CompressionType,
+    compression_level: flate2::Compression,
+    signing: SigningType,
+    framing: EncryptionFraming,
+}
+
+impl PipelineConfig {
+    pub fn new(
+        encryption: EncryptionType,
+        encryption_secret: EncryptionSecret,
+        compression: CompressionType,
+        compression_level: flate2::Compression,
+        signing: SigningType,
+    ) -> Self {
+        PipelineConfig {
+            encryption,
+            encryption_secret,
+            compression,
+            compression_level,
+            signing,
+            framing: EncryptionFraming::default(),
         }
     }
+
+    /// For archives that record entries with another framing.
+    pub fn with_framing(self, framing: EncryptionFraming) -> Self {
+        PipelineConfig { framing, ..self }
+    }
+
+    pub fn encryption(&self) -> &EncryptionType {
+        &self.encryption
+    }
+
+    pub fn compression(&self) -> &CompressionType {
+        &self.compression
+    }
+
+    pub fn compression_level(&self) -> &flate2::Compression {
+        &self.compression_level
+    }
+
+    pub fn signing(&self) -> &SigningType {
+        &self.signing
+    }
+
+    pub fn secret_type(&self) -> SecretType {
+        SecretType::from(&self.encryption_secret)
+    }
+
+    /// Returns a builder with every setting filled in, leaving
+    /// only the source and destination to be provided.
+    pub fn builder(&self) -> ProcessingPipelineBuilder<
+        '_,
+        &EncryptionType,
+        &EncryptionSecret,
+        &CompressionType,
+        &flate2::Compression,
+        &SigningType,
+        (),
+        ()
+    > {
+        ProcessingPipeline::builder()
+            .with_encryption(&self.encryption)
+            .with_encryption_secret(&self.encryption_secret)
+            .with_compression(&self.compression)
+            .with_compression_level(&self.compression_level)
+            .with_signing(&self.signing)
+            .with_framing(self.framing)
+    }
+
+    pub fn pipeline(&self, source: PathBuf, destination: PathBuf) -> ProcessingPipeline<'_> {
+        self.builder()
+            .with_source(source)
+            .with_destination(destination)
+            .build()
+    }
 }
 
-pub struct ProcessingPipeline<'a> {
+// The source and destination default to paths for the file based
+// pipelines, though a pipeline built without them can still be driven
+// over arbitrary readers/writers using compress_stream/decompress_stream.
+pub struct ProcessingPipeline<'a, S = PathBuf, D = PathBuf> {
     encryption: &'a EncryptionType,
     encryption_secret: &'a EncryptionSecret,
     compression: &'a CompressionType,
     compression_level: &'a flate2::Compression,
     signing: &'a SigningType,
-    source: PathBuf,
-    destination: PathBuf,
+    source: S,
+    destination: D,
+    size_hint: Option<u64>,
+    framing: EncryptionFraming,
 }
 
 impl <'a> ProcessingPipeline<'a> {
@@ -177,135 +338,235 @@ impl <'a> ProcessingPipeline<'a> {
     }
 
     pub fn compress_dir(self) -> Result<(), PipelineCompressionError> {
+        let mut source = File::open(&self.source)?;
         let io = File::create(&self.destination)?;
 
-        self.build_encryptor(io)
+        self.compress_stream(&mut source, io)
     }
 
     pub fn decompress_dir(self) -> Result<(), PipelineDecompressionError> {
         let io = File::open(&self.source)?;
+        let mut destination = File::create(&self.destination)?;
+
+        self.decompress_stream(io, &mut destination)?;
 
-        self.build_dencryptor(io)
+        Ok(())
     }
+}
+
+impl <'a, D> ProcessingPipeline<'a, PathBuf, D> {
+    // Runs the source through the whole decompression pipeline, discarding
+    // the output, so that only the verifier's result is produced.
+    // None is returned when the signing method has nothing to verify.
+    pub fn verify_dir(self) -> Result<Option<Vec<u8>>, PipelineDecompressionError> {
+        let io = File::open(&self.source)?;
 
-    pub fn build_encryptor<T>(self, io: T) -> Result<(), PipelineCompressionError> 
+        self.decompress_stream(io, &mut sink())
+    }
+}
+
+impl <'a, S, D> ProcessingPipeline<'a, S, D> {
+    pub fn compress_stream<R, T>(&self, input: &mut R, io: T) -> Result<(), PipelineCompressionError>
     where
+        R: Read,
         T: Write,
     {
-        let encryption_secret = (*self.encryption_secret).clone(); // TODO: Try to get rid of this clone...
-
-        match encryption_secret {
-            EncryptionSecret::Password(p) => match *self.encryption {
-                EncryptionType::XChaCha => self.build_compressor(XChaChaPolyAlgorithm::new().with_key(p).encryptor(io)?),
-                EncryptionType::ChaCha => self.build_compressor(ChaChaPolyAlgorithm::new().with_key(p).encryptor(io)?),
-                EncryptionType::AesGcm => self.build_compressor(AesGcmAlgorithm::new().with_key(p).encryptor(io)?),
-                EncryptionType::Passthrough => self.build_compressor(EncryptorPassthrough::from(io)),
-            },
-            EncryptionSecret::Key(_) => {
-                unimplemented!("Key encryption not yet implemented")
-            }
-            EncryptionSecret::None => self.build_compressor(EncryptorPassthrough::from(io)),
+        self.build_encryptor(input, io)
+    }
+
+    pub fn decompress_stream<T, W>(&self, io: T, output: &mut W) -> Result<Option<Vec<u8>>, PipelineDecompressionError>
+    where
+        T: Read,
+        W: Write,
+    {
+        self.build_dencryptor(io, output)
+    }
+
+    /// Object safe counterpart to compress_stream, for callers that only
+    /// have trait objects, such as across plugin or FFI boundaries.
+    pub fn compress_dyn(&self, mut input: &mut dyn Read, io: &mut dyn Write) -> Result<(), PipelineCompressionError> {
+        self.compress_stream(&mut input, io)
+    }
+
+    /// Object safe counterpart to decompress_stream.
+    pub fn decompress_dyn(&self, io: &mut dyn Read, mut output: &mut dyn Write) -> Result<Option<Vec<u8>>, PipelineDecompressionError> {
+        self.decompress_stream(io, &mut output)
+    }
+
+    /// Writes 'input' to 'io' as a single entry, prefixed by its length so
+    /// that readers can skip over it without decompressing it. The length
+    /// isn't known until the entry is written, so it is patched in by
+    /// seeking back afterwards. Outputs that can't seek, such as stdout,
+    /// should use compress_stream.
+    pub fn compress_entry<R, T>(&self, input: &mut R, io: &mut T) -> Result<EntrySpan, PipelineCompressionError>
+    where
+        R: Read,
+        T: Write + Seek,
+    {
+        let offset = io.stream_position()?;
+
+        io.write_all(&[0; ENTRY_HEADER_SIZE])?;
+        self.compress_stream(input, &mut *io)?;
+
+        let end = io.stream_position()?;
+        let length = end - offset - ENTRY_HEADER_SIZE as u64;
+
+        io.seek(SeekFrom::Start(offset))?;
+        io.write_all(&length.to_le_bytes())?;
+        io.seek(SeekFrom::Start(end))?;
+
+        Ok(EntrySpan { offset, length })
+    }
+
+    /// Reads a single entry written by compress_entry, leaving 'io'
+    /// positioned at the start of the next entry.
+    pub fn decompress_entry<T, W>(&self, io: &mut T, output: &mut W) -> Result<Option<Vec<u8>>, PipelineDecompressionError>
+    where
+        T: Read,
+        W: Write,
+    {
+        let mut header = [0; ENTRY_HEADER_SIZE];
+        io.read_exact(&mut header)?;
+
+        self.decompress_stream(io.take(u64::from_le_bytes(header)), output)
+    }
+
+    // Only needed for an encrypted pipeline, so a keyfile isn't read for
+    // the rest.
+    fn key(&self) -> Result<Option<Vec<u8>>, EncryptorInitError> {
+        match self.encryption {
+            EncryptionType::Passthrough => Ok(None),
+            encryption => self.encryption_secret.key(encryption).map_err(|e| EncryptionSecretError::from(e).into()),
+        }
+    }
+
+    pub fn build_encryptor<R, T>(&self, input: &mut R, io: T) -> Result<(), PipelineCompressionError> 
+    where
+        R: Read,
+        T: Write,
+    {
+        match (self.key()?, self.encryption) {
+            (Some(key), EncryptionType::XChaCha) => self.build_compressor(input, XChaChaPolyAlgorithm::new().with_key(key).encryptor(io)?),
+            (Some(key), EncryptionType::ChaCha) => self.build_compressor(input, ChaChaPolyAlgorithm::new().with_key(key).encryptor(io)?),
+            (Some(key), EncryptionType::AesGcm) => self.build_compressor(input, AesGcmAlgorithm::new().with_key(key).encryptor(io)?),
+            (_, EncryptionType::Passthrough) | (None, _) => self.build_compressor(input, EncryptorPassthrough::from(io)),
         }
     }
 
-    pub fn build_compressor<T>(&self, io: T) -> Result<(), PipelineCompressionError>
+    pub fn build_compressor<R, T>(&self, input: &mut R, io: T) -> Result<(), PipelineCompressionError>
     where
+        R: Read,
         T: EncryptionModule,
     {
+        debug!("Encrypting with: {:?}", io.algorithm());
+
         let compression_level = *self.compression_level; // TODO: Try to get rid of this copy...
 
         match *self.compression {
-            CompressionType::Lz4 => self.build_signer(Lz4Algorithm::new().compressor(io)?),
+            CompressionType::Lz4 => self.build_signer(input, Lz4Algorithm::new().compressor(io)?),
             CompressionType::Gzip => self.build_signer(
+                input,
                 GzipAlgorithm::with_compression_level(compression_level).compressor(io)?,
             ),
-            CompressionType::Snappy => self.build_signer(SnappyAlgorithm::new().compressor(io)?),
-            CompressionType::Passthrough => self.build_signer(PassthroughAlgorithm::new().compressor(io)?),
+            CompressionType::Snappy => self.build_signer(input, SnappyAlgorithm::new().compressor(io)?),
+            CompressionType::Passthrough => self.build_signer(input, PassthroughAlgorithm::new().compressor(io)?),
+            CompressionType::Auto => Err(CompressorInitError::UnresolvedAlgorithm.into()),
         }
     }
 
-    pub fn build_signer<T>(&self, io: T) -> Result<(), PipelineCompressionError> 
+    pub fn build_signer<R, T>(&self, input: &mut R, io: T) -> Result<(), PipelineCompressionError> 
     where
+        R: Read,
         T: Compress,
     {
+        debug!("Compressing with: {:?}", io.algorithm());
+
         match *self.signing {
             SigningType::Passthrough => {
                 let pipeline = PipelineTask::from_writer(SignerPassthrough::from(io));
-                self.execute_compression_pipeline(pipeline)
+                self.execute_compression_pipeline(input, pipeline)
             }
         }
     }
 
-    fn execute_compression_pipeline<T>(&self, pipeline: T) -> Result<(), PipelineCompressionError> 
+    fn execute_compression_pipeline<R, T>(&self, input: &mut R, pipeline: T) -> Result<(), PipelineCompressionError> 
     where 
+        R: Read,
         T: CompressionPipeline,
     {
-        let mut source = File::open(&self.source)?;
+        let buffer_size = buffer_size(self.size_hint);
+
+        debug!("Compressing with a {} byte buffer", buffer_size);
 
-        match pipeline.compress(&mut source) {
+        match pipeline.compress(&mut BufReader::with_capacity(buffer_size, input)) {
             Ok(_) => Ok(()),
             Err(e) => Err(e)
         }
     }
 
-    pub fn build_dencryptor<T>(self, io: T) -> Result<(), PipelineDecompressionError> 
+    pub fn build_dencryptor<T, W>(&self, io: T, output: &mut W) -> Result<Option<Vec<u8>>, PipelineDecompressionError> 
     where
         T: Read,
+        W: Write,
     {
-        let encryption_secret = (*self.encryption_secret).clone(); // TODO: Try to get rid of this clone...
-
-        match encryption_secret {
-            EncryptionSecret::Password(p) => match *self.encryption {
-                EncryptionType::XChaCha => self.build_decompressor(XChaChaPolyAlgorithm::new().with_key(p).decryptor(io)?),
-                EncryptionType::ChaCha => self.build_decompressor(ChaChaPolyAlgorithm::new().with_key(p).decryptor(io)?),
-                EncryptionType::AesGcm => self.build_decompressor(AesGcmAlgorithm::new().with_key(p).decryptor(io)?),
-                EncryptionType::Passthrough => self.build_decompressor(DecryptorPassthrough::from(io)),
-            },
-            EncryptionSecret::Key(_) => {
-                unimplemented!("Key encryption not yet implemented")
-            }
-            EncryptionSecret::None => self.build_decompressor(DecryptorPassthrough::from(io)),
+        match (self.key()?, self.encryption) {
+            (Some(key), EncryptionType::XChaCha) => self.build_decompressor(XChaChaPolyAlgorithm::new().with_key(key).with_framing(self.framing).decryptor(io)?, output),
+            (Some(key), EncryptionType::ChaCha) => self.build_decompressor(ChaChaPolyAlgorithm::new().with_key(key).with_framing(self.framing).decryptor(io)?, output),
+            (Some(key), EncryptionType::AesGcm) => self.build_decompressor(AesGcmAlgorithm::new().with_key(key).with_framing(self.framing).decryptor(io)?, output),
+            (_, EncryptionType::Passthrough) | (None, _) => self.build_decompressor(DecryptorPassthrough::from(io), output),
         }
     }
 
-    pub fn build_decompressor<T>(&self, io: T) -> Result<(), PipelineDecompressionError>
+    pub fn build_decompressor<T, W>(&self, io: T, output: &mut W) -> Result<Option<Vec<u8>>, PipelineDecompressionError>
     where
         T: DecryptionModule,
+        W: Write,
     {
+        debug!("Decrypting with: {:?}", io.algorithm());
+
         let compression_level = *self.compression_level; // TODO: Try to get rid of this copy...
 
         match *self.compression {
-            CompressionType::Lz4 => self.build_verifier(Lz4Algorithm::new().decompressor(io)?),
+            CompressionType::Lz4 => self.build_verifier(Lz4Algorithm::new().decompressor(io)?, output),
             CompressionType::Gzip => self.build_verifier(
                 GzipAlgorithm::with_compression_level(compression_level).decompressor(io)?,
+                output,
             ),
-            CompressionType::Snappy => self.build_verifier(SnappyAlgorithm::new().decompressor(io)?),
-            CompressionType::Passthrough => self.build_verifier(PassthroughAlgorithm::new().decompressor(io)?),
+            CompressionType::Snappy => self.build_verifier(SnappyAlgorithm::new().decompressor(io)?, output),
+            CompressionType::Passthrough => self.build_verifier(PassthroughAlgorithm::new().decompressor(io)?, output),
+            CompressionType::Auto => Err(CompressorInitError::UnresolvedAlgorithm.into()),
         }
     }
 
-    pub fn build_verifier<T>(&self, io: T) -> Result<(), PipelineDecompressionError> 
+    pub fn build_verifier<T, W>(&self, io: T, output: &mut W) -> Result<Option<Vec<u8>>, PipelineDecompressionError> 
     where
         T: Decompress,
+        W: Write,
     {
+        debug!("Decompressing with: {:?}", io.algorithm());
+
         match *self.signing {
             SigningType::Passthrough => {
                 let pipeline = PipelineTask::from_reader(VerifierPassthrough::from(io));
-                self.execute_decompression_pipeline(pipeline)
+                self.execute_decompression_pipeline(pipeline, output)
             }
         }
     }
 
-    fn execute_decompression_pipeline<T>(&self, pipeline: T) -> Result<(), PipelineDecompressionError> 
+    fn execute_decompression_pipeline<T, W>(&self, pipeline: T, output: &mut W) -> Result<Option<Vec<u8>>, PipelineDecompressionError> 
     where 
         T: DecompressionPipeline,
+        W: Write,
     {
-        let mut destination = File::create(&self.destination)?;
+        let buffer_size = buffer_size(self.size_hint);
 
-        match pipeline.decompress(&mut destination) {
-            Ok(_) => Ok(()),
-            Err(e) => Err(e)
-        }
+        debug!("Decompressing with a {} byte buffer", buffer_size);
+
+        let mut writer = BufWriter::with_capacity(buffer_size, output);
+        let result = pipeline.decompress(&mut writer)?;
+        writer.flush()?;
+
+        Ok(result)
     }
 }
 
@@ -369,7 +630,18 @@ where
     where
         F: Write,
     {
-        copy(&mut self.inner, output)?;
+        // Backends report a stream that ends early as UnexpectedEof, this is
+        // surfaced separately so a truncated entry can't pass as complete.
+        // Snappy frames carry no end marker, so a stream cut exactly on a
+        // frame boundary is indistinguishable from a complete one.
+        copy(&mut self.inner, output).map_err(|e| match e.kind() {
+            ErrorKind::UnexpectedEof => PipelineDecompressionError::UnexpectedEof(e.to_string()),
+            _ if e.get_ref().is_some_and(|e| e.is::<DecryptError>()) => {
+                let inner = e.into_inner().expect("Checked above").downcast::<DecryptError>().expect("Checked above");
+                PipelineDecompressionError::DecryptError(*inner)
+            },
+            _ => e.into(),
+        })?;
         Ok(self.inner.finalise()?)
     }
 }
@@ -471,3 +743,188 @@ where
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secret_for(encryption: &EncryptionType) -> EncryptionSecret {
+        match encryption {
+            EncryptionType::Passthrough => EncryptionSecret::None,
+            _ => EncryptionSecret::Password(vec![7; 32]),
+        }
+    }
+
+    #[test]
+    fn every_combination_round_trips() {
+        let input = b"every combination of algorithms\n".repeat(1000);
+        let level = flate2::Compression::default();
+        let signing = SigningType::default();
+
+        // Auto is only resolved from an archive, never built.
+        for compression in CompressionType::ALL.into_iter().filter(|c| *c != CompressionType::Auto) {
+            for encryption in EncryptionType::ALL {
+                let secret = secret_for(&encryption);
+
+                let output = crate::compress_bytes(&input, &encryption, &secret, &compression, &level, &signing)
+                    .unwrap_or_else(|e| panic!("{:?} with {:?} failed to compress: {}", compression, encryption, e));
+
+                let restored = crate::decompress_bytes(&output, &encryption, &secret, &compression, &signing)
+                    .unwrap_or_else(|e| panic!("{:?} with {:?} failed to decompress: {}", compression, encryption, e));
+
+                assert_eq!(restored, input, "{:?} with {:?}", compression, encryption);
+            }
+        }
+    }
+
+    // Data that doesn't compress to nothing, so that every stage has bytes
+    // of its own buffered at the end.
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state: u32 = len as u32;
+
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 24) as u8
+            })
+            .collect()
+    }
+
+    // A stage finalised before the one above it has written out its buffer
+    // loses the end of the entry, which only shows when the last block
+    // lands on a boundary: the encryptor's chunks, and the buffer the
+    // pipeline reads through, with and without a size hint.
+    #[test]
+    fn boundaries_round_trip() {
+        let chunk = crate::encryption::stream::CHUNK_SIZE;
+        let buffer = DEFAULT_BUFFER_SIZE;
+        let small = SMALL_FILE_SIZE as usize;
+
+        let sizes = [
+            chunk - 1, chunk, chunk + 1, chunk * 2,
+            buffer - 1, buffer, buffer + 1, buffer * 2,
+            small, small + 1,
+        ];
+
+        let level = flate2::Compression::fast();
+        let signing = SigningType::default();
+
+        for len in sizes {
+            let input = noise(len);
+
+            for compression in CompressionType::ALL.into_iter().filter(|c| *c != CompressionType::Auto) {
+                for encryption in EncryptionType::ALL {
+                    let secret = secret_for(&encryption);
+
+                    for size_hint in [None, Some(len as u64)] {
+                        let mut builder = ProcessingPipeline::builder()
+                            .with_compression(&compression)
+                            .with_compression_level(&level)
+                            .with_encryption(&encryption)
+                            .with_encryption_secret(&secret)
+                            .with_signing(&signing);
+
+                        if let Some(size_hint) = size_hint {
+                            builder = builder.with_size_hint(size_hint);
+                        }
+
+                        let mut output = Vec::new();
+                        builder.build().compress_stream(&mut input.as_slice(), &mut output).unwrap();
+
+                        let restored = crate::decompress_bytes(&output, &encryption, &secret, &compression, &signing).unwrap();
+
+                        assert_eq!(
+                            restored.len(),
+                            input.len(),
+                            "{:?} with {:?}, {} bytes, size hint {:?}",
+                            compression, encryption, len, size_hint,
+                        );
+                        assert!(restored == input, "{:?} with {:?}, {} bytes: contents differ", compression, encryption, len);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_streams_fail() {
+        let input = noise(100_000);
+        let level = flate2::Compression::default();
+        let signing = SigningType::default();
+
+        for compression in [CompressionType::Gzip, CompressionType::Lz4, CompressionType::Snappy] {
+            let output = crate::compress_bytes(&input, &EncryptionType::Passthrough, &EncryptionSecret::None, &compression, &level, &signing).unwrap();
+
+            // Part way into a block or frame, rather than on the boundary
+            // snappy can't tell from the end.
+            let truncated = &output[..output.len() / 2];
+            let decompressed = crate::decompress_bytes(truncated, &EncryptionType::Passthrough, &EncryptionSecret::None, &compression, &signing);
+
+            assert!(matches!(decompressed, Err(PipelineDecompressionError::UnexpectedEof(_))), "{:?}: {:?}", compression, decompressed.map(|d| d.len()));
+        }
+    }
+
+    #[test]
+    fn keyfiles_round_trip() {
+        let dir = std::env::temp_dir();
+        let key = dir.join(format!("zap-test-pipeline-{}.key", std::process::id()));
+        let other = dir.join(format!("zap-test-pipeline-other-{}.key", std::process::id()));
+
+        std::fs::write(&key, [1; 32]).unwrap();
+        std::fs::write(&other, [2; 32]).unwrap();
+
+        let secret = EncryptionSecret::Key(key.to_string_lossy().into_owned());
+        let wrong = EncryptionSecret::Key(other.to_string_lossy().into_owned());
+
+        let input = b"encrypted with a keyfile".repeat(100);
+        let level = flate2::Compression::default();
+        let signing = SigningType::default();
+
+        for encryption in EncryptionType::ALL.into_iter().filter(|e| *e != EncryptionType::Passthrough) {
+            let output = crate::compress_bytes(&input, &encryption, &secret, &CompressionType::Lz4, &level, &signing).unwrap();
+
+            assert_eq!(crate::decompress_bytes(&output, &encryption, &secret, &CompressionType::Lz4, &signing).unwrap(), input);
+            assert!(crate::decompress_bytes(&output, &encryption, &wrong, &CompressionType::Lz4, &signing).is_err(), "{:?}", encryption);
+        }
+
+        std::fs::remove_file(&key).unwrap();
+        std::fs::remove_file(&other).unwrap();
+    }
+
+    #[test]
+    fn wrong_algorithm_is_reported() {
+        let secret = EncryptionSecret::Password(vec![3; 32]);
+        let input = b"encrypted with one algorithm".repeat(100);
+        let level = flate2::Compression::default();
+        let signing = SigningType::default();
+
+        let encrypted: Vec<EncryptionType> = EncryptionType::ALL.into_iter().filter(|e| *e != EncryptionType::Passthrough).collect();
+
+        for used in encrypted.iter() {
+            let output = crate::compress_bytes(&input, used, &secret, &CompressionType::Lz4, &level, &signing).unwrap();
+
+            for given in encrypted.iter().filter(|given| *given != used) {
+                let result = crate::decompress_bytes(&output, given, &secret, &CompressionType::Lz4, &signing);
+                assert!(result.is_err_and(|e| e.is_wrong_algorithm()), "{:?} read as {:?}", used, given);
+            }
+
+            // The right algorithm with the wrong password opens with none of them.
+            let result = crate::decompress_bytes(&output, used, &EncryptionSecret::Password(vec![4; 32]), &CompressionType::Lz4, &signing);
+            assert!(result.is_err_and(|e| !e.is_wrong_algorithm()), "{:?}", used);
+        }
+    }
+
+    #[test]
+    fn auto_is_not_built() {
+        let result = crate::compress_bytes(
+            b"",
+            &EncryptionType::Passthrough,
+            &EncryptionSecret::None,
+            &CompressionType::Auto,
+            &flate2::Compression::default(),
+            &SigningType::default(),
+        );
+
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,282 @@
+use rayon::{ThreadPool, ThreadPoolBuildError, ThreadPoolBuilder};
+
+use crate::{
+    compression::CompressionType,
+    encryption::{EncryptionSecret, EncryptionType},
+    error::{CompressionError, DecompressionError, PipelineCompressionError, PipelineDecompressionError},
+    options::{CompressOptions, DecompressOptions},
+    report::DirectoryReport,
+    signing::SigningType,
+};
+
+/// Algorithms, options and a thread pool shared by every call, for
+/// programs that compress many times rather than once. The free functions
+/// of the same names take everything as arguments and use rayon's global
+/// pool instead.
+pub struct Processor {
+    pool: ThreadPool,
+    encryption: EncryptionType,
+    encryption_secret: EncryptionSecret,
+    compression: CompressionType,
+    compression_level: flate2::Compression,
+    signing: SigningType,
+    compress_options: CompressOptions,
+    decompress_options: DecompressOptions,
+}
+
+impl Processor {
+    pub fn builder() -> ProcessorBuilder {
+        ProcessorBuilder::default()
+    }
+
+    pub fn compress_directory(&self, input_folder_path: &str, output_folder_path: &str) -> Result<DirectoryReport, CompressionError> {
+        self.pool.install(|| {
+            crate::compress_directory(
+                input_folder_path,
+                output_folder_path,
+                self.encryption.clone(),
+                self.encryption_secret.clone(),
+                self.compression.clone(),
+                self.compression_level,
+                self.signing.clone(),
+                &self.compress_options,
+            )
+        })
+    }
+
+    /// As compress_directory, with each directory stored under its name.
+    pub fn compress_directories(&self, input_folder_paths: &[&str], output_folder_path: &str) -> Result<DirectoryReport, CompressionError> {
+        self.pool.install(|| {
+            crate::compress_directories(
+                input_folder_paths,
+                output_folder_path,
+                self.encryption.clone(),
+                self.encryption_secret.clone(),
+                self.compression.clone(),
+                self.compression_level,
+                self.signing.clone(),
+                &self.compress_options,
+            )
+        })
+    }
+
+    /// The algorithms recorded in the archive's manifest are used over the
+    /// processor's, as with decompress_directory.
+    pub fn decompress_directory(&self, input_folder_path: &str, output_folder_path: &str) -> Result<DirectoryReport, DecompressionError> {
+        self.pool.install(|| {
+            crate::decompress_directory(
+                input_folder_path,
+                output_folder_path,
+                self.encryption.clone(),
+                self.encryption_secret.clone(),
+                self.compression.clone(),
+                self.signing.clone(),
+                &self.decompress_options,
+            )
+        })
+    }
+
+    // A single buffer is processed on the calling thread, the pool isn't used.
+    pub fn compress_bytes(&self, input: &[u8]) -> Result<Vec<u8>, PipelineCompressionError> {
+        crate::compress_bytes(
+            input,
+            &self.encryption,
+            &self.encryption_secret,
+            &self.compression,
+            &self.compression_level,
+            &self.signing,
+        )
+    }
+
+    pub fn decompress_bytes(&self, input: &[u8]) -> Result<Vec<u8>, PipelineDecompressionError> {
+        crate::decompress_bytes(input, &self.encryption, &self.encryption_secret, &self.compression, &self.signing)
+    }
+}
+
+/// Defaults to no encryption or compression, and a thread per CPU.
+#[derive(Default)]
+pub struct ProcessorBuilder {
+    threads: usize,
+    thread_name: Option<String>,
+    encryption: EncryptionType,
+    encryption_secret: EncryptionSecret,
+    compression: CompressionType,
+    compression_level: flate2::Compression,
+    signing: SigningType,
+    compress_options: CompressOptions,
+    decompress_options: DecompressOptions,
+}
+
+impl ProcessorBuilder {
+    /// Number of threads in the pool, 0 for one per CPU.
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Names the pool's threads 'name-0', 'name-1' and so on, to tell them
+    /// apart from the rest of the program's.
+    pub fn with_thread_name(mut self, name: impl Into<String>) -> Self {
+        self.thread_name = Some(name.into());
+        self
+    }
+
+    pub fn with_encryption(mut self, encryption: EncryptionType, encryption_secret: EncryptionSecret) -> Self {
+        self.encryption = encryption;
+        self.encryption_secret = encryption_secret;
+        self
+    }
+
+    pub fn with_compression(mut self, compression: CompressionType, compression_level: flate2::Compression) -> Self {
+        self.compression = compression;
+        self.compression_level = compression_level;
+        self
+    }
+
+    pub fn with_signing(mut self, signing: SigningType) -> Self {
+        self.signing = signing;
+        self
+    }
+
+    /// Includes the progress to update, and the size under which files
+    /// are compressed in memory.
+    pub fn with_compress_options(mut self, options: CompressOptions) -> Self {
+        self.compress_options = options;
+        self
+    }
+
+    pub fn with_decompress_options(mut self, options: DecompressOptions) -> Self {
+        self.decompress_options = options;
+        self
+    }
+
+    pub fn build(self) -> Result<Processor, ThreadPoolBuildError> {
+        let mut pool = ThreadPoolBuilder::new().num_threads(self.threads);
+
+        if let Some(name) = self.thread_name {
+            pool = pool.thread_name(move |n| format!("{}-{}", name, n));
+        }
+
+        Ok(Processor {
+            pool: pool.build()?,
+            encryption: self.encryption,
+            encryption_secret: self.encryption_secret,
+            compression: self.compression,
+            compression_level: self.compression_level,
+            signing: self.signing,
+            compress_options: self.compress_options,
+            decompress_options: self.decompress_options,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+    use crate::options::ContentTransform;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("zap-processor-{}-{}", name, std::process::id()))
+    }
+
+    fn write_files(root: &Path, files: &[(&str, &[u8])]) {
+        for (name, data) in files {
+            std::fs::create_dir_all(root).unwrap();
+            std::fs::write(root.join(name), data).unwrap();
+        }
+    }
+
+    fn processor() -> Processor {
+        Processor::builder()
+            .with_encryption(EncryptionType::XChaCha, EncryptionSecret::Password(vec![7; 32]))
+            .with_compression(CompressionType::Lz4, flate2::Compression::default())
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn work_runs_on_the_named_pool() {
+        let root = temp_path("pool");
+        let (input, output) = (root.join("input"), root.join("output"));
+
+        let names = ["a", "b", "c", "d", "e", "f", "g", "h"].map(|name| (name, name.repeat(1000)));
+        write_files(&input, &names.each_ref().map(|(name, data)| (*name, data.as_bytes())));
+
+        // The transform is called on whichever thread compresses the file.
+        let threads: Arc<Mutex<HashSet<String>>> = Arc::default();
+        let seen = threads.clone();
+
+        let options = CompressOptions {
+            transform: Some(ContentTransform::new(move |_, contents| {
+                let name = std::thread::current().name().unwrap_or_default().to_string();
+                seen.lock().unwrap().insert(name);
+
+                Box::new(contents)
+            })),
+            ..CompressOptions::default()
+        };
+
+        let processor = Processor::builder()
+            .with_threads(2)
+            .with_thread_name("zap-pool")
+            .with_compress_options(options)
+            .build()
+            .unwrap();
+
+        let report = processor.compress_directory(&input.to_string_lossy(), &output.to_string_lossy());
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let threads = threads.lock().unwrap();
+
+        assert_eq!(processor.pool.current_num_threads(), 2);
+        assert_eq!(report.unwrap().processed.len(), names.len());
+        assert!(!threads.is_empty() && threads.len() <= 2);
+        assert!(threads.iter().all(|name| name == "zap-pool-0" || name == "zap-pool-1"), "{:?}", threads);
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let processor = processor();
+        let input = b"processed".repeat(1000);
+
+        let compressed = processor.compress_bytes(&input).unwrap();
+
+        assert_ne!(compressed, input);
+        assert_eq!(processor.decompress_bytes(&compressed).unwrap(), input);
+    }
+
+    #[test]
+    fn directories_round_trip_through_one_processor() {
+        let root = temp_path("directories");
+        let processor = processor();
+
+        let results: Vec<_> = ["first", "second"]
+            .iter()
+            .map(|name| {
+                let (input, output, restored) = (root.join(name), root.join(format!("{}.zap", name)), root.join(format!("{}.out", name)));
+                let data = name.repeat(1000);
+
+                write_files(&input, &[("file.txt", data.as_bytes())]);
+
+                let compressed = processor.compress_directory(&input.to_string_lossy(), &output.to_string_lossy());
+                let decompressed = processor.decompress_directory(&output.to_string_lossy(), &restored.to_string_lossy());
+
+                (compressed.map(|r| r.processed.len()).ok(), decompressed.map(|r| r.processed.len()).ok(), std::fs::read(restored.join("file.txt")).ok(), data)
+            })
+            .collect();
+
+        std::fs::remove_dir_all(&root).unwrap();
+
+        for (compressed, decompressed, restored, data) in results {
+            assert_eq!(compressed, Some(1));
+            assert_eq!(decompressed, Some(1));
+            assert_eq!(restored, Some(data.into_bytes()));
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+// Throughput is averaged over this many of the most recently finished
+// files, so that the ETA follows changes in speed without jumping
+// around on every file.
+const THROUGHPUT_SAMPLES: usize = 32;
+
+/// Counts the files and bytes finished by compress_directory or
+/// decompress_directory, so that progress can be displayed from
+/// another thread while they run. The same counts are then reused
+/// for packing the archive, see start_packing.
+#[derive(Debug, Default)]
+pub struct Progress {
+    packing: AtomicBool,
+    total_files: AtomicU64,
+    total_bytes: AtomicU64,
+    done_files: AtomicU64,
+    done_bytes: AtomicU64,
+    current: Mutex<Option<PathBuf>>,
+    // When each recent file finished, and the bytes done at that point.
+    samples: Mutex<VecDeque<(Instant, u64)>>,
+}
+
+/// What the counted files and bytes are being finished by.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProgressPhase {
+    /// Compressing or decompressing files.
+    Processing,
+    /// Writing the archive, which only counts bytes.
+    Packing,
+}
+
+/// Progress at a point in time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgressSnapshot {
+    pub phase: ProgressPhase,
+    pub total_files: u64,
+    pub total_bytes: u64,
+    pub done_files: u64,
+    pub done_bytes: u64,
+    /// The file started most recently. Files are processed in parallel,
+    /// so others may be in progress too. None while packing.
+    pub current_path: Option<PathBuf>,
+    /// Bytes per second, None until a file has finished.
+    pub throughput: Option<f64>,
+    pub eta: Option<Duration>,
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn start(&self, files: u64, bytes: u64) {
+        self.total_files.store(files, Ordering::Relaxed);
+        self.total_bytes.store(bytes, Ordering::Relaxed);
+        self.done_files.store(0, Ordering::Relaxed);
+        self.done_bytes.store(0, Ordering::Relaxed);
+        *self.current.lock().expect("Progress lock poisoned") = None;
+
+        let mut samples = self.samples.lock().expect("Progress lock poisoned");
+        samples.clear();
+        samples.push_back((Instant::now(), 0));
+    }
+
+    /// Starts counting again for packing 'bytes' into an archive, which
+    /// happens outside of the library, eg: through zapf.
+    pub fn start_packing(&self, bytes: u64) {
+        self.packing.store(true, Ordering::Relaxed);
+        self.start(0, bytes);
+    }
+
+    /// Counts bytes written to the archive after start_packing.
+    pub fn pack_bytes(&self, bytes: u64) {
+        self.advance(0, bytes);
+    }
+
+    pub(crate) fn start_file(&self, path: &Path) {
+        *self.current.lock().expect("Progress lock poisoned") = Some(path.to_path_buf());
+    }
+
+    pub(crate) fn finish_file(&self, bytes: u64) {
+        self.advance(1, bytes);
+    }
+
+    fn advance(&self, files: u64, bytes: u64) {
+        self.done_files.fetch_add(files, Ordering::Relaxed);
+        let done = self.done_bytes.fetch_add(bytes, Ordering::Relaxed) + bytes;
+
+        let mut samples = self.samples.lock().expect("Progress lock poisoned");
+
+        // One more than the window is kept, as the first sample is
+        // only used as the starting point.
+        if samples.len() > THROUGHPUT_SAMPLES {
+            samples.pop_front();
+        }
+
+        samples.push_back((Instant::now(), done));
+    }
+
+    pub fn snapshot(&self) -> ProgressSnapshot {
+        let total_bytes = self.total_bytes.load(Ordering::Relaxed);
+        let done_bytes = self.done_bytes.load(Ordering::Relaxed);
+
+        let samples = self.samples.lock().expect("Progress lock poisoned");
+
+        // Measured up to now rather than the last finished file, so that
+        // the throughput drops while a large file is being processed.
+        let throughput = match (samples.front(), samples.len()) {
+            (Some((since, bytes)), 2..) => {
+                let elapsed = since.elapsed().as_secs_f64();
+                (elapsed > 0.0).then(|| (done_bytes - bytes) as f64 / elapsed)
+            },
+            _ => None,
+        };
+
+        let eta = throughput
+            .filter(|t| *t > 0.0)
+            .map(|t| Duration::from_secs_f64(total_bytes.saturating_sub(done_bytes) as f64 / t));
+
+        let phase = match self.packing.load(Ordering::Relaxed) {
+            true => ProgressPhase::Packing,
+            false => ProgressPhase::Processing,
+        };
+
+        ProgressSnapshot {
+            phase,
+            total_files: self.total_files.load(Ordering::Relaxed),
+            total_bytes,
+            done_files: self.done_files.load(Ordering::Relaxed),
+            done_bytes,
+            current_path: self.current.lock().expect("Progress lock poisoned").clone(),
+            throughput,
+            eta,
+        }
+    }
+}
@@ -0,0 +1,444 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{copy, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::error::RecoveryError;
+
+// An archive may end with an optional recovery record, allowing damaged
+// blocks of everything before it to be reconstructed:
+//
+//   [data][parity blocks][block digests][trailer]
+//
+// The data is split into blocks of 'block_size' bytes, the last one padded
+// with zeroes. Parity block 'j' is the xor of every data block 'i' where
+// 'i % parity_blocks == j', so each parity block can restore one damaged
+// data block from its group. The digests, one per data block followed by
+// one per parity block, are used to find the damaged blocks.
+//
+// The trailer holds:
+//
+//   [data length: u64 le][block size: u32 le][percent: u32 le][parity blocks: u64 le][RECOVERY_MAGIC]
+//
+// As with the footer, the trailer must agree with the size of the file
+// for a recovery record to be found. The record is applied last, so that
+// it also covers the footer when there is one.
+pub const RECOVERY_MAGIC: &[u8; 8] = b"ZAPRCVR1";
+pub const RECOVERY_BLOCK_SIZE: u64 = 64 * 1024;
+
+const TRAILER_SIZE: u64 = 32;
+const DIGEST_SIZE: u64 = 32;
+
+/// Describes the recovery record appended to an archive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecoveryRecord {
+    pub data_len: u64,
+    pub block_size: u64,
+    /// Parity, as a percentage of the number of data blocks.
+    pub percent: u32,
+    pub parity_blocks: u64,
+}
+
+impl RecoveryRecord {
+    pub fn data_blocks(&self) -> u64 {
+        self.data_len.div_ceil(self.block_size)
+    }
+
+    // None when the trailer holds nonsense that overflows.
+    fn record_len(&self) -> Option<u64> {
+        let parity = self.parity_blocks.checked_mul(self.block_size)?;
+        let digests = self.data_blocks().checked_add(self.parity_blocks)?.checked_mul(DIGEST_SIZE)?;
+
+        parity.checked_add(digests)?.checked_add(TRAILER_SIZE)
+    }
+
+    fn parity_offset(&self) -> u64 {
+        self.data_len
+    }
+
+    fn digest_offset(&self) -> u64 {
+        self.data_len + self.parity_blocks * self.block_size
+    }
+
+    fn to_bytes(&self) -> [u8; TRAILER_SIZE as usize] {
+        let mut bytes = [0; TRAILER_SIZE as usize];
+
+        bytes[..8].copy_from_slice(&self.data_len.to_le_bytes());
+        bytes[8..12].copy_from_slice(&(self.block_size as u32).to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.percent.to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.parity_blocks.to_le_bytes());
+        bytes[24..].copy_from_slice(RECOVERY_MAGIC);
+
+        bytes
+    }
+}
+
+/// The outcome of repairing an archive, by block index.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RepairReport {
+    pub checked_blocks: u64,
+    /// Data blocks that were damaged and have been restored.
+    pub repaired: Vec<u64>,
+    /// Parity blocks that were damaged and have been rewritten.
+    pub rebuilt_parity: Vec<u64>,
+}
+
+/// Appends a recovery record to the archive at 'path', with 'percent'
+/// parity blocks for every hundred data blocks, rounded up.
+/// The parity is held in memory while it is computed.
+pub fn append_recovery(path: &Path, percent: u32) -> Result<RecoveryRecord, RecoveryError> {
+    if !(1..=100).contains(&percent) {
+        return Err(RecoveryError::InvalidPercent(percent));
+    }
+
+    if read_recovery(path)?.is_some() {
+        return Err(RecoveryError::AlreadyPresent(path.display().to_string()));
+    }
+
+    let mut file = OpenOptions::new().read(true).append(true).open(path)?;
+    let data_len = file.metadata()?.len();
+
+    let mut record = RecoveryRecord {
+        data_len,
+        block_size: RECOVERY_BLOCK_SIZE,
+        percent,
+        parity_blocks: 0,
+    };
+
+    let data_blocks = record.data_blocks();
+
+    if data_blocks > 0 {
+        record.parity_blocks = (data_blocks * percent as u64).div_ceil(100).max(1);
+    }
+
+    let block_size = record.block_size as usize;
+    let mut parity = vec![0; record.parity_blocks as usize * block_size];
+    let mut digests: Vec<[u8; 32]> = Vec::with_capacity((data_blocks + record.parity_blocks) as usize);
+    let mut block = vec![0; block_size];
+
+    file.seek(SeekFrom::Start(0))?;
+    let mut reader = BufReader::new(&mut file).take(data_len);
+
+    for i in 0..data_blocks {
+        read_block(&mut reader, &mut block)?;
+        digests.push(Sha256::digest(&block).into());
+
+        let group = (i % record.parity_blocks) as usize;
+        xor_into(&mut parity[group * block_size..(group + 1) * block_size], &block);
+    }
+
+    drop(reader);
+
+    parity.chunks(block_size).for_each(|p| digests.push(Sha256::digest(p).into()));
+
+    let mut writer = BufWriter::new(&mut file);
+
+    writer.write_all(&parity)?;
+    digests.iter().try_for_each(|d| writer.write_all(d))?;
+    writer.write_all(&record.to_bytes())?;
+    writer.flush()?;
+
+    drop(writer);
+    file.sync_all()?;
+
+    Ok(record)
+}
+
+/// Reads the recovery record of the archive at 'path', None when it has none.
+pub fn read_recovery(path: &Path) -> Result<Option<RecoveryRecord>, RecoveryError> {
+    Ok(read_record(&mut File::open(path)?)?)
+}
+
+/// Length of 'file' without its recovery record, if it has one.
+pub(crate) fn payload_len(file: &mut File) -> Result<u64, std::io::Error> {
+    match read_record(file)? {
+        Some(record) => Ok(record.data_len),
+        None => file.metadata().map(|m| m.len()),
+    }
+}
+
+/// Copies the archive at 'path', without its recovery record, to 'destination'.
+pub fn copy_data(path: &Path, record: &RecoveryRecord, destination: &Path) -> Result<(), RecoveryError> {
+    let mut reader = BufReader::new(File::open(path)?).take(record.data_len);
+    let mut writer = BufWriter::new(File::create(destination)?);
+
+    copy(&mut reader, &mut writer)?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Checks every block of the archive at 'path' against the recovery
+/// record and restores the damaged ones in place. Fails without writing
+/// anything when a group has more damaged blocks than it can restore.
+pub fn repair(path: &Path) -> Result<RepairReport, RecoveryError> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+
+    let record = read_record(&mut file)?
+        .ok_or_else(|| RecoveryError::Missing(path.display().to_string()))?;
+
+    let data_blocks = record.data_blocks();
+    let block_size = record.block_size as usize;
+
+    let mut digests = vec![[0; 32]; (data_blocks + record.parity_blocks) as usize];
+    file.seek(SeekFrom::Start(record.digest_offset()))?;
+    digests.iter_mut().try_for_each(|d| file.read_exact(d))?;
+
+    let mut report = RepairReport {
+        checked_blocks: data_blocks + record.parity_blocks,
+        ..Default::default()
+    };
+
+    let mut block = vec![0; block_size];
+    let mut damaged: Vec<Vec<u64>> = vec![Vec::new(); record.parity_blocks as usize];
+
+    for i in 0..data_blocks {
+        read_data_block(&mut file, &record, i, &mut block)?;
+
+        if Sha256::digest(&block).as_slice() != digests[i as usize] {
+            damaged[(i % record.parity_blocks) as usize].push(i);
+        }
+    }
+
+    // Every group is checked before anything is written, so that an
+    // archive that can't be repaired is left as it was.
+    let mut restored: Vec<(u64, Vec<u8>)> = Vec::new();
+    let mut rebuilt: Vec<(u64, Vec<u8>)> = Vec::new();
+
+    for (group, blocks) in damaged.iter().enumerate() {
+        let group = group as u64;
+
+        let mut parity = vec![0; block_size];
+        read_parity_block(&mut file, &record, group, &mut parity)?;
+
+        let parity_intact = Sha256::digest(&parity).as_slice() == digests[(data_blocks + group) as usize];
+
+        match (blocks.as_slice(), parity_intact) {
+            ([], true) => (),
+            ([], false) => {
+                // Only the parity is damaged, so it is computed again.
+                parity.fill(0);
+
+                for i in (group..data_blocks).step_by(record.parity_blocks as usize) {
+                    read_data_block(&mut file, &record, i, &mut block)?;
+                    xor_into(&mut parity, &block);
+                }
+
+                rebuilt.push((group, parity));
+            },
+            ([lost], true) => {
+                for i in (group..data_blocks).step_by(record.parity_blocks as usize).filter(|i| i != lost) {
+                    read_data_block(&mut file, &record, i, &mut block)?;
+                    xor_into(&mut parity, &block);
+                }
+
+                if Sha256::digest(&parity).as_slice() != digests[*lost as usize] {
+                    return Err(RecoveryError::Unrecoverable(path.display().to_string(), group));
+                }
+
+                restored.push((*lost, parity));
+            },
+            _ => return Err(RecoveryError::Unrecoverable(path.display().to_string(), group)),
+        }
+    }
+
+    for (i, data) in restored {
+        let offset = i * record.block_size;
+        let len = record.block_size.min(record.data_len - offset) as usize;
+
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(&data[..len])?;
+
+        report.repaired.push(i);
+    }
+
+    for (group, parity) in rebuilt {
+        file.seek(SeekFrom::Start(record.parity_offset() + group * record.block_size))?;
+        file.write_all(&parity)?;
+
+        report.rebuilt_parity.push(group);
+    }
+
+    file.sync_all()?;
+
+    Ok(report)
+}
+
+fn read_record(file: &mut File) -> Result<Option<RecoveryRecord>, std::io::Error> {
+    let len = file.metadata()?.len();
+
+    if len < TRAILER_SIZE {
+        return Ok(None);
+    }
+
+    let mut bytes = [0; TRAILER_SIZE as usize];
+    file.seek(SeekFrom::End(-(TRAILER_SIZE as i64)))?;
+    file.read_exact(&mut bytes)?;
+
+    if &bytes[24..] != RECOVERY_MAGIC {
+        return Ok(None);
+    }
+
+    let record = RecoveryRecord {
+        data_len: u64::from_le_bytes(bytes[..8].try_into().expect("Slice is 8 bytes")),
+        block_size: u32::from_le_bytes(bytes[8..12].try_into().expect("Slice is 4 bytes")) as u64,
+        percent: u32::from_le_bytes(bytes[12..16].try_into().expect("Slice is 4 bytes")),
+        parity_blocks: u64::from_le_bytes(bytes[16..24].try_into().expect("Slice is 8 bytes")),
+    };
+
+    if record.block_size == 0
+        || (record.parity_blocks == 0) != (record.data_len == 0)
+        || record.record_len().and_then(|l| l.checked_add(record.data_len)) != Some(len)
+    {
+        return Ok(None);
+    }
+
+    Ok(Some(record))
+}
+
+fn read_data_block(file: &mut File, record: &RecoveryRecord, index: u64, block: &mut [u8]) -> Result<(), std::io::Error> {
+    let offset = index * record.block_size;
+
+    file.seek(SeekFrom::Start(offset))?;
+    read_block(&mut file.take(record.data_len - offset), block)
+}
+
+fn read_parity_block(file: &mut File, record: &RecoveryRecord, group: u64, block: &mut [u8]) -> Result<(), std::io::Error> {
+    file.seek(SeekFrom::Start(record.parity_offset() + group * record.block_size))?;
+    file.read_exact(block)
+}
+
+// Fills 'block' from 'reader', padding it with zeroes if the
+// reader ends first.
+fn read_block<R: Read>(reader: &mut R, block: &mut [u8]) -> Result<(), std::io::Error> {
+    let mut filled = 0;
+
+    while filled < block.len() {
+        match reader.read(&mut block[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+
+    block[filled..].fill(0);
+
+    Ok(())
+}
+
+fn xor_into(target: &mut [u8], block: &[u8]) {
+    target.iter_mut().zip(block).for_each(|(t, b)| *t ^= b);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Three full blocks and a fourth padded with zeroes, with two parity
+    // blocks: one for blocks 0 and 2, one for blocks 1 and 3.
+    const DATA_LEN: u64 = 3 * RECOVERY_BLOCK_SIZE + 1000;
+    const PERCENT: u32 = 50;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("zap-recovery-{}-{}", name, std::process::id()))
+    }
+
+    // An archive with a recovery record, and its bytes before any damage.
+    fn archive(name: &str) -> (std::path::PathBuf, Vec<u8>) {
+        let path = temp_path(name);
+        let data: Vec<u8> = (0..DATA_LEN).map(|i| (i % 251) as u8).collect();
+
+        std::fs::write(&path, &data).unwrap();
+        append_recovery(&path, PERCENT).unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+
+        (path, bytes)
+    }
+
+    fn corrupt(path: &Path, offset: u64) {
+        let mut bytes = std::fs::read(path).unwrap();
+        bytes[offset as usize] ^= 0xff;
+        std::fs::write(path, bytes).unwrap();
+    }
+
+    #[test]
+    fn damaged_data_blocks_are_restored() {
+        let (path, original) = archive("data");
+
+        // The first block, and the last one, which is only partly data.
+        corrupt(&path, 10);
+        corrupt(&path, DATA_LEN - 1);
+
+        let report = repair(&path);
+        let repaired = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(report.unwrap(), RepairReport { checked_blocks: 6, repaired: vec![0, 3], rebuilt_parity: vec![] });
+        assert_eq!(repaired, original);
+    }
+
+    #[test]
+    fn damaged_parity_is_rebuilt() {
+        let (path, original) = archive("parity");
+
+        corrupt(&path, DATA_LEN + RECOVERY_BLOCK_SIZE + 5);
+
+        let report = repair(&path);
+        let repaired = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(report.unwrap(), RepairReport { checked_blocks: 6, repaired: vec![], rebuilt_parity: vec![1] });
+        assert_eq!(repaired, original);
+    }
+
+    #[test]
+    fn two_damaged_blocks_in_a_group_are_unrecoverable() {
+        let (path, _) = archive("unrecoverable");
+
+        // Blocks 0 and 2, both restored from the first parity block, and
+        // block 1, which could be restored on its own.
+        corrupt(&path, 10);
+        corrupt(&path, RECOVERY_BLOCK_SIZE + 10);
+        corrupt(&path, 2 * RECOVERY_BLOCK_SIZE + 10);
+
+        let damaged = std::fs::read(&path).unwrap();
+        let report = repair(&path);
+        let after = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(report, Err(RecoveryError::Unrecoverable(_, 0))));
+        assert_eq!(after, damaged);
+    }
+
+    #[test]
+    fn only_one_record_is_appended() {
+        let (path, original) = archive("twice");
+
+        let result = append_recovery(&path, PERCENT);
+        let after = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(result, Err(RecoveryError::AlreadyPresent(_))));
+        assert_eq!(after, original);
+    }
+
+    #[test]
+    fn record_must_match_the_file() {
+        let (path, original) = archive("truncated");
+
+        // The trailer is intact, but the data it describes is a byte short.
+        std::fs::write(&path, &original[1..]).unwrap();
+
+        let record = read_record(&mut File::open(&path).unwrap());
+        let len = payload_len(&mut File::open(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(record.unwrap(), None);
+        assert_eq!(len.unwrap(), original.len() as u64 - 1);
+    }
+}
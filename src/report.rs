@@ -0,0 +1,154 @@
+use std::{fmt::Display, path::PathBuf, time::Duration};
+
+use crate::{compression::CompressionType, manifest::Manifest};
+
+/// Summary of the files handled while processing a directory.
+#[derive(Debug, Default)]
+pub struct DirectoryReport {
+    pub processed: Vec<FileReport>,
+    pub skipped: Vec<SkippedFile>,
+    /// Files with the same contents as a processed file, which are
+    /// recorded in the manifest instead of being stored again.
+    pub duplicates: Vec<DuplicateFile>,
+    /// Time taken to find the files to process, before any of them were.
+    pub walk_elapsed: Duration,
+    /// Time taken to process every file, on however many threads, rather
+    /// than the sum of each file's.
+    pub process_elapsed: Duration,
+}
+
+impl DirectoryReport {
+    /// Total size of the processed files' sources.
+    pub fn bytes(&self) -> u64 {
+        self.processed.iter().map(|f| f.bytes).sum()
+    }
+
+    /// Total size of the processed files' destinations.
+    pub fn written_bytes(&self) -> u64 {
+        self.processed.iter().map(|f| f.written_bytes).sum()
+    }
+
+    /// Size of the processed files' destinations over their sources, eg:
+    /// 0.25 when compressing to a quarter of the size. None when nothing
+    /// was read.
+    pub fn ratio(&self) -> Option<f64> {
+        let bytes = self.bytes();
+        let written = self.written_bytes();
+
+        (bytes > 0).then(|| written as f64 / bytes as f64)
+    }
+}
+
+#[derive(Debug)]
+pub struct FileReport {
+    pub source: PathBuf,
+    pub destination: PathBuf,
+    /// Size of the source file.
+    pub bytes: u64,
+    /// Size of the destination file, 0 if it couldn't be read.
+    pub written_bytes: u64,
+    /// Time taken to process the file, including any retries.
+    pub elapsed: Duration,
+}
+
+#[derive(Debug)]
+pub struct DuplicateFile {
+    pub path: PathBuf,
+    /// The source file whose contents are stored in its place.
+    pub duplicate_of: PathBuf,
+}
+
+#[derive(Debug)]
+pub struct SkippedFile {
+    pub path: PathBuf,
+    pub reason: SkipReason,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SkipReason {
+    /// The file was removed after the directory was scanned.
+    Removed,
+    /// The file was modified while it was being processed.
+    Changed,
+    /// The path is a FIFO, socket or device node rather than a regular file.
+    NotRegularFile,
+    /// Another entry is extracted to the same path.
+    Conflict,
+    /// The path has no more components than were asked to be stripped.
+    TooFewComponents,
+    /// The name, or the name of a directory it is in, starts with '.'.
+    Hidden,
+    /// The name, or the name of a directory it is in, is used by a
+    /// version control system.
+    VersionControl,
+    /// The path it would be written to is longer than the platform allows.
+    PathTooLong,
+}
+
+impl Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SkipReason::Removed => write!(f, "file was removed"),
+            SkipReason::Changed => write!(f, "file was modified"),
+            SkipReason::NotRegularFile => write!(f, "not a regular file"),
+            SkipReason::Conflict => write!(f, "conflicts with another entry"),
+            SkipReason::TooFewComponents => write!(f, "too few path components to strip"),
+            SkipReason::Hidden => write!(f, "hidden"),
+            SkipReason::VersionControl => write!(f, "version control"),
+            SkipReason::PathTooLong => write!(f, "path is too long"),
+        }
+    }
+}
+
+/// Result of running a file through a verifier. The signature is
+/// None when the signing method has nothing to verify.
+#[derive(Debug)]
+pub struct VerifiedFile {
+    pub path: PathBuf,
+    pub signature: Option<Vec<u8>>,
+}
+
+/// Contents of an unpacked archive, without decompressing any entries.
+#[derive(Debug)]
+pub struct ArchiveListing {
+    pub manifest: Option<Manifest>,
+    pub entries: Vec<ListedEntry>,
+}
+
+#[derive(Debug)]
+pub struct ListedEntry {
+    /// Path the entry will be extracted to, relative to the output directory.
+    pub path: PathBuf,
+    /// Size of the entry after compression and encryption, 0 for duplicates.
+    pub stored_size: u64,
+    pub compression: CompressionType,
+    /// The entry whose contents are extracted to this path, for entries
+    /// stored once with --dedup.
+    pub duplicate_of: Option<PathBuf>,
+}
+
+/// How a directory differs from an archive, by the path each file is
+/// extracted to.
+#[derive(Debug, Default)]
+pub struct ArchiveComparison {
+    /// In the directory but not the archive.
+    pub added: Vec<PathBuf>,
+    /// In the archive but not the directory.
+    pub removed: Vec<PathBuf>,
+    /// In both, with a different size or modification time.
+    pub modified: Vec<PathBuf>,
+    /// In both, but archived by an older version of zap that didn't
+    /// record their size and modification time.
+    pub unchecked: Vec<PathBuf>,
+}
+
+impl ArchiveComparison {
+    pub fn is_in_sync(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+pub(crate) enum FileOutcome {
+    Processed(FileReport),
+    Skipped(SkippedFile),
+}
@@ -5,6 +5,8 @@ use std::io::{
     Error, Read, Write
 };
 
+use serde::{Deserialize, Serialize};
+
 use crate::{error::SignerInitError, compression::{Compress, Decompress}};
 
 pub trait Signer<U> {
@@ -32,6 +34,8 @@ pub struct SignerMode;
 pub struct VerifierMode;
 
 pub trait Sign: Write {
+    /// Finalises the compressor beneath, which finalises the encryptor,
+    /// so that every stage is written out before the next is finished.
     fn finalise(self) -> Result<Option<Vec<u8>>, Error>;
 }
 pub trait Verify: Read {
@@ -54,8 +58,19 @@ where T: Decompress
     fn verifier(&self, reader: T) -> Result<Self::Verifier, SignerInitError>;
 }
 
-#[derive(Default, Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum SigningType {
-    #[default]
+    /// Entries are not signed, verifying them produces no signature.
     Passthrough,
+}
+
+/// Archives are unsigned unless a signing method is asked for. This is
+/// kept explicit rather than derived as the default decides whether
+/// archives are signed at all, so changing it must be deliberate.
+#[allow(clippy::derivable_impls)]
+impl Default for SigningType {
+    fn default() -> Self {
+        SigningType::Passthrough
+    }
 }
\ No newline at end of file
@@ -0,0 +1,110 @@
+use std::ops::Range;
+
+// Finding the holes of sparse files, eg: VM disk images, which are mostly
+// blocks of zeroes that needn't be compressed or written out.
+//
+// Nothing in archiving or extraction uses this yet. It is the scan that
+// sparse file support will be built on, kept public and out of the docs
+// only so that benches/zero_runs.rs can measure it ahead of that.
+//
+// A block is checked LANES bytes at a time, OR'd together with no branch
+// inside a lane, which the compiler turns into vector instructions. Only
+// between lanes is the scan cut short on a non zero byte, so data blocks
+// cost little more than a lane to rule out. See benches/zero_runs.rs for
+// how this compares to checking a byte at a time.
+const LANES: usize = 64;
+
+/// Size of the blocks holes are found in, that of a filesystem block.
+pub const BLOCK_SIZE: usize = 4096;
+
+/// Whether every byte of 'buf' is zero.
+pub fn is_zero(buf: &[u8]) -> bool {
+    let mut lanes = buf.chunks_exact(LANES);
+
+    lanes.by_ref().all(|lane| lane.iter().fold(0, |acc, b| acc | b) == 0)
+        && lanes.remainder().iter().all(|b| *b == 0)
+}
+
+/// The runs of 'block_size' blocks of 'buf' that are all zero, merged
+/// where they are next to each other. A shorter last block is a hole too
+/// when it is all zero.
+pub fn zero_runs(buf: &[u8], block_size: usize) -> Vec<Range<usize>> {
+    runs(buf, block_size, is_zero)
+}
+
+/// zero_runs checking a byte at a time, kept to compare it against.
+pub fn zero_runs_naive(buf: &[u8], block_size: usize) -> Vec<Range<usize>> {
+    runs(buf, block_size, |block| block.iter().all(|b| *b == 0))
+}
+
+fn runs(buf: &[u8], block_size: usize, zero: impl Fn(&[u8]) -> bool) -> Vec<Range<usize>> {
+    assert!(block_size > 0, "Block size must not be zero");
+
+    let mut runs: Vec<Range<usize>> = Vec::new();
+
+    for (i, block) in buf.chunks(block_size).enumerate() {
+        if !zero(block) {
+            continue;
+        }
+
+        let start = i * block_size;
+        let end = start + block.len();
+
+        match runs.last_mut() {
+            Some(last) if last.end == start => last.end = end,
+            _ => runs.push(start..end),
+        }
+    }
+
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image() -> Vec<u8> {
+        // Data, a hole of three blocks, a block with its last byte set,
+        // then a short block of zeroes.
+        let mut buf = vec![1; BLOCK_SIZE];
+        buf.extend(vec![0; BLOCK_SIZE * 3]);
+        buf.extend(vec![0; BLOCK_SIZE - 1]);
+        buf.push(1);
+        buf.extend(vec![0; 100]);
+
+        buf
+    }
+
+    #[test]
+    fn finds_holes() {
+        let buf = image();
+
+        assert_eq!(
+            zero_runs(&buf, BLOCK_SIZE),
+            vec![BLOCK_SIZE..BLOCK_SIZE * 4, BLOCK_SIZE * 5..BLOCK_SIZE * 5 + 100],
+        );
+    }
+
+    #[test]
+    fn agrees_with_naive() {
+        let buf = image();
+
+        for block_size in [1, 63, 64, 65, 512, BLOCK_SIZE, BLOCK_SIZE * 7] {
+            assert_eq!(zero_runs(&buf, block_size), zero_runs_naive(&buf, block_size), "block size {}", block_size);
+        }
+    }
+
+    #[test]
+    fn checks_every_byte() {
+        // Set in the middle of a lane, at the end of one, and in the remainder.
+        for i in [0, 31, LANES - 1, LANES, LANES * 3 + 5, LANES * 4 + 2] {
+            let mut buf = vec![0; LANES * 4 + 3];
+            buf[i] = 0x80;
+
+            assert!(!is_zero(&buf), "missed byte {}", i);
+        }
+
+        assert!(is_zero(&[]));
+        assert!(is_zero(&[0; LANES * 4 + 3]));
+    }
+}
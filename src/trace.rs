@@ -0,0 +1,67 @@
+// Per-file spans for the 'tracing' feature, so that the time and outcome
+// of each file can be correlated. Without the feature these do nothing,
+// and the log macros used elsewhere are unaffected either way.
+
+use std::path::Path;
+
+use crate::compression::CompressionType;
+
+#[cfg(feature = "tracing")]
+use std::{fmt::Display, time::Instant};
+
+/// Entered when created and exited when dropped, recording how long the
+/// file took. The outcome should be set before it is dropped.
+pub(crate) struct FileSpan {
+    #[cfg(feature = "tracing")]
+    span: tracing::span::EnteredSpan,
+    #[cfg(feature = "tracing")]
+    started: Instant,
+}
+
+#[cfg(feature = "tracing")]
+impl FileSpan {
+    pub(crate) fn enter(operation: &'static str, path: &Path, algorithm: &CompressionType, bytes: u64) -> Self {
+        let span = tracing::info_span!(
+            "file",
+            operation,
+            path = %path.display(),
+            algorithm = ?algorithm,
+            bytes,
+            elapsed_ms = tracing::field::Empty,
+            outcome = tracing::field::Empty,
+            error = tracing::field::Empty,
+        );
+
+        FileSpan {
+            span: span.entered(),
+            started: Instant::now(),
+        }
+    }
+
+    pub(crate) fn outcome(&self, outcome: &'static str) {
+        self.span.record("outcome", outcome);
+    }
+
+    pub(crate) fn failed(&self, error: &dyn Display) {
+        self.span.record("outcome", "failed");
+        self.span.record("error", tracing::field::display(error));
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl Drop for FileSpan {
+    fn drop(&mut self) {
+        self.span.record("elapsed_ms", self.started.elapsed().as_millis() as u64);
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+impl FileSpan {
+    pub(crate) fn enter(_operation: &'static str, _path: &Path, _algorithm: &CompressionType, _bytes: u64) -> Self {
+        FileSpan {}
+    }
+
+    pub(crate) fn outcome(&self, _outcome: &'static str) {}
+
+    pub(crate) fn failed(&self, _error: &dyn std::fmt::Display) {}
+}